@@ -0,0 +1,425 @@
+// src/audit.rs
+//! This module contains the tamper-evident audit log of security events:
+//! * append-only log of logins, failures, account changes and denials
+//! * every entry carries the keyed hash of the entry before it
+//! * a head file pins the newest entry, so truncation is detected too
+//! * a head left behind by a crash is moved up to the entry it missed
+//! * verification of the whole chain
+
+// ==================== IMPORTS ====================
+
+use crate::auth_utils::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{OpenOptions, read_to_string};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// ==================== CONSTANTS ====================
+
+/// `prev` of the first entry in a log
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// suffix of the file pinning the sequence number and hash of the newest entry
+pub const HEAD_SUFFIX: &str = ".head";
+
+/// file mode of the audit log and its head, readable and writable by the owner only
+const AUDIT_MODE: u32 = 0o600;
+
+// ==================== STRUCTURES ====================
+
+/// how an audited action ended
+/// # Variants
+/// * `Success` - the action was carried out
+/// * `Failure` - the action failed, e.g. a wrong password
+/// * `Denied` - the user lacked the permissions to attempt it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+    Denied,
+}
+
+impl fmt::Display for AuditOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuditOutcome::Success => write!(f, "success"),
+            AuditOutcome::Failure => write!(f, "failure"),
+            AuditOutcome::Denied => write!(f, "denied"),
+        }
+    }
+}
+
+/// one line of the audit log
+/// # Fields
+/// * `seq` - position in the log, starting at 1
+/// * `time` - seconds since the unix epoch
+/// * `actor` - account that performed the action, empty when logged out
+/// * `event` - what happened, usually the shell command
+/// * `target` - account acted upon, if any
+/// * `outcome` - how the action ended
+/// * `detail` - free-form description
+/// * `prev` - hash of the previous entry, `GENESIS_HASH` for the first
+/// * `hash` - HMAC-SHA256 of this entry with `hash` left empty, keyed with the seal key
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub seq: u64,
+    pub time: u64,
+    pub actor: String,
+    pub event: String,
+    pub target: String,
+    pub outcome: AuditOutcome,
+    pub detail: String,
+    pub prev: String,
+    pub hash: String,
+}
+
+/// Methods for the struct
+impl AuditEntry {
+    /// compute the hash of the entry, which covers every field but `hash` itself
+    /// it is keyed, so nobody without the key can write an entry that chains on
+    /// # Arguments
+    /// * `key` - seal key
+    /// # Return
+    /// * hex encoded HMAC-SHA256
+    pub fn digest(&self, key: &[u8]) -> String {
+        let unhashed = AuditEntry {
+            hash: String::new(),
+            ..self.clone()
+        };
+        let line = serde_json::to_vec(&unhashed).expect("audit entries always serialize");
+        hex::encode(hmac_sha256(key, &line))
+    }
+}
+
+/// Displays the entry as one human readable line
+impl fmt::Display for AuditEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let or_dash = |s: &str| match s.is_empty() {
+            true => "-".to_string(),
+            false => s.to_string(),
+        };
+        write!(
+            f,
+            "{:>5} {} {:<10} {:<12} {:<10} {:<8} {}",
+            self.seq,
+            format_time(self.time),
+            or_dash(&self.actor),
+            self.event,
+            or_dash(&self.target),
+            self.outcome,
+            self.detail
+        )
+    }
+}
+
+/// errors returned when reading or verifying the audit log
+/// # Variants
+/// * `Io` - the log could not be read or written
+/// * `Malformed` - a line of the log is not an entry
+/// * `Tampered` - an entry was edited, removed or reordered
+/// * `Truncated` - the log does not reach the entry pinned by the head file
+#[derive(Debug)]
+pub enum AuditError {
+    Io(std::io::Error),
+    Malformed(usize),
+    Tampered(u64, String),
+    Truncated(String),
+}
+
+impl fmt::Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuditError::Io(e) => write!(f, "{}", e),
+            AuditError::Malformed(line) => write!(f, "line #{} is not an audit entry", line),
+            AuditError::Tampered(seq, reason) => write!(f, "entry {}: {}", seq, reason),
+            AuditError::Truncated(reason) => write!(f, "log truncated: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for AuditError {}
+
+impl From<std::io::Error> for AuditError {
+    fn from(e: std::io::Error) -> Self {
+        AuditError::Io(e)
+    }
+}
+
+/// append-only, hash-chained log of security events
+/// # Fields
+/// * `location` - filepath of the log, locked with `<log>.lock` while appending
+/// * `key_location` - filepath of the seal key the entries are hashed with
+/// # Methods
+/// * `new` - refers to a log, which is created on the first entry
+/// * `record` - appends an entry
+/// * `entries` - reads every entry
+/// * `verify` - checks the whole chain against the head file
+pub struct AuditLog {
+    location: String,
+    key_location: String,
+}
+
+/// Methods for the struct
+impl AuditLog {
+    /// refer to an audit log, creating nothing until the first entry
+    /// a head left behind by a crash between writing an entry and its head is moved up
+    /// # Arguments
+    /// * `filepath` - filepath of the log
+    /// * `key_path` - filepath of the seal key, generated on the first entry if missing
+    pub fn new(filepath: &str, key_path: &str) -> Self {
+        let log = AuditLog {
+            location: filepath.to_string(),
+            key_location: key_path.to_string(),
+        };
+        if !Path::new(&log.location).exists() {
+            return log;
+        }
+        let recovered = lock_file(Path::new(&log.lock_path()), true)
+            .map_err(AuditError::from)
+            .and_then(|_lock| log.recover_head());
+        if let Err(e) = recovered {
+            eprintln!(
+                "\x1b[91mFailed to recover the head of audit log '{}'. Error: {}\x1b[0m",
+                log.location, e
+            );
+        }
+        log
+    }
+
+    /// append an entry to the log, printing an error if it can't be written
+    /// # Arguments
+    /// * `actor` - account performing the action, empty when logged out
+    /// * `event` - what happened
+    /// * `target` - account acted upon, empty if none
+    /// * `outcome` - how the action ended
+    /// * `detail` - free-form description
+    pub fn record(
+        &self,
+        actor: &str,
+        event: &str,
+        target: &str,
+        outcome: AuditOutcome,
+        detail: &str,
+    ) {
+        if let Err(e) = self.append(actor, event, target, outcome, detail) {
+            eprintln!(
+                "\x1b[91mFailed to write to audit log '{}'. Error: {}\x1b[0m",
+                self.location, e
+            );
+        }
+    }
+
+    /// internal method to chain a new entry onto the newest one and append it
+    fn append(
+        &self,
+        actor: &str,
+        event: &str,
+        target: &str,
+        outcome: AuditOutcome,
+        detail: &str,
+    ) -> Result<(), AuditError> {
+        let _lock = lock_file(Path::new(&self.lock_path()), true)?;
+        let key = read_seal_key(Path::new(&self.key_location))?;
+
+        // chain onto the pinned head, or the last entry if the head is missing
+        let (seq, prev) = match self.recover_head()? {
+            Some(head) => head,
+            None => match self.entries()?.pop() {
+                Some(last) => (last.seq, last.hash),
+                None => (0, GENESIS_HASH.to_string()),
+            },
+        };
+        let mut entry = AuditEntry {
+            seq: seq + 1,
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            actor: actor.to_string(),
+            event: event.to_string(),
+            target: target.to_string(),
+            outcome,
+            detail: detail.to_string(),
+            prev,
+            hash: String::new(),
+        };
+        entry.hash = entry.digest(&key);
+
+        let mut line = serde_json::to_string(&entry).expect("audit entries always serialize");
+        line.push('\n');
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(AUDIT_MODE)
+            .open(&self.location)?;
+        file.write_all(line.as_bytes())?;
+        file.sync_all()?;
+        atomic_write(
+            &self.head_path(),
+            format!("{} {}\n", entry.seq, entry.hash).as_bytes(),
+            AUDIT_MODE,
+        )?;
+        Ok(())
+    }
+
+    /// internal method to get the path of the head file
+    fn head_path(&self) -> String {
+        format!("{}{}", self.location, HEAD_SUFFIX)
+    }
+
+    /// internal method to get the path of the lock file guarding appends
+    fn lock_path(&self) -> String {
+        format!("{}.lock", self.location)
+    }
+
+    /// internal method to move the head up to the last entry when a crash came between
+    /// appending that entry and writing its head, so the next entry doesn't reuse its seq
+    /// the caller must hold the log lock
+    /// # Return
+    /// * the head after recovery, `None` if there is no head file
+    fn recover_head(&self) -> Result<Option<(u64, String)>, AuditError> {
+        let head = self.read_head()?;
+        let last = match self.entries()?.pop() {
+            Some(last) => last,
+            None => return Ok(head),
+        };
+        let (seq, hash) = head.clone().unwrap_or((0, GENESIS_HASH.to_string()));
+
+        // only an entry that follows the head and carries a valid keyed hash is taken up
+        if last.seq != seq + 1 || last.prev != hash {
+            return Ok(head);
+        }
+        let key = read_seal_key(Path::new(&self.key_location))?;
+        if !constant_time_eq(last.hash.as_bytes(), last.digest(&key).as_bytes()) {
+            return Ok(head);
+        }
+        atomic_write(
+            &self.head_path(),
+            format!("{} {}\n", last.seq, last.hash).as_bytes(),
+            AUDIT_MODE,
+        )?;
+        Ok(Some((last.seq, last.hash)))
+    }
+
+    /// internal method to read the sequence number and hash pinned by the head file
+    /// # Return
+    /// * the pinned entry, `None` if there is no head file yet
+    fn read_head(&self) -> Result<Option<(u64, String)>, AuditError> {
+        let head = match read_to_string(self.head_path()) {
+            Ok(head) => head,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        match head.trim_end().split_once(' ') {
+            Some((seq, hash)) => match seq.parse() {
+                Ok(seq) => Ok(Some((seq, hash.to_string()))),
+                Err(_) => Err(AuditError::Truncated("head file is malformed".to_string())),
+            },
+            None => Err(AuditError::Truncated("head file is malformed".to_string())),
+        }
+    }
+
+    /// read every entry of the log, without checking the chain
+    /// # Return
+    /// * entries in the order they were written, empty if there is no log yet
+    pub fn entries(&self) -> Result<Vec<AuditEntry>, AuditError> {
+        let data = match read_to_string(&self.location) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        data.lines()
+            .enumerate()
+            .map(|(i, line)| serde_json::from_str(line).map_err(|_| AuditError::Malformed(i + 1)))
+            .collect()
+    }
+
+    /// check that no entry was edited, removed or reordered, and that none are missing at the end
+    /// # Return
+    /// * number of entries verified, or the first problem found
+    pub fn verify(&self) -> Result<usize, AuditError> {
+        let entries = self.entries()?;
+        let key = match entries.is_empty() {
+            true => Vec::new(),
+            false => read_seal_key(Path::new(&self.key_location))?,
+        };
+        let mut prev = GENESIS_HASH.to_string();
+        for (i, entry) in entries.iter().enumerate() {
+            if entry.seq != i as u64 + 1 {
+                return Err(AuditError::Tampered(
+                    entry.seq,
+                    format!("expected sequence number {}", i + 1),
+                ));
+            }
+            if entry.prev != prev {
+                return Err(AuditError::Tampered(
+                    entry.seq,
+                    "does not follow the previous entry".to_string(),
+                ));
+            }
+            if !constant_time_eq(entry.hash.as_bytes(), entry.digest(&key).as_bytes()) {
+                return Err(AuditError::Tampered(
+                    entry.seq,
+                    "contents do not match its hash".to_string(),
+                ));
+            }
+            prev = entry.hash.clone();
+        }
+
+        // the head pins the newest entry, so removing entries from the end is noticed
+        let last = entries.last().map_or(0, |e| e.seq);
+        match self.read_head()? {
+            None if entries.is_empty() => Ok(0),
+            None => Err(AuditError::Truncated("head file is missing".to_string())),
+            Some((seq, _)) if seq > last => Err(AuditError::Truncated(format!(
+                "head is at entry {} but the log ends at {}",
+                seq, last
+            ))),
+            Some((seq, _)) if seq < last => Err(AuditError::Tampered(
+                seq + 1,
+                "was appended without updating the head".to_string(),
+            )),
+            Some((_, hash)) if hash != prev => Err(AuditError::Tampered(
+                last,
+                "does not match the head".to_string(),
+            )),
+            Some(_) => Ok(entries.len()),
+        }
+    }
+}
+
+// ==================== FUNCTIONS ====================
+
+/// This function formats a unix timestamp as a UTC date and time
+/// # Arguments
+/// * `secs` - seconds since the unix epoch
+/// # Return
+/// * timestamp like `2024-01-31 23:59:59`
+pub fn format_time(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // civil date from days since 1970-01-01, in 400 year eras starting in march
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
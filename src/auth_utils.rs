@@ -5,17 +5,29 @@
 //! * authentication
 //! * secure password input
 //! * credential storage
+//! * group storage
 
 // ==================== IMPORTS ====================
 
+use crate::dbcheck::*;
+use crate::username::*;
 use base64::{Engine as _, engine::general_purpose};
 use rand_core::{OsRng, TryRngCore};
 use rpassword::prompt_password;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
-use std::fs::write;
+use std::fs::{
+    OpenOptions, Permissions, TryLockError, copy, hard_link, read, remove_file, rename,
+    set_permissions,
+};
 use std::io::BufReader;
 use std::io::prelude::*;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 
 // ==================== CONSTANTS ====================
 
@@ -26,7 +38,122 @@ pub const DEF_SALT_LEN: usize = 16;
 pub const DEF_HASH_COST: usize = 12;
 
 /// version of hashing algorithm
-const HASH_VERSION: &str = "sha256iter-1";
+pub const HASH_VERSION: &str = "sha256iter-1";
+
+/// version of the onion hash, pbkdf2-sha256 wrapped around a `HASH_VERSION` digest
+pub const ONION_HASH_VERSION: &str = "pbkdf2-sha256iter-1";
+
+/// hash versions new passwords can be hashed with
+pub const HASH_VERSIONS: [&str; 2] = [HASH_VERSION, ONION_HASH_VERSION];
+
+/// highest cost accepted in a stored hash, e.g. 2^n iterations
+pub const MAX_HASH_COST: usize = 30;
+
+/// highest cost of the pbkdf2 layer of an onion hash, lower than `MAX_HASH_COST` since every
+/// pbkdf2 iteration runs several sha-256 compressions
+pub const MAX_ONION_COST: usize = 20;
+
+/// length of a sha-256 digest, in bytes
+const SHA256_LEN: usize = 32;
+
+/// predefined root user name
+pub const ROOT: &str = "root";
+
+/// uid (and primary gid) reserved for the root account
+pub const ROOT_UID: u32 = 0;
+
+/// first uid handed out to regular accounts, like `UID_MIN` in login.defs
+pub const FIRST_UID: u32 = 1000;
+
+/// lowest gid given to groups created with `groupadd`, clear of the uids given to accounts so
+/// a new group never shares a gid with an account's primary group
+pub const FIRST_GID: u32 = 10000;
+
+/// permissions of the group file, readable by the owner only, since unlike /etc/group its
+/// memberships grant capabilities and it is not covered by the database seal
+pub const GROUP_MODE: u32 = 0o600;
+
+/// directory under which regular accounts get their home directory
+pub const HOME_BASE: &str = "/home";
+
+/// home directory of the root account
+pub const ROOT_HOME: &str = "/root";
+
+/// login shell assigned to new accounts
+pub const DEF_SHELL: &str = "/bin/sh";
+
+/// password field of a passwd record whose hash lives in the shadow file
+pub const SHADOW_PLACEHOLDER: &str = "x";
+
+/// hash field of an account that cannot be authenticated against
+pub const LOCKED_HASH: &str = "!";
+
+/// file mode of the passwd file, world readable
+pub const PASSWD_MODE: u32 = 0o644;
+
+/// file mode of the shadow file, readable and writable by the owner only
+pub const SHADOW_MODE: u32 = 0o600;
+
+/// suffix of the temporary file a database is written to before it replaces the original
+pub const TEMP_SUFFIX: &str = "+";
+
+/// suffix of the copy of the previous database kept after every write
+pub const BACKUP_SUFFIX: &str = "-";
+
+/// name of the lock file guarding the database, created next to the passwd file like `lckpwdf`
+pub const LOCK_FILE: &str = ".pwd.lock";
+
+/// suffix of the file holding the integrity seal of the passwd and shadow files
+pub const SEAL_SUFFIX: &str = ".seal";
+
+/// name of the key file used to seal the database, created next to the passwd file
+pub const SEAL_KEY_FILE: &str = ".pwd.key";
+
+/// MAC algorithm named in the seal file
+const SEAL_ALGO: &str = "hmac-sha256";
+
+/// length of a generated seal key, in bytes
+const SEAL_KEY_LEN: usize = 32;
+
+/// how long to wait for another process to release the database lock
+const LOCK_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// delay between attempts to take the database lock
+const LOCK_RETRY: Duration = Duration::from_millis(100);
+
+/// header of a key-value store log, followed by the log's generation id
+const KV_MAGIC: &str = "credkv-1";
+
+/// superseded log entries tolerated before a key-value store log is compacted
+const KV_COMPACT_MIN: usize = 1024;
+
+/// header of an encrypted store, followed by its kdf parameters and nonce
+const ENC_MAGIC: &str = "credenc-1";
+
+/// key derivation function named in the header of an encrypted store
+const ENC_KDF: &str = "pbkdf2-sha256";
+
+/// pbkdf2 iterations used to derive the master key of a new encrypted store
+pub const ENC_ITERATIONS: u32 = 1 << 14;
+
+/// most pbkdf2 iterations accepted from the header of an encrypted store, which is read
+/// before anything is authenticated
+pub const MAX_ENC_ITERATIONS: u32 = 1 << 20;
+
+/// block size of sha256, used to pad hmac keys, in bytes
+const SHA256_BLOCK_LEN: usize = 64;
+
+/// "expand 32-byte k", the first four words of every chacha20 state
+const CHACHA_CONSTANTS: [u32; 4] = [0x61707865, 0x3320646e, 0x79622d32, 0x6b206574];
+
+/// length of a chacha20 key, in bytes
+pub const CHACHA_KEY_LEN: usize = 32;
+
+/// length of a chacha20 nonce, in bytes
+pub const CHACHA_NONCE_LEN: usize = 12;
+
+/// length of a poly1305 tag, in bytes
+pub const POLY1305_TAG_LEN: usize = 16;
 
 /// initial h values for sha256 - first 32bits of fractional portion of square roots of first 8 primes
 const SHA_H_INITIAL: [u32; 8] = [
@@ -45,208 +172,2528 @@ const SHA_K_INITIAL: [u32; 64] = [
     0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
 ];
 
+/// initial h values for sha1 (FIPS 180-4)
+const SHA1_H_INITIAL: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+
+/// round constants for sha1, one for each group of 20 rounds
+const SHA1_K: [u32; 4] = [0x5a827999, 0x6ed9eba1, 0x8f1bbcdc, 0xca62c1d6];
+
 // ==================== STRUCTURES ====================
 
-/// data structure that holds users and their credentials
+/// the comma separated GECOS (user information) field of a passwd record
+/// # Fields
+/// * `full_name` - user's full name
+/// * `room` - room or office number
+/// * `work_phone` - work phone number
+/// * `home_phone` - home phone number
+/// * `other` - any remaining free-form information
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Gecos {
+    pub full_name: String,
+    pub room: String,
+    pub work_phone: String,
+    pub home_phone: String,
+    pub other: String,
+}
+
+/// Methods for the struct
+impl Gecos {
+    /// parse a GECOS field, missing subfields are left empty
+    /// # Arguments
+    /// * `field` - raw GECOS field as stored in the passwd file
+    pub fn parse(field: &str) -> Self {
+        let mut parts = field.splitn(5, ',').map(|p| p.to_string());
+        Gecos {
+            full_name: parts.next().unwrap_or_default(),
+            room: parts.next().unwrap_or_default(),
+            work_phone: parts.next().unwrap_or_default(),
+            home_phone: parts.next().unwrap_or_default(),
+            other: parts.next().unwrap_or_default(),
+        }
+    }
+
+    /// checks that a value can be stored in a GECOS subfield without corrupting the record
+    /// # Arguments
+    /// * `value` - candidate subfield value
+    /// # Return
+    /// * whether or not the value is free of separators and control characters
+    pub fn is_valid_field(value: &str) -> bool {
+        !value
+            .chars()
+            .any(|c| c == ':' || c == ',' || c.is_control())
+    }
+}
+
+/// GECOS fields are written back comma separated, with trailing empty subfields dropped
+impl fmt::Display for Gecos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let fields = [
+            &self.full_name,
+            &self.room,
+            &self.work_phone,
+            &self.home_phone,
+            &self.other,
+        ];
+        let used = fields
+            .iter()
+            .rposition(|s| !s.is_empty())
+            .map_or(0, |i| i + 1);
+        let joined: Vec<&str> = fields[..used].iter().map(|s| s.as_str()).collect();
+        write!(f, "{}", joined.join(","))
+    }
+}
+
+/// a single account in the passwd file: `name:hash:uid:gid:gecos:home:shell`
+/// # Fields
+/// * `name` - account name
+/// * `hash` - hashed password string
+/// * `uid` - numeric user id
+/// * `gid` - numeric id of the primary group
+/// * `gecos` - user information
+/// * `home` - home directory
+/// * `shell` - login shell
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserRecord {
+    name: String,
+    hash: String,
+    uid: u32,
+    gid: u32,
+    gecos: Gecos,
+    home: String,
+    shell: String,
+}
+
+/// Methods for the struct
+impl UserRecord {
+    /// create a record with default gecos, home and shell
+    /// # Arguments
+    /// * `name` - account name
+    /// * `hash` - hashed password
+    /// * `uid` - numeric user id, also used as the primary gid
+    pub fn new(name: &str, hash: &str, uid: u32) -> Self {
+        UserRecord {
+            name: name.to_string(),
+            hash: hash.to_string(),
+            uid,
+            gid: uid,
+            gecos: Gecos::default(),
+            home: Self::default_home(name, uid),
+            shell: DEF_SHELL.to_string(),
+        }
+    }
+
+    /// parse a line of the passwd file
+    /// # Arguments
+    /// * `line` - either a full seven field record or a legacy `name:hash` pair
+    /// * `legacy_uid` - uid to assign if the line is a legacy pair
+    /// # Return
+    /// * the record, or `None` if the line is malformed
+    pub fn parse(line: &str, legacy_uid: u32) -> Option<Self> {
+        let fields: Vec<&str> = line.split(':').collect();
+        match fields.len() {
+            2 => {
+                let (name, hash) = (fields[0], fields[1]);
+                if name.is_empty() {
+                    return None;
+                }
+                Some(UserRecord::new(name, hash, legacy_uid))
+            }
+            7 => {
+                if fields[0].is_empty() {
+                    return None;
+                }
+                Some(UserRecord {
+                    name: fields[0].to_string(),
+                    hash: fields[1].to_string(),
+                    uid: fields[2].parse().ok()?,
+                    gid: fields[3].parse().ok()?,
+                    gecos: Gecos::parse(fields[4]),
+                    home: fields[5].to_string(),
+                    shell: fields[6].to_string(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// the record in passwd line format with the hash inline instead of `SHADOW_PLACEHOLDER`
+    pub fn record_line(&self) -> String {
+        format!(
+            "{}:{}:{}:{}:{}:{}:{}",
+            self.name, self.hash, self.uid, self.gid, self.gecos, self.home, self.shell
+        )
+    }
+
+    /// the record's line in the shadow file, `name:hash` followed by empty aging fields
+    pub fn shadow_line(&self) -> String {
+        format!("{}:{}:::::::", self.name, self.hash)
+    }
+
+    /// home directory given to a new account
+    fn default_home(name: &str, uid: u32) -> String {
+        if uid == ROOT_UID {
+            ROOT_HOME.to_string()
+        } else {
+            format!("{}/{}", HOME_BASE, name)
+        }
+    }
+
+    /// account name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// hashed password string
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    /// numeric user id
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    /// numeric id of the primary group
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    /// user information field
+    pub fn gecos(&self) -> &Gecos {
+        &self.gecos
+    }
+
+    /// home directory
+    pub fn home(&self) -> &str {
+        &self.home
+    }
+
+    /// login shell
+    pub fn shell(&self) -> &str {
+        &self.shell
+    }
+
+    /// change the account name, home directory is left untouched like `usermod -l`
+    pub fn set_name(&mut self, name: &str) -> &mut Self {
+        self.name = name.to_string();
+        self
+    }
+
+    /// replace the hashed password
+    pub fn set_hash(&mut self, hash: &str) -> &mut Self {
+        self.hash = hash.to_string();
+        self
+    }
+
+    /// replace the user information field
+    pub fn set_gecos(&mut self, gecos: Gecos) -> &mut Self {
+        self.gecos = gecos;
+        self
+    }
+}
+
+/// records are written back in passwd line format, the hash is replaced by `SHADOW_PLACEHOLDER`
+impl fmt::Display for UserRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:{}:{}:{}:{}",
+            self.name, SHADOW_PLACEHOLDER, self.uid, self.gid, self.gecos, self.home, self.shell
+        )
+    }
+}
+
+/// problems with a stored hash string
+/// # Variants
+/// * `Format` - not of the form `$version$cost$salt$hash$`, plus `cost$salt$` of the inner hash for an onion hash
+/// * `UnknownVersion` - names a hashing scheme this build does not know
+/// * `Cost` - the cost is not a number from 0 to the highest cost of its layer
+/// * `Salt` - the salt is not valid base64
+/// * `Digest` - the digest is not a base64 encoded sha-256
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HashError {
+    Format,
+    UnknownVersion(String),
+    Cost(String, usize),
+    Salt,
+    Digest,
+}
+
+impl fmt::Display for HashError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HashError::Format => write!(f, "expected $version$cost$salt$hash$"),
+            HashError::UnknownVersion(version) => {
+                write!(f, "unknown hash version '{}'", version)
+            }
+            HashError::Cost(cost, max) => {
+                write!(f, "cost '{}' out of range 0-{}", cost, max)
+            }
+            HashError::Salt => write!(f, "salt is not valid base64"),
+            HashError::Digest => write!(f, "digest is not {} base64 encoded bytes", SHA256_LEN),
+        }
+    }
+}
+
+impl std::error::Error for HashError {}
+
+/// errors returned when changing the credential database
+/// # Variants
+/// * `NotFound` - the named account does not exist
+/// * `AlreadyExists` - the named account already exists
+/// * `Io` - the database files could not be locked or written
+/// * `Corrupt` - the database could not be decoded, or the master secret is wrong
+/// * `Unsupported` - the backend does not support the operation
+/// * `InvalidName` - a new account name breaks the username policy
+/// * `NoFreeUid` - every uid above the highest one in use is taken
+/// * `Protected` - the named account can't be renamed, since the shell would recreate it
+#[derive(Debug)]
+pub enum StoreError {
+    NotFound(String),
+    AlreadyExists(String),
+    Io(std::io::Error),
+    Corrupt(String),
+    Unsupported(String),
+    InvalidName(String, UsernameError),
+    NoFreeUid,
+    Protected(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StoreError::NotFound(username) => write!(f, "account {} not found", username),
+            StoreError::AlreadyExists(username) => {
+                write!(f, "account {} already exists", username)
+            }
+            StoreError::Io(e) => write!(f, "{}", e),
+            StoreError::Corrupt(reason) => write!(f, "{}", reason),
+            StoreError::Unsupported(operation) => {
+                write!(f, "{} is not supported by this backend", operation)
+            }
+            StoreError::InvalidName(username, reason) => {
+                write!(f, "username {:?} {}", username, reason)
+            }
+            StoreError::NoFreeUid => {
+                write!(
+                    f,
+                    "an account already has uid {}, the highest there is",
+                    u32::MAX
+                )
+            }
+            StoreError::Protected(username) => write!(f, "account {} can't be renamed", username),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<std::io::Error> for StoreError {
+    fn from(e: std::io::Error) -> Self {
+        StoreError::Io(e)
+    }
+}
+
+/// a single change staged in a `Transaction`
+/// # Variants
+/// * `Set` - create a user or change an existing users password
+/// * `SetRecord` - create or replace a full account record
+/// * `Remove` - delete a user, which must exist
+/// * `Rename` - change an account name, the new name must be free and the old one not root
+pub enum TransactionOp {
+    Set(String, String),
+    SetRecord(UserRecord),
+    Remove(String),
+    Rename(String, String),
+}
+
+/// Methods for the enum
+impl TransactionOp {
+    /// apply the change to a set of account records
+    /// # Arguments
+    /// * `records` - account records keyed by name
+    /// * `policy` - rules every new account name must follow
+    /// # Return
+    /// * why the change could not be made, `records` may be partially changed in that case
+    pub fn apply(
+        self,
+        records: &mut HashMap<String, UserRecord>,
+        policy: &UsernamePolicy,
+    ) -> Result<(), StoreError> {
+        match self {
+            TransactionOp::Set(username, hashword) => match records.get_mut(&username) {
+                Some(record) => {
+                    record.set_hash(&hashword);
+                }
+                None => {
+                    let username = Self::admit(records, policy, &username)?;
+                    let uid = free_uid(records)?;
+                    records.insert(username.clone(), UserRecord::new(&username, &hashword, uid));
+                }
+            },
+            TransactionOp::SetRecord(mut record) => {
+                if !records.contains_key(record.name()) {
+                    let username = Self::admit(records, policy, record.name())?;
+                    record.set_name(&username);
+                }
+                records.insert(record.name().into(), record);
+            }
+            TransactionOp::Remove(username) => {
+                if records.remove(&username).is_none() {
+                    return Err(StoreError::NotFound(username));
+                }
+            }
+            TransactionOp::Rename(old_name, new_name) => {
+                if old_name == ROOT {
+                    return Err(StoreError::Protected(old_name));
+                }
+                if records.contains_key(&new_name) {
+                    return Err(StoreError::AlreadyExists(new_name));
+                }
+                let mut record = match records.remove(&old_name) {
+                    Some(record) => record,
+                    None => return Err(StoreError::NotFound(old_name)),
+                };
+                let new_name = Self::admit(records, policy, &new_name)?;
+                record.set_name(&new_name);
+                records.insert(new_name, record);
+            }
+        }
+        Ok(())
+    }
+
+    /// internal function to normalize and check a name that is not in use yet
+    /// # Return
+    /// * the normalized name, which may turn out to be in use already
+    fn admit(
+        records: &HashMap<String, UserRecord>,
+        policy: &UsernamePolicy,
+        username: &str,
+    ) -> Result<String, StoreError> {
+        let normalized = policy
+            .check(username, records.keys())
+            .map_err(|e| StoreError::InvalidName(username.to_string(), e))?;
+        if records.contains_key(&normalized) {
+            return Err(StoreError::AlreadyExists(normalized));
+        }
+        Ok(normalized)
+    }
+}
+
+/// a batch of changes to the credential database that is written to disk all at once
+/// nothing changes until `commit`, and if any change fails none of them are kept
 /// # Fields
-/// * `cred_hashmap` - hashmap that holds credentials
-/// * `storage_location` - filepath to where credentials are stored on disk
+/// * `database` - database the changes are made to
+/// * `ops` - changes staged so far, in order
 /// # Methods
-/// * `new` - creates data structure
-/// * `list_users` - returns a list of all registered users
-/// * `contains` - checks for the existence of a user
-/// * `set` - creates a user or changes an existing users password
-/// * `get` - retrieves a users hashed password if they exist
-/// * `remove` - deletes a user from the system
-pub struct UserCredentials {
-    cred_hashmap: HashMap<String, String>,
-    storage_location: String,
+/// * `set` - stages creating a user or changing an existing users password
+/// * `set_record` - stages creating or replacing a full account record
+/// * `remove` - stages deleting a user
+/// * `rename` - stages changing a users account name
+/// * `commit` - applies every staged change in one write
+/// * `rollback` - discards every staged change
+pub struct Transaction<'a, S: CredentialStore + ?Sized> {
+    database: &'a mut S,
+    ops: Vec<TransactionOp>,
 }
 
 /// Methods for the struct
-impl UserCredentials {
-    /// create a new credential struct
+impl<S: CredentialStore + ?Sized> Transaction<'_, S> {
+    /// stage registering a user or changing an existing users password
     /// # Arguments
-    /// * `filepath` - filepath of where credentials are stored on disk
-    pub fn new(filepath: &str) -> Self {
-        UserCredentials {
-            cred_hashmap: Self::read_disk(filepath),
-            storage_location: filepath.to_string().clone(),
+    /// * `username` - account name
+    /// * `hashword` - hashed password
+    pub fn set(mut self, username: &str, hashword: &str) -> Self {
+        self.ops
+            .push(TransactionOp::Set(username.into(), hashword.into()));
+        self
+    }
+
+    /// stage creating or replacing a full account record
+    /// # Arguments
+    /// * `record` - account record, keyed by its name
+    pub fn set_record(mut self, record: UserRecord) -> Self {
+        self.ops.push(TransactionOp::SetRecord(record));
+        self
+    }
+
+    /// stage deleting a users record, failing the commit if it does not exist
+    /// # Arguments
+    /// * `username` - account name
+    pub fn remove(mut self, username: &str) -> Self {
+        self.ops.push(TransactionOp::Remove(username.into()));
+        self
+    }
+
+    /// stage renaming an account, keeping the rest of its record
+    /// # Arguments
+    /// * `old_name` - current account name, must exist
+    /// * `new_name` - new account name, must not exist
+    pub fn rename(mut self, old_name: &str, new_name: &str) -> Self {
+        self.ops
+            .push(TransactionOp::Rename(old_name.into(), new_name.into()));
+        self
+    }
+
+    /// apply every staged change and write the result to the store in one atomic write
+    /// # Return
+    /// * the first error encountered, in which case the database is left unchanged
+    pub fn commit(self) -> Result<(), StoreError> {
+        self.database.commit(self.ops)
+    }
+
+    /// discard every staged change
+    pub fn rollback(self) {}
+}
+
+/// guard for a credential store lock, the lock is released when it is dropped
+/// # Fields
+/// * `file` - locked file, `None` for stores that need no locking
+pub struct StoreLock {
+    _file: Option<File>,
+}
+
+/// storage backend for account records
+/// # Methods
+/// * `backend` - name of the backend, e.g. for messages
+/// * `list_users` - returns a list of all registered users
+/// * `get` - retrieves a users record if they exist
+/// * `lock` - takes the store's lock for reading or writing
+/// * `refresh` - reloads the store if another process changed it
+/// * `commit` - applies a batch of changes atomically
+/// * `username_policy`, `set_username_policy` - rules new account names must follow
+/// * `contains` - checks for the existence of a user
+/// * `next_uid` - allocates a free uid for a new account
+/// * `authenticate` - checks a password against a users stored hash
+/// * `rotate_master_key` - re-encrypts the store under a new master secret
+/// * `tampered` - reports a failed integrity check
+/// * `load_error` - reports stored data that exists but could not be loaded
+/// * `reseal` - accepts outside changes to the store
+pub trait CredentialStore {
+    /// name of the backend
+    fn backend(&self) -> &'static str;
+
+    /// method that lists all registered users
+    /// # Return
+    /// * Vec<string> - list of all users
+    fn list_users(&self) -> Vec<String>;
+
+    /// method that retrieves a users account record
+    /// # Arguments
+    /// * `username` - account name
+    /// # Return
+    /// * account record if it exists
+    fn get(&self, username: &str) -> Option<UserRecord>;
+
+    /// method that takes the store's lock, blocking other processes for a while at most
+    /// # Arguments
+    /// * `exclusive` - whether to lock for writing rather than reading
+    /// # Return
+    /// * guard holding the lock
+    fn lock(&self, exclusive: bool) -> std::io::Result<StoreLock>;
+
+    /// method that reloads the store if another process changed it since it was last read
+    /// # Return
+    /// * whether or not the store was reloaded
+    fn refresh(&mut self) -> bool;
+
+    /// method that applies staged changes on top of the latest stored records
+    /// # Arguments
+    /// * `ops` - changes to apply, in order
+    /// # Return
+    /// * the first error encountered, in which case nothing is changed
+    fn commit(&mut self, ops: Vec<TransactionOp>) -> Result<(), StoreError>;
+
+    /// method that gets the rules new account names must follow
+    fn username_policy(&self) -> &UsernamePolicy;
+
+    /// method that replaces the rules new account names must follow
+    /// # Arguments
+    /// * `policy` - new rules, existing accounts are not checked against them
+    fn set_username_policy(&mut self, policy: UsernamePolicy);
+
+    /// method that checks if a user is registered
+    /// # Arguments
+    /// * `username` - account name
+    /// # Return
+    /// * whether or not user exists
+    fn contains(&self, username: &str) -> bool {
+        self.get(username).is_some()
+    }
+
+    /// method that allocates a uid for a new account
+    /// # Return
+    /// * lowest uid above every uid in use, starting at `FIRST_UID`, or `NoFreeUid` once an
+    ///   account holds the highest uid
+    fn next_uid(&self) -> Result<u32, StoreError> {
+        self.list_users()
+            .iter()
+            .filter_map(|u| self.get(u))
+            .map(|u| u.uid.checked_add(1).ok_or(StoreError::NoFreeUid))
+            .try_fold(FIRST_UID, |next, uid| Ok(next.max(uid?)))
+    }
+
+    /// method that authenticates a user
+    /// # Arguments
+    /// * `username` - String of users account name
+    /// * `password` - String of user's password (raw)
+    /// # Return
+    /// * whether or not user is authenticated
+    fn authenticate(&self, username: &str, password: &str) -> bool {
+        match self.get(username) {
+            Some(record) => verify_password(record.hash(), password),
+            None => false,
         }
     }
 
-    /// internal method to read stored credentials from disk
+    /// method that reports whether the stored data failed its integrity check on load
+    /// # Return
+    /// * whether or not the store was modified outside of the program
+    fn tampered(&self) -> bool {
+        false
+    }
+
+    /// method that reports why stored data that exists could not be loaded, so nothing is
+    /// written over it, least of all a new root account
+    /// # Return
+    /// * the reason, `None` if the store loaded or does not exist yet
+    fn load_error(&self) -> Option<String> {
+        None
+    }
+
+    /// method that accepts the stored data as it is now after an intentional manual edit
+    /// # Return
+    /// * `Unsupported` unless the backend seals its data
+    fn reseal(&mut self) -> Result<(), StoreError> {
+        Err(StoreError::Unsupported("resealing".to_string()))
+    }
+
+    /// method that re-encrypts the store under a new master secret
     /// # Arguments
-    /// * `filepath` - path to file
+    /// * `current` - master secret the store is encrypted with
+    /// * `new` - master secret to encrypt with from now on
+    /// # Return
+    /// * `Unsupported` unless the backend encrypts its data
+    #[allow(unused_variables)]
+    fn rotate_master_key(&mut self, current: &[u8], new: &[u8]) -> Result<(), StoreError> {
+        Err(StoreError::Unsupported("master key rotation".to_string()))
+    }
+
+    /// method that checks the stored data for inconsistencies, like `pwck`
     /// # Return
-    /// * hashmap - populated with user credentials, empty if file unable to be read
-    fn read_disk(filepath: &str) -> HashMap<String, String> {
-        let mut ret_val: HashMap<String, String> = HashMap::new();
+    /// * every problem found, empty if the store is consistent
+    fn check(&mut self) -> Result<Vec<Diagnostic>, StoreError> {
+        self.refresh();
+        Ok(check_records(self))
+    }
+
+    /// method that quarantines bad records instead of letting them be dropped, and locks
+    /// accounts whose hash can't be verified
+    /// # Return
+    /// * what was repaired, `Unsupported` unless the backend has somewhere to quarantine to
+    fn repair(&mut self) -> Result<RepairReport, StoreError> {
+        Err(StoreError::Unsupported("repair".to_string()))
+    }
+}
+
+/// convenience methods available on every credential store
+/// # Methods
+/// * `set` - creates a user or changes an existing users password
+/// * `set_record` - creates or replaces a full account record
+/// * `remove` - deletes a user from the system
+/// * `transaction` - starts a batch of changes committed together
+pub trait CredentialStoreExt: CredentialStore {
+    /// method that registers a user or changes and existing users password
+    /// new accounts are given the next free uid
+    /// # Arguments
+    /// * `username` - account name
+    /// * `hashword` - hashed password
+    fn set(&mut self, username: &str, hashword: &str) -> &mut Self {
+        if let Err(e) = self.transaction().set(username, hashword).commit() {
+            eprintln!(
+                "\x1b[91mFailed to save account '{}'. Error: {}\x1b[0m",
+                username, e
+            );
+        }
+        self
+    }
+
+    /// method that creates or replaces a full account record
+    /// # Arguments
+    /// * `record` - account record, keyed by its name
+    fn set_record(&mut self, record: UserRecord) -> &mut Self {
+        let username = record.name().to_string();
+        if let Err(e) = self.transaction().set_record(record).commit() {
+            eprintln!(
+                "\x1b[91mFailed to save account '{}'. Error: {}\x1b[0m",
+                username, e
+            );
+        }
+        self
+    }
+
+    /// method that deletes a users record
+    /// # Arguments
+    /// * `username` - account name
+    fn remove(&mut self, username: &str) -> &mut Self {
+        if let Err(e) = self.transaction().remove(username).commit() {
+            eprintln!(
+                "\x1b[91mFailed to remove account '{}'. Error: {}\x1b[0m",
+                username, e
+            );
+        }
+        self
+    }
+
+    /// method that starts a batch of changes which are stored together on commit
+    /// # Return
+    /// * an empty transaction on this store
+    fn transaction(&mut self) -> Transaction<'_, Self> {
+        Transaction {
+            database: self,
+            ops: Vec::new(),
+        }
+    }
+}
+
+impl<S: CredentialStore + ?Sized> CredentialStoreExt for S {}
+
+/// credential store kept in a passwd and shadow file pair
+/// # Fields
+/// * `cred_hashmap` - hashmap that holds account records
+/// * `passwd_location` - filepath to where public account info is stored on disk
+/// * `shadow_location` - filepath to where password hashes are stored on disk
+/// * `fingerprint` - digests of the files as last read or written, to notice outside changes
+/// * `tampered` - whether the files did not match their seal when last read
+/// * `sealed_root` - root's hash as of the last seal, the only login allowed while tampered
+/// * `unreadable` - why files that exist could not be loaded when last read
+/// * `dropped` - lines that could not be loaded, quarantined before a write drops them for good
+/// * `policy` - rules new account names must follow
+/// # Methods
+/// * `new` - creates data structure
+pub struct UserCredentials {
+    cred_hashmap: HashMap<String, UserRecord>,
+    passwd_location: String,
+    shadow_location: String,
+    fingerprint: Vec<Option<Vec<u8>>>,
+    tampered: bool,
+    sealed_root: Option<String>,
+    unreadable: Option<String>,
+    dropped: Vec<Diagnostic>,
+    policy: UsernamePolicy,
+}
+
+/// Methods for the struct
+impl UserCredentials {
+    /// create a new credential struct
+    /// hashes still stored in the passwd file are moved to the shadow file
+    /// # Arguments
+    /// * `passwd_path` - filepath of where public account info is stored on disk
+    /// * `shadow_path` - filepath of where password hashes are stored on disk
+    pub fn new(passwd_path: &str, shadow_path: &str) -> Self {
+        let lock = Self::lock_disk(passwd_path, true);
+        if let Err(e) = &lock {
+            eprintln!(
+                "\x1b[91mFailed to lock '{}', loading anyway. Error: {}\x1b[0m",
+                passwd_path, e
+            );
+        }
+        let (cred_hashmap, migrated, diagnostics) = Self::read_disk(passwd_path, shadow_path);
+        let mut database = UserCredentials {
+            cred_hashmap,
+            passwd_location: passwd_path.to_string(),
+            shadow_location: shadow_path.to_string(),
+            fingerprint: Self::fingerprint(passwd_path, shadow_path),
+            tampered: false,
+            sealed_root: None,
+            unreadable: None,
+            dropped: Vec::new(),
+            policy: UsernamePolicy::default(),
+        };
+        database.unreadable = Self::load_error(passwd_path, shadow_path, &database.cred_hashmap);
+        database.load_diagnostics(diagnostics);
+        database.check_seal(lock.is_ok());
+        database.fingerprint = Self::fingerprint(passwd_path, shadow_path);
+        if migrated > 0 && lock.is_ok() && !database.tampered && database.unreadable.is_none() {
+            match database.write_disk(&database.cred_hashmap) {
+                Ok(()) => println!(
+                    "moved {} password hash(es) from '{}' to '{}'",
+                    migrated, passwd_path, shadow_path
+                ),
+                Err(e) => eprintln!(
+                    "\x1b[91mFailed to move password hashes to '{}'. Error: {}\x1b[0m",
+                    shadow_path, e
+                ),
+            }
+            database.dropped.clear();
+            database.fingerprint = Self::fingerprint(passwd_path, shadow_path);
+        }
+        database
+    }
+
+    /// internal method to take the advisory lock guarding the database files, like `lckpwdf`
+    /// # Arguments
+    /// * `passwd_path` - path to passwd file, the lock file lives in the same directory
+    /// * `exclusive` - whether to lock for writing rather than reading
+    /// # Return
+    /// * the open lock file, the lock is released when it is dropped
+    fn lock_disk(passwd_path: &str, exclusive: bool) -> std::io::Result<File> {
+        lock_file(&Self::sibling(passwd_path, LOCK_FILE), exclusive)
+    }
+
+    /// internal method to locate a file in the same directory as the passwd file
+    fn sibling(passwd_path: &str, name: &str) -> std::path::PathBuf {
+        match Path::new(passwd_path).parent() {
+            Some(dir) => dir.join(name),
+            None => Path::new(name).to_path_buf(),
+        }
+    }
+
+    /// internal method to digest the database files as they currently are on disk
+    /// # Return
+    /// * sha256 of the passwd, shadow and seal files, `None` for a missing file
+    fn fingerprint(passwd_path: &str, shadow_path: &str) -> Vec<Option<Vec<u8>>> {
+        let seal_path = format!("{}{}", passwd_path, SEAL_SUFFIX);
+        [passwd_path, shadow_path, &seal_path]
+            .iter()
+            .map(|path| read(path).ok().map(sha256))
+            .collect()
+    }
+
+    /// internal method to compute the seal of the passwd and shadow files
+    /// # Arguments
+    /// * `key` - seal key
+    /// * `passwd` - contents of the passwd file
+    /// * `shadow` - contents of the shadow file
+    /// # Return
+    /// * hex encoded MAC over both files
+    fn compute_seal(key: &[u8], passwd: &[u8], shadow: &[u8]) -> String {
+        // the length prefix keeps bytes from moving between the files unnoticed
+        let mut message = (passwd.len() as u64).to_be_bytes().to_vec();
+        message.extend_from_slice(passwd);
+        message.extend_from_slice(shadow);
+        hex::encode(hmac_sha256(key, &message))
+    }
+
+    /// internal method to compute the line of the seal vouching for root's hash
+    /// # Arguments
+    /// * `key` - seal key
+    /// * `hash` - root's password hash
+    /// # Return
+    /// * root's name, hash and hex encoded MAC over both
+    fn seal_root_line(key: &[u8], hash: &str) -> String {
+        let mac = hmac_sha256(key, format!("{}:{}", ROOT, hash).as_bytes());
+        format!("{} {} {}", ROOT, hash, hex::encode(mac))
+    }
+
+    /// internal method to verify the files on disk against their seal, flagging a mismatch
+    /// a missing database has nothing to verify, and a database from before sealing existed
+    /// is sealed the first time it is loaded, but a seal or key going missing any other way
+    /// counts as tampering
+    /// # Arguments
+    /// * `first_load` - whether this is the first load and the caller holds the write lock
+    fn check_seal(&mut self, first_load: bool) {
+        let seal_path = format!("{}{}", self.passwd_location, SEAL_SUFFIX);
+        let key_path = Self::sibling(&self.passwd_location, SEAL_KEY_FILE);
+        self.sealed_root = None;
+        let passwd = match read(&self.passwd_location) {
+            Ok(passwd) => passwd,
+            Err(_) => {
+                self.tampered = false;
+                return;
+            }
+        };
+        let shadow = read(&self.shadow_location).unwrap_or_default();
+        let key = read(&key_path);
+        let seal = std::fs::read_to_string(&seal_path);
+        let root = self.cred_hashmap.get(ROOT).map(|r| r.hash().to_string());
+
+        // neither a seal nor a key means the database predates sealing, not that it was edited
+        let missing = |e: &std::io::Error| e.kind() == std::io::ErrorKind::NotFound;
+        if let (Err(k), Err(s)) = (&key, &seal)
+            && missing(k)
+            && missing(s)
+            && first_load
+            && self.unreadable.is_none()
+        {
+            match self.write_seal(&passwd, &shadow, root.as_deref()) {
+                Ok(()) => println!("sealed '{}' for the first time", self.passwd_location),
+                Err(e) => eprintln!(
+                    "\x1b[91mFailed to seal '{}'. Error: {}\x1b[0m",
+                    self.passwd_location, e
+                ),
+            }
+            self.tampered = false;
+            return;
+        }
+
+        let reason = match (&key, &seal) {
+            (Err(_), _) => Some("the seal key is missing"),
+            (_, Err(_)) => Some("the seal is missing"),
+            (Ok(key), Ok(seal)) => {
+                let mut lines = seal.lines();
+                let expected = format!(
+                    "{} {}",
+                    SEAL_ALGO,
+                    Self::compute_seal(key, &passwd, &shadow)
+                );
+                let intact = constant_time_eq(
+                    lines.next().unwrap_or_default().trim_end().as_bytes(),
+                    expected.as_bytes(),
+                );
+
+                // root's hash is vouched for on its own, so it still holds when the files don't
+                self.sealed_root = lines.map(str::trim_end).find_map(|line| {
+                    let (hash, _) = line.strip_prefix(&format!("{} ", ROOT))?.rsplit_once(' ')?;
+                    let expected = Self::seal_root_line(key, hash);
+                    constant_time_eq(line.as_bytes(), expected.as_bytes()).then(|| hash.to_string())
+                });
+
+                // seals from before root's hash was part of them gain it while they still hold
+                if intact && self.sealed_root.is_none() && root.is_some() && first_load {
+                    if let Err(e) = self.write_seal(&passwd, &shadow, root.as_deref()) {
+                        eprintln!(
+                            "\x1b[91mFailed to reseal '{}'. Error: {}\x1b[0m",
+                            self.passwd_location, e
+                        );
+                    }
+                    self.sealed_root = root;
+                }
+                match intact {
+                    true => None,
+                    false => Some("the files do not match their seal"),
+                }
+            }
+        };
+        self.tampered = reason.is_some();
+        if let Some(reason) = reason {
+            eprintln!(
+                "\x1b[91m'{}' may have been tampered with: {}. Only {} can log in, with the password it had when last sealed, until it is resealed.\x1b[0m",
+                self.passwd_location, reason, ROOT
+            );
+        }
+    }
+
+    /// internal method to read the seal key, generating one if there is none yet
+    fn seal_key(&self) -> std::io::Result<Vec<u8>> {
+        read_seal_key(&Self::sibling(&self.passwd_location, SEAL_KEY_FILE))
+    }
+
+    /// internal method to reload the database if the files changed since they were last seen
+    /// the caller must hold the database lock
+    /// # Return
+    /// * whether or not the database was reloaded
+    fn sync_disk(&mut self) -> bool {
+        let current = Self::fingerprint(&self.passwd_location, &self.shadow_location);
+        if current == self.fingerprint {
+            return false;
+        }
+        println!(
+            "'{}' was changed by another process, reloading",
+            self.passwd_location
+        );
+        let (cred_hashmap, _, diagnostics) =
+            Self::read_disk(&self.passwd_location, &self.shadow_location);
+        self.unreadable =
+            Self::load_error(&self.passwd_location, &self.shadow_location, &cred_hashmap);
+        self.cred_hashmap = cred_hashmap;
+        self.fingerprint = current;
+        self.load_diagnostics(diagnostics);
+        self.check_seal(false);
+        true
+    }
+
+    /// internal method to find files that exist but could not be loaded
+    /// # Arguments
+    /// * `passwd_path` - path to passwd file
+    /// * `shadow_path` - path to shadow file
+    /// * `records` - records that were loaded from them
+    /// # Return
+    /// * why the files could not be loaded, `None` if they loaded or do not exist
+    fn load_error(
+        passwd_path: &str,
+        shadow_path: &str,
+        records: &HashMap<String, UserRecord>,
+    ) -> Option<String> {
+        for path in [passwd_path, shadow_path] {
+            match File::open(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Some(format!("'{}' could not be read: {}", path, e));
+                }
+                _ => {}
+            }
+        }
+        let data = std::fs::read_to_string(passwd_path).unwrap_or_default();
+        let root_line = format!("{}:", ROOT);
+        if data.lines().any(|line| line.starts_with(&root_line)) && !records.contains_key(ROOT) {
+            return Some(format!(
+                "the {} record in '{}' could not be loaded",
+                ROOT, passwd_path
+            ));
+        }
+        match data.lines().any(|line| !line.trim().is_empty()) && records.is_empty() {
+            true => Some(format!("no account in '{}' could be loaded", passwd_path)),
+            false => None,
+        }
+    }
+
+    /// internal method to print the problems found while loading, remembering the lines that
+    /// could not be loaded so the next write quarantines them
+    fn load_diagnostics(&mut self, diagnostics: Vec<Diagnostic>) {
+        for diagnostic in &diagnostics {
+            eprintln!("\x1b[91m{}\x1b[0m", diagnostic);
+        }
+        self.dropped = diagnostics
+            .into_iter()
+            .filter(|d| d.record.is_some())
+            .collect();
+        if !self.dropped.is_empty() {
+            eprintln!(
+                "\x1b[91m{} record(s) could not be loaded, run dbcheck to review them\x1b[0m",
+                self.dropped.len()
+            );
+        }
+    }
+
+    /// internal method to get the path of the quarantine file
+    fn quarantine_path(&self) -> String {
+        format!("{}{}", self.passwd_location, QUARANTINE_SUFFIX)
+    }
+
+    /// internal method to read the lines of a file
+    /// # Arguments
+    /// * `filepath` - path to file
+    /// # Return
+    /// * numbered lines, or `None` if the file could not be opened
+    fn read_lines(filepath: &str) -> Option<Vec<(i32, String)>> {
+        let data: File = File::open(filepath).ok()?;
+        let reader = BufReader::new(data);
+        let mut ret_val: Vec<(i32, String)> = Vec::new();
+        let mut counter: i32 = 0;
+        for record in reader.lines() {
+            counter += 1;
+            match record {
+                Ok(r) => ret_val.push((counter, r)),
+                Err(e) => {
+                    eprintln!(
+                        "\x1b[91mUnable to process line #{} of '{}'. Error: {}\x1b[0m",
+                        counter, filepath, e
+                    );
+                }
+            };
+        }
+        Some(ret_val)
+    }
+
+    /// internal method to read stored credentials from disk
+    /// # Arguments
+    /// * `passwd_path` - path to passwd file
+    /// * `shadow_path` - path to shadow file
+    /// # Return
+    /// * hashmap - populated with user records, empty if passwd file unable to be read
+    /// * number of records whose hash was found in the passwd file instead of the shadow file
+    /// * problems found in the files, lines they concern are left out of the hashmap
+    fn read_disk(
+        passwd_path: &str,
+        shadow_path: &str,
+    ) -> (HashMap<String, UserRecord>, usize, Vec<Diagnostic>) {
+        let mut ret_val: HashMap<String, UserRecord> = HashMap::new();
+        let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+        // read database file
+        let lines = match Self::read_lines(passwd_path) {
+            Some(lines) => lines,
+            None => {
+                eprintln!("No database found, continuing with no accounts");
+                let backup = format!("{}{}", passwd_path, BACKUP_SUFFIX);
+                if Path::new(&backup).exists() {
+                    eprintln!(
+                        "\x1b[91mA backup of the previous database exists at '{}'\x1b[0m",
+                        backup
+                    );
+                }
+                return (ret_val, 0, diagnostics);
+            }
+        };
+
+        // legacy `name:hash` records get uids after every uid already on file
+        let mut legacy: Vec<(i32, String)> = Vec::new();
+
+        // parse database
+        for (counter, record) in lines {
+            if record.split(':').count() == 2 {
+                legacy.push((counter, record));
+                continue;
+            }
+            let problem = match UserRecord::parse(&record, 0) {
+                Some(user) => Self::insert_parsed(&mut ret_val, user).err(),
+                None => Some(Problem::Malformed),
+            };
+            if let Some(problem) = problem {
+                diagnostics
+                    .push(Diagnostic::new(problem, Some(record)).at(passwd_path, counter as usize));
+            }
+        }
+
+        // upgrade legacy records
+        for (line, record) in legacy {
+            let (username, _) = record.split_once(':').unwrap_or_default();
+            let uid = match username {
+                ROOT if !ret_val.values().any(|u| u.uid == ROOT_UID) => Ok(ROOT_UID),
+                _ => free_uid(&ret_val),
+            };
+            let problem = match uid.map(|uid| UserRecord::parse(&record, uid)) {
+                Ok(Some(user)) => Self::insert_parsed(&mut ret_val, user).err(),
+                Ok(None) => Some(Problem::Malformed),
+                Err(_) => Some(Problem::NoFreeUid(username.to_string())),
+            };
+            if let Some(problem) = problem {
+                diagnostics
+                    .push(Diagnostic::new(problem, Some(record)).at(passwd_path, line as usize));
+            }
+        }
+
+        // parse shadow file
+        let mut shadow: HashMap<String, (i32, String)> = HashMap::new();
+        for (counter, record) in Self::read_lines(shadow_path).unwrap_or_default() {
+            let mut fields = record.split(':');
+            let problem = match (fields.next(), fields.next()) {
+                (Some(username), Some(_)) if shadow.contains_key(username) => {
+                    Problem::Duplicate(username.to_string())
+                }
+                (Some(username), Some(_)) if !username.is_empty() => {
+                    shadow.insert(username.into(), (counter, record));
+                    continue;
+                }
+                _ => Problem::Malformed,
+            };
+            diagnostics
+                .push(Diagnostic::new(problem, Some(record)).at(shadow_path, counter as usize));
+        }
+
+        // join passwd and shadow records
+        let mut migrated: usize = 0;
+        let mut users: Vec<&mut UserRecord> = ret_val.values_mut().collect();
+        users.sort_by_key(|u| u.uid);
+        for user in users {
+            match shadow.remove(&user.name) {
+                Some((_, record)) => {
+                    if user.hash != SHADOW_PLACEHOLDER {
+                        migrated += 1;
+                    }
+                    user.hash = record.split(':').nth(1).unwrap_or_default().to_string();
+                }
+                None if user.hash == SHADOW_PLACEHOLDER => {
+                    diagnostics.push(Diagnostic::new(Problem::NoShadow(user.name.clone()), None));
+                    user.hash = LOCKED_HASH.to_string();
+                }
+                None => migrated += 1,
+            }
+        }
+        let mut orphans: Vec<(String, (i32, String))> = shadow.into_iter().collect();
+        orphans.sort_by_key(|(_, (line, _))| *line);
+        for (username, (line, record)) in orphans {
+            diagnostics.push(
+                Diagnostic::new(Problem::Orphaned(username), Some(record))
+                    .at(shadow_path, line as usize),
+            );
+        }
+        (ret_val, migrated, diagnostics)
+    }
+
+    /// internal method to add a freshly parsed record, rejecting duplicates
+    fn insert_parsed(
+        records: &mut HashMap<String, UserRecord>,
+        user: UserRecord,
+    ) -> Result<(), Problem> {
+        if records.contains_key(user.name()) {
+            return Err(Problem::Duplicate(user.name().to_string()));
+        }
+        records.insert(user.name().to_string(), user);
+        Ok(())
+    }
+
+    /// internal method to update credentials on disk
+    /// # Arguments
+    /// * `records` - account records to store
+    /// # Return
+    /// * io error of the file that failed to write
+    fn write_disk(&self, records: &HashMap<String, UserRecord>) -> std::io::Result<()> {
+        // keep what was left out on load, the files are about to be rewritten without it
+        let quarantined = quarantine(&self.quarantine_path(), &self.dropped)?;
+        if quarantined > 0 {
+            println!(
+                "quarantined {} record(s) in '{}'",
+                quarantined,
+                self.quarantine_path()
+            );
+        }
+
+        let mut passwd_buf: String = "".to_string();
+        let mut shadow_buf: String = "".to_string();
+
+        // generate database buffers, ordered by uid like /etc/passwd
+        let root = records.get(ROOT).map(|r| r.hash());
+        let mut records: Vec<&UserRecord> = records.values().collect();
+        records.sort_by_key(|r| (r.uid, r.name.clone()));
+        for record in records {
+            passwd_buf.push_str(&format!("{}\n", record));
+            shadow_buf.push_str(&format!("{}\n", record.shadow_line()));
+        }
+
+        // write shadow first so every account in passwd has a hash, atomic_write only backs
+        // up a shadow file that exists, any older backup is from an earlier write
+        let had_shadow = Path::new(&self.shadow_location).exists();
+        atomic_write(&self.shadow_location, shadow_buf.as_bytes(), SHADOW_MODE)?;
+
+        // write to disk, undoing the shadow write if passwd can't follow
+        if let Err(e) = atomic_write(&self.passwd_location, passwd_buf.as_bytes(), PASSWD_MODE) {
+            let _ = match had_shadow {
+                true => rename(
+                    format!("{}{}", self.shadow_location, BACKUP_SUFFIX),
+                    &self.shadow_location,
+                ),
+                false => remove_file(&self.shadow_location),
+            };
+            return Err(e);
+        }
+
+        // seal what was just written
+        self.write_seal(passwd_buf.as_bytes(), shadow_buf.as_bytes(), root)
+    }
+
+    /// internal method to seal the contents of the passwd and shadow files
+    /// # Arguments
+    /// * `passwd` - contents of the passwd file
+    /// * `shadow` - contents of the shadow file
+    /// * `root` - root's hash in them, sealed on a line of its own
+    fn write_seal(&self, passwd: &[u8], shadow: &[u8], root: Option<&str>) -> std::io::Result<()> {
+        let key = self.seal_key()?;
+        let mut seal = format!(
+            "{} {}\n",
+            SEAL_ALGO,
+            Self::compute_seal(&key, passwd, shadow)
+        );
+        if let Some(hash) = root {
+            seal.push_str(&format!("{}\n", Self::seal_root_line(&key, hash)));
+        }
+        atomic_write(
+            &format!("{}{}", self.passwd_location, SEAL_SUFFIX),
+            seal.as_bytes(),
+            SHADOW_MODE,
+        )
+    }
+}
+
+/// the passwd and shadow files are locked with `.pwd.lock` and rewritten on every commit
+impl CredentialStore for UserCredentials {
+    fn backend(&self) -> &'static str {
+        "file"
+    }
+
+    fn username_policy(&self) -> &UsernamePolicy {
+        &self.policy
+    }
+
+    fn set_username_policy(&mut self, policy: UsernamePolicy) {
+        self.policy = policy;
+    }
+
+    fn list_users(&self) -> Vec<String> {
+        self.cred_hashmap.keys().cloned().collect()
+    }
+
+    fn get(&self, username: &str) -> Option<UserRecord> {
+        self.cred_hashmap.get(username).cloned()
+    }
+
+    fn contains(&self, username: &str) -> bool {
+        self.cred_hashmap.contains_key(username)
+    }
+
+    fn next_uid(&self) -> Result<u32, StoreError> {
+        free_uid(&self.cred_hashmap)
+    }
+
+    fn lock(&self, exclusive: bool) -> std::io::Result<StoreLock> {
+        Ok(StoreLock {
+            _file: Some(Self::lock_disk(&self.passwd_location, exclusive)?),
+        })
+    }
+
+    fn refresh(&mut self) -> bool {
+        match self.lock(false) {
+            Ok(_lock) => self.sync_disk(),
+            Err(e) => {
+                eprintln!(
+                    "\x1b[91mFailed to lock '{}'. Error: {}\x1b[0m",
+                    self.passwd_location, e
+                );
+                false
+            }
+        }
+    }
+
+    /// the database is locked for the whole read-modify-write, and reloaded first if another
+    /// process changed it, so their changes are merged rather than overwritten
+    fn commit(&mut self, ops: Vec<TransactionOp>) -> Result<(), StoreError> {
+        let _lock = self.lock(true)?;
+        self.sync_disk();
+
+        // writing now would replace whatever could not be read
+        if let Some(reason) = &self.unreadable {
+            return Err(StoreError::Corrupt(reason.clone()));
+        }
+
+        // sealing changes on top of tampered files would hide the tampering
+        if self.tampered {
+            return Err(StoreError::Corrupt(format!(
+                "'{}' does not match its seal, inspect it and run reseal",
+                self.passwd_location
+            )));
+        }
+
+        // apply to a copy so a failure part way through leaves nothing behind
+        let mut staged = self.cred_hashmap.clone();
+        for op in ops {
+            op.apply(&mut staged, &self.policy)?;
+        }
+        self.write_disk(&staged)?;
+        self.cred_hashmap = staged;
+        self.dropped.clear();
+        self.fingerprint = Self::fingerprint(&self.passwd_location, &self.shadow_location);
+        Ok(())
+    }
+
+    /// while the files do not match their seal, only root may authenticate, and only against
+    /// the hash it had when the files were last sealed, since the files themselves can't be
+    /// trusted to hold root's real one
+    fn authenticate(&self, username: &str, password: &str) -> bool {
+        if self.tampered {
+            return match (username, &self.sealed_root) {
+                (ROOT, Some(hash)) => verify_password(hash, password),
+                _ => {
+                    eprintln!(
+                        "\x1b[91mRefusing to authenticate '{}' against an unsealed database\x1b[0m",
+                        username
+                    );
+                    false
+                }
+            };
+        }
+        match self.get(username) {
+            Some(record) => verify_password(record.hash(), password),
+            None => false,
+        }
+    }
+
+    fn tampered(&self) -> bool {
+        self.tampered
+    }
+
+    fn load_error(&self) -> Option<String> {
+        self.unreadable.clone()
+    }
+
+    /// seals the files exactly as they are, so lines that can't be loaded stay for dbcheck
+    fn reseal(&mut self) -> Result<(), StoreError> {
+        let _lock = self.lock(true)?;
+        self.sync_disk();
+        let passwd = read(&self.passwd_location)?;
+        let shadow = read(&self.shadow_location).unwrap_or_default();
+        let root = self.cred_hashmap.get(ROOT).map(|r| r.hash().to_string());
+        self.write_seal(&passwd, &shadow, root.as_deref())?;
+        self.fingerprint = Self::fingerprint(&self.passwd_location, &self.shadow_location);
+        self.tampered = false;
+        Ok(())
+    }
+
+    /// re-reads the files, so problems that were there on load are reported again
+    fn check(&mut self) -> Result<Vec<Diagnostic>, StoreError> {
+        let _lock = self.lock(false)?;
+        self.sync_disk();
+        let (records, _, mut diagnostics) =
+            Self::read_disk(&self.passwd_location, &self.shadow_location);
+        diagnostics.extend(check_hashes(records.values()));
+        if !records.contains_key(ROOT) {
+            diagnostics.push(Diagnostic::new(Problem::NoRoot, None));
+        }
+        Ok(diagnostics)
+    }
+
+    /// quarantines lines that can't be loaded and records with unusable hashes to
+    /// `<passwd>.quarantine`, locks those accounts and rewrites the files without the rest
+    fn repair(&mut self) -> Result<RepairReport, StoreError> {
+        let _lock = self.lock(true)?;
+        self.sync_disk();
+
+        // repairing tampered files would seal whatever was injected into them
+        if self.tampered {
+            return Err(StoreError::Corrupt(format!(
+                "'{}' does not match its seal, inspect it and run reseal",
+                self.passwd_location
+            )));
+        }
+
+        let (mut records, _, mut repaired) =
+            Self::read_disk(&self.passwd_location, &self.shadow_location);
+        let bad_hashes = check_hashes(records.values());
+        for diagnostic in &bad_hashes {
+            if let Some(record) = diagnostic.username().and_then(|u| records.get_mut(u)) {
+                record.set_hash(LOCKED_HASH);
+            }
+        }
+        repaired.extend(bad_hashes);
+        if repaired.is_empty() {
+            return Ok(RepairReport::default());
+        }
+
+        // write_disk only quarantines what was dropped, so hand it the bad hashes as well
+        self.dropped = repaired
+            .iter()
+            .filter(|d| d.record.is_some())
+            .cloned()
+            .collect();
+        let quarantined = !self.dropped.is_empty();
+        self.write_disk(&records)?;
+        self.unreadable = Self::load_error(&self.passwd_location, &self.shadow_location, &records);
+        self.cred_hashmap = records;
+        self.dropped.clear();
+        self.fingerprint = Self::fingerprint(&self.passwd_location, &self.shadow_location);
+        Ok(RepairReport {
+            repaired,
+            quarantine: quarantined.then(|| self.quarantine_path()),
+        })
+    }
+}
+
+/// credential store that only lives in memory, for tests and throwaway sessions
+/// # Fields
+/// * `records` - account records keyed by name
+/// * `policy` - rules new account names must follow
+/// # Methods
+/// * `new` - creates an empty store
+#[derive(Default)]
+pub struct MemoryStore {
+    records: HashMap<String, UserRecord>,
+    policy: UsernamePolicy,
+}
+
+/// Methods for the struct
+impl MemoryStore {
+    /// create an empty in-memory store
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// nothing is shared with other processes, so locking and refreshing are no-ops
+impl CredentialStore for MemoryStore {
+    fn backend(&self) -> &'static str {
+        "memory"
+    }
+
+    fn username_policy(&self) -> &UsernamePolicy {
+        &self.policy
+    }
+
+    fn set_username_policy(&mut self, policy: UsernamePolicy) {
+        self.policy = policy;
+    }
+
+    fn list_users(&self) -> Vec<String> {
+        self.records.keys().cloned().collect()
+    }
+
+    fn get(&self, username: &str) -> Option<UserRecord> {
+        self.records.get(username).cloned()
+    }
+
+    fn lock(&self, _exclusive: bool) -> std::io::Result<StoreLock> {
+        Ok(StoreLock { _file: None })
+    }
+
+    fn refresh(&mut self) -> bool {
+        false
+    }
+
+    fn commit(&mut self, ops: Vec<TransactionOp>) -> Result<(), StoreError> {
+        let mut staged = self.records.clone();
+        for op in ops {
+            op.apply(&mut staged, &self.policy)?;
+        }
+        self.records = staged;
+        Ok(())
+    }
+}
+
+/// credential store kept in an append-only key-value log, so a commit only writes the
+/// records it changed instead of rewriting every account
+///
+/// the log starts with a `KV_MAGIC <generation>` header, followed by batches of
+/// `put <name:hash:uid:gid:gecos:home:shell>` and `del <name>` lines that each end with
+/// `commit <count>`. Batches without a matching commit line were interrupted and are ignored.
+/// Once enough entries are superseded the log is compacted into a snapshot with a new generation.
+/// # Fields
+/// * `records` - account records keyed by name, as of the last replayed batch
+/// * `location` - filepath of the log
+/// * `generation` - id from the header, changes whenever the log is compacted
+/// * `loaded_len` - bytes of the log that have been replayed
+/// * `garbage` - log entries that have been superseded by later ones
+/// * `unreadable` - why the log could not be loaded when last read
+/// * `policy` - rules new account names must follow
+/// # Methods
+/// * `new` - opens or creates a log
+pub struct KvStore {
+    records: HashMap<String, UserRecord>,
+    location: String,
+    generation: String,
+    loaded_len: usize,
+    garbage: usize,
+    unreadable: Option<String>,
+    policy: UsernamePolicy,
+}
+
+/// Methods for the struct
+impl KvStore {
+    /// open a key-value log, creating it if it does not exist
+    /// # Arguments
+    /// * `filepath` - filepath of the log
+    pub fn new(filepath: &str) -> Self {
+        let mut store = KvStore {
+            records: HashMap::new(),
+            location: filepath.to_string(),
+            generation: String::new(),
+            loaded_len: 0,
+            garbage: 0,
+            unreadable: None,
+            policy: UsernamePolicy::default(),
+        };
+        let lock = store.lock(true);
+        if let Err(e) = &lock {
+            eprintln!(
+                "\x1b[91mFailed to lock '{}', loading anyway. Error: {}\x1b[0m",
+                filepath, e
+            );
+        }
+        if Path::new(filepath).exists() {
+            store.reload();
+        } else if lock.is_ok() {
+            eprintln!("No database found, continuing with no accounts");
+            if let Err(e) = store.compact() {
+                eprintln!(
+                    "\x1b[91mFailed to create '{}'. Error: {}\x1b[0m",
+                    filepath, e
+                );
+            }
+        }
+        store
+    }
+
+    /// internal method to read the log from the start
+    fn reload(&mut self) {
+        let data = match read(&self.location) {
+            Ok(data) => String::from_utf8_lossy(&data).to_string(),
+            Err(e) => {
+                eprintln!(
+                    "\x1b[91mFailed to read '{}'. Error: {}\x1b[0m",
+                    self.location, e
+                );
+                self.unreadable = Some(format!("'{}' could not be read: {}", self.location, e));
+                return;
+            }
+        };
+        let header_len = data.find('\n').map_or(data.len(), |i| i + 1);
+        self.generation = match data[..header_len].trim_end().split_once(' ') {
+            Some((KV_MAGIC, generation)) => generation.to_string(),
+            _ => {
+                eprintln!(
+                    "\x1b[91m'{}' is not a {} log, continuing with no accounts\x1b[0m",
+                    self.location, KV_MAGIC
+                );
+                self.unreadable = Some(format!("'{}' is not a {} log", self.location, KV_MAGIC));
+                self.loaded_len = data.len();
+                return;
+            }
+        };
+        self.unreadable = None;
+        self.records.clear();
+        self.garbage = 0;
+        self.loaded_len = header_len;
+        self.replay(&data);
+    }
+
+    /// internal method to apply every complete batch after `loaded_len`
+    /// # Arguments
+    /// * `data` - full contents of the log
+    fn replay(&mut self, data: &str) {
+        let mut pending: Vec<&str> = Vec::new();
+        let mut offset = self.loaded_len;
+        for line in data[self.loaded_len..].split_inclusive('\n') {
+            offset += line.len();
+            if !line.ends_with('\n') {
+                break; // torn final line
+            }
+            let line = line.trim_end_matches('\n');
+            if let Some(count) = line.strip_prefix("commit ") {
+                if count.parse() != Ok(pending.len()) {
+                    eprintln!(
+                        "\x1b[91mCorrupt batch ending at byte {} of '{}', skipping batch.\x1b[0m",
+                        offset, self.location
+                    );
+                } else {
+                    for entry in pending.drain(..) {
+                        self.apply_entry(entry, offset);
+                    }
+                }
+                pending.clear();
+                self.loaded_len = offset;
+            } else {
+                pending.push(line);
+            }
+        }
+        if !pending.is_empty() {
+            eprintln!(
+                "\x1b[91mIgnoring {} uncommitted entries at the end of '{}'\x1b[0m",
+                pending.len(),
+                self.location
+            );
+        }
+    }
+
+    /// internal method to apply one `put` or `del` entry of a committed batch
+    fn apply_entry(&mut self, entry: &str, offset: usize) {
+        let replaced = match entry.split_once(' ') {
+            Some(("put", line)) => match UserRecord::parse(line, 0) {
+                Some(record) if line.split(':').count() == 7 => {
+                    self.records.insert(record.name().into(), record).is_some()
+                }
+                _ => {
+                    eprintln!(
+                        "\x1b[91mInvalid entry in batch ending at byte {} of '{}'\x1b[0m",
+                        offset, self.location
+                    );
+                    false
+                }
+            },
+            Some(("del", username)) => self.records.remove(username).is_some(),
+            _ => {
+                eprintln!(
+                    "\x1b[91mInvalid entry in batch ending at byte {} of '{}'\x1b[0m",
+                    offset, self.location
+                );
+                false
+            }
+        };
+        if replaced {
+            self.garbage += 2; // the old entry and this one
+        }
+    }
+
+    /// internal method to pick up batches appended or compactions made by other processes
+    /// the caller must hold the store lock
+    /// # Return
+    /// * whether or not anything changed
+    fn sync_disk(&mut self) -> bool {
+        let data = match read(&self.location) {
+            Ok(data) => String::from_utf8_lossy(&data).to_string(),
+            Err(_) => return false,
+        };
+        let header = format!("{} {}\n", KV_MAGIC, self.generation);
+        if !data.starts_with(&header) || data.len() < self.loaded_len {
+            self.reload();
+            return true;
+        }
+        let before = self.loaded_len;
+        self.replay(&data);
+        self.loaded_len != before
+    }
+
+    /// internal method to rewrite the log as a single snapshot batch under a new generation
+    fn compact(&mut self) -> std::io::Result<()> {
+        let mut generation = [0u8; 8];
+        OsRng
+            .try_fill_bytes(&mut generation)
+            .map_err(std::io::Error::other)?;
+        let generation = hex::encode(generation);
+        let mut buf = format!("{} {}\n", KV_MAGIC, generation);
+        let header_len = buf.len();
+        let mut records: Vec<&UserRecord> = self.records.values().collect();
+        records.sort_by_key(|r| (r.uid, r.name.clone()));
+        for record in &records {
+            buf.push_str(&format!("put {}\n", record.record_line()));
+        }
+        if !records.is_empty() {
+            buf.push_str(&format!("commit {}\n", records.len()));
+        }
+        atomic_write(&self.location, buf.as_bytes(), SHADOW_MODE)?;
+        self.generation = generation;
+        self.loaded_len = if records.is_empty() {
+            header_len
+        } else {
+            buf.len()
+        };
+        self.garbage = 0;
+        Ok(())
+    }
+}
+
+/// the log is locked with `<log>.lock`, and each commit appends one batch
+impl CredentialStore for KvStore {
+    fn backend(&self) -> &'static str {
+        "kv"
+    }
+
+    fn username_policy(&self) -> &UsernamePolicy {
+        &self.policy
+    }
+
+    fn set_username_policy(&mut self, policy: UsernamePolicy) {
+        self.policy = policy;
+    }
+
+    fn list_users(&self) -> Vec<String> {
+        self.records.keys().cloned().collect()
+    }
+
+    fn get(&self, username: &str) -> Option<UserRecord> {
+        self.records.get(username).cloned()
+    }
+
+    fn contains(&self, username: &str) -> bool {
+        self.records.contains_key(username)
+    }
+
+    fn next_uid(&self) -> Result<u32, StoreError> {
+        free_uid(&self.records)
+    }
+
+    fn load_error(&self) -> Option<String> {
+        self.unreadable.clone()
+    }
+
+    fn lock(&self, exclusive: bool) -> std::io::Result<StoreLock> {
+        let lock_path = format!("{}.lock", self.location);
+        Ok(StoreLock {
+            _file: Some(lock_file(Path::new(&lock_path), exclusive)?),
+        })
+    }
+
+    fn refresh(&mut self) -> bool {
+        match self.lock(false) {
+            Ok(_lock) => self.sync_disk(),
+            Err(e) => {
+                eprintln!(
+                    "\x1b[91mFailed to lock '{}'. Error: {}\x1b[0m",
+                    self.location, e
+                );
+                false
+            }
+        }
+    }
+
+    fn commit(&mut self, ops: Vec<TransactionOp>) -> Result<(), StoreError> {
+        let _lock = self.lock(true)?;
+        self.sync_disk();
+
+        // appending now would bury whatever could not be read
+        if let Some(reason) = &self.unreadable {
+            return Err(StoreError::Corrupt(reason.clone()));
+        }
+
+        // apply to a copy, then log only the records that differ
+        let mut staged = self.records.clone();
+        for op in ops {
+            op.apply(&mut staged, &self.policy)?;
+        }
+        let mut batch: Vec<String> = Vec::new();
+        let mut superseded: usize = 0;
+        for (username, record) in &staged {
+            match self.records.get(username) {
+                Some(old) if old == record => continue,
+                Some(_) => superseded += 1,
+                None => {}
+            }
+            batch.push(format!("put {}", record.record_line()));
+        }
+        for username in self.records.keys() {
+            if !staged.contains_key(username) {
+                batch.push(format!("del {}", username));
+                superseded += 1;
+            }
+        }
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        // drop any torn batch left by a crashed writer, then append and flush ours
+        let mut file = OpenOptions::new().write(true).open(&self.location)?;
+        file.set_len(self.loaded_len as u64)?;
+        file.seek(std::io::SeekFrom::End(0))?;
+        let mut buf = batch.join("\n");
+        buf.push_str(&format!("\ncommit {}\n", batch.len()));
+        file.write_all(buf.as_bytes())?;
+        file.sync_all()?;
+
+        self.garbage += superseded * 2; // the old entries and the ones replacing them
+        self.loaded_len += buf.len();
+        self.records = staged;
+        if self.garbage > KV_COMPACT_MIN && self.garbage > self.records.len() {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// quarantines records with unusable hashes to `<log>.quarantine` and locks those accounts
+    fn repair(&mut self) -> Result<RepairReport, StoreError> {
+        let quarantine_path = format!("{}{}", self.location, QUARANTINE_SUFFIX);
+        repair_records(self, &quarantine_path)
+    }
+}
+
+/// Encrypted-at-rest credential store.
+/// The file is a header line naming the kdf, its parameters and the nonce,
+/// followed by the base64 ChaCha20-Poly1305 sealed JSON list of records.
+/// The header is authenticated as additional data, so its parameters cannot be swapped.
+/// # Fields
+/// * `records` - account records keyed by name, as of the last read or write
+/// * `location` - filepath of the encrypted database
+/// * `key` - master key derived from the master secret
+/// * `salt` - kdf salt the master key was derived with
+/// * `iterations` - kdf iterations the master key was derived with
+/// * `disk_state` - digest of the file as last read or written
+/// * `policy` - rules new account names must follow
+/// # Methods
+/// * `open` - opens or creates an encrypted database
+pub struct EncryptedStore {
+    records: HashMap<String, UserRecord>,
+    location: String,
+    key: [u8; CHACHA_KEY_LEN],
+    salt: Vec<u8>,
+    iterations: u32,
+    disk_state: Option<Vec<u8>>,
+    policy: UsernamePolicy,
+}
+
+/// Methods for the struct
+impl EncryptedStore {
+    /// open an encrypted database, creating it if it does not exist
+    /// # Arguments
+    /// * `filepath` - filepath of the encrypted database
+    /// * `secret` - master passphrase or key file contents
+    /// # Return
+    /// * the store, or `Corrupt` if the secret is wrong or the file was tampered with
+    pub fn open(filepath: &str, secret: &[u8]) -> Result<Self, StoreError> {
+        let lock_path = format!("{}.lock", filepath);
+        let _lock = lock_file(Path::new(&lock_path), true)?;
+        if !Path::new(filepath).exists() {
+            eprintln!("No database found, continuing with no accounts");
+            let salt = random_bytes(DEF_SALT_LEN)?;
+            let mut store = EncryptedStore {
+                records: HashMap::new(),
+                location: filepath.to_string(),
+                key: Self::derive_key(secret, &salt, ENC_ITERATIONS),
+                salt,
+                iterations: ENC_ITERATIONS,
+                disk_state: None,
+                policy: UsernamePolicy::default(),
+            };
+            store.write_disk(&HashMap::new())?;
+            return Ok(store);
+        }
+
+        let data = read(filepath)?;
+        let (salt, iterations) = Self::parse_header(&data)?;
+        let key = Self::derive_key(secret, &salt, iterations);
+        let records = Self::decrypt(&data, &key)?;
+        Ok(EncryptedStore {
+            records,
+            location: filepath.to_string(),
+            key,
+            salt,
+            iterations,
+            disk_state: Some(sha256(data)),
+            policy: UsernamePolicy::default(),
+        })
+    }
+
+    /// internal method to derive a master key from a master secret
+    fn derive_key(secret: &[u8], salt: &[u8], iterations: u32) -> [u8; CHACHA_KEY_LEN] {
+        pbkdf2_sha256(secret, salt, iterations, CHACHA_KEY_LEN)
+            .try_into()
+            .expect("pbkdf2 returns the requested length")
+    }
+
+    /// internal method to split a header line into its fields
+    /// # Arguments
+    /// * `data` - full contents of the encrypted database
+    /// # Return
+    /// * header line, salt, iterations, nonce and the sealed body
+    #[allow(clippy::type_complexity)]
+    fn split(data: &[u8]) -> Result<(&[u8], Vec<u8>, u32, Vec<u8>, Vec<u8>), StoreError> {
+        let corrupt = || StoreError::Corrupt("not an encrypted credential database".to_string());
+        let text = std::str::from_utf8(data).map_err(|_| corrupt())?;
+        let (header, body) = text.split_once('\n').ok_or_else(corrupt)?;
+        let fields: Vec<&str> = header.split(' ').collect();
+        let (iterations, salt, nonce) = match fields.as_slice() {
+            [ENC_MAGIC, ENC_KDF, iterations, salt, nonce] => (iterations, salt, nonce),
+            _ => return Err(corrupt()),
+        };
+        let iterations: u32 = iterations.parse().map_err(|_| corrupt())?;
+        let salt = base64_decode(salt).map_err(|_| corrupt())?;
+        let nonce = base64_decode(nonce).map_err(|_| corrupt())?;
+        let sealed = base64_decode(body.trim_end()).map_err(|_| corrupt())?;
+        if iterations == 0 || nonce.len() != CHACHA_NONCE_LEN {
+            return Err(corrupt());
+        }
+        if iterations > MAX_ENC_ITERATIONS {
+            return Err(StoreError::Corrupt(format!(
+                "{} kdf iterations is more than the {} an encrypted database may ask for",
+                iterations, MAX_ENC_ITERATIONS
+            )));
+        }
+        Ok((header.as_bytes(), salt, iterations, nonce, sealed))
+    }
+
+    /// internal method to read the kdf parameters from the header
+    fn parse_header(data: &[u8]) -> Result<(Vec<u8>, u32), StoreError> {
+        let (_, salt, iterations, _, _) = Self::split(data)?;
+        Ok((salt, iterations))
+    }
+
+    /// internal method to authenticate and decrypt the records
+    /// # Arguments
+    /// * `data` - full contents of the encrypted database
+    /// * `key` - master key
+    /// # Return
+    /// * account records, or `Corrupt` if the key is wrong or the data was tampered with
+    fn decrypt(
+        data: &[u8],
+        key: &[u8; CHACHA_KEY_LEN],
+    ) -> Result<HashMap<String, UserRecord>, StoreError> {
+        let (header, _, _, nonce, sealed) = Self::split(data)?;
+        let nonce: [u8; CHACHA_NONCE_LEN] = nonce.try_into().expect("checked by split");
+        let plaintext = chacha20poly1305_open(key, &nonce, header, &sealed).ok_or(
+            StoreError::Corrupt("wrong master secret or corrupt database".to_string()),
+        )?;
+        let records: Vec<UserRecord> = serde_json::from_slice(&plaintext)
+            .map_err(|e| StoreError::Corrupt(format!("invalid decrypted records: {}", e)))?;
+        Ok(records
+            .into_iter()
+            .map(|r| (r.name().to_string(), r))
+            .collect())
+    }
+
+    /// internal method to pick up changes made by other processes
+    /// the caller must hold the store lock
+    /// # Return
+    /// * whether or not the records were reloaded
+    fn sync_disk(&mut self) -> bool {
+        let data = match read(&self.location) {
+            Ok(data) => data,
+            Err(_) => return false,
+        };
+        let digest = sha256(data.clone());
+        if self.disk_state.as_ref() == Some(&digest) {
+            return false;
+        }
+        match Self::decrypt(&data, &self.key) {
+            Ok(records) => {
+                self.records = records;
+                self.disk_state = Some(digest);
+                true
+            }
+            Err(e) => {
+                eprintln!(
+                    "\x1b[91mFailed to reload '{}', keeping loaded accounts. Error: {}\x1b[0m",
+                    self.location, e
+                );
+                false
+            }
+        }
+    }
+
+    /// internal method to seal the records under a fresh nonce and replace the file
+    /// the caller must hold the store lock
+    fn write_disk(&mut self, records: &HashMap<String, UserRecord>) -> std::io::Result<()> {
+        let nonce: [u8; CHACHA_NONCE_LEN] = random_bytes(CHACHA_NONCE_LEN)?
+            .try_into()
+            .expect("requested nonce length");
+        let header = format!(
+            "{} {} {} {} {}",
+            ENC_MAGIC,
+            ENC_KDF,
+            self.iterations,
+            base64_encode(&self.salt),
+            base64_encode(&nonce)
+        );
+        let mut sorted: Vec<&UserRecord> = records.values().collect();
+        sorted.sort_by_key(|r| (r.uid, r.name.clone()));
+        let plaintext = serde_json::to_vec(&sorted).map_err(std::io::Error::other)?;
+        let sealed = chacha20poly1305_seal(&self.key, &nonce, header.as_bytes(), &plaintext);
+        let contents = format!("{}\n{}\n", header, base64_encode(&sealed));
+        atomic_write(&self.location, contents.as_bytes(), SHADOW_MODE)?;
+        self.disk_state = Some(sha256(contents.into_bytes()));
+        Ok(())
+    }
+}
+
+/// the database is locked with `<file>.lock`, and each commit re-encrypts the whole file
+impl CredentialStore for EncryptedStore {
+    fn backend(&self) -> &'static str {
+        "encrypted"
+    }
+
+    fn username_policy(&self) -> &UsernamePolicy {
+        &self.policy
+    }
+
+    fn set_username_policy(&mut self, policy: UsernamePolicy) {
+        self.policy = policy;
+    }
+
+    fn list_users(&self) -> Vec<String> {
+        self.records.keys().cloned().collect()
+    }
+
+    fn get(&self, username: &str) -> Option<UserRecord> {
+        self.records.get(username).cloned()
+    }
+
+    fn contains(&self, username: &str) -> bool {
+        self.records.contains_key(username)
+    }
+
+    fn next_uid(&self) -> Result<u32, StoreError> {
+        free_uid(&self.records)
+    }
+
+    fn lock(&self, exclusive: bool) -> std::io::Result<StoreLock> {
+        let lock_path = format!("{}.lock", self.location);
+        Ok(StoreLock {
+            _file: Some(lock_file(Path::new(&lock_path), exclusive)?),
+        })
+    }
+
+    fn refresh(&mut self) -> bool {
+        match self.lock(false) {
+            Ok(_lock) => self.sync_disk(),
+            Err(e) => {
+                eprintln!(
+                    "\x1b[91mFailed to lock '{}'. Error: {}\x1b[0m",
+                    self.location, e
+                );
+                false
+            }
+        }
+    }
+
+    fn commit(&mut self, ops: Vec<TransactionOp>) -> Result<(), StoreError> {
+        let _lock = self.lock(true)?;
+        self.sync_disk();
+
+        let mut staged = self.records.clone();
+        for op in ops {
+            op.apply(&mut staged, &self.policy)?;
+        }
+        self.write_disk(&staged)?;
+        self.records = staged;
+        Ok(())
+    }
+
+    fn rotate_master_key(&mut self, current: &[u8], new: &[u8]) -> Result<(), StoreError> {
+        let _lock = self.lock(true)?;
+        self.sync_disk();
+
+        let current_key = Self::derive_key(current, &self.salt, self.iterations);
+        if !constant_time_eq(&current_key, &self.key) {
+            return Err(StoreError::Corrupt("wrong master secret".to_string()));
+        }
+
+        // a new salt, so the old key cannot open the new file even with the same secret
+        let (old_key, old_salt, old_iterations) = (self.key, self.salt.clone(), self.iterations);
+        self.salt = random_bytes(DEF_SALT_LEN)?;
+        self.iterations = ENC_ITERATIONS;
+        self.key = Self::derive_key(new, &self.salt, self.iterations);
+        let records = self.records.clone();
+        if let Err(e) = self.write_disk(&records) {
+            (self.key, self.salt, self.iterations) = (old_key, old_salt, old_iterations);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+}
+
+/// a single group in the group file: `name:gid:members`
+/// # Fields
+/// * `name` - group name
+/// * `gid` - numeric group id
+/// * `members` - accounts with the group as a supplementary group
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupRecord {
+    name: String,
+    gid: u32,
+    members: Vec<String>,
+}
+
+/// Methods for the struct
+impl GroupRecord {
+    /// create a group with no members
+    /// # Arguments
+    /// * `name` - group name
+    /// * `gid` - numeric group id
+    pub fn new(name: &str, gid: u32) -> Self {
+        GroupRecord {
+            name: name.to_string(),
+            gid,
+            members: Vec::new(),
+        }
+    }
+
+    /// parse a line of the group file
+    /// # Arguments
+    /// * `line` - `name:gid:members` with members comma separated
+    /// # Return
+    /// * the record, or `None` if the line is malformed
+    pub fn parse(line: &str) -> Option<Self> {
+        let fields: Vec<&str> = line.split(':').collect();
+        if fields.len() != 3 || !Self::is_valid_name(fields[0]) {
+            return None;
+        }
+        Some(GroupRecord {
+            name: fields[0].to_string(),
+            gid: fields[1].parse().ok()?,
+            members: fields[2]
+                .split(',')
+                .filter(|m| !m.is_empty())
+                .map(|m| m.to_string())
+                .collect(),
+        })
+    }
+
+    /// checks that a name can be stored in the group file without corrupting it
+    /// # Arguments
+    /// * `name` - candidate group name
+    /// # Return
+    /// * whether or not the name is non-empty and free of separators, whitespace and
+    ///   control characters
+    pub fn is_valid_name(name: &str) -> bool {
+        !name.is_empty()
+            && !name.starts_with('-')
+            && !name
+                .chars()
+                .any(|c| c == ':' || c == ',' || c.is_whitespace() || c.is_control())
+    }
+
+    /// group name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// numeric group id
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    /// accounts with the group as a supplementary group
+    pub fn members(&self) -> &[String] {
+        &self.members
+    }
+
+    /// whether an account is in the group, as its primary group or as a member
+    /// # Arguments
+    /// * `user` - account record
+    pub fn contains(&self, user: &UserRecord) -> bool {
+        user.gid == self.gid || self.members.iter().any(|m| m == user.name())
+    }
+}
+
+/// records are written back in group line format
+impl fmt::Display for GroupRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.name, self.gid, self.members.join(","))
+    }
+}
+
+/// errors returned when changing the group database
+/// # Variants
+/// * `NotFound` - the named group does not exist
+/// * `AlreadyExists` - the named group already exists
+/// * `GidTaken` - another group already has the gid
+/// * `InvalidName` - the name can't be stored in the group file
+/// * `NotMember` - the account is not a member of the group
+/// * `AlreadyMember` - the account is already a member of the group
+/// * `PrimaryGroup` - the group is the named account's primary group
+/// * `NoFreeGid` - every gid above the highest one in use is taken
+/// * `Io` - the group file could not be locked or written
+#[derive(Debug)]
+pub enum GroupError {
+    NotFound(String),
+    AlreadyExists(String),
+    GidTaken(u32),
+    InvalidName(String),
+    NotMember(String, String),
+    AlreadyMember(String, String),
+    PrimaryGroup(String, String),
+    NoFreeGid,
+    Io(std::io::Error),
+}
+
+impl fmt::Display for GroupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GroupError::NotFound(group) => write!(f, "group {} not found", group),
+            GroupError::AlreadyExists(group) => write!(f, "group {} already exists", group),
+            GroupError::GidTaken(gid) => write!(f, "gid {} is already in use", gid),
+            GroupError::InvalidName(group) => write!(
+                f,
+                "group name {:?} may not be empty, start with '-', or contain ':', ',', whitespace or control characters",
+                group
+            ),
+            GroupError::NotMember(user, group) => {
+                write!(f, "{} is not a member of {}", user, group)
+            }
+            GroupError::AlreadyMember(user, group) => {
+                write!(f, "{} is already a member of {}", user, group)
+            }
+            GroupError::PrimaryGroup(group, user) => {
+                write!(f, "{} is the primary group of {}", group, user)
+            }
+            GroupError::NoFreeGid => {
+                write!(
+                    f,
+                    "a group already has gid {}, the highest there is",
+                    u32::MAX
+                )
+            }
+            GroupError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for GroupError {}
+
+impl From<std::io::Error> for GroupError {
+    fn from(e: std::io::Error) -> Self {
+        GroupError::Io(e)
+    }
+}
+
+/// groups kept in a group file next to the credential store, like /etc/group
+/// # Fields
+/// * `groups` - hashmap that holds group records
+/// * `location` - filepath of the group file, locked with `<group>.lock` while changing it
+/// * `fingerprint` - digest of the file as last read or written, to notice outside changes
+/// * `dropped` - lines that could not be loaded, quarantined before a write drops them for good
+/// # Methods
+/// * `new` - loads the group file
+/// * `refresh` - reloads the file if another process changed it
+/// * `list` - lists every group
+/// * `get` - retrieves a group by name
+/// * `by_gid` - retrieves a group by gid
+/// * `groups_of` - lists the groups an account is in
+/// * `is_member` - checks whether an account is in a group
+/// * `next_gid` - allocates a gid for a new group
+/// * `add`, `remove` - creates and deletes groups
+/// * `add_member`, `remove_member` - changes a group's members
+/// * `rename_member`, `remove_user` - follows account renames and deletions
+pub struct GroupDatabase {
+    groups: HashMap<String, GroupRecord>,
+    location: String,
+    fingerprint: Option<Vec<u8>>,
+    dropped: Vec<Diagnostic>,
+}
+
+/// Methods for the struct
+impl GroupDatabase {
+    /// load the group file, a missing file is an empty database
+    /// # Arguments
+    /// * `filepath` - filepath of the group file
+    pub fn new(filepath: &str) -> Self {
+        let mut database = GroupDatabase {
+            groups: HashMap::new(),
+            location: filepath.to_string(),
+            fingerprint: None,
+            dropped: Vec::new(),
+        };
+        database.load();
+        database
+    }
 
-        // read database file
-        let data: File = match File::open(filepath) {
-            Ok(file) => file,
-            Err(e) => {
-                eprintln!("No database found, continuing with no accounts");
-                drop(e);
-                return ret_val;
+    /// internal method to read the group file, printing the lines that could not be loaded
+    fn load(&mut self) {
+        let data = match read(&self.location) {
+            Ok(data) => data,
+            Err(_) => {
+                self.groups.clear();
+                self.fingerprint = None;
+                self.dropped.clear();
+                return;
             }
         };
-
-        let reader = BufReader::new(data);
-        let mut counter: i32 = 0;
-
-        // parse database
-        for record in reader.lines() {
-            counter += 1;
-            let record = match record {
-                Ok(r) => r,
-                Err(e) => {
-                    eprintln!(
-                        "\x1b[91mUnable to process line #{} of '{}'. Error: {}\x1b[0m",
-                        counter, filepath, e
-                    );
-                    continue;
-                }
-            };
-            match record.split_once(":") {
-                Some((username, hashword)) => {
-                    if ret_val.contains_key(username) {
-                        eprintln!(
-                            "\x1b[91mDuplicate user '{}' found on line #{} of '{}', skipping record.\x1b[0m",
-                            username, counter, filepath
-                        );
-                    } else {
-                        ret_val.insert(username.into(), hashword.into());
-                    }
+        let mut groups: HashMap<String, GroupRecord> = HashMap::new();
+        let mut diagnostics: Vec<Diagnostic> = Vec::new();
+        for (index, line) in String::from_utf8_lossy(&data).lines().enumerate() {
+            let problem = match GroupRecord::parse(line) {
+                Some(group) if groups.contains_key(group.name()) => {
+                    Problem::DuplicateGroup(group.name().to_string())
                 }
-                None => {
-                    eprintln!(
-                        "\x1b[91mInvalid entry in line #{} of '{}'\x1b[0m",
-                        counter, filepath
-                    );
+                Some(group) => {
+                    groups.insert(group.name().to_string(), group);
+                    continue;
                 }
+                None => Problem::Malformed,
             };
+            diagnostics.push(
+                Diagnostic::new(problem, Some(line.to_string())).at(&self.location, index + 1),
+            );
         }
-        ret_val
+        for diagnostic in &diagnostics {
+            eprintln!("\x1b[91m{}\x1b[0m", diagnostic);
+        }
+        self.groups = groups;
+        self.fingerprint = Some(sha256(data));
+        self.dropped = diagnostics;
     }
 
-    /// internal method to update credentials on disk
-    fn write_disk(&self) {
-        let mut write_buf: String = "".to_string();
-
-        // generate database buffer
-        for record in self.list_users() {
-            match self.cred_hashmap.get(&record) {
-                Some(hash) => {
-                    write_buf.push_str(&format!("{}:{}\n", record, hash));
-                }
-                None => {
-                    eprintln!(
-                        "\x1b[91mPassword not found for '{}', skipping write.\x1b[0m",
-                        record
-                    );
-                    continue;
-                }
-            }
+    /// reload the group file if another process changed it since it was last read
+    /// # Return
+    /// * whether or not the file was reloaded
+    pub fn refresh(&mut self) -> bool {
+        let current = read(&self.location).ok().map(sha256);
+        if current == self.fingerprint {
+            return false;
         }
+        self.load();
+        true
+    }
 
-        // write to disk
-        match write(&self.storage_location, write_buf) {
-            Ok(()) => {}
-            Err(e) => {
-                eprintln!(
-                    "\x1b[91mFailed to write to '{}'. Error: {}\x1b[0m",
-                    self.storage_location, e
-                );
-            }
+    /// internal method to change the groups under the file's lock and write them back
+    /// # Arguments
+    /// * `change` - edits the groups, an error leaves the database unchanged
+    fn update<T, F>(&mut self, change: F) -> Result<T, GroupError>
+    where
+        F: FnOnce(&mut HashMap<String, GroupRecord>) -> Result<T, GroupError>,
+    {
+        let lock_path = format!("{}.lock", self.location);
+        let _lock = lock_file(Path::new(&lock_path), true)?;
+        self.refresh();
+        let mut groups = self.groups.clone();
+        let result = change(&mut groups)?;
+
+        // keep what was left out on load, the file is about to be rewritten without it
+        let quarantine_path = format!("{}{}", self.location, QUARANTINE_SUFFIX);
+        let quarantined = quarantine(&quarantine_path, &self.dropped)?;
+        if quarantined > 0 {
+            println!(
+                "quarantined {} record(s) in '{}'",
+                quarantined, quarantine_path
+            );
         }
+
+        // ordered by gid like /etc/group
+        let mut records: Vec<&GroupRecord> = groups.values().collect();
+        records.sort_by_key(|g| (g.gid, g.name.clone()));
+        let data: String = records.iter().map(|g| format!("{}\n", g)).collect();
+        atomic_write(&self.location, data.as_bytes(), GROUP_MODE)?;
+        self.groups = groups;
+        self.fingerprint = Some(sha256(data.into_bytes()));
+        self.dropped.clear();
+        Ok(result)
     }
 
-    /// method that lists all registered users
+    /// list every group
     /// # Return
-    /// * Vec<string> - list of all users
-    pub fn list_users(&self) -> Vec<String> {
-        self.cred_hashmap.keys().cloned().collect()
+    /// * groups ordered by gid
+    pub fn list(&self) -> Vec<GroupRecord> {
+        let mut groups: Vec<GroupRecord> = self.groups.values().cloned().collect();
+        groups.sort_by_key(|g| (g.gid, g.name.clone()));
+        groups
     }
 
-    /// method that checks if a user is registered
+    /// retrieve a group by name
     /// # Arguments
-    /// * `username` - account name
-    /// # Return
-    /// * whether or not user exists
-    pub fn contains(&self, username: &str) -> bool {
-        self.cred_hashmap.contains_key(username.into())
+    /// * `name` - group name
+    pub fn get(&self, name: &str) -> Option<&GroupRecord> {
+        self.groups.get(name)
     }
 
-    /// method that retrieves a users hashed password
+    /// retrieve a group by gid, the lowest named one if several share it
     /// # Arguments
-    /// * `username` - account name
+    /// * `gid` - numeric group id
+    pub fn by_gid(&self, gid: u32) -> Option<&GroupRecord> {
+        self.groups
+            .values()
+            .filter(|g| g.gid == gid)
+            .min_by_key(|g| g.name.as_str())
+    }
+
+    /// list the groups an account is in
+    /// # Arguments
+    /// * `user` - account record
     /// # Return
-    /// * hashed password if it exists
-    pub fn get(&self, username: &str) -> Option<&String> {
-        self.cred_hashmap.get(username.into())
+    /// * groups ordered by gid, the primary group included if it is in the file
+    pub fn groups_of(&self, user: &UserRecord) -> Vec<GroupRecord> {
+        self.list()
+            .into_iter()
+            .filter(|g| g.contains(user))
+            .collect()
     }
 
-    /// method that registers a user or changes and existing users password
+    /// check whether an account is in a group, as its primary group or as a member
     /// # Arguments
-    /// * `username` - account name
-    /// * `hashword` - hashed password
-    pub fn set(&mut self, username: &str, hashword: &str) -> &mut Self {
-        self.cred_hashmap.insert(username.into(), hashword.into());
-        self.write_disk();
-        self
+    /// * `user` - account record
+    /// * `group` - group name
+    pub fn is_member(&self, user: &UserRecord, group: &str) -> bool {
+        self.groups.get(group).is_some_and(|g| g.contains(user))
     }
 
-    /// method that deletes a users record
+    /// allocate a gid for a new group
+    /// # Return
+    /// * lowest gid above every gid in use, starting at `FIRST_GID`, or `NoFreeGid` once a
+    ///   group holds the highest gid
+    pub fn next_gid(&self) -> Result<u32, GroupError> {
+        self.groups
+            .values()
+            .map(|g| g.gid.checked_add(1).ok_or(GroupError::NoFreeGid))
+            .try_fold(FIRST_GID, |next, gid| Ok(next.max(gid?)))
+    }
+
+    /// create a group
     /// # Arguments
-    /// * `username` - account name
-    pub fn remove(&mut self, username: &str) -> &mut Self {
-        self.cred_hashmap.remove(username.into());
-        self.write_disk();
-        self
+    /// * `name` - group name
+    /// * `gid` - numeric group id, which must be free
+    /// # Return
+    /// * the group created
+    pub fn add(&mut self, name: &str, gid: u32) -> Result<GroupRecord, GroupError> {
+        if !GroupRecord::is_valid_name(name) {
+            return Err(GroupError::InvalidName(name.to_string()));
+        }
+        self.update(|groups| {
+            if groups.contains_key(name) {
+                return Err(GroupError::AlreadyExists(name.to_string()));
+            }
+            if groups.values().any(|g| g.gid == gid) {
+                return Err(GroupError::GidTaken(gid));
+            }
+            let group = GroupRecord::new(name, gid);
+            groups.insert(name.to_string(), group.clone());
+            Ok(group)
+        })
     }
 
-    /// method that authenticates a user
+    /// delete a group
     /// # Arguments
-    /// * `database` - a `UserCredentials` database storing user credentials
-    /// * `username` - String of users account name
-    /// * `password` - String of user's password (raw)
+    /// * `name` - group name
     /// # Return
-    /// * whether or not user is authenticated
-    pub fn authenticate(&mut self, username: &str, password: &str) -> bool {
-        if !self.contains(username) {
-            return false;
-        } else {
-            // generate hash to compare
-            let entry_string = self.get(username).expect("failed to unwrap username");
-            let mut entry_iter = entry_string.split("$");
-            entry_iter.next();
-            entry_iter.next();
-            let cost = entry_iter
-                .next()
-                .expect("Failed to unwrap slice")
-                .parse()
-                .expect("failed to unwrap parse");
-            let salt = entry_iter.next().expect("failed to unwrap salt");
+    /// * the group deleted
+    pub fn remove(&mut self, name: &str) -> Result<GroupRecord, GroupError> {
+        self.update(|groups| {
+            groups
+                .remove(name)
+                .ok_or_else(|| GroupError::NotFound(name.to_string()))
+        })
+    }
+
+    /// add an account to a group's members
+    /// # Arguments
+    /// * `group` - group name
+    /// * `user` - account name
+    pub fn add_member(&mut self, group: &str, user: &str) -> Result<(), GroupError> {
+        self.update(|groups| {
+            let record = groups
+                .get_mut(group)
+                .ok_or_else(|| GroupError::NotFound(group.to_string()))?;
+            if record.members.iter().any(|m| m == user) {
+                return Err(GroupError::AlreadyMember(
+                    user.to_string(),
+                    group.to_string(),
+                ));
+            }
+            record.members.push(user.to_string());
+            Ok(())
+        })
+    }
+
+    /// remove an account from a group's members
+    /// # Arguments
+    /// * `group` - group name
+    /// * `user` - account name
+    pub fn remove_member(&mut self, group: &str, user: &str) -> Result<(), GroupError> {
+        self.update(|groups| {
+            let record = groups
+                .get_mut(group)
+                .ok_or_else(|| GroupError::NotFound(group.to_string()))?;
+            let before = record.members.len();
+            record.members.retain(|m| m != user);
+            match record.members.len() == before {
+                true => Err(GroupError::NotMember(user.to_string(), group.to_string())),
+                false => Ok(()),
+            }
+        })
+    }
 
-            let hash = hash_password(password, salt, cost);
+    /// follow an account rename in every group it is a member of
+    /// # Arguments
+    /// * `old` - previous account name
+    /// * `new` - new account name
+    /// # Return
+    /// * number of groups changed
+    pub fn rename_member(&mut self, old: &str, new: &str) -> Result<usize, GroupError> {
+        if !self
+            .groups
+            .values()
+            .any(|g| g.members.iter().any(|m| m == old))
+        {
+            return Ok(0);
+        }
+        self.update(|groups| {
+            let mut count: usize = 0;
+            for member in groups.values_mut().flat_map(|g| g.members.iter_mut()) {
+                if member == old {
+                    *member = new.to_string();
+                    count += 1;
+                }
+            }
+            Ok(count)
+        })
+    }
 
-            // compare
-            return &hash == entry_string;
+    /// remove a deleted account from every group it is a member of
+    /// # Arguments
+    /// * `user` - account name
+    /// # Return
+    /// * number of groups changed
+    pub fn remove_user(&mut self, user: &str) -> Result<usize, GroupError> {
+        if !self
+            .groups
+            .values()
+            .any(|g| g.members.iter().any(|m| m == user))
+        {
+            return Ok(0);
         }
+        self.update(|groups| {
+            let mut count: usize = 0;
+            for group in groups.values_mut() {
+                let before = group.members.len();
+                group.members.retain(|m| m != user);
+                count += before - group.members.len();
+            }
+            Ok(count)
+        })
     }
 }
 
 // ==================== FUNCTIONS ====================
 
 /// Wrapper for encoding bytes to base64
-fn base64_encode(data: &[u8]) -> String {
+pub fn base64_encode(data: &[u8]) -> String {
     general_purpose::STANDARD.encode(data)
 }
 
 /// Wrapper for decoding base64 to bytes
-fn base64_decode(s: &str) -> Result<Vec<u8>, base64::DecodeError> {
+pub fn base64_decode(s: &str) -> Result<Vec<u8>, base64::DecodeError> {
     general_purpose::STANDARD.decode(s)
 }
 
@@ -255,14 +2702,14 @@ fn base64_decode(s: &str) -> Result<Vec<u8>, base64::DecodeError> {
 /// * `message` - message to be encrypted, in byte format (Vec<u8>)
 /// # Return
 /// * The encrypted message, still in byte format
-fn sha256(mut message: Vec<u8>) -> Vec<u8> {
+pub fn sha256(mut message: Vec<u8>) -> Vec<u8> {
     #![allow(non_snake_case)]
 
     // initialize hash values
-    let mut hs: [u32; 8] = SHA_H_INITIAL.clone();
+    let mut hs: [u32; 8] = SHA_H_INITIAL;
 
     // initialize round constants
-    let k: [u32; 64] = SHA_K_INITIAL.clone();
+    let k: [u32; 64] = SHA_K_INITIAL;
 
     // length calculations
     let L: u64 = (message.len() * 8) as u64;
@@ -288,12 +2735,10 @@ fn sha256(mut message: Vec<u8>) -> Vec<u8> {
         let mut w: [u32; 64] = [0; 64];
 
         // convert bytes to 32bit words and place in w[]
-        let mut i = 0;
-        for word in chunk.chunks_exact(4) {
+        for (i, word) in chunk.chunks_exact(4).enumerate() {
             assert_eq!(word.len(), 4); // ensure chunk size of 4
             let word: [u8; 4] = word.try_into().expect("Could not convert slice to array");
             w[i] = u32::from_be_bytes(word);
-            i += 1;
         }
 
         // "extend" first 16 words to remaining 48 words
@@ -348,13 +2793,392 @@ fn sha256(mut message: Vec<u8>) -> Vec<u8> {
 
     // produce final hash value
     let mut digest: Vec<u8> = Vec::new();
-    for i in 0..8 {
-        digest.append(&mut hs[i].to_be_bytes().to_vec());
+    for h in hs {
+        digest.append(&mut h.to_be_bytes().to_vec());
     }
     assert_eq!(digest.len() * 8, 256); // ensure hashed result is 256 bits
     digest
 }
 
+/// A custom implementation of sha-1, **NOT SECURE** and broken for collisions,
+/// only kept because SRP's RFC 5054 test vectors are defined with it
+/// # Arguments
+/// * `message` - message to be hashed, in byte format (Vec<u8>)
+/// # Return
+/// * The 20 byte digest
+pub fn sha1(mut message: Vec<u8>) -> Vec<u8> {
+    let mut hs: [u32; 5] = SHA1_H_INITIAL;
+    let length: u64 = (message.len() * 8) as u64;
+
+    // same padding as sha-256
+    message.push(128_u8);
+    while message.len() % 64 != 56 {
+        message.push(0_u8);
+    }
+    message.extend(length.to_be_bytes());
+
+    for chunk in message.chunks_exact(64) {
+        let mut w: [u32; 80] = [0; 80];
+        for (i, word) in chunk.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = hs;
+        for (i, &word) in w.iter().enumerate() {
+            let f = match i / 20 {
+                0 => (b & c) | ((!b) & d),
+                2 => (b & c) | (b & d) | (c & d),
+                _ => b ^ c ^ d,
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(SHA1_K[i / 20])
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (h, v) in hs.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+
+    hs.iter().flat_map(|h| h.to_be_bytes()).collect()
+}
+
+/// HMAC (RFC 2104) over the custom sha-256, **NOT SECURE**
+/// # Arguments
+/// * `key` - secret key, hashed first if longer than a sha-256 block
+/// * `message` - message to authenticate
+/// # Return
+/// * 32 byte message authentication code
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut key_block = match key.len() > SHA256_BLOCK_LEN {
+        true => sha256(key.to_vec()),
+        false => key.to_vec(),
+    };
+    key_block.resize(SHA256_BLOCK_LEN, 0);
+
+    // H((K ^ opad) || H((K ^ ipad) || message))
+    let mut inner: Vec<u8> = key_block.iter().map(|b| b ^ 0x36).collect();
+    inner.extend_from_slice(message);
+    let mut outer: Vec<u8> = key_block.iter().map(|b| b ^ 0x5c).collect();
+    outer.append(&mut sha256(inner));
+    sha256(outer)
+}
+
+/// PBKDF2 (RFC 8018) with HMAC-SHA256 as the pseudorandom function
+/// # Arguments
+/// * `password` - secret to derive a key from
+/// * `salt` - random salt
+/// * `iterations` - number of HMAC iterations per output block
+/// * `len` - length of the derived key, in bytes
+/// # Return
+/// * derived key
+pub fn pbkdf2_sha256(password: &[u8], salt: &[u8], iterations: u32, len: usize) -> Vec<u8> {
+    let mut derived: Vec<u8> = Vec::new();
+    let mut block: u32 = 1;
+    while derived.len() < len {
+        // T_i = U_1 ^ U_2 ^ ... ^ U_c, with U_1 = PRF(P, S || INT(i))
+        let mut message = salt.to_vec();
+        message.extend_from_slice(&block.to_be_bytes());
+        let mut u = hmac_sha256(password, &message);
+        let mut t = u.clone();
+        for _ in 1..iterations {
+            u = hmac_sha256(password, &u);
+            for (t, u) in t.iter_mut().zip(&u) {
+                *t ^= u;
+            }
+        }
+        derived.append(&mut t);
+        block += 1;
+    }
+    derived.truncate(len);
+    derived
+}
+
+/// Compares two byte strings in time that depends only on their length
+/// # Arguments
+/// * `a` - first byte string
+/// * `b` - second byte string
+/// # Return
+/// * whether or not they are equal
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// internal chacha20 quarter round on four words of the state
+fn chacha20_quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+/// A custom implementation of the chacha20 block function (RFC 8439), **NOT SECURE**
+/// # Arguments
+/// * `key` - 256 bit key
+/// * `counter` - block counter
+/// * `nonce` - 96 bit nonce
+/// # Return
+/// * 64 bytes of keystream
+pub fn chacha20_block(
+    key: &[u8; CHACHA_KEY_LEN],
+    counter: u32,
+    nonce: &[u8; CHACHA_NONCE_LEN],
+) -> [u8; 64] {
+    // constants, key, counter, nonce, all little endian words
+    let mut state: [u32; 16] = [0; 16];
+    state[..4].copy_from_slice(&CHACHA_CONSTANTS);
+    for (i, word) in key.chunks_exact(4).enumerate() {
+        state[4 + i] = u32::from_le_bytes(word.try_into().expect("chunk of 4"));
+    }
+    state[12] = counter;
+    for (i, word) in nonce.chunks_exact(4).enumerate() {
+        state[13 + i] = u32::from_le_bytes(word.try_into().expect("chunk of 4"));
+    }
+
+    // 20 rounds, alternating column and diagonal rounds
+    let mut working = state;
+    for _ in 0..10 {
+        chacha20_quarter_round(&mut working, 0, 4, 8, 12);
+        chacha20_quarter_round(&mut working, 1, 5, 9, 13);
+        chacha20_quarter_round(&mut working, 2, 6, 10, 14);
+        chacha20_quarter_round(&mut working, 3, 7, 11, 15);
+        chacha20_quarter_round(&mut working, 0, 5, 10, 15);
+        chacha20_quarter_round(&mut working, 1, 6, 11, 12);
+        chacha20_quarter_round(&mut working, 2, 7, 8, 13);
+        chacha20_quarter_round(&mut working, 3, 4, 9, 14);
+    }
+
+    // add the original state and serialize
+    let mut block = [0u8; 64];
+    for (i, word) in working.iter().enumerate() {
+        block[i * 4..i * 4 + 4].copy_from_slice(&word.wrapping_add(state[i]).to_le_bytes());
+    }
+    block
+}
+
+/// Encrypts or decrypts data by xoring it with the chacha20 keystream
+/// # Arguments
+/// * `key` - 256 bit key
+/// * `counter` - block counter of the first block
+/// * `nonce` - 96 bit nonce
+/// * `data` - plaintext or ciphertext
+/// # Return
+/// * ciphertext or plaintext
+pub fn chacha20_xor(
+    key: &[u8; CHACHA_KEY_LEN],
+    counter: u32,
+    nonce: &[u8; CHACHA_NONCE_LEN],
+    data: &[u8],
+) -> Vec<u8> {
+    let mut output: Vec<u8> = Vec::with_capacity(data.len());
+    for (i, chunk) in data.chunks(64).enumerate() {
+        let keystream = chacha20_block(key, counter.wrapping_add(i as u32), nonce);
+        output.extend(chunk.iter().zip(keystream).map(|(d, k)| d ^ k));
+    }
+    output
+}
+
+/// A custom implementation of the poly1305 one-time authenticator (RFC 8439), **NOT SECURE**
+/// arithmetic mod 2^130 - 5 is done in five 26 bit limbs
+/// # Arguments
+/// * `key` - 256 bit one-time key, `r` followed by `s`
+/// * `message` - message to authenticate
+/// # Return
+/// * 128 bit tag
+pub fn poly1305(key: &[u8; 32], message: &[u8]) -> [u8; POLY1305_TAG_LEN] {
+    const MASK: u64 = 0x3ffffff;
+    let le32 =
+        |b: &[u8], i: usize| u32::from_le_bytes(b[i..i + 4].try_into().expect("4 bytes")) as u64;
+
+    // clamp r and split into limbs
+    let r0 = le32(key, 0) & 0x3ffffff;
+    let r1 = (le32(key, 3) >> 2) & 0x3ffff03;
+    let r2 = (le32(key, 6) >> 4) & 0x3ffc0ff;
+    let r3 = (le32(key, 9) >> 6) & 0x3f03fff;
+    let r4 = (le32(key, 12) >> 8) & 0x00fffff;
+    let (s1, s2, s3, s4) = (r1 * 5, r2 * 5, r3 * 5, r4 * 5);
+
+    let mut h: [u64; 5] = [0; 5];
+    for chunk in message.chunks(16) {
+        // pad the block, full blocks get their high bit at 2^128
+        let mut block = [0u8; 17];
+        block[..chunk.len()].copy_from_slice(chunk);
+        block[chunk.len()] = 1;
+        let hibit = (block[16] as u64) << 24;
+        h[0] += le32(&block, 0) & MASK;
+        h[1] += (le32(&block, 3) >> 2) & MASK;
+        h[2] += (le32(&block, 6) >> 4) & MASK;
+        h[3] += (le32(&block, 9) >> 6) & MASK;
+        h[4] += (le32(&block, 12) >> 8) | hibit;
+
+        // h *= r mod 2^130 - 5
+        let d0 = h[0] * r0 + h[1] * s4 + h[2] * s3 + h[3] * s2 + h[4] * s1;
+        let mut d1 = h[0] * r1 + h[1] * r0 + h[2] * s4 + h[3] * s3 + h[4] * s2;
+        let mut d2 = h[0] * r2 + h[1] * r1 + h[2] * r0 + h[3] * s4 + h[4] * s3;
+        let mut d3 = h[0] * r3 + h[1] * r2 + h[2] * r1 + h[3] * r0 + h[4] * s4;
+        let mut d4 = h[0] * r4 + h[1] * r3 + h[2] * r2 + h[3] * r1 + h[4] * r0;
+
+        // partial carry
+        d1 += d0 >> 26;
+        h[0] = d0 & MASK;
+        d2 += d1 >> 26;
+        h[1] = d1 & MASK;
+        d3 += d2 >> 26;
+        h[2] = d2 & MASK;
+        d4 += d3 >> 26;
+        h[3] = d3 & MASK;
+        h[0] += (d4 >> 26) * 5;
+        h[4] = d4 & MASK;
+        h[1] += h[0] >> 26;
+        h[0] &= MASK;
+    }
+
+    // full carry
+    for i in 1..5 {
+        h[i] += h[i - 1] >> 26;
+        h[i - 1] &= MASK;
+    }
+    h[0] += (h[4] >> 26) * 5;
+    h[4] &= MASK;
+    h[1] += h[0] >> 26;
+    h[0] &= MASK;
+
+    // compute h - p and keep it if it did not underflow
+    let mut g: [u64; 5] = [0; 5];
+    g[0] = h[0] + 5;
+    for i in 1..5 {
+        g[i] = h[i] + (g[i - 1] >> 26);
+        g[i - 1] &= MASK;
+    }
+    let underflow = g[4] < (1 << 26);
+    g[4] = g[4].wrapping_sub(1 << 26);
+    if !underflow {
+        h = g;
+    }
+
+    // h mod 2^128, plus s
+    let h0 = h[0] | (h[1] << 26);
+    let h1 = (h[1] >> 6) | (h[2] << 20);
+    let h2 = (h[2] >> 12) | (h[3] << 14);
+    let h3 = (h[3] >> 18) | (h[4] << 8);
+    let mut tag = [0u8; POLY1305_TAG_LEN];
+    let mut carry: u64 = 0;
+    for (i, limb) in [h0, h1, h2, h3].iter().enumerate() {
+        let sum = (limb & 0xffffffff) + le32(key, 16 + i * 4) + carry;
+        tag[i * 4..i * 4 + 4].copy_from_slice(&(sum as u32).to_le_bytes());
+        carry = sum >> 32;
+    }
+    tag
+}
+
+/// internal function to build the poly1305 input of the chacha20-poly1305 AEAD
+fn aead_mac_data(aad: &[u8], ciphertext: &[u8]) -> Vec<u8> {
+    let pad16 = |len: usize| vec![0u8; (16 - len % 16) % 16];
+    let mut data = aad.to_vec();
+    data.append(&mut pad16(aad.len()));
+    data.extend_from_slice(ciphertext);
+    data.append(&mut pad16(ciphertext.len()));
+    data.extend_from_slice(&(aad.len() as u64).to_le_bytes());
+    data.extend_from_slice(&(ciphertext.len() as u64).to_le_bytes());
+    data
+}
+
+/// internal function to derive the one-time poly1305 key from block 0 of the keystream
+fn aead_poly_key(key: &[u8; CHACHA_KEY_LEN], nonce: &[u8; CHACHA_NONCE_LEN]) -> [u8; 32] {
+    let block = chacha20_block(key, 0, nonce);
+    block[..32].try_into().expect("32 bytes")
+}
+
+/// Encrypts and authenticates data with chacha20-poly1305 (RFC 8439), **NOT SECURE**
+/// # Arguments
+/// * `key` - 256 bit key
+/// * `nonce` - 96 bit nonce, must never be reused with the same key
+/// * `aad` - additional data that is authenticated but not encrypted
+/// * `plaintext` - data to encrypt
+/// # Return
+/// * ciphertext followed by the 16 byte tag
+pub fn chacha20poly1305_seal(
+    key: &[u8; CHACHA_KEY_LEN],
+    nonce: &[u8; CHACHA_NONCE_LEN],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Vec<u8> {
+    let mut sealed = chacha20_xor(key, 1, nonce, plaintext);
+    let tag = poly1305(&aead_poly_key(key, nonce), &aead_mac_data(aad, &sealed));
+    sealed.extend_from_slice(&tag);
+    sealed
+}
+
+/// Verifies and decrypts data sealed with `chacha20poly1305_seal`
+/// # Arguments
+/// * `key` - 256 bit key
+/// * `nonce` - 96 bit nonce used to seal
+/// * `aad` - additional data used to seal
+/// * `sealed` - ciphertext followed by the 16 byte tag
+/// # Return
+/// * plaintext, or `None` if the tag does not match
+pub fn chacha20poly1305_open(
+    key: &[u8; CHACHA_KEY_LEN],
+    nonce: &[u8; CHACHA_NONCE_LEN],
+    aad: &[u8],
+    sealed: &[u8],
+) -> Option<Vec<u8>> {
+    let split = sealed.len().checked_sub(POLY1305_TAG_LEN)?;
+    let (ciphertext, tag) = sealed.split_at(split);
+    let expected = poly1305(&aead_poly_key(key, nonce), &aead_mac_data(aad, ciphertext));
+    if !constant_time_eq(&expected, tag) {
+        return None;
+    }
+    Some(chacha20_xor(key, 1, nonce, ciphertext))
+}
+
+/// This function checks a password against a stored hash string
+/// # Arguments
+/// * `entry_string` - stored hash, as produced by `hash_password` or `onion_hash_password`
+/// * `password` - raw password
+/// # Return
+/// * whether or not the password matches, locked or malformed hashes never match
+pub fn verify_password(entry_string: &str, password: &str) -> bool {
+    if entry_string == LOCKED_HASH || validate_hash(entry_string).is_err() {
+        return false;
+    }
+    let fields: Vec<&str> = entry_string.split('$').collect();
+    let (cost, salt) = (fields[2].parse().unwrap_or_default(), fields[3]);
+
+    let hash = match fields[1] {
+        // the inner hash is the one that was stored before the migration
+        ONION_HASH_VERSION => {
+            let inner_cost = fields[5].parse().unwrap_or_default();
+            match wrap_hash(&hash_password(password, fields[6], inner_cost), salt, cost) {
+                Ok(hash) => hash,
+                Err(_) => return false,
+            }
+        }
+        _ => hash_password(password, salt, cost),
+    };
+
+    // compare
+    constant_time_eq(hash.as_bytes(), entry_string.as_bytes())
+}
+
 /// This function uses a custum implementation of sha-256 to hash a password
 /// **DO NOT USE** for real world applications, it is definitely not secure
 /// # Arguments
@@ -386,6 +3210,103 @@ pub fn hash_password(password: &str, salt: &str, cost: usize) -> String {
     formatted_password
 }
 
+/// This function hashes a password with the onion hash, as if it had been migrated
+/// # Arguments
+/// * `password` - plaintext password to be hashed
+/// * `salt` - base64 encoding of random salt, used by both layers
+/// * `cost` - computational cost of both layers (2^n iterations), at most `MAX_ONION_COST` for
+///   the outer one
+/// # Return
+/// * Hashed password, see `wrap_hash`
+pub fn onion_hash_password(password: &str, salt: &str, cost: usize) -> String {
+    let outer_cost = cost.min(MAX_ONION_COST);
+    wrap_hash(&hash_password(password, salt, cost), salt, outer_cost)
+        .expect("freshly computed hashes are well formed")
+}
+
+/// This function wraps a stored `HASH_VERSION` hash in pbkdf2-sha256, without the password
+/// **DO NOT USE** for real world applications, it is definitely not secure
+/// # Arguments
+/// * `hash` - hash string as produced by `hash_password`
+/// * `salt` - base64 encoding of random salt for the outer layer
+/// * `cost` - computational cost of the outer layer (2^n pbkdf2 iterations), at most
+///   `MAX_ONION_COST`
+/// # Return
+/// * Hashed password: `$AA$BB$CCCCCCCCCCCCCCCCCCCCCC$DDDDDDDDDDDDDDDDDDDDDDDDDDDDDDD$EE$FFFFFFFFFFFFFFFFFFFFFF$`
+///     * `A` - the type and version of the hashing algorithm (pbkdf2-sha256iter-1)
+///     * `B` - the 'cost' of the outer pbkdf2 layer (2^n iterations)
+///     * `C` - the base64 encoded 'salt' of the outer layer
+///     * `D` - the base64 encoded pbkdf2 of the inner digest
+///     * `E` - the 'cost' of the inner sha256iter-1 hash
+///     * `F` - the base64 encoded 'salt' of the inner hash
+/// * `UnknownVersion` if the hash is not a `HASH_VERSION` hash, `Cost` if the cost is too high
+pub fn wrap_hash(hash: &str, salt: &str, cost: usize) -> Result<String, HashError> {
+    validate_hash(hash)?;
+    let fields: Vec<&str> = hash.split('$').collect();
+    if fields.len() < 2 || fields[1] != HASH_VERSION {
+        return Err(HashError::UnknownVersion(
+            fields.get(1).unwrap_or(&hash).to_string(),
+        ));
+    }
+    if cost > MAX_ONION_COST {
+        return Err(HashError::Cost(cost.to_string(), MAX_ONION_COST));
+    }
+    let inner = base64_decode(fields[4]).map_err(|_| HashError::Digest)?;
+    let outer_salt = base64_decode(salt).map_err(|_| HashError::Salt)?;
+    let digest = pbkdf2_sha256(&inner, &outer_salt, 1u32 << cost, SHA256_LEN);
+    Ok(format!(
+        "${}${}${}${}${}${}$",
+        ONION_HASH_VERSION,
+        cost,
+        salt,
+        base64_encode(&digest),
+        fields[2],
+        fields[3]
+    ))
+}
+
+/// This function checks that a stored hash string is well formed
+/// # Arguments
+/// * `hash` - hash string as produced by `hash_password`, or `LOCKED_HASH`
+/// # Return
+/// * the first problem found
+pub fn validate_hash(hash: &str) -> Result<(), HashError> {
+    if hash == LOCKED_HASH {
+        return Ok(());
+    }
+    let fields: Vec<&str> = hash.split('$').collect();
+    if fields.len() < 6 || !fields[0].is_empty() || !fields[fields.len() - 1].is_empty() {
+        return Err(HashError::Format);
+    }
+    let field_count = match fields[1] {
+        HASH_VERSION => 6,
+        ONION_HASH_VERSION => 8,
+        version => return Err(HashError::UnknownVersion(version.to_string())),
+    };
+    if fields.len() != field_count {
+        return Err(HashError::Format);
+    }
+
+    // the onion hash appends the cost and salt of the inner hash
+    let layers: &[(usize, usize, usize)] = match field_count {
+        8 => &[(2, 3, MAX_ONION_COST), (5, 6, MAX_HASH_COST)],
+        _ => &[(2, 3, MAX_HASH_COST)],
+    };
+    for &(cost, salt, max) in layers {
+        match fields[cost].parse::<usize>() {
+            Ok(n) if n <= max => {}
+            _ => return Err(HashError::Cost(fields[cost].to_string(), max)),
+        }
+        if base64_decode(fields[salt]).is_err() {
+            return Err(HashError::Salt);
+        }
+    }
+    match base64_decode(fields[4]) {
+        Ok(digest) if digest.len() == SHA256_LEN => Ok(()),
+        _ => Err(HashError::Digest),
+    }
+}
+
 /// This function creates random data to be used as a salt in a cryptographic hash
 /// # Arguments
 /// * `num_bytes` - usize number of bytes to create
@@ -403,7 +3324,172 @@ pub fn get_salt(num_bytes: Option<usize>) -> String {
             panic!("OsRng failed, {}", e);
         }
     }
-    return base64_encode(&salt);
+    base64_encode(&salt)
+}
+
+/// internal function to fill a buffer from the OS random number generator
+fn random_bytes(num_bytes: usize) -> std::io::Result<Vec<u8>> {
+    let mut bytes: Vec<u8> = vec![0u8; num_bytes];
+    OsRng
+        .try_fill_bytes(&mut bytes)
+        .map_err(std::io::Error::other)?;
+    Ok(bytes)
+}
+
+/// internal function to find the uid after the highest one in use, at least `FIRST_UID`
+/// uids freed by deleted accounts are not handed out again, like `useradd`, so once an
+/// account holds `u32::MAX` there is none left
+fn free_uid(records: &HashMap<String, UserRecord>) -> Result<u32, StoreError> {
+    records
+        .values()
+        .map(|u| u.uid.checked_add(1).ok_or(StoreError::NoFreeUid))
+        .try_fold(FIRST_UID, |next, uid| Ok(next.max(uid?)))
+}
+
+/// This function reads the key sealing the database and keying the audit log, generating one
+/// if there is none yet
+/// # Arguments
+/// * `key_path` - key file, created readable by the owner only
+/// # Return
+/// * the key
+pub fn read_seal_key(key_path: &Path) -> std::io::Result<Vec<u8>> {
+    read_or_create_key(key_path, SEAL_KEY_LEN, SHADOW_MODE)
+}
+
+/// This function reads a random key from a file, generating the file if there is none yet
+/// the key is written to a temporary file and linked into place, so processes racing to
+/// create it all end up with the one that got there first, never a half written one
+/// # Arguments
+/// * `key_path` - key file
+/// * `len` - length of a generated key, in bytes
+/// * `mode` - file mode of a generated key file
+/// # Return
+/// * the key
+pub fn read_or_create_key(key_path: &Path, len: usize, mode: u32) -> std::io::Result<Vec<u8>> {
+    match read(key_path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        result => return result,
+    }
+    let key = random_bytes(len)?;
+    let temp_name = format!(
+        "{}+{}",
+        key_path.file_name().unwrap_or_default().to_string_lossy(),
+        hex::encode(random_bytes(8)?)
+    );
+    let temp_path = key_path.with_file_name(temp_name);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(&temp_path)?;
+    let linked = file
+        .write_all(&key)
+        .and_then(|_| file.sync_all())
+        .and_then(|_| std::fs::hard_link(&temp_path, key_path));
+    let _ = remove_file(&temp_path);
+    match linked {
+        Ok(()) => Ok(key),
+        // another process created it first
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => read(key_path),
+        Err(e) => Err(e),
+    }
+}
+
+/// This function takes an advisory lock on a file, like `lckpwdf`
+/// # Arguments
+/// * `lock_path` - lock file, created if it does not exist
+/// * `exclusive` - whether to lock for writing rather than reading
+/// # Return
+/// * the open lock file, the lock is released when it is dropped
+pub fn lock_file(lock_path: &Path, exclusive: bool) -> std::io::Result<File> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(SHADOW_MODE)
+        .open(lock_path)?;
+
+    // retry for a while, like lckpwdf does, instead of blocking forever
+    let start = Instant::now();
+    loop {
+        let result = match exclusive {
+            true => file.try_lock(),
+            false => file.try_lock_shared(),
+        };
+        match result {
+            Ok(()) => return Ok(file),
+            Err(TryLockError::WouldBlock) if start.elapsed() < LOCK_TIMEOUT => sleep(LOCK_RETRY),
+            Err(TryLockError::WouldBlock) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("'{}' is held by another process", lock_path.display()),
+                ));
+            }
+            Err(TryLockError::Error(e)) => return Err(e),
+        }
+    }
+}
+
+/// This function replaces a file without ever leaving it partially written
+/// the new contents go to `<path>+`, are flushed to disk and renamed over the original,
+/// and the previous version is kept as `<path>-` like shadow-utils does
+/// # Arguments
+/// * `path` - file to replace
+/// * `contents` - new contents of the file
+/// * `mode` - unix permissions of the new file
+/// # Return
+/// * io error of the first step that failed, the original file is untouched in that case
+pub fn atomic_write(path: &str, contents: &[u8], mode: u32) -> std::io::Result<()> {
+    atomic_write_with(path, mode, |file| file.write_all(contents))
+}
+
+/// Same as `atomic_write`, but the temporary file is filled by a callback
+/// # Arguments
+/// * `path` - file to replace
+/// * `mode` - unix permissions of the new file
+/// * `fill` - writes the new contents, an error aborts the replacement
+/// # Return
+/// * io error of the first step that failed, the original file is untouched in that case
+pub fn atomic_write_with<F>(path: &str, mode: u32, fill: F) -> std::io::Result<()>
+where
+    F: FnOnce(&mut File) -> std::io::Result<()>,
+{
+    let temp_path = format!("{}{}", path, TEMP_SUFFIX);
+    let backup_path = format!("{}{}", path, BACKUP_SUFFIX);
+
+    // write and flush the temporary file, cleaning up after any failure
+    let result = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(&temp_path)
+        .and_then(|mut file| {
+            set_permissions(&temp_path, Permissions::from_mode(mode))?;
+            fill(&mut file)?;
+            file.sync_all()
+        });
+    if let Err(e) = result {
+        let _ = remove_file(&temp_path);
+        return Err(e);
+    }
+
+    // keep the previous version around, linking where possible so nothing is rewritten
+    if Path::new(path).exists() {
+        let _ = remove_file(&backup_path);
+        if hard_link(path, &backup_path).is_err() {
+            copy(path, &backup_path)?;
+        }
+    }
+
+    // swap in the new file and make the rename itself durable
+    rename(&temp_path, path)?;
+    let parent = match Path::new(path).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(parent)?.sync_all()
 }
 
 /// function that securly gets a password input from the user
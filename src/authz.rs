@@ -0,0 +1,542 @@
+// src/authz.rs
+//! This module contains capability based authorization:
+//! * named capabilities, one per kind of privileged action, required by shell commands
+//! * roles bundling capabilities, assigned to users, `%groups` or `ALL`
+//! * a role table shared by every shell using the same data directory
+//! * root, and commands run through sudo, hold every capability
+
+// ==================== IMPORTS ====================
+
+use crate::auth_utils::*;
+use crate::sudo::Principal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::read;
+use std::path::Path;
+
+// ==================== CONSTANTS ====================
+
+/// file mode of the role table, readable by the owner only like the group file, since it is
+/// not covered by the database seal
+const ROLES_MODE: u32 = 0o600;
+
+/// role given every capability in a new role table
+pub const ADMIN_ROLE: &str = "admin";
+
+/// role allowed to read the audit log in a new role table, assigned to `%adm`
+pub const AUDITOR_ROLE: &str = "auditor";
+
+/// group the auditor role is assigned to in a new role table, like `adm` on Debian
+pub const ADM_GROUP: &str = "adm";
+
+// ==================== STRUCTURES ====================
+
+/// kinds of privileged action
+/// # Variants
+/// * `UserCreate` - create accounts
+/// * `UserDelete` - delete accounts
+/// * `UserRenameOther` - rename other accounts
+/// * `UserPasswdOther` - change other accounts' passwords
+/// * `UserChfnOther` - change other accounts' user information
+/// * `GroupCreate` - create groups
+/// * `GroupDelete` - delete groups
+/// * `GroupMembers` - add and remove group members
+/// * `DbReset` - delete every account
+/// * `DbExport` - export the database, hashes included
+/// * `DbImport` - import accounts
+/// * `DbRekey` - re-encrypt the database
+/// * `DbReseal` - accept outside changes to the database
+/// * `DbCheck` - check and repair the database
+/// * `DbRehash` - wrap every hash
+/// * `AuditRead` - read and verify the audit log
+/// * `SessionOther` - list and revoke other users' sessions
+/// * `TokenRevokeOther` - revoke other users' tokens
+/// * `RoleManage` - create, delete and assign roles
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Capability {
+    #[serde(rename = "user.create")]
+    UserCreate,
+    #[serde(rename = "user.delete")]
+    UserDelete,
+    #[serde(rename = "user.rename.other")]
+    UserRenameOther,
+    #[serde(rename = "user.passwd.other")]
+    UserPasswdOther,
+    #[serde(rename = "user.chfn.other")]
+    UserChfnOther,
+    #[serde(rename = "group.create")]
+    GroupCreate,
+    #[serde(rename = "group.delete")]
+    GroupDelete,
+    #[serde(rename = "group.members")]
+    GroupMembers,
+    #[serde(rename = "db.reset")]
+    DbReset,
+    #[serde(rename = "db.export")]
+    DbExport,
+    #[serde(rename = "db.import")]
+    DbImport,
+    #[serde(rename = "db.rekey")]
+    DbRekey,
+    #[serde(rename = "db.reseal")]
+    DbReseal,
+    #[serde(rename = "db.check")]
+    DbCheck,
+    #[serde(rename = "db.rehash")]
+    DbRehash,
+    #[serde(rename = "audit.read")]
+    AuditRead,
+    #[serde(rename = "session.other")]
+    SessionOther,
+    #[serde(rename = "token.revoke.other")]
+    TokenRevokeOther,
+    #[serde(rename = "role.manage")]
+    RoleManage,
+}
+
+/// Methods for the enum
+impl Capability {
+    /// every capability, in the order they are listed
+    pub const ALL: [Capability; 19] = [
+        Capability::UserCreate,
+        Capability::UserDelete,
+        Capability::UserRenameOther,
+        Capability::UserPasswdOther,
+        Capability::UserChfnOther,
+        Capability::GroupCreate,
+        Capability::GroupDelete,
+        Capability::GroupMembers,
+        Capability::DbReset,
+        Capability::DbExport,
+        Capability::DbImport,
+        Capability::DbRekey,
+        Capability::DbReseal,
+        Capability::DbCheck,
+        Capability::DbRehash,
+        Capability::AuditRead,
+        Capability::SessionOther,
+        Capability::TokenRevokeOther,
+        Capability::RoleManage,
+    ];
+
+    /// the capability's name, e.g. `user.create`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Capability::UserCreate => "user.create",
+            Capability::UserDelete => "user.delete",
+            Capability::UserRenameOther => "user.rename.other",
+            Capability::UserPasswdOther => "user.passwd.other",
+            Capability::UserChfnOther => "user.chfn.other",
+            Capability::GroupCreate => "group.create",
+            Capability::GroupDelete => "group.delete",
+            Capability::GroupMembers => "group.members",
+            Capability::DbReset => "db.reset",
+            Capability::DbExport => "db.export",
+            Capability::DbImport => "db.import",
+            Capability::DbRekey => "db.rekey",
+            Capability::DbReseal => "db.reseal",
+            Capability::DbCheck => "db.check",
+            Capability::DbRehash => "db.rehash",
+            Capability::AuditRead => "audit.read",
+            Capability::SessionOther => "session.other",
+            Capability::TokenRevokeOther => "token.revoke.other",
+            Capability::RoleManage => "role.manage",
+        }
+    }
+
+    /// look a capability up by name
+    /// # Arguments
+    /// * `name` - capability name, e.g. `user.create`
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.name() == name)
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// who may run a shell command
+/// # Variants
+/// * `Public` - anyone, logged in or not
+/// * `LoggedIn` - any logged in user
+/// * `Requires` - users holding the capability
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Public,
+    LoggedIn,
+    Requires(Capability),
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Access::Public => write!(f, "anyone"),
+            Access::LoggedIn => write!(f, "logged in users"),
+            Access::Requires(capability) => write!(f, "{}", capability),
+        }
+    }
+}
+
+/// a role given to a user, group or everyone
+/// # Fields
+/// * `role` - role name
+/// * `principal` - `user`, `%group` or `ALL`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Assignment {
+    pub role: String,
+    pub principal: Principal,
+}
+
+/// contents of the role table
+/// # Fields
+/// * `roles` - capabilities of each role, by name
+/// * `assignments` - who holds which role
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoleSet {
+    pub roles: BTreeMap<String, Vec<Capability>>,
+    pub assignments: Vec<Assignment>,
+}
+
+/// a new role table has an admin role holding everything, and an auditor role for `%adm`
+impl Default for RoleSet {
+    fn default() -> Self {
+        RoleSet {
+            roles: BTreeMap::from([
+                (ADMIN_ROLE.to_string(), Capability::ALL.to_vec()),
+                (AUDITOR_ROLE.to_string(), vec![Capability::AuditRead]),
+            ]),
+            assignments: vec![Assignment {
+                role: AUDITOR_ROLE.to_string(),
+                principal: Principal::Group(ADM_GROUP.to_string()),
+            }],
+        }
+    }
+}
+
+/// errors returned when changing the role table
+/// # Variants
+/// * `Io` - the table could not be locked, read or written
+/// * `Corrupt` - the table is not a role set
+/// * `NotFound` - the named role does not exist
+/// * `AlreadyExists` - the named role already exists
+/// * `InvalidName` - the name can't be used for a role
+/// * `NotAssigned` - the role is not assigned to the principal
+/// * `AlreadyAssigned` - the role is already assigned to the principal
+#[derive(Debug)]
+pub enum RoleError {
+    Io(std::io::Error),
+    Corrupt(String),
+    NotFound(String),
+    AlreadyExists(String),
+    InvalidName(String),
+    NotAssigned(String, Principal),
+    AlreadyAssigned(String, Principal),
+}
+
+impl fmt::Display for RoleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RoleError::Io(e) => write!(f, "{}", e),
+            RoleError::Corrupt(reason) => write!(f, "role table is corrupt: {}", reason),
+            RoleError::NotFound(role) => write!(f, "role {} not found", role),
+            RoleError::AlreadyExists(role) => write!(f, "role {} already exists", role),
+            RoleError::InvalidName(role) => write!(
+                f,
+                "role name {:?} may not be empty, start with '-' or '%', or contain ',', whitespace or control characters",
+                role
+            ),
+            RoleError::NotAssigned(role, principal) => {
+                write!(f, "role {} is not assigned to {}", role, principal)
+            }
+            RoleError::AlreadyAssigned(role, principal) => {
+                write!(f, "role {} is already assigned to {}", role, principal)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RoleError {}
+
+impl From<std::io::Error> for RoleError {
+    fn from(e: std::io::Error) -> Self {
+        RoleError::Io(e)
+    }
+}
+
+/// roles of every shell using the same data directory, kept in a JSON file
+/// # Fields
+/// * `set` - roles and assignments as last read or written
+/// * `location` - filepath of the table, locked with `<table>.lock` while changing it
+/// * `fingerprint` - digest of the file as last read or written, to notice outside changes
+/// # Methods
+/// * `new` - loads the table, or the default roles if there is none yet
+/// * `refresh` - reloads the table if another process changed it
+/// * `roles` - gets the roles and assignments
+/// * `capabilities_of` - collects the capabilities a user holds
+/// * `create`, `delete` - adds and removes roles
+/// * `assign`, `unassign` - gives and takes away roles
+/// * `rename_user`, `remove_principal` - follows account and group renames and deletions
+pub struct RoleTable {
+    set: RoleSet,
+    location: String,
+    fingerprint: Option<Vec<u8>>,
+}
+
+/// Methods for the struct
+impl RoleTable {
+    /// load a role table, a missing table holds the default roles until it is first changed
+    /// # Arguments
+    /// * `filepath` - filepath of the table
+    pub fn new(filepath: &str) -> Self {
+        let mut table = RoleTable {
+            set: RoleSet::default(),
+            location: filepath.to_string(),
+            fingerprint: None,
+        };
+        if let Err(e) = table.load() {
+            eprintln!(
+                "\x1b[91mFailed to load roles from '{}', only root holds any capability. Error: {}\x1b[0m",
+                filepath, e
+            );
+        }
+        table
+    }
+
+    /// internal method to read the table, leaving no roles at all if it is unreadable
+    fn load(&mut self) -> Result<(), RoleError> {
+        let data = match read(&self.location) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                self.set = RoleSet::default();
+                self.fingerprint = None;
+                return Ok(());
+            }
+            Err(e) => {
+                self.set = RoleSet {
+                    roles: BTreeMap::new(),
+                    assignments: Vec::new(),
+                };
+                return Err(e.into());
+            }
+        };
+        self.fingerprint = Some(sha256(data.clone()));
+        match serde_json::from_slice(&data) {
+            Ok(set) => {
+                self.set = set;
+                Ok(())
+            }
+            Err(e) => {
+                self.set = RoleSet {
+                    roles: BTreeMap::new(),
+                    assignments: Vec::new(),
+                };
+                Err(RoleError::Corrupt(e.to_string()))
+            }
+        }
+    }
+
+    /// reload the table if another process changed it since it was last read
+    /// # Return
+    /// * whether or not the table was reloaded
+    pub fn refresh(&mut self) -> bool {
+        let current = read(&self.location).ok().map(sha256);
+        if current == self.fingerprint {
+            return false;
+        }
+        if let Err(e) = self.load() {
+            eprintln!(
+                "\x1b[91mFailed to reload roles from '{}'. Error: {}\x1b[0m",
+                self.location, e
+            );
+        }
+        true
+    }
+
+    /// internal method to change the table under its lock and write it back
+    /// # Arguments
+    /// * `change` - edits the roles, an error leaves the table unchanged
+    fn update<T, F>(&mut self, change: F) -> Result<T, RoleError>
+    where
+        F: FnOnce(&mut RoleSet) -> Result<T, RoleError>,
+    {
+        let lock_path = format!("{}.lock", self.location);
+        let _lock = lock_file(Path::new(&lock_path), true)?;
+        self.refresh();
+        let mut set = self.set.clone();
+        let result = change(&mut set)?;
+        let data =
+            serde_json::to_vec_pretty(&set).map_err(|e| RoleError::Corrupt(e.to_string()))?;
+        atomic_write(&self.location, &data, ROLES_MODE)?;
+        self.fingerprint = Some(sha256(data));
+        self.set = set;
+        Ok(result)
+    }
+
+    /// roles and who holds them
+    pub fn roles(&self) -> &RoleSet {
+        &self.set
+    }
+
+    /// collect the capabilities a user holds through their roles
+    /// # Arguments
+    /// * `user` - account name
+    /// * `in_group` - whether the user is in a group
+    /// # Return
+    /// * capabilities in the order of `Capability::ALL`, without duplicates
+    pub fn capabilities_of<G: Fn(&str) -> bool>(&self, user: &str, in_group: G) -> Vec<Capability> {
+        let mut capabilities: Vec<Capability> = self
+            .set
+            .assignments
+            .iter()
+            .filter(|a| a.principal.matches(user, &in_group))
+            .filter_map(|a| self.set.roles.get(&a.role))
+            .flatten()
+            .copied()
+            .collect();
+        capabilities.sort();
+        capabilities.dedup();
+        capabilities
+    }
+
+    /// create a role
+    /// # Arguments
+    /// * `role` - role name
+    /// * `capabilities` - capabilities the role bundles
+    pub fn create(&mut self, role: &str, capabilities: &[Capability]) -> Result<(), RoleError> {
+        if role.is_empty()
+            || role.starts_with(['-', '%'])
+            || role
+                .chars()
+                .any(|c| c == ',' || c.is_whitespace() || c.is_control())
+        {
+            return Err(RoleError::InvalidName(role.to_string()));
+        }
+        self.update(|set| {
+            if set.roles.contains_key(role) {
+                return Err(RoleError::AlreadyExists(role.to_string()));
+            }
+            set.roles.insert(role.to_string(), capabilities.to_vec());
+            Ok(())
+        })
+    }
+
+    /// delete a role and every assignment of it
+    /// # Arguments
+    /// * `role` - role name
+    pub fn delete(&mut self, role: &str) -> Result<(), RoleError> {
+        self.update(|set| {
+            set.roles
+                .remove(role)
+                .ok_or_else(|| RoleError::NotFound(role.to_string()))?;
+            set.assignments.retain(|a| a.role != role);
+            Ok(())
+        })
+    }
+
+    /// give a role to a user, group or everyone
+    /// # Arguments
+    /// * `role` - role name
+    /// * `principal` - who to give it to
+    pub fn assign(&mut self, role: &str, principal: &Principal) -> Result<(), RoleError> {
+        self.update(|set| {
+            if !set.roles.contains_key(role) {
+                return Err(RoleError::NotFound(role.to_string()));
+            }
+            if set
+                .assignments
+                .iter()
+                .any(|a| a.role == role && a.principal == *principal)
+            {
+                return Err(RoleError::AlreadyAssigned(
+                    role.to_string(),
+                    principal.clone(),
+                ));
+            }
+            set.assignments.push(Assignment {
+                role: role.to_string(),
+                principal: principal.clone(),
+            });
+            Ok(())
+        })
+    }
+
+    /// take a role away from a user, group or everyone
+    /// # Arguments
+    /// * `role` - role name
+    /// * `principal` - who to take it from
+    pub fn unassign(&mut self, role: &str, principal: &Principal) -> Result<(), RoleError> {
+        self.update(|set| {
+            let before = set.assignments.len();
+            set.assignments
+                .retain(|a| !(a.role == role && a.principal == *principal));
+            match set.assignments.len() == before {
+                true => Err(RoleError::NotAssigned(role.to_string(), principal.clone())),
+                false => Ok(()),
+            }
+        })
+    }
+
+    /// carry a renamed account's assignments over to its new name
+    /// # Arguments
+    /// * `old` - previous account name
+    /// * `new` - new account name
+    /// # Return
+    /// * number of assignments changed
+    pub fn rename_user(&mut self, old: &str, new: &str) -> Result<usize, RoleError> {
+        let old = Principal::User(old.to_string());
+        if !self.set.assignments.iter().any(|a| a.principal == old) {
+            return Ok(0);
+        }
+        self.update(|set| {
+            let mut count: usize = 0;
+            for assignment in set.assignments.iter_mut().filter(|a| a.principal == old) {
+                assignment.principal = Principal::User(new.to_string());
+                count += 1;
+            }
+            Ok(count)
+        })
+    }
+
+    /// remove every assignment to a deleted account or group
+    /// # Arguments
+    /// * `principal` - the account or group
+    /// # Return
+    /// * number of assignments removed
+    pub fn remove_principal(&mut self, principal: &Principal) -> Result<usize, RoleError> {
+        if !self
+            .set
+            .assignments
+            .iter()
+            .any(|a| a.principal == *principal)
+        {
+            return Ok(0);
+        }
+        self.update(|set| {
+            let before = set.assignments.len();
+            set.assignments.retain(|a| a.principal != *principal);
+            Ok(before - set.assignments.len())
+        })
+    }
+}
+
+// ==================== FUNCTIONS ====================
+
+/// This function lists capabilities for display
+/// # Arguments
+/// * `capabilities` - capabilities to list
+/// # Return
+/// * comma separated names, `none` if there are none
+pub fn format_capabilities(capabilities: &[Capability]) -> String {
+    match capabilities.is_empty() {
+        true => "none".to_string(),
+        false => capabilities
+            .iter()
+            .map(|c| c.name())
+            .collect::<Vec<&str>>()
+            .join(","),
+    }
+}
@@ -0,0 +1,404 @@
+// src/bignum.rs
+//! This module contains a small arbitrary-precision unsigned integer, enough for SRP:
+//! * conversion from and to big-endian bytes and hex
+//! * addition, subtraction, multiplication and division with remainder (Knuth's algorithm D)
+//! * modular exponentiation by square-and-multiply
+//! * a Miller-Rabin probable prime test, to check group constants
+//!
+//! Nothing here runs in constant time, so like the custom sha-256 it is **NOT SECURE** against
+//! an attacker who can time it
+
+// ==================== IMPORTS ====================
+
+use std::cmp::Ordering;
+use std::fmt;
+
+// ==================== CONSTANTS ====================
+
+/// bits in a limb
+const LIMB_BITS: u32 = 32;
+
+// ==================== STRUCTURES ====================
+
+/// unsigned integer of any size
+/// # Fields
+/// * `limbs` - 32 bit digits, least significant first, without leading zero limbs
+/// # Methods
+/// * `zero`, `from_u32`, `from_bytes_be`, `from_hex` - make a number
+/// * `to_bytes_be`, `to_bytes_be_padded` - write it as big-endian bytes
+/// * `is_zero`, `is_even`, `bits` - inspect it
+/// * `add`, `sub`, `mul`, `div_rem`, `rem` - arithmetic, `sub` panics below zero
+/// * `mod_pow` - modular exponentiation
+/// * `is_probable_prime` - Miller-Rabin test
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BigUint {
+    limbs: Vec<u32>,
+}
+
+/// Methods for the struct
+impl BigUint {
+    /// internal constructor dropping leading zero limbs
+    fn from_limbs(mut limbs: Vec<u32>) -> Self {
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        BigUint { limbs }
+    }
+
+    /// zero
+    pub fn zero() -> Self {
+        BigUint { limbs: Vec::new() }
+    }
+
+    /// a small number
+    /// # Arguments
+    /// * `n` - its value
+    pub fn from_u32(n: u32) -> Self {
+        Self::from_limbs(vec![n])
+    }
+
+    /// read a big-endian byte string
+    /// # Arguments
+    /// * `bytes` - most significant byte first, leading zeros allowed
+    pub fn from_bytes_be(bytes: &[u8]) -> Self {
+        let limbs = bytes
+            .rchunks(4)
+            .map(|chunk| chunk.iter().fold(0u32, |limb, &b| (limb << 8) | b as u32))
+            .collect();
+        Self::from_limbs(limbs)
+    }
+
+    /// read a hex string, ignoring whitespace
+    /// # Arguments
+    /// * `hex` - hex digits, most significant first
+    /// # Return
+    /// * the number, `None` if a character is not a hex digit
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let digits: Vec<u32> = hex
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| c.to_digit(16))
+            .collect::<Option<_>>()?;
+        let limbs = digits
+            .rchunks(8)
+            .map(|chunk| chunk.iter().fold(0u32, |limb, &d| (limb << 4) | d))
+            .collect();
+        Some(Self::from_limbs(limbs))
+    }
+
+    /// write the number as big-endian bytes, without leading zeros, zero as no bytes
+    pub fn to_bytes_be(&self) -> Vec<u8> {
+        let bytes: Vec<u8> = self
+            .limbs
+            .iter()
+            .rev()
+            .flat_map(|limb| limb.to_be_bytes())
+            .collect();
+        let leading = bytes.iter().take_while(|&&b| b == 0).count();
+        bytes[leading..].to_vec()
+    }
+
+    /// write the number as big-endian bytes, left padded with zeros
+    /// # Arguments
+    /// * `len` - length to pad to, no shorter than the number
+    pub fn to_bytes_be_padded(&self, len: usize) -> Vec<u8> {
+        let bytes = self.to_bytes_be();
+        let mut padded = vec![0u8; len.saturating_sub(bytes.len())];
+        padded.extend(bytes);
+        padded
+    }
+
+    /// whether the number is zero
+    pub fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    /// whether the number is even
+    pub fn is_even(&self) -> bool {
+        self.limbs.first().is_none_or(|limb| limb & 1 == 0)
+    }
+
+    /// number of significant bits
+    pub fn bits(&self) -> usize {
+        match self.limbs.last() {
+            Some(top) => self.limbs.len() * LIMB_BITS as usize - top.leading_zeros() as usize,
+            None => 0,
+        }
+    }
+
+    /// internal method to test a bit, counting from the least significant
+    fn bit(&self, index: usize) -> bool {
+        self.limbs
+            .get(index / LIMB_BITS as usize)
+            .is_some_and(|limb| limb >> (index % LIMB_BITS as usize) & 1 == 1)
+    }
+
+    /// sum of two numbers
+    pub fn add(&self, other: &Self) -> Self {
+        let (long, short) = match self.limbs.len() >= other.limbs.len() {
+            true => (&self.limbs, &other.limbs),
+            false => (&other.limbs, &self.limbs),
+        };
+        let mut limbs = Vec::with_capacity(long.len() + 1);
+        let mut carry = 0u64;
+        for (i, &limb) in long.iter().enumerate() {
+            let sum = limb as u64 + *short.get(i).unwrap_or(&0) as u64 + carry;
+            limbs.push(sum as u32);
+            carry = sum >> LIMB_BITS;
+        }
+        limbs.push(carry as u32);
+        Self::from_limbs(limbs)
+    }
+
+    /// difference of two numbers
+    /// # Return
+    /// * `self - other`, `None` if it would be negative
+    pub fn checked_sub(&self, other: &Self) -> Option<Self> {
+        if *self < *other {
+            return None;
+        }
+        let mut limbs = Vec::with_capacity(self.limbs.len());
+        let mut borrow = 0i64;
+        for (i, &limb) in self.limbs.iter().enumerate() {
+            let diff = limb as i64 - *other.limbs.get(i).unwrap_or(&0) as i64 - borrow;
+            limbs.push(diff as u32);
+            borrow = (diff < 0) as i64;
+        }
+        Some(Self::from_limbs(limbs))
+    }
+
+    /// difference of two numbers, panicking if it would be negative
+    pub fn sub(&self, other: &Self) -> Self {
+        self.checked_sub(other)
+            .expect("BigUint subtraction would be negative")
+    }
+
+    /// product of two numbers
+    pub fn mul(&self, other: &Self) -> Self {
+        if self.is_zero() || other.is_zero() {
+            return Self::zero();
+        }
+        let mut limbs = vec![0u32; self.limbs.len() + other.limbs.len()];
+        for (i, &a) in self.limbs.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &b) in other.limbs.iter().enumerate() {
+                let t = a as u64 * b as u64 + limbs[i + j] as u64 + carry;
+                limbs[i + j] = t as u32;
+                carry = t >> LIMB_BITS;
+            }
+            limbs[i + other.limbs.len()] = carry as u32;
+        }
+        Self::from_limbs(limbs)
+    }
+
+    /// quotient and remainder, by Knuth's algorithm D (TAOCP vol. 2, 4.3.1)
+    /// # Arguments
+    /// * `divisor` - number to divide by, panics if zero
+    pub fn div_rem(&self, divisor: &Self) -> (Self, Self) {
+        assert!(!divisor.is_zero(), "BigUint division by zero");
+        if *self < *divisor {
+            return (Self::zero(), self.clone());
+        }
+        if divisor.limbs.len() == 1 {
+            let d = divisor.limbs[0] as u64;
+            let mut quotient = vec![0u32; self.limbs.len()];
+            let mut rem = 0u64;
+            for (i, &limb) in self.limbs.iter().enumerate().rev() {
+                let cur = (rem << LIMB_BITS) | limb as u64;
+                quotient[i] = (cur / d) as u32;
+                rem = cur % d;
+            }
+            return (Self::from_limbs(quotient), Self::from_u32(rem as u32));
+        }
+
+        // normalize so the divisor's top limb has its high bit set
+        let shift = divisor.limbs.last().map_or(0, |top| top.leading_zeros());
+        let v = shl_limbs(&divisor.limbs, shift);
+        let mut u = shl_limbs(&self.limbs, shift);
+        u.resize(self.limbs.len() + 1, 0);
+        let n = v.len();
+        let m = self.limbs.len() - n;
+        let base = 1u64 << LIMB_BITS;
+        let mut quotient = vec![0u32; m + 1];
+
+        for j in (0..=m).rev() {
+            // estimate the quotient digit from the top two limbs, then correct it
+            let top = ((u[j + n] as u64) << LIMB_BITS) | u[j + n - 1] as u64;
+            let mut qhat = top / v[n - 1] as u64;
+            let mut rhat = top % v[n - 1] as u64;
+            while qhat >= base
+                || qhat * v[n - 2] as u64 > ((rhat << LIMB_BITS) | u[j + n - 2] as u64)
+            {
+                qhat -= 1;
+                rhat += v[n - 1] as u64;
+                if rhat >= base {
+                    break;
+                }
+            }
+
+            // multiply and subtract
+            let mut borrow = 0i64;
+            for i in 0..n {
+                let p = qhat * v[i] as u64;
+                let t = u[i + j] as i64 - borrow - (p & 0xFFFF_FFFF) as i64;
+                u[i + j] = t as u32;
+                borrow = (p >> LIMB_BITS) as i64 - (t >> LIMB_BITS);
+            }
+            let t = u[j + n] as i64 - borrow;
+            u[j + n] = t as u32;
+
+            // the estimate was one too large, add the divisor back
+            if t < 0 {
+                qhat -= 1;
+                let mut carry = 0u64;
+                for i in 0..n {
+                    let sum = u[i + j] as u64 + v[i] as u64 + carry;
+                    u[i + j] = sum as u32;
+                    carry = sum >> LIMB_BITS;
+                }
+                u[j + n] = u[j + n].wrapping_add(carry as u32);
+            }
+            quotient[j] = qhat as u32;
+        }
+
+        u.truncate(n);
+        (
+            Self::from_limbs(quotient),
+            Self::from_limbs(shr_limbs(&u, shift)),
+        )
+    }
+
+    /// remainder of a division
+    /// # Arguments
+    /// * `modulus` - number to divide by, panics if zero
+    pub fn rem(&self, modulus: &Self) -> Self {
+        self.div_rem(modulus).1
+    }
+
+    /// `self^exponent mod modulus`, by left-to-right square-and-multiply
+    /// # Arguments
+    /// * `exponent` - power to raise to
+    /// * `modulus` - modulus, panics if zero
+    pub fn mod_pow(&self, exponent: &Self, modulus: &Self) -> Self {
+        let base = self.rem(modulus);
+        let mut result = Self::from_u32(1).rem(modulus);
+        for index in (0..exponent.bits()).rev() {
+            result = result.mul(&result).rem(modulus);
+            if exponent.bit(index) {
+                result = result.mul(&base).rem(modulus);
+            }
+        }
+        result
+    }
+
+    /// Miller-Rabin probable prime test with fixed small prime bases
+    /// # Arguments
+    /// * `rounds` - number of bases to try, up to 12
+    /// # Return
+    /// * `false` if the number is certainly composite, `true` if it is probably prime
+    pub fn is_probable_prime(&self, rounds: usize) -> bool {
+        const BASES: [u32; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+        let one = Self::from_u32(1);
+        let two = Self::from_u32(2);
+        if *self < two {
+            return false;
+        }
+        for &p in &BASES {
+            let p = Self::from_u32(p);
+            if *self == p {
+                return true;
+            }
+            if self.rem(&p).is_zero() {
+                return false;
+            }
+        }
+
+        // self - 1 = d * 2^s with d odd
+        let minus_one = self.sub(&one);
+        let mut d = minus_one.clone();
+        let mut s = 0;
+        while d.is_even() {
+            d = d.div_rem(&two).0;
+            s += 1;
+        }
+        'bases: for &a in BASES.iter().take(rounds) {
+            let mut x = Self::from_u32(a).mod_pow(&d, self);
+            if x == one || x == minus_one {
+                continue;
+            }
+            for _ in 1..s {
+                x = x.mul(&x).rem(self);
+                if x == minus_one {
+                    continue 'bases;
+                }
+            }
+            return false;
+        }
+        true
+    }
+}
+
+impl Ord for BigUint {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.limbs
+            .len()
+            .cmp(&other.limbs.len())
+            .then_with(|| self.limbs.iter().rev().cmp(other.limbs.iter().rev()))
+    }
+}
+
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigUint {
+    /// lowercase hex, `0` for zero
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.limbs.split_last() {
+            Some((top, rest)) => {
+                write!(f, "{:x}", top)?;
+                for limb in rest.iter().rev() {
+                    write!(f, "{:08x}", limb)?;
+                }
+                Ok(())
+            }
+            None => write!(f, "0"),
+        }
+    }
+}
+
+// ==================== FUNCTIONS ====================
+
+/// internal function to shift limbs left by less than a limb, growing them by a limb if needed
+fn shl_limbs(limbs: &[u32], shift: u32) -> Vec<u32> {
+    if shift == 0 {
+        return limbs.to_vec();
+    }
+    let mut shifted = Vec::with_capacity(limbs.len() + 1);
+    let mut carry = 0u32;
+    for &limb in limbs {
+        shifted.push((limb << shift) | carry);
+        carry = limb >> (LIMB_BITS - shift);
+    }
+    if carry != 0 {
+        shifted.push(carry);
+    }
+    shifted
+}
+
+/// internal function to shift limbs right by less than a limb
+fn shr_limbs(limbs: &[u32], shift: u32) -> Vec<u32> {
+    if shift == 0 {
+        return limbs.to_vec();
+    }
+    (0..limbs.len())
+        .map(|i| {
+            let high = limbs
+                .get(i + 1)
+                .map_or(0, |next| next << (LIMB_BITS - shift));
+            (limbs[i] >> shift) | high
+        })
+        .collect()
+}
@@ -0,0 +1,276 @@
+// src/bin/credplayd.rs
+//! Authentication daemon serving the credential database to other local services:
+//! * listens on `daemon.listen`, a TCP address or a Unix socket
+//! * answers connections on a pool of `daemon.max_connections` workers, each keeping its
+//!   environment open between connections, and turns away connections beyond that
+//! * closes connections idle for longer than `session.idle_timeout`, or `DEF_READ_TIMEOUT`
+//!   when sessions never idle out, and those that stop reading their replies for as long
+//! * tells local clients apart by the uid owning their socket, for the throttle of failed
+//!   `AUTH` requests
+//! * creates a Unix socket readable by its owner only from the start
+
+// ==================== IMPORTS ====================
+
+use credential_playground::auth_utils::*;
+use credential_playground::config::*;
+use credential_playground::daemon::*;
+use credential_playground::shell::Environment;
+use std::io::{BufReader, Read, Write};
+use std::net::TcpListener;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::net::UnixListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// ==================== STRUCTURES ====================
+
+/// a connection waiting for a worker: its read side, its write side, the client's address and
+/// who the client is to the throttle
+type Job = (Box<dyn Read + Send>, Box<dyn Write + Send>, String, String);
+
+/// the workers connections are handed to
+/// # Fields
+/// * `jobs` - connections waiting for a worker
+/// * `busy` - connections admitted and not yet closed
+/// * `limit` - connections served at once, as many as there are workers
+/// # Methods
+/// * `start` - starts the workers
+/// * `dispatch` - hands a connection to a worker, or turns it away when all are busy
+struct Pool {
+    jobs: Sender<Job>,
+    busy: Arc<AtomicUsize>,
+    limit: usize,
+}
+
+/// Methods for the struct
+impl Pool {
+    /// start `daemon.max_connections` workers, sharing one throttle of failed `AUTH` requests
+    /// # Arguments
+    /// * `config` - effective configuration
+    fn start(config: Arc<Config>) -> Self {
+        let (jobs, receiver) = channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let busy = Arc::new(AtomicUsize::new(0));
+        let throttle = Arc::new(AuthThrottle::default());
+        for _ in 0..config.max_connections {
+            let (config, receiver) = (config.clone(), receiver.clone());
+            let (busy, throttle) = (busy.clone(), throttle.clone());
+            std::thread::spawn(move || work(&config, &receiver, &busy, &throttle));
+        }
+        Pool {
+            jobs,
+            busy,
+            limit: config.max_connections,
+        }
+    }
+
+    /// hand a connection to a worker, or turn it away when every worker is busy
+    /// # Arguments
+    /// * `reader` - the connection, read side
+    /// * `writer` - the connection, write side
+    /// * `peer` - address of the client
+    /// * `client` - who the client is to the throttle
+    fn dispatch<R, W>(&self, reader: R, mut writer: W, peer: String, client: String)
+    where
+        R: Read + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        // only this thread admits connections, so the count can't grow past the check
+        if self.busy.load(Ordering::SeqCst) >= self.limit {
+            let _ = Reply::Err("too many connections".to_string()).write_to(&mut writer);
+            return;
+        }
+        self.busy.fetch_add(1, Ordering::SeqCst);
+        let _ = self
+            .jobs
+            .send((Box::new(reader), Box::new(writer), peer, client));
+    }
+}
+
+// ==================== HELPERS ====================
+
+/// function a worker runs, serving one connection after another with the same environment
+/// # Arguments
+/// * `config` - effective configuration
+/// * `receiver` - connections waiting for a worker
+/// * `busy` - connections admitted and not yet closed
+/// * `throttle` - failed `AUTH` requests of every connection
+fn work(
+    config: &Config,
+    receiver: &Mutex<Receiver<Job>>,
+    busy: &AtomicUsize,
+    throttle: &Arc<AuthThrottle>,
+) {
+    let mut env: Option<Environment> = None;
+    loop {
+        let job = receiver.lock().unwrap_or_else(|e| e.into_inner()).recv();
+        let (reader, mut writer, peer, client) = match job {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        // the store is opened on the first connection, and again only if that failed
+        let opened = match env.take() {
+            Some(env) => Ok(env),
+            None => open_environment(config),
+        };
+        match opened {
+            Ok(opened) => {
+                let mut connection = Connection::new(opened, &peer, &client, throttle.clone());
+                if let Err(e) = connection.serve(BufReader::new(reader), writer) {
+                    eprintln!(
+                        "\x1b[91mConnection from {} failed. Error: {}\x1b[0m",
+                        peer, e
+                    );
+                }
+                env = Some(connection.into_env());
+            }
+            Err(e) => {
+                let _ = Reply::Err(e.to_string()).write_to(&mut writer);
+            }
+        }
+        busy.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// function to read the timeout of connections from the configuration
+/// # Arguments
+/// * `config` - effective configuration
+/// # Return
+/// * the read and write timeout, `DEF_READ_TIMEOUT` seconds when sessions never idle out, so
+///   an idle connection, or one that doesn't read its replies, can't hold a worker forever
+fn connection_timeout(config: &Config) -> Duration {
+    match config.idle_timeout {
+        0 => Duration::from_secs(DEF_READ_TIMEOUT),
+        secs => Duration::from_secs(secs),
+    }
+}
+
+// ==================== MAINLOOP ====================
+
+/// This is the accept loop of the daemon
+fn main() {
+    // load configuration
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let config = match Config::load(&args) {
+        Ok(config) => config,
+        Err(ConfigError::Help) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("\x1b[91m{}\x1b[0m", e);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&config.dir)
+    {
+        eprintln!(
+            "\x1b[91mFailed to create '{}'. Error: {}\x1b[0m",
+            config.dir, e
+        );
+    }
+
+    // the shell creates root, with a password typed at its prompt
+    match open_environment(&config) {
+        Ok(env) if env.database.contains(ROOT) => {}
+        Ok(_) => {
+            eprintln!(
+                "\x1b[91mNo root account in '{}', start credential_playground once to create it\x1b[0m",
+                config.dir
+            );
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("\x1b[91mFailed to open the database. Error: {}\x1b[0m", e);
+            std::process::exit(1);
+        }
+    }
+
+    let timeout = Some(connection_timeout(&config));
+    let listen = ListenAddr::parse(&config.listen).expect("daemon.listen is validated on load");
+    let config = Arc::new(config);
+    match listen {
+        ListenAddr::Tcp(addr) => {
+            let listener = match TcpListener::bind(&addr) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    eprintln!("\x1b[91mFailed to listen on {}. Error: {}\x1b[0m", addr, e);
+                    std::process::exit(1);
+                }
+            };
+            // port 0 picks a free port, so report the one bound
+            match listener.local_addr() {
+                Ok(local) => println!("credplayd listening on {}", local),
+                Err(_) => println!("credplayd listening on {}", addr),
+            }
+            let pool = Pool::start(config);
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        eprintln!("\x1b[91mFailed to accept. Error: {}\x1b[0m", e);
+                        continue;
+                    }
+                };
+                let (peer, client) = match (stream.local_addr(), stream.peer_addr()) {
+                    (Ok(local), Ok(peer)) => (peer.to_string(), tcp_client(&local, &peer)),
+                    _ => ("unknown".to_string(), "unknown".to_string()),
+                };
+                let _ = stream.set_read_timeout(timeout);
+                let _ = stream.set_write_timeout(timeout);
+                match stream.try_clone() {
+                    Ok(reader) => pool.dispatch(reader, stream, peer, client),
+                    Err(e) => eprintln!("\x1b[91mFailed to accept. Error: {}\x1b[0m", e),
+                }
+            }
+        }
+        ListenAddr::Unix(path) => {
+            // a socket left behind by an earlier run would make bind fail
+            if std::fs::symlink_metadata(&path)
+                .is_ok_and(|meta| std::os::unix::fs::FileTypeExt::is_socket(&meta.file_type()))
+            {
+                let _ = std::fs::remove_file(&path);
+            }
+            // create the socket with its mode, a chmod after bind would leave a moment in which
+            // anyone may connect; no other thread runs yet to be surprised by the umask
+            // SAFETY: umask only swaps the process's file mode mask
+            let umask = unsafe { libc::umask(0o777 & !SOCKET_MODE) };
+            let bound = UnixListener::bind(&path);
+            // SAFETY: as above, restoring the mask the process started with
+            unsafe { libc::umask(umask) };
+            let listener = match bound {
+                Ok(listener) => listener,
+                Err(e) => {
+                    eprintln!(
+                        "\x1b[91mFailed to listen on '{}'. Error: {}\x1b[0m",
+                        path, e
+                    );
+                    std::process::exit(1);
+                }
+            };
+            println!("credplayd listening on {}{}", UNIX_PREFIX, path);
+            let pool = Pool::start(config);
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        eprintln!("\x1b[91mFailed to accept. Error: {}\x1b[0m", e);
+                        continue;
+                    }
+                };
+                let (peer, client) = unix_client(&stream, &path);
+                let _ = stream.set_read_timeout(timeout);
+                let _ = stream.set_write_timeout(timeout);
+                match stream.try_clone() {
+                    Ok(reader) => pool.dispatch(reader, stream, peer, client),
+                    Err(e) => eprintln!("\x1b[91mFailed to accept. Error: {}\x1b[0m", e),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,797 @@
+// src/config.rs
+//! This module contains the runtime configuration of the shell:
+//! * built-in defaults
+//! * system and per-user TOML config files
+//! * environment variable overrides
+//! * command-line flags
+//! * where each effective value came from
+
+// ==================== IMPORTS ====================
+
+use crate::auth_utils::*;
+use crate::daemon::{DEF_LISTEN, DEF_MAX_CONNECTIONS, ListenAddr};
+use crate::scram::{DEF_SCRAM_ITERATIONS, MIN_SCRAM_ITERATIONS};
+use crate::session::{DEF_IDLE_ACTION, DEF_IDLE_TIMEOUT, DEF_SESSION_LIFETIME, IDLE_ACTIONS};
+use crate::srp::{DEF_SRP_GROUP, SRP_GROUPS};
+use crate::sudo::DEF_SUDO_TIMEOUT;
+use crate::token::DEF_TOKEN_LIFETIME;
+use crate::username::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::read_to_string;
+
+// ==================== CONSTANTS ====================
+
+/// system-wide config file, read first
+pub const SYSTEM_CONFIG: &str = "/etc/credplay/config.toml";
+
+/// per-user config file, relative to `$XDG_CONFIG_HOME` or `~/.config`
+pub const USER_CONFIG: &str = "credplay/config.toml";
+
+/// data directory, relative to `$XDG_DATA_HOME` or `~/.local/share`
+pub const DATA_DIR: &str = "credplay";
+
+/// where the database was kept before `storage.dir` existed, the crate's source tree
+pub const LEGACY_DATA_DIR: &str = env!("CARGO_MANIFEST_DIR");
+
+/// files of a database kept in `LEGACY_DATA_DIR`
+const LEGACY_DATA_FILES: &[&str] = &["passwd", "shadow", "passwd.kv", "passwd.enc", "audit.log"];
+
+/// every setting, with the environment variable and command-line flag that override it
+/// # Columns
+/// * key - `section.name`, as written in config files
+/// * environment variable
+/// * command-line flag taking the value, if any
+pub const SETTINGS: &[(&str, &str, Option<&str>)] = &[
+    ("storage.dir", "CREDPLAY_DIR", Some("--dir")),
+    ("storage.backend", "CREDPLAY_BACKEND", Some("--backend")),
+    ("storage.passwd", "CREDPLAY_PASSWD", None),
+    ("storage.shadow", "CREDPLAY_SHADOW", None),
+    ("storage.group", "CREDPLAY_GROUP", None),
+    ("storage.kv", "CREDPLAY_KV", None),
+    ("storage.encrypted", "CREDPLAY_ENCRYPTED", None),
+    ("storage.keyfile", "CREDPLAY_KEYFILE", Some("--keyfile")),
+    ("storage.audit", "CREDPLAY_AUDIT", None),
+    ("storage.sessions", "CREDPLAY_SESSIONS", None),
+    ("storage.token_key", "CREDPLAY_TOKEN_KEY", None),
+    ("storage.revoked_tokens", "CREDPLAY_REVOKED_TOKENS", None),
+    ("storage.sudoers", "CREDPLAY_SUDOERS", None),
+    ("storage.roles", "CREDPLAY_ROLES", None),
+    ("storage.policy", "CREDPLAY_POLICY", None),
+    ("storage.verifiers", "CREDPLAY_VERIFIERS", None),
+    ("prompt.icon", "CREDPLAY_PROMPT", None),
+    ("prompt.color", "CREDPLAY_COLOR", None),
+    ("prompt.user_color", "CREDPLAY_USER_COLOR", None),
+    ("prompt.root_color", "CREDPLAY_ROOT_COLOR", None),
+    ("session.idle_timeout", "CREDPLAY_IDLE_TIMEOUT", None),
+    ("session.lifetime", "CREDPLAY_SESSION_LIFETIME", None),
+    ("session.idle_action", "CREDPLAY_IDLE_ACTION", None),
+    ("token.lifetime", "CREDPLAY_TOKEN_LIFETIME", None),
+    ("token.issue_on_login", "CREDPLAY_TOKEN_ON_LOGIN", None),
+    ("sudo.timeout", "CREDPLAY_SUDO_TIMEOUT", None),
+    ("authz.utc_offset", "CREDPLAY_UTC_OFFSET", None),
+    ("hash.algorithm", "CREDPLAY_HASH_ALGORITHM", None),
+    ("hash.cost", "CREDPLAY_HASH_COST", Some("--hash-cost")),
+    ("hash.scram_iterations", "CREDPLAY_SCRAM_ITERATIONS", None),
+    ("hash.srp_group", "CREDPLAY_SRP_GROUP", None),
+    ("daemon.listen", "CREDPLAY_LISTEN", Some("--listen")),
+    ("daemon.max_connections", "CREDPLAY_MAX_CONNECTIONS", None),
+    (
+        "policy.min_password_length",
+        "CREDPLAY_MIN_PASSWORD_LENGTH",
+        None,
+    ),
+    ("policy.username_pattern", "CREDPLAY_USERNAME_PATTERN", None),
+    (
+        "policy.username_min_length",
+        "CREDPLAY_USERNAME_MIN_LENGTH",
+        None,
+    ),
+    (
+        "policy.username_max_length",
+        "CREDPLAY_USERNAME_MAX_LENGTH",
+        None,
+    ),
+    ("policy.reserved_names", "CREDPLAY_RESERVED_NAMES", None),
+    (
+        "policy.case_insensitive_names",
+        "CREDPLAY_CASE_INSENSITIVE_NAMES",
+        None,
+    ),
+    (
+        "policy.reject_confusable_names",
+        "CREDPLAY_REJECT_CONFUSABLE_NAMES",
+        None,
+    ),
+];
+
+/// backends that `storage.backend` may name
+pub const BACKENDS: [&str; 4] = ["file", "memory", "kv", "encrypted"];
+
+/// command-line usage
+pub const USAGE: &str = "usage: credential_playground [options]
+  --config <file>       read another config file after the system and user ones
+  --set <key>=<value>   override any setting, e.g. --set prompt.icon='> '
+  --dir <dir>           directory holding the database files
+  --backend <name>      credential store backend: file, memory, kv or encrypted
+  --keyfile <file>      key file unlocking the encrypted backend
+  --hash-cost <n>       hash cost for new passwords, 2^n iterations
+  --listen <address>    address credplayd listens on, host:port or unix:<path>
+  --no-color            disable the colored prompt
+  --help                print this message";
+
+// ==================== STRUCTURES ====================
+
+/// where the effective value of a setting came from
+/// # Variants
+/// * `Default` - built-in default
+/// * `File` - a config file, by path
+/// * `Env` - an environment variable, by name
+/// * `Flag` - a command-line flag
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File(String),
+    Env(String),
+    Flag(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "file {}", path),
+            Source::Env(var) => write!(f, "env {}", var),
+            Source::Flag(flag) => write!(f, "flag {}", flag),
+        }
+    }
+}
+
+/// errors returned while loading the configuration
+/// # Variants
+/// * `Io` - a config file exists but could not be read
+/// * `Parse` - a config file is not valid TOML
+/// * `UnknownKey` - a setting that does not exist, and where it was found
+/// * `Invalid` - a value that does not fit its setting, and where it was found
+/// * `Usage` - the command line could not be parsed
+/// * `Help` - `--help` was given
+/// * `LegacyData` - the default data directory is empty but the old one holds a database
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(String, std::io::Error),
+    Parse(String, String),
+    UnknownKey(String, Source),
+    Invalid(String, String, Source),
+    Usage(String),
+    Help,
+    LegacyData(String, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "failed to read '{}': {}", path, e),
+            ConfigError::Parse(path, e) => write!(f, "failed to parse '{}': {}", path, e),
+            ConfigError::UnknownKey(key, source) => {
+                write!(f, "unknown setting '{}' ({})", key, source)
+            }
+            ConfigError::Invalid(key, reason, source) => {
+                write!(f, "invalid {} ({}): {}", key, source, reason)
+            }
+            ConfigError::Usage(reason) => write!(f, "{}\n{}", reason, USAGE),
+            ConfigError::Help => write!(f, "{}", USAGE),
+            ConfigError::LegacyData(old, new) => write!(
+                f,
+                "found a database in '{}', where it was kept before storage.dir existed, but none in '{}'. Move its files to '{}', or set storage.dir (--dir, CREDPLAY_DIR) to '{}'",
+                old, new, new, old
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// effective configuration of the shell
+/// # Fields
+/// * `dir` - directory holding the database files unless a path is set explicitly
+/// * `backend` - credential store backend, one of `BACKENDS`
+/// * `passwd` - passwd file of the file backend
+/// * `shadow` - shadow file of the file backend
+/// * `group` - group file, used with every backend
+/// * `kv` - log of the key-value backend
+/// * `encrypted` - database of the encrypted backend
+/// * `keyfile` - key file unlocking the encrypted backend, prompt if unset
+/// * `audit` - audit log
+/// * `sessions` - session table
+/// * `token_key` - key bearer tokens are signed with
+/// * `revoked_tokens` - revocation list of bearer tokens
+/// * `sudoers` - policy of who may run which commands with sudo
+/// * `roles` - role table
+/// * `policy` - authorization policy checked before every command
+/// * `verifiers` - verifier table of challenge-response logins
+/// * `prompt_icon` - text after the username in the prompt
+/// * `color` - whether or not the prompt is colored
+/// * `user_color` - ANSI color code of regular usernames in the prompt
+/// * `root_color` - ANSI color code of root in the prompt
+/// * `idle_timeout` - seconds without a command before a session expires, 0 for never
+/// * `session_lifetime` - seconds after login before a session expires, 0 for never
+/// * `idle_action` - what an idle timeout does to a session waiting at the prompt, one of
+///   `IDLE_ACTIONS`, only a terminal prompt is interrupted, with piped input a timed-out
+///   session is caught before the next command instead
+/// * `token_lifetime` - seconds a bearer token is valid for
+/// * `token_on_login` - whether or not a bearer token is printed on every login
+/// * `sudo_timeout` - seconds a sudo authentication is remembered for, 0 to always ask
+/// * `utc_offset` - minutes local time is ahead of UTC, for the policy's time of day
+/// * `hash_algorithm` - hash algorithm for new passwords
+/// * `hash_cost` - hash cost for new passwords, 2^n iterations
+/// * `scram_iterations` - PBKDF2 iterations of new SCRAM-SHA-256 credentials
+/// * `srp_group` - size in bits of the RFC 5054 group of new SRP verifiers, one of `SRP_GROUPS`
+/// * `listen` - address `credplayd` listens on, `host:port` or `unix:<path>`
+/// * `max_connections` - connections `credplayd` serves at once, more are turned away
+/// * `min_password_length` - shortest password accepted for new passwords
+/// * `username_policy` - rules new account names must follow
+/// * `sources` - where each setting came from, by key
+/// # Methods
+/// * `load` - layers defaults, config files, environment and flags
+/// * `set` - overrides one setting
+/// * `entries` - lists every effective value and its source
+/// * `passwd_path`, `shadow_path`, `group_path`, `kv_path`, `encrypted_path`, `audit_path`,
+///   `sessions_path`, `token_key_path`, `revoked_tokens_path`, `sudoers_path`,
+///   `roles_path`, `policy_path`, `verifiers_path` - resolved paths
+/// * `seal_key_path` - key file next to the passwd file
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub dir: String,
+    pub backend: String,
+    pub passwd: Option<String>,
+    pub shadow: Option<String>,
+    pub group: Option<String>,
+    pub kv: Option<String>,
+    pub encrypted: Option<String>,
+    pub keyfile: Option<String>,
+    pub audit: Option<String>,
+    pub sessions: Option<String>,
+    pub token_key: Option<String>,
+    pub revoked_tokens: Option<String>,
+    pub sudoers: Option<String>,
+    pub roles: Option<String>,
+    pub policy: Option<String>,
+    pub verifiers: Option<String>,
+    pub prompt_icon: String,
+    pub color: bool,
+    pub user_color: String,
+    pub root_color: String,
+    pub idle_timeout: u64,
+    pub session_lifetime: u64,
+    pub idle_action: String,
+    pub token_lifetime: u64,
+    pub token_on_login: bool,
+    pub sudo_timeout: u64,
+    pub utc_offset: i64,
+    pub hash_algorithm: String,
+    pub hash_cost: usize,
+    pub scram_iterations: u32,
+    pub srp_group: u32,
+    pub listen: String,
+    pub max_connections: usize,
+    pub min_password_length: usize,
+    pub username_policy: UsernamePolicy,
+    sources: HashMap<&'static str, Source>,
+}
+
+/// built-in defaults
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            dir: default_dir("XDG_DATA_HOME", ".local/share", DATA_DIR),
+            backend: "file".to_string(),
+            passwd: None,
+            shadow: None,
+            group: None,
+            kv: None,
+            encrypted: None,
+            keyfile: None,
+            audit: None,
+            sessions: None,
+            token_key: None,
+            revoked_tokens: None,
+            sudoers: None,
+            roles: None,
+            policy: None,
+            verifiers: None,
+            prompt_icon: "$ ".to_string(),
+            color: true,
+            user_color: "92".to_string(),
+            root_color: "91".to_string(),
+            idle_timeout: DEF_IDLE_TIMEOUT,
+            session_lifetime: DEF_SESSION_LIFETIME,
+            idle_action: DEF_IDLE_ACTION.to_string(),
+            token_lifetime: DEF_TOKEN_LIFETIME,
+            token_on_login: false,
+            sudo_timeout: DEF_SUDO_TIMEOUT,
+            utc_offset: 0,
+            hash_algorithm: HASH_VERSION.to_string(),
+            hash_cost: DEF_HASH_COST,
+            scram_iterations: DEF_SCRAM_ITERATIONS,
+            srp_group: DEF_SRP_GROUP,
+            listen: DEF_LISTEN.to_string(),
+            max_connections: DEF_MAX_CONNECTIONS,
+            min_password_length: 1,
+            username_policy: UsernamePolicy::default(),
+            sources: HashMap::new(),
+        }
+    }
+}
+
+/// Methods for the struct
+impl Config {
+    /// load the configuration, later layers overriding earlier ones:
+    /// defaults, system file, user file, `--config` files, environment, flags
+    /// # Arguments
+    /// * `args` - command-line arguments, without the program name
+    /// # Return
+    /// * the effective configuration, or the first problem found
+    pub fn load(args: &[String]) -> Result<Self, ConfigError> {
+        let (config_files, flags) = parse_args(args)?;
+        let mut config = Config::default();
+
+        // config files, a missing system or user file is fine
+        config.apply_file(SYSTEM_CONFIG, false)?;
+        let user_file = default_dir("XDG_CONFIG_HOME", ".config", USER_CONFIG);
+        config.apply_file(&user_file, false)?;
+        for path in config_files {
+            config.apply_file(&path, true)?;
+        }
+
+        // environment
+        for (key, var, _) in SETTINGS {
+            if let Ok(value) = std::env::var(var) {
+                config.set(key, &value, Source::Env(var.to_string()))?;
+            }
+        }
+
+        // flags
+        for (key, value, flag) in flags {
+            config.set(&key, &value, Source::Flag(flag))?;
+        }
+
+        // starting over in the new default directory would look like every account vanished
+        if config.source("storage.dir") == Source::Default {
+            config.check_legacy_data()?;
+        }
+        Ok(config)
+    }
+
+    /// internal method to refuse a default data directory without a database while the
+    /// directory used before `storage.dir` existed still holds one
+    fn check_legacy_data(&self) -> Result<(), ConfigError> {
+        let found = |dir: &str| {
+            LEGACY_DATA_FILES
+                .iter()
+                .any(|file| std::path::Path::new(dir).join(file).exists())
+        };
+        match self.dir != LEGACY_DATA_DIR && !found(&self.dir) && found(LEGACY_DATA_DIR) {
+            true => Err(ConfigError::LegacyData(
+                LEGACY_DATA_DIR.to_string(),
+                self.dir.clone(),
+            )),
+            false => Ok(()),
+        }
+    }
+
+    /// internal method to apply every setting in a config file
+    /// # Arguments
+    /// * `path` - config file
+    /// * `required` - whether a missing file is an error
+    fn apply_file(&mut self, path: &str, required: bool) -> Result<(), ConfigError> {
+        let contents = match read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => return Ok(()),
+            Err(e) => return Err(ConfigError::Io(path.to_string(), e)),
+        };
+        let table: toml::Table = toml::from_str(&contents)
+            .map_err(|e| ConfigError::Parse(path.to_string(), e.to_string()))?;
+        let source = Source::File(path.to_string());
+        for (section, values) in table {
+            let values = match values {
+                toml::Value::Table(values) => values,
+                _ => return Err(ConfigError::UnknownKey(section, source)),
+            };
+            for (name, value) in values {
+                let value = match value {
+                    toml::Value::String(s) => s,
+                    toml::Value::Array(items) => items
+                        .iter()
+                        .map(|item| match item {
+                            toml::Value::String(s) => s.clone(),
+                            other => other.to_string(),
+                        })
+                        .collect::<Vec<String>>()
+                        .join(","),
+                    other => other.to_string(),
+                };
+                self.set(&format!("{}.{}", section, name), &value, source.clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// override one setting
+    /// # Arguments
+    /// * `key` - `section.name` of the setting
+    /// * `value` - new value, parsed according to the setting
+    /// * `source` - where the value came from
+    /// # Return
+    /// * `UnknownKey` or `Invalid` if the value was not applied
+    pub fn set(&mut self, key: &str, value: &str, source: Source) -> Result<(), ConfigError> {
+        let invalid = |reason: &str| {
+            ConfigError::Invalid(key.to_string(), reason.to_string(), source.clone())
+        };
+        let path = || match value.is_empty() {
+            true => None,
+            false => Some(value.to_string()),
+        };
+        match key {
+            "storage.dir" => self.dir = value.to_string(),
+            "storage.backend" if BACKENDS.contains(&value) => self.backend = value.to_string(),
+            "storage.backend" => {
+                return Err(invalid(&format!("expected one of {}", BACKENDS.join(", "))));
+            }
+            "storage.passwd" => self.passwd = path(),
+            "storage.shadow" => self.shadow = path(),
+            "storage.group" => self.group = path(),
+            "storage.kv" => self.kv = path(),
+            "storage.encrypted" => self.encrypted = path(),
+            "storage.keyfile" => self.keyfile = path(),
+            "storage.audit" => self.audit = path(),
+            "storage.sessions" => self.sessions = path(),
+            "storage.token_key" => self.token_key = path(),
+            "storage.revoked_tokens" => self.revoked_tokens = path(),
+            "storage.sudoers" => self.sudoers = path(),
+            "storage.roles" => self.roles = path(),
+            "storage.policy" => self.policy = path(),
+            "storage.verifiers" => self.verifiers = path(),
+            "prompt.icon" => self.prompt_icon = value.to_string(),
+            "prompt.color" => {
+                self.color = value
+                    .parse()
+                    .map_err(|_| invalid("expected true or false"))?
+            }
+            "prompt.user_color" | "prompt.root_color" => {
+                if value.is_empty() || !value.chars().all(|c| c.is_ascii_digit() || c == ';') {
+                    return Err(invalid("expected an ANSI color code like 92 or 1;31"));
+                }
+                match key {
+                    "prompt.user_color" => self.user_color = value.to_string(),
+                    _ => self.root_color = value.to_string(),
+                }
+            }
+            "session.idle_timeout" => {
+                self.idle_timeout = value
+                    .parse()
+                    .map_err(|_| invalid("expected seconds, 0 for never"))?
+            }
+            "session.lifetime" => {
+                self.session_lifetime = value
+                    .parse()
+                    .map_err(|_| invalid("expected seconds, 0 for never"))?
+            }
+            "session.idle_action" if IDLE_ACTIONS.contains(&value) => {
+                self.idle_action = value.to_string()
+            }
+            "session.idle_action" => {
+                return Err(invalid(&format!(
+                    "expected one of {}",
+                    IDLE_ACTIONS.join(", ")
+                )));
+            }
+            "token.lifetime" => match value.parse() {
+                Ok(lifetime) if lifetime > 0 => self.token_lifetime = lifetime,
+                _ => return Err(invalid("expected seconds")),
+            },
+            "token.issue_on_login" => {
+                self.token_on_login = value
+                    .parse()
+                    .map_err(|_| invalid("expected true or false"))?
+            }
+            "sudo.timeout" => {
+                self.sudo_timeout = value
+                    .parse()
+                    .map_err(|_| invalid("expected seconds, 0 to always ask"))?
+            }
+            "authz.utc_offset" => match value.parse() {
+                Ok(offset) if (-12 * 60..=14 * 60).contains(&offset) => self.utc_offset = offset,
+                _ => return Err(invalid("expected minutes from -720 to 840")),
+            },
+            "hash.algorithm" if HASH_VERSIONS.contains(&value) => {
+                self.hash_algorithm = value.to_string()
+            }
+            "hash.algorithm" => {
+                return Err(invalid(&format!(
+                    "expected one of {}",
+                    HASH_VERSIONS.join(", ")
+                )));
+            }
+            "hash.cost" => match value.parse() {
+                Ok(cost) if cost <= MAX_HASH_COST => self.hash_cost = cost,
+                _ => return Err(invalid(&format!("expected 0 to {}", MAX_HASH_COST))),
+            },
+            "hash.scram_iterations" => match value.parse() {
+                Ok(iterations) if iterations >= MIN_SCRAM_ITERATIONS => {
+                    self.scram_iterations = iterations
+                }
+                _ => {
+                    return Err(invalid(&format!(
+                        "expected at least {}",
+                        MIN_SCRAM_ITERATIONS
+                    )));
+                }
+            },
+            "hash.srp_group" => match value.parse() {
+                Ok(bits) if SRP_GROUPS.contains(&bits) => self.srp_group = bits,
+                _ => {
+                    let groups: Vec<String> = SRP_GROUPS.iter().map(u32::to_string).collect();
+                    return Err(invalid(&format!("expected one of {}", groups.join(", "))));
+                }
+            },
+            "daemon.listen" => match ListenAddr::parse(value) {
+                Some(_) => self.listen = value.to_string(),
+                None => return Err(invalid("expected host:port or unix:<path>")),
+            },
+            "daemon.max_connections" => match value.parse() {
+                Ok(count) if count > 0 => self.max_connections = count,
+                _ => return Err(invalid("expected a whole number above 0")),
+            },
+            "policy.min_password_length" => {
+                self.min_password_length = value
+                    .parse()
+                    .map_err(|_| invalid("expected a whole number"))?
+            }
+            "policy.username_pattern" => {
+                self.username_policy.pattern =
+                    regex::Regex::new(value).map_err(|e| invalid(&e.to_string()))?
+            }
+            "policy.username_min_length" => {
+                self.username_policy.min_length = value
+                    .parse()
+                    .map_err(|_| invalid("expected a whole number"))?
+            }
+            "policy.username_max_length" => {
+                self.username_policy.max_length = value
+                    .parse()
+                    .map_err(|_| invalid("expected a whole number"))?
+            }
+            "policy.reserved_names" => {
+                self.username_policy.reserved = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            }
+            "policy.case_insensitive_names" => {
+                self.username_policy.case_insensitive = value
+                    .parse()
+                    .map_err(|_| invalid("expected true or false"))?
+            }
+            "policy.reject_confusable_names" => {
+                self.username_policy.reject_confusables = value
+                    .parse()
+                    .map_err(|_| invalid("expected true or false"))?
+            }
+            _ => return Err(ConfigError::UnknownKey(key.to_string(), source)),
+        }
+        let key = SETTINGS
+            .iter()
+            .find(|(k, _, _)| *k == key)
+            .map(|(k, _, _)| *k)
+            .expect("every matched key is in SETTINGS");
+        self.sources.insert(key, source);
+        Ok(())
+    }
+
+    /// where a setting came from
+    /// # Arguments
+    /// * `key` - `section.name` of the setting
+    /// # Return
+    /// * source of the effective value
+    pub fn source(&self, key: &str) -> Source {
+        self.sources.get(key).cloned().unwrap_or(Source::Default)
+    }
+
+    /// list every effective value, with paths resolved against `storage.dir`
+    /// # Return
+    /// * key, value and source of each setting, in `SETTINGS` order
+    pub fn entries(&self) -> Vec<(&'static str, String, Source)> {
+        SETTINGS
+            .iter()
+            .map(|(key, _, _)| {
+                let value = match *key {
+                    "storage.dir" => self.dir.clone(),
+                    "storage.backend" => self.backend.clone(),
+                    "storage.passwd" => self.passwd_path(),
+                    "storage.shadow" => self.shadow_path(),
+                    "storage.group" => self.group_path(),
+                    "storage.kv" => self.kv_path(),
+                    "storage.encrypted" => self.encrypted_path(),
+                    "storage.keyfile" => self.keyfile.clone().unwrap_or_default(),
+                    "storage.audit" => self.audit_path(),
+                    "storage.sessions" => self.sessions_path(),
+                    "storage.token_key" => self.token_key_path(),
+                    "storage.revoked_tokens" => self.revoked_tokens_path(),
+                    "storage.sudoers" => self.sudoers_path(),
+                    "storage.roles" => self.roles_path(),
+                    "storage.policy" => self.policy_path(),
+                    "storage.verifiers" => self.verifiers_path(),
+                    "prompt.icon" => format!("{:?}", self.prompt_icon),
+                    "prompt.color" => self.color.to_string(),
+                    "prompt.user_color" => self.user_color.clone(),
+                    "prompt.root_color" => self.root_color.clone(),
+                    "session.idle_timeout" => self.idle_timeout.to_string(),
+                    "session.lifetime" => self.session_lifetime.to_string(),
+                    "session.idle_action" => self.idle_action.clone(),
+                    "token.lifetime" => self.token_lifetime.to_string(),
+                    "token.issue_on_login" => self.token_on_login.to_string(),
+                    "sudo.timeout" => self.sudo_timeout.to_string(),
+                    "authz.utc_offset" => self.utc_offset.to_string(),
+                    "hash.algorithm" => self.hash_algorithm.clone(),
+                    "hash.cost" => self.hash_cost.to_string(),
+                    "hash.scram_iterations" => self.scram_iterations.to_string(),
+                    "hash.srp_group" => self.srp_group.to_string(),
+                    "daemon.listen" => self.listen.clone(),
+                    "daemon.max_connections" => self.max_connections.to_string(),
+                    "policy.min_password_length" => self.min_password_length.to_string(),
+                    "policy.username_pattern" => self.username_policy.pattern.to_string(),
+                    "policy.username_min_length" => self.username_policy.min_length.to_string(),
+                    "policy.username_max_length" => self.username_policy.max_length.to_string(),
+                    "policy.reserved_names" => self.username_policy.reserved.join(","),
+                    "policy.case_insensitive_names" => {
+                        self.username_policy.case_insensitive.to_string()
+                    }
+                    "policy.reject_confusable_names" => {
+                        self.username_policy.reject_confusables.to_string()
+                    }
+                    _ => unreachable!("every key in SETTINGS has a value"),
+                };
+                (*key, value, self.source(key))
+            })
+            .collect()
+    }
+
+    /// internal method to resolve a database path, defaulting to a file in `dir`
+    fn resolve(&self, path: &Option<String>, file_name: &str) -> String {
+        match path {
+            Some(path) => path.clone(),
+            None => format!("{}/{}", self.dir, file_name),
+        }
+    }
+
+    /// passwd file of the file backend
+    pub fn passwd_path(&self) -> String {
+        self.resolve(&self.passwd, "passwd")
+    }
+
+    /// shadow file of the file backend
+    pub fn shadow_path(&self) -> String {
+        self.resolve(&self.shadow, "shadow")
+    }
+
+    /// group file
+    pub fn group_path(&self) -> String {
+        self.resolve(&self.group, "group")
+    }
+
+    /// log of the key-value backend
+    pub fn kv_path(&self) -> String {
+        self.resolve(&self.kv, "passwd.kv")
+    }
+
+    /// database of the encrypted backend
+    pub fn encrypted_path(&self) -> String {
+        self.resolve(&self.encrypted, "passwd.enc")
+    }
+
+    /// audit log
+    pub fn audit_path(&self) -> String {
+        self.resolve(&self.audit, "audit.log")
+    }
+
+    /// session table
+    pub fn sessions_path(&self) -> String {
+        self.resolve(&self.sessions, "sessions.json")
+    }
+
+    /// key bearer tokens are signed with
+    pub fn token_key_path(&self) -> String {
+        self.resolve(&self.token_key, "token.key")
+    }
+
+    /// revocation list of bearer tokens
+    pub fn revoked_tokens_path(&self) -> String {
+        self.resolve(&self.revoked_tokens, "tokens.revoked")
+    }
+
+    /// policy of who may run which commands with sudo
+    pub fn sudoers_path(&self) -> String {
+        self.resolve(&self.sudoers, "sudoers")
+    }
+
+    /// role table
+    pub fn roles_path(&self) -> String {
+        self.resolve(&self.roles, "roles.json")
+    }
+
+    /// authorization policy checked before every command
+    pub fn policy_path(&self) -> String {
+        self.resolve(&self.policy, "authz.policy")
+    }
+
+    /// verifier table of challenge-response logins
+    pub fn verifiers_path(&self) -> String {
+        self.resolve(&self.verifiers, "verifiers.json")
+    }
+
+    /// key sealing the file backend and keying the audit log, always next to the passwd file
+    pub fn seal_key_path(&self) -> String {
+        let passwd = self.passwd_path();
+        match std::path::Path::new(&passwd).parent() {
+            Some(dir) => dir.join(SEAL_KEY_FILE).to_string_lossy().to_string(),
+            None => SEAL_KEY_FILE.to_string(),
+        }
+    }
+}
+
+// ==================== FUNCTIONS ====================
+
+/// internal function to find a directory under an XDG base directory
+/// # Arguments
+/// * `xdg_var` - XDG variable naming the base directory
+/// * `home_fallback` - base directory relative to `$HOME` if the variable is unset
+/// * `name` - path under the base directory
+/// # Return
+/// * the path, relative to the working directory if neither variable is set
+fn default_dir(xdg_var: &str, home_fallback: &str, name: &str) -> String {
+    match (std::env::var(xdg_var), std::env::var("HOME")) {
+        (Ok(base), _) if !base.is_empty() => format!("{}/{}", base, name),
+        (_, Ok(home)) if !home.is_empty() => format!("{}/{}/{}", home, home_fallback, name),
+        _ => name.to_string(),
+    }
+}
+
+/// internal function to split the command line into config files and setting overrides
+/// # Arguments
+/// * `args` - command-line arguments, without the program name
+/// # Return
+/// * `--config` files, and key, value and flag of every override, in order
+#[allow(clippy::type_complexity)]
+fn parse_args(
+    args: &[String],
+) -> Result<(Vec<String>, Vec<(String, String, String)>), ConfigError> {
+    let mut config_files: Vec<String> = Vec::new();
+    let mut flags: Vec<(String, String, String)> = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or(ConfigError::Usage(format!("{} needs a value", arg)))
+        };
+        match arg.as_str() {
+            "--help" | "-h" => return Err(ConfigError::Help),
+            "--config" => config_files.push(value()?),
+            "--no-color" => flags.push(("prompt.color".into(), "false".into(), arg.clone())),
+            "--set" => match value()?.split_once('=') {
+                Some((key, v)) => flags.push((key.to_string(), v.to_string(), arg.clone())),
+                None => return Err(ConfigError::Usage("--set expects <key>=<value>".into())),
+            },
+            _ => match SETTINGS
+                .iter()
+                .find(|(_, _, flag)| *flag == Some(arg.as_str()))
+            {
+                Some((key, _, _)) => flags.push((key.to_string(), value()?, arg.clone())),
+                None => return Err(ConfigError::Usage(format!("unknown option '{}'", arg))),
+            },
+        }
+    }
+    Ok((config_files, flags))
+}
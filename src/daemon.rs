@@ -0,0 +1,657 @@
+// src/daemon.rs
+//! This module contains the line protocol `credplayd` serves other services with:
+//! * one request per line, `AUTH`, `USERADD`, `PASSWD`, `LIST` or `QUIT`, answered with an
+//!   `OK` or `ERR` line
+//! * an `Environment` per connection, over the same files the shell uses, whose session
+//!   starts with `AUTH` and shows up in `session list`; a worker reuses one environment for
+//!   the connections it serves, so the store is opened once per worker
+//! * failed `AUTH` requests counted per client across connections, which is turned away for
+//!   a while after too many; a local client is known by the uid owning its socket, so local
+//!   services don't lock each other out, a remote one by its address
+//! * permission checks through the shell commands each request stands for, so roles and the
+//!   authorization policy apply to connections the same way
+//! * the TCP or Unix socket address the daemon listens on
+//!
+//! The protocol has no transport security, so it should only listen on loopback or a Unix
+//! socket
+
+// ==================== IMPORTS ====================
+
+use crate::audit::*;
+use crate::auth_utils::*;
+use crate::authz::*;
+use crate::config::*;
+use crate::session::*;
+use crate::shell::*;
+use crate::verifier::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+
+// ==================== CONSTANTS ====================
+
+/// address listened on by default, loopback only
+pub const DEF_LISTEN: &str = "127.0.0.1:7420";
+
+/// prefix of a Unix socket address
+pub const UNIX_PREFIX: &str = "unix:";
+
+/// line sent to every new connection
+pub const GREETING: &str = "OK credplayd ready";
+
+/// file mode of a Unix socket, connectable by its owner only
+pub const SOCKET_MODE: u32 = 0o600;
+
+/// longest request line accepted, in bytes
+const MAX_LINE_LEN: usize = 4096;
+
+/// failed `AUTH` requests before a connection is closed
+const MAX_AUTH_FAILURES: u32 = 3;
+
+/// connections served at once by default
+pub const DEF_MAX_CONNECTIONS: usize = 64;
+
+/// seconds a connection may sit idle when sessions never idle out
+pub const DEF_READ_TIMEOUT: u64 = 300;
+
+/// failed `AUTH` requests from one client, over all its connections, before it is turned away
+const MAX_PEER_AUTH_FAILURES: usize = 10;
+
+/// seconds a failed `AUTH` request counts against its client
+const AUTH_FAILURE_WINDOW: u64 = 60;
+
+/// tables of the kernel's TCP sockets, with the uid owning each
+const PROC_TCP_TABLES: [&str; 2] = ["/proc/net/tcp", "/proc/net/tcp6"];
+
+// ==================== STRUCTURES ====================
+
+/// address the daemon listens on
+/// # Variants
+/// * `Tcp` - `host:port`, as given
+/// * `Unix` - filepath of a Unix socket, written `unix:<path>`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListenAddr {
+    Tcp(String),
+    Unix(String),
+}
+
+/// answer to one request
+/// # Variants
+/// * `Ok` - `OK <text>`
+/// * `List` - `OK <count>` followed by one line per item
+/// * `Err` - `ERR <text>`
+/// * `Bye` - `OK bye`, after which the connection is closed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reply {
+    Ok(String),
+    List(Vec<String>),
+    Err(String),
+    Bye,
+}
+
+/// failed `AUTH` requests of every connection by client, so a client can't get around the
+/// per-connection limit by reconnecting
+/// # Fields
+/// * `failures` - times of the failures still counted, by client as `tcp_client` and
+///   `unix_client` name it
+/// # Methods
+/// * `allows` - whether a client may try to authenticate
+/// * `record` - counts a failure against a client
+#[derive(Debug, Default)]
+pub struct AuthThrottle {
+    failures: Mutex<HashMap<String, Vec<u64>>>,
+}
+
+/// one client connection and the session it authenticated
+/// # Fields
+/// * `env` - environment of the connection, its own store handle and session
+/// * `peer` - address of the client, for the audit log
+/// * `client` - who the client is to the throttle
+/// * `failures` - failed `AUTH` requests so far
+/// * `throttle` - failed `AUTH` requests of every connection
+/// # Methods
+/// * `new` - starts a connection over an environment
+/// * `handle` - answers one request line
+/// * `serve` - greets the client and answers requests until it quits or disconnects
+/// * `close` - ends the connection's session
+/// * `into_env` - hands the environment back, to serve the next connection with
+pub struct Connection {
+    env: Environment,
+    peer: String,
+    client: String,
+    failures: u32,
+    throttle: Arc<AuthThrottle>,
+}
+
+/// Methods for the enum
+impl ListenAddr {
+    /// read a listen address
+    /// # Arguments
+    /// * `text` - `host:port`, or `unix:<path>`
+    /// # Return
+    /// * the address, `None` if a TCP address has no port or a Unix one no path
+    pub fn parse(text: &str) -> Option<Self> {
+        if let Some(path) = text.strip_prefix(UNIX_PREFIX) {
+            return match path.is_empty() {
+                true => None,
+                false => Some(ListenAddr::Unix(path.to_string())),
+            };
+        }
+        match text.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                Some(ListenAddr::Tcp(text.to_string()))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "{}", addr),
+            ListenAddr::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path),
+        }
+    }
+}
+
+/// Methods for the enum
+impl Reply {
+    /// write the reply to the client
+    /// # Arguments
+    /// * `writer` - the connection
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match self {
+            Reply::Ok(text) => writeln!(writer, "OK {}", text)?,
+            Reply::List(items) => {
+                writeln!(writer, "OK {}", items.len())?;
+                for item in items {
+                    writeln!(writer, "{}", item)?;
+                }
+            }
+            Reply::Err(text) => writeln!(writer, "ERR {}", text)?,
+            Reply::Bye => writeln!(writer, "OK bye")?,
+        }
+        writer.flush()
+    }
+}
+
+/// Methods for the struct
+impl AuthThrottle {
+    /// whether a client may try to authenticate, fewer than `MAX_PEER_AUTH_FAILURES` of its
+    /// requests having failed within `AUTH_FAILURE_WINDOW` seconds
+    /// # Arguments
+    /// * `client` - who the client is
+    pub fn allows(&self, client: &str) -> bool {
+        let mut failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+        Self::expire(&mut failures);
+        failures
+            .get(client)
+            .is_none_or(|times| times.len() < MAX_PEER_AUTH_FAILURES)
+    }
+
+    /// count a failed `AUTH` request against a client
+    /// # Arguments
+    /// * `client` - who the client is
+    pub fn record(&self, client: &str) {
+        let mut failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+        Self::expire(&mut failures);
+        failures.entry(client.to_string()).or_default().push(now());
+    }
+
+    /// internal method to forget failures older than the window, and clients left without any
+    fn expire(failures: &mut HashMap<String, Vec<u64>>) {
+        let since = now().saturating_sub(AUTH_FAILURE_WINDOW);
+        failures.retain(|_, times| {
+            times.retain(|&time| time > since);
+            !times.is_empty()
+        });
+    }
+}
+
+/// Methods for the struct
+impl Connection {
+    /// start a connection over an environment, logged out
+    /// # Arguments
+    /// * `env` - environment to serve the connection with, from `open_environment` or the
+    ///   `into_env` of a connection before it
+    /// * `peer` - address of the client
+    /// * `client` - who the client is to the throttle
+    /// * `throttle` - failed `AUTH` requests of every connection
+    pub fn new(env: Environment, peer: &str, client: &str, throttle: Arc<AuthThrottle>) -> Self {
+        Connection {
+            env,
+            peer: peer.to_string(),
+            client: client.to_string(),
+            failures: 0,
+            throttle,
+        }
+    }
+
+    /// hand the environment back once the connection is closed
+    pub fn into_env(self) -> Environment {
+        self.env
+    }
+
+    /// greet the client and answer its requests until it quits, disconnects, stays idle past
+    /// the read timeout or fails to authenticate too often
+    /// # Arguments
+    /// * `reader` - requests from the client
+    /// * `writer` - replies to the client
+    pub fn serve<R: BufRead, W: Write>(&mut self, mut reader: R, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{}", GREETING)?;
+        writer.flush()?;
+        let result = loop {
+            let mut line = String::new();
+            let read = reader
+                .by_ref()
+                .take(MAX_LINE_LEN as u64 + 1)
+                .read_line(&mut line);
+            let (reply, close) = match read {
+                Ok(0) => break Ok(()),
+                Ok(_) if line.len() > MAX_LINE_LEN => {
+                    // skip the rest of the line, closing with unread input would reset the
+                    // connection before the client reads the reply
+                    if !line.ends_with('\n')
+                        && let Err(e) = reader.skip_until(b'\n')
+                    {
+                        break Err(e);
+                    }
+                    (Reply::Err("line too long".to_string()), false)
+                }
+                Ok(_) => {
+                    let reply = self.handle(line.trim_end_matches(['\r', '\n']));
+                    let close = reply == Reply::Bye || self.failures >= MAX_AUTH_FAILURES;
+                    (reply, close)
+                }
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    (Reply::Err("idle timeout".to_string()), true)
+                }
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                    (Reply::Err("requests must be UTF-8".to_string()), false)
+                }
+                Err(e) => break Err(e),
+            };
+            if let Err(e) = reply.write_to(&mut writer) {
+                break Err(e);
+            }
+            if close {
+                break Ok(());
+            }
+        };
+        self.close();
+        result
+    }
+
+    /// answer one request line
+    /// # Arguments
+    /// * `line` - the request, without its line ending
+    pub fn handle(&mut self, line: &str) -> Reply {
+        // pick up changes made by shells and other connections
+        self.env.database.refresh();
+        self.env.groups.refresh();
+        self.env.roles.refresh();
+        self.env.verifiers.refresh();
+
+        // the session may have timed out or been revoked since the last request
+        if let Err((user, e)) = self.env.validate_session() {
+            return Reply::Err(format!("session of {} ended: {}", user, e));
+        }
+
+        let (verb, args) = line.split_once(' ').unwrap_or((line, ""));
+        match verb.to_ascii_uppercase().as_str() {
+            "AUTH" => self.auth(args),
+            "USERADD" => self.useradd(args),
+            "PASSWD" => self.passwd(args),
+            "LIST" => self.list(args),
+            "QUIT" => Reply::Bye,
+            "" => Reply::Err("empty request".to_string()),
+            _ => Reply::Err(format!("unknown command {}", verb)),
+        }
+    }
+
+    /// end the connection's session
+    pub fn close(&mut self) {
+        if self.env.user() != NULLUSER {
+            let user = self.env.user().to_string();
+            self.audit("logout", &user, AuditOutcome::Success, "connection closed");
+        }
+        self.env.end_all_sessions();
+    }
+
+    /// internal method to audit an event of this connection
+    fn audit(&self, event: &str, target: &str, outcome: AuditOutcome, detail: &str) {
+        let detail = format!("{} (credplayd, {})", detail, self.peer);
+        audit(&self.env, event, target, outcome, &detail);
+    }
+
+    /// internal method to run a request as the shell command it stands for, if the policy and
+    /// the command's access allow it, holding the capability that access names on a policy
+    /// permit as `run_command` does
+    /// # Arguments
+    /// * `cmd` - shell command the request stands for
+    /// * `argv` - that command's arguments
+    /// * `action` - carries the request out
+    fn run<F>(&mut self, cmd: &Command, argv: &[&str], action: F) -> Reply
+    where
+        F: FnOnce(&mut Self) -> Reply,
+    {
+        let argv: Vec<String> = argv.iter().map(|arg| arg.to_string()).collect();
+        let ruling = self.env.authorize(cmd, &argv);
+        if !ruling.allows() {
+            let detail = match &ruling {
+                Ruling::Access(false) => format!("permission denied, requires {}", cmd.access),
+                other => format!("permission denied, {}", other),
+            };
+            self.audit(cmd.name, "", AuditOutcome::Denied, &detail);
+            return Reply::Err("permission denied".to_string());
+        }
+        if let (Ruling::Permitted(_), Some(capability)) = (&ruling, self.env.lent(cmd, &argv))
+            && let Some(session) = self.env.session.as_mut()
+        {
+            session.granted = Some(capability);
+        }
+        let reply = action(self);
+        if let Some(session) = self.env.session.as_mut() {
+            session.granted = None;
+        }
+        reply
+    }
+
+    /// internal method for `AUTH <username> <password>`, starting the connection's session
+    fn auth(&mut self, args: &str) -> Reply {
+        let (user, password) = match args.split_once(' ') {
+            Some((user, password)) if !user.is_empty() => (user.to_string(), password.to_string()),
+            _ => return Reply::Err("usage: AUTH <username> <password>".to_string()),
+        };
+        if self.env.user() != NULLUSER {
+            return Reply::Err(format!("already authenticated as {}", self.env.user()));
+        }
+        self.run(&LOGIN, &["login", &user], |conn| {
+            if !conn.throttle.allows(&conn.client) {
+                conn.failures += 1;
+                conn.audit(
+                    "login",
+                    &user,
+                    AuditOutcome::Denied,
+                    "too many failed logins from this client",
+                );
+                return Reply::Err("too many failed logins, try again later".to_string());
+            }
+            if !conn.env.database.authenticate(&user, &password) {
+                conn.failures += 1;
+                conn.throttle.record(&conn.client);
+                conn.audit(
+                    "login",
+                    &user,
+                    AuditOutcome::Failure,
+                    "authentication failed",
+                );
+                return Reply::Err("authentication failed".to_string());
+            }
+            upgrade_verifiers(&mut conn.env, &user, &password);
+            if !conn.env.start_session(&user, &[AuthFactor::Password]) {
+                return Reply::Err("failed to start a session".to_string());
+            }
+            conn.audit("login", &user, AuditOutcome::Success, "logged in");
+            Reply::Ok(format!("authenticated as {}", user))
+        })
+    }
+
+    /// internal method for `USERADD <username> <password>`, like `mkuser`
+    fn useradd(&mut self, args: &str) -> Reply {
+        let (user, password) = match args.split_once(' ') {
+            Some((user, password)) if !user.is_empty() => (user.to_string(), password.to_string()),
+            _ => return Reply::Err("usage: USERADD <username> <password>".to_string()),
+        };
+        self.run(&MKUSER, &["mkuser", &user], |conn| {
+            if conn.env.database.contains(&user) {
+                return Reply::Err(format!("account {} already exists", user));
+            }
+            let (hash, verifiers) = match hash_new_password(&conn.env.config, &user, &password) {
+                Ok(new) => new,
+                Err(reason) => return Reply::Err(reason),
+            };
+            match conn.env.database.transaction().set(&user, &hash).commit() {
+                Ok(()) => {
+                    store_verifiers(&mut conn.env, &user, verifiers);
+                    conn.audit("mkuser", &user, AuditOutcome::Success, "created account");
+                    Reply::Ok(format!("created account {}", user))
+                }
+                Err(e) => {
+                    conn.audit("mkuser", &user, AuditOutcome::Failure, &e.to_string());
+                    Reply::Err(e.to_string())
+                }
+            }
+        })
+    }
+
+    /// internal method for `PASSWD <username> <new password>`, like `chpass`
+    /// the connection's `AUTH` stands in for the current password the shell asks for,
+    /// changing another account's password takes `user.passwd.other`, and only root may
+    /// change root's
+    fn passwd(&mut self, args: &str) -> Reply {
+        let (user, password) = match args.split_once(' ') {
+            Some((user, password)) if !user.is_empty() => (user.to_string(), password.to_string()),
+            _ => return Reply::Err("usage: PASSWD <username> <new password>".to_string()),
+        };
+        // policy rules see the request as the shell would, `chpass` for the own account
+        let own = user == self.env.user();
+        let argv: &[&str] = match own {
+            true => &["chpass"],
+            false => &["chpass", &user],
+        };
+        self.run(&CHPASS, argv, |conn| {
+            if !own && !conn.env.can(Capability::UserPasswdOther) {
+                let detail = format!(
+                    "permission denied, requires {}",
+                    Capability::UserPasswdOther
+                );
+                conn.audit("chpass", &user, AuditOutcome::Denied, &detail);
+                return Reply::Err("permission denied".to_string());
+            }
+            if user == ROOT && conn.env.user() != ROOT {
+                let detail = format!("only {} may change the {} account", ROOT, ROOT);
+                conn.audit("chpass", &user, AuditOutcome::Denied, &detail);
+                return Reply::Err("permission denied".to_string());
+            }
+            if !conn.env.database.contains(&user) {
+                return Reply::Err(format!("account {} not found", user));
+            }
+            let (hash, verifiers) = match hash_new_password(&conn.env.config, &user, &password) {
+                Ok(new) => new,
+                Err(reason) => return Reply::Err(reason),
+            };
+            match conn.env.database.transaction().set(&user, &hash).commit() {
+                Ok(()) => {
+                    store_verifiers(&mut conn.env, &user, verifiers);
+                    revoke_tokens(&conn.env, &user);
+                    conn.audit("chpass", &user, AuditOutcome::Success, "changed password");
+                    Reply::Ok(format!("changed password for {}", user))
+                }
+                Err(e) => {
+                    conn.audit("chpass", &user, AuditOutcome::Failure, &e.to_string());
+                    Reply::Err(e.to_string())
+                }
+            }
+        })
+    }
+
+    /// internal method for `LIST`, like `users` but sorted
+    /// unlike the shell, a connection must authenticate first, so a client that merely
+    /// reaches the socket can't enumerate accounts
+    fn list(&mut self, args: &str) -> Reply {
+        if !args.is_empty() {
+            return Reply::Err("usage: LIST".to_string());
+        }
+        if self.env.user() == NULLUSER {
+            self.audit("users", "", AuditOutcome::Denied, "not authenticated");
+            return Reply::Err("permission denied".to_string());
+        }
+        self.run(&USERS, &["users"], |conn| {
+            let mut users = conn.env.database.list_users();
+            users.sort();
+            Reply::List(users)
+        })
+    }
+}
+
+// ==================== FUNCTIONS ====================
+
+/// open the credential store `storage.backend` selects, without prompting
+/// # Arguments
+/// * `config` - effective configuration
+/// # Return
+/// * the store, `Unsupported` for a memory store, which no other process could share, and for
+///   an encrypted store without a key file
+pub fn open_store(config: &Config) -> Result<Box<dyn CredentialStore>, StoreError> {
+    match config.backend.as_str() {
+        "memory" => Err(StoreError::Unsupported(
+            "serving a database to other processes".to_string(),
+        )),
+        "kv" => Ok(Box::new(KvStore::new(&config.kv_path()))),
+        "encrypted" => match &config.keyfile {
+            Some(keyfile) => {
+                let secret = std::fs::read(keyfile)?;
+                Ok(Box::new(EncryptedStore::open(
+                    &config.encrypted_path(),
+                    &secret,
+                )?))
+            }
+            None => Err(StoreError::Unsupported(
+                "unlocking without storage.keyfile".to_string(),
+            )),
+        },
+        _ => Ok(Box::new(UserCredentials::new(
+            &config.passwd_path(),
+            &config.shadow_path(),
+        ))),
+    }
+}
+
+/// name a TCP client for the throttle: a loopback client by the uid owning its socket, since
+/// every local service shares the loopback address, any other by its address
+/// # Arguments
+/// * `local` - address the daemon accepted the connection on
+/// * `peer` - address of the client
+/// # Return
+/// * `uid <uid>`, or the client's address without its port
+pub fn tcp_client(local: &SocketAddr, peer: &SocketAddr) -> String {
+    let owner = match peer.ip().is_loopback() {
+        true => PROC_TCP_TABLES.iter().find_map(|table| {
+            std::fs::read_to_string(table)
+                .ok()
+                .and_then(|table| socket_owner(&table, local, peer))
+        }),
+        false => None,
+    };
+    match owner {
+        Some(uid) => format!("uid {}", uid),
+        None => peer.ip().to_string(),
+    }
+}
+
+/// name a Unix socket client for the audit log and the throttle by the credentials the kernel
+/// recorded when it connected
+/// # Arguments
+/// * `stream` - the accepted connection
+/// * `path` - filepath of the socket
+/// # Return
+/// * the client's address, `<path> pid <pid> uid <uid>`, and `uid <uid>`, or the path for
+///   both if the credentials can't be read
+pub fn unix_client(stream: &UnixStream, path: &str) -> (String, String) {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: cred and len outlive the call, and len is the size of cred
+    let read = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    match read {
+        0 => (
+            format!("{} pid {} uid {}", path, cred.pid, cred.uid),
+            format!("uid {}", cred.uid),
+        ),
+        _ => (path.to_string(), path.to_string()),
+    }
+}
+
+/// find the uid owning the client's end of a TCP connection in a kernel socket table
+/// # Arguments
+/// * `table` - contents of `/proc/net/tcp` or `/proc/net/tcp6`
+/// * `local` - address the daemon accepted the connection on
+/// * `peer` - address of the client
+/// # Return
+/// * the uid, `None` if no socket goes from `peer` to `local`
+pub fn socket_owner(table: &str, local: &SocketAddr, peer: &SocketAddr) -> Option<u32> {
+    let (from, to) = (proc_addr(peer), proc_addr(local));
+    table.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [_, source, dest, _, _, _, _, uid, ..]
+                if source.eq_ignore_ascii_case(&from) && dest.eq_ignore_ascii_case(&to) =>
+            {
+                uid.parse().ok()
+            }
+            _ => None,
+        }
+    })
+}
+
+/// internal function to write a socket address the way the kernel's socket tables do, each
+/// 32 bit word of the address in host byte order and the port in hex
+fn proc_addr(addr: &SocketAddr) -> String {
+    let octets = match addr.ip() {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    let words: String = octets
+        .chunks(4)
+        .map(|word| {
+            format!(
+                "{:08X}",
+                u32::from_ne_bytes([word[0], word[1], word[2], word[3]])
+            )
+        })
+        .collect();
+    format!("{}:{:04X}", words, addr.port())
+}
+
+/// open an environment over the configured files, logged out, as the shell starts one
+/// # Arguments
+/// * `config` - effective configuration
+pub fn open_environment(config: &Config) -> Result<Environment, StoreError> {
+    let mut database = open_store(config)?;
+    database.set_username_policy(config.username_policy.clone());
+    Ok(Environment {
+        session: None,
+        vars: BTreeMap::new(),
+        stack: Vec::new(),
+        database,
+        groups: GroupDatabase::new(&config.group_path()),
+        roles: RoleTable::new(&config.roles_path()),
+        verifiers: VerifierTable::new(&config.verifiers_path()),
+        audit: AuditLog::new(&config.audit_path(), &config.seal_key_path()),
+        sessions: SessionTable::new(&config.sessions_path()),
+        config: config.clone(),
+        commands: vec![&LOGIN, &MKUSER, &CHPASS, &USERS],
+    })
+}
@@ -0,0 +1,267 @@
+// src/dbcheck.rs
+//! This module contains the consistency checker for the credential database, like `pwck`:
+//! * structured diagnostics instead of lines printed to stderr
+//! * malformed lines, duplicate users and orphaned shadow entries
+//! * unparseable or unknown hashes, bad salts and out of range costs
+//! * a missing root account
+//! * a quarantine file bad records are moved to, instead of being dropped
+
+// ==================== IMPORTS ====================
+
+use crate::audit::format_time;
+use crate::auth_utils::*;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// ==================== CONSTANTS ====================
+
+/// suffix of the file a store's bad records are quarantined in
+pub const QUARANTINE_SUFFIX: &str = ".quarantine";
+
+/// file mode of quarantine files, which may hold password hashes
+const QUARANTINE_MODE: u32 = 0o600;
+
+// ==================== STRUCTURES ====================
+
+/// kinds of inconsistency found in a credential store
+/// # Variants
+/// * `Malformed` - a line that is not a record
+/// * `Duplicate` - a second record for the named account
+/// * `DuplicateGroup` - a second record for the named group
+/// * `Orphaned` - a shadow entry for an account that is not in the passwd file
+/// * `NoShadow` - an account with no shadow entry, which is locked
+/// * `BadHash` - an account whose hash can't be verified
+/// * `NoFreeUid` - a legacy record for the named account that no uid is left to upgrade to
+/// * `NoRoot` - there is no root account
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Problem {
+    Malformed,
+    Duplicate(String),
+    DuplicateGroup(String),
+    Orphaned(String),
+    NoShadow(String),
+    BadHash(String, HashError),
+    NoFreeUid(String),
+    NoRoot,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Malformed => write!(f, "malformed record"),
+            Problem::Duplicate(username) => write!(f, "duplicate user '{}'", username),
+            Problem::DuplicateGroup(group) => write!(f, "duplicate group '{}'", group),
+            Problem::Orphaned(username) => {
+                write!(f, "shadow entry for '{}' has no account", username)
+            }
+            Problem::NoShadow(username) => {
+                write!(f, "no shadow entry for '{}', account locked", username)
+            }
+            Problem::BadHash(username, reason) => {
+                write!(f, "hash of '{}' is unusable: {}", username, reason)
+            }
+            Problem::NoFreeUid(username) => {
+                write!(f, "no uid is left for legacy account '{}'", username)
+            }
+            Problem::NoRoot => write!(f, "there is no {} account", ROOT),
+        }
+    }
+}
+
+/// one problem found by a check
+/// # Fields
+/// * `problem` - what is wrong
+/// * `location` - file and line number the problem was found at, if it is tied to a line
+/// * `record` - the offending record as stored, which is what gets quarantined
+/// # Methods
+/// * `new` - creates a diagnostic not tied to a line
+/// * `at` - ties the diagnostic to a line
+/// * `username` - account the problem concerns
+/// * `repairable` - whether a repair can resolve it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub problem: Problem,
+    pub location: Option<(String, usize)>,
+    pub record: Option<String>,
+}
+
+/// Methods for the struct
+impl Diagnostic {
+    /// create a diagnostic that is not tied to a line
+    /// # Arguments
+    /// * `problem` - what is wrong
+    /// * `record` - the offending record, if there is one to quarantine
+    pub fn new(problem: Problem, record: Option<String>) -> Self {
+        Diagnostic {
+            problem,
+            location: None,
+            record,
+        }
+    }
+
+    /// tie the diagnostic to a line
+    /// # Arguments
+    /// * `filepath` - file the line is in
+    /// * `line` - line number, starting at 1
+    pub fn at(mut self, filepath: &str, line: usize) -> Self {
+        self.location = Some((filepath.to_string(), line));
+        self
+    }
+
+    /// account the problem concerns, if any
+    pub fn username(&self) -> Option<&str> {
+        match &self.problem {
+            Problem::Duplicate(username)
+            | Problem::Orphaned(username)
+            | Problem::NoShadow(username)
+            | Problem::BadHash(username, _)
+            | Problem::NoFreeUid(username) => Some(username),
+            Problem::Malformed | Problem::DuplicateGroup(_) | Problem::NoRoot => None,
+        }
+    }
+
+    /// whether a repair resolves the problem, a missing root has to be created instead
+    pub fn repairable(&self) -> bool {
+        self.problem != Problem::NoRoot
+    }
+}
+
+/// Displays the location, if any, followed by the problem
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.location {
+            Some((filepath, line)) => write!(f, "'{}' line #{}: {}", filepath, line, self.problem),
+            None => write!(f, "{}", self.problem),
+        }
+    }
+}
+
+/// what a repair changed
+/// # Fields
+/// * `repaired` - problems that were resolved
+/// * `quarantine` - file the offending records were appended to, if any were
+#[derive(Clone, Debug, Default)]
+pub struct RepairReport {
+    pub repaired: Vec<Diagnostic>,
+    pub quarantine: Option<String>,
+}
+
+// ==================== FUNCTIONS ====================
+
+/// This function checks the hash of every record
+/// # Arguments
+/// * `records` - account records to check
+/// # Return
+/// * a `BadHash` diagnostic for every hash that can't be verified, ordered by uid
+pub fn check_hashes<'a, I>(records: I) -> Vec<Diagnostic>
+where
+    I: IntoIterator<Item = &'a UserRecord>,
+{
+    let mut records: Vec<&UserRecord> = records.into_iter().collect();
+    records.sort_by_key(|r| (r.uid(), r.name().to_string()));
+    records
+        .into_iter()
+        .filter_map(|record| {
+            validate_hash(record.hash()).err().map(|e| {
+                Diagnostic::new(
+                    Problem::BadHash(record.name().to_string(), e),
+                    Some(record.record_line()),
+                )
+            })
+        })
+        .collect()
+}
+
+/// This function runs the checks that apply to every backend
+/// # Arguments
+/// * `store` - credential store to check
+/// # Return
+/// * hash problems ordered by uid, followed by a missing root
+pub fn check_records<S: CredentialStore + ?Sized>(store: &S) -> Vec<Diagnostic> {
+    let records: Vec<UserRecord> = store
+        .list_users()
+        .iter()
+        .filter_map(|username| store.get(username))
+        .collect();
+    let mut diagnostics = check_hashes(&records);
+    if !store.contains(ROOT) {
+        diagnostics.push(Diagnostic::new(Problem::NoRoot, None));
+    }
+    diagnostics
+}
+
+/// This function locks every account with an unusable hash, after quarantining its record
+/// # Arguments
+/// * `store` - credential store to repair
+/// * `quarantine_path` - file the records are appended to
+/// # Return
+/// * the accounts that were locked
+pub fn repair_records<S: CredentialStore + ?Sized>(
+    store: &mut S,
+    quarantine_path: &str,
+) -> Result<RepairReport, StoreError> {
+    store.refresh();
+    let repaired: Vec<Diagnostic> = check_records(store)
+        .into_iter()
+        .filter(|d| d.repairable())
+        .collect();
+    if repaired.is_empty() {
+        return Ok(RepairReport::default());
+    }
+
+    // quarantine first, so the hashes survive even if the commit fails
+    quarantine(quarantine_path, &repaired)?;
+    let ops: Vec<TransactionOp> = repaired
+        .iter()
+        .filter_map(|d| d.username().and_then(|username| store.get(username)))
+        .map(|mut record| {
+            record.set_hash(LOCKED_HASH);
+            TransactionOp::SetRecord(record)
+        })
+        .collect();
+    store.commit(ops)?;
+    Ok(RepairReport {
+        repaired,
+        quarantine: Some(quarantine_path.to_string()),
+    })
+}
+
+/// This function appends the offending records of diagnostics to a quarantine file
+/// each record is preceded by a `#` comment with the time and the diagnostic
+/// # Arguments
+/// * `filepath` - quarantine file, created readable by the owner only
+/// * `diagnostics` - diagnostics whose records to append, those without one are skipped
+/// # Return
+/// * number of records appended
+pub fn quarantine(filepath: &str, diagnostics: &[Diagnostic]) -> std::io::Result<usize> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let mut buf = String::new();
+    let mut count: usize = 0;
+    for diagnostic in diagnostics {
+        if let Some(record) = &diagnostic.record {
+            buf.push_str(&format!(
+                "# {} {}\n{}\n",
+                format_time(now),
+                diagnostic,
+                record
+            ));
+            count += 1;
+        }
+    }
+    if count == 0 {
+        return Ok(0);
+    }
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .mode(QUARANTINE_MODE)
+        .open(filepath)?;
+    file.write_all(buf.as_bytes())?;
+    file.sync_all()?;
+    Ok(count)
+}
@@ -0,0 +1,37 @@
+// src/lib.rs
+//! Library half of the credential playground:
+//! * `audit` - tamper-evident, hash-chained log of security events
+//! * `auth_utils` - hashing, salting, credential storage and password input
+//! * `authz` - capabilities, and the roles that grant them to users and groups
+//! * `bignum` - arbitrary-precision unsigned integers and modular exponentiation, for SRP
+//! * `config` - runtime configuration from config files, environment and flags
+//! * `daemon` - line protocol `credplayd` serves logins and account changes with
+//! * `dbcheck` - consistency checks and repair of the credential database
+//! * `policy` - permit and forbid rules checked before every command, and their explanation
+//! * `scram` - SCRAM-SHA-256 challenge-response authentication, client and server
+//! * `session` - login sessions with timeouts, and the table used to list and revoke them
+//! * `shell` - faux-shell environment and commands, driven by the REPL in `main.rs`
+//! * `srp` - SRP-6a password-authenticated key exchange, client and server
+//! * `sudo` - sudoers policy deciding who may run which commands with `sudo`
+//! * `token` - signed bearer tokens (JWT HS256) that other tools can verify offline
+//! * `transfer` - JSON/TOML export and import of the credential database
+//! * `username` - username policy enforced when accounts are created or renamed
+//! * `verifier` - per-account verifiers for challenge-response logins, made alongside the hash
+
+pub mod audit;
+pub mod auth_utils;
+pub mod authz;
+pub mod bignum;
+pub mod config;
+pub mod daemon;
+pub mod dbcheck;
+pub mod policy;
+pub mod scram;
+pub mod session;
+pub mod shell;
+pub mod srp;
+pub mod sudo;
+pub mod token;
+pub mod transfer;
+pub mod username;
+pub mod verifier;
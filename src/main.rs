@@ -2,46 +2,81 @@
 
 // ==================== IMPORTS ====================
 
-mod auth_utils;
-use auth_utils::*;
-
-mod shell;
-use shell::*;
-
-use std::io::{self, Write};
+use credential_playground::audit::*;
+use credential_playground::auth_utils::*;
+use credential_playground::authz::*;
+use credential_playground::config::*;
+use credential_playground::session::*;
+use credential_playground::shell::*;
+use credential_playground::verifier::*;
+use std::collections::BTreeMap;
+use std::os::unix::fs::DirBuilderExt;
 
 // ==================== CONSTANTS ====================
 
-/// path to file where user data is stored
-const STORAGE_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/passwd");
-
-/// prompt icon
-const PROMPT_ICON: &str = "$ ";
-
-/// toggle color mode
-const COLOR_MODE: bool = true;
-
-/// prompt username color
-const USERNAME_COLOR: &str = "92";
-
-/// prompt root username colo
-const USERNAME_ROOT_COLOR: &str = "91";
+/// attempts at the master passphrase before giving up
+const MASTER_ATTEMPTS: u8 = 3;
 
 // ==================== HELPERS ====================
 
-/// function to get inline input from the user
+/// function to open the credential store selected by `storage.backend`
 /// # Arguments
-/// * `prompt` - &str with which to prompt the user for input
+/// * `config` - effective configuration
 /// # Return
-/// * input given by the user
-pub fn inline_input(prompt: &str) -> String {
-    let mut input_buffer: String = String::new();
-    print!("{}", prompt);
-    io::stdout().flush().expect("stdout.flush() failed");
-    io::stdin()
-        .read_line(&mut input_buffer)
-        .expect("stdin.read_line() failed");
-    input_buffer.trim().into()
+/// * the selected store
+fn open_store(config: &Config) -> Box<dyn CredentialStore> {
+    match config.backend.as_str() {
+        "memory" => Box::new(MemoryStore::new()),
+        "kv" => Box::new(KvStore::new(&config.kv_path())),
+        "encrypted" => Box::new(open_encrypted_store(config)),
+        _ => Box::new(UserCredentials::new(
+            &config.passwd_path(),
+            &config.shadow_path(),
+        )),
+    }
+}
+
+/// function to unlock the encrypted database with a key file or master passphrase
+/// exits the process if the database cannot be unlocked
+/// # Arguments
+/// * `config` - effective configuration
+/// # Return
+/// * the unlocked store
+fn open_encrypted_store(config: &Config) -> EncryptedStore {
+    let path = config.encrypted_path();
+
+    // a key file gets a single attempt
+    if let Some(keyfile) = &config.keyfile {
+        let result = std::fs::read(keyfile)
+            .map_err(StoreError::from)
+            .and_then(|secret| EncryptedStore::open(&path, &secret));
+        match result {
+            Ok(store) => return store,
+            Err(e) => {
+                eprintln!(
+                    "\x1b[91mFailed to unlock '{}' with key file '{}'. Error: {}\x1b[0m",
+                    path, keyfile, e
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // confirm the passphrase of a new database, since nothing can recover it
+    let creating = !std::path::Path::new(&path).exists();
+    for _ in 0..MASTER_ATTEMPTS {
+        let secret = password_input("master passphrase: ", creating);
+        match EncryptedStore::open(&path, secret.as_bytes()) {
+            Ok(store) => return store,
+            Err(StoreError::Corrupt(e)) => eprintln!("\x1b[91m{}\x1b[0m", e),
+            Err(e) => {
+                eprintln!("\x1b[91mFailed to open '{}'. Error: {}\x1b[0m", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    eprintln!("\x1b[91mToo many failed attempts\x1b[0m");
+    std::process::exit(1);
 }
 
 // ==================== MAINLOOP ====================
@@ -49,13 +84,44 @@ pub fn inline_input(prompt: &str) -> String {
 /// This is the REPL to simulate logins and credential management
 #[allow(unused_variables)]
 fn main() {
+    // load configuration
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let config = match Config::load(&args) {
+        Ok(config) => config,
+        Err(ConfigError::Help) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("\x1b[91m{}\x1b[0m", e);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&config.dir)
+    {
+        eprintln!(
+            "\x1b[91mFailed to create '{}'. Error: {}\x1b[0m",
+            config.dir, e
+        );
+    }
+
     println!("\n=== Credential Playground ===");
 
     // setup environment variables
     let mut env: Environment = Environment {
-        user: NULLUSER.to_string(),                   // start out logged out
-        permissions: P_NONE,                          // start out logged oud
-        database: UserCredentials::new(STORAGE_PATH), // load passwd file
+        session: None, // start out logged out
+        vars: BTreeMap::new(),
+        stack: Vec::new(),
+        database: open_store(&config), // load credential store
+        groups: GroupDatabase::new(&config.group_path()),
+        roles: RoleTable::new(&config.roles_path()),
+        verifiers: VerifierTable::new(&config.verifiers_path()),
+        audit: AuditLog::new(&config.audit_path(), &config.seal_key_path()),
+        sessions: SessionTable::new(&config.sessions_path()),
+        config,
         // reference all commands
         commands: vec![
             &HELP,
@@ -63,55 +129,128 @@ fn main() {
             &USERS,
             &CLEAR,
             &LOGOUT,
+            &LOCK,
             &LOGIN,
             &SWITCHUSER,
+            &SU,
             &CHNAME,
             &CHPASS,
+            &CHFN,
             &RMUSER,
             &MKUSER,
             &RESET,
+            &GROUPADD,
+            &GROUPDEL,
+            &GPASSWD,
+            &GROUPS,
+            &ID,
+            &EXPORT,
+            &IMPORT,
+            &REKEY,
+            &RESEAL,
+            &DBCHECK,
+            &REHASH,
+            &AUDIT,
+            &SESSION,
+            &TOKEN,
+            &SUDO,
+            &ROLE,
+            &AUTHZ,
+            &SCRAM,
+            &SRP,
+            &ENV,
+            &SETENV,
+            &UNSETENV,
+            &CONFIG,
             &EXIT,
         ],
     };
 
+    env.database
+        .set_username_policy(env.config.username_policy.clone());
+
     // REPL mainloop
     loop {
+        // pick up changes made by other shells using the same database
+        env.database.refresh();
+        env.groups.refresh();
+        env.roles.refresh();
+        env.verifiers.refresh();
+
         // create root user if none found
         if !env.database.contains(ROOT) {
+            // a database that exists but can't be read must not be replaced by a fresh one
+            if let Some(reason) = env.database.load_error() {
+                eprintln!(
+                    "\x1b[91mNo root account could be loaded, {}. Fix it or restore it from its backup, no new root is created over it\x1b[0m",
+                    reason
+                );
+                std::process::exit(1);
+            }
+            // an account created on top of tampered files could never be sealed
+            if env.database.tampered() {
+                eprintln!(
+                    "\x1b[91mNo root account in a database that failed its integrity check, restore it from its backup\x1b[0m"
+                );
+                std::process::exit(1);
+            }
             println!("no root account found, creating one");
-            env.database.set(
-                &ROOT,
-                &&hash_password(
-                    &password_input("root password: ", true),
-                    &get_salt(None),
-                    DEF_HASH_COST,
-                ),
+            let (hash, verifiers) = match new_password_hash(&env.config, ROOT, "root password: ") {
+                Some(new) => new,
+                None => continue,
+            };
+            env.database
+                .set_record(UserRecord::new(ROOT, &hash, ROOT_UID));
+            store_verifiers(&mut env, ROOT, verifiers);
+            audit(
+                &env,
+                "mkuser",
+                ROOT,
+                AuditOutcome::Success,
+                "created root account",
             );
             println!("root created");
         }
 
-        // generate prompt string
-        let (p_username, p_icon) = match env.user.as_str() {
-            NULLUSER => ("".to_string(), PROMPT_ICON.to_string()),
-            _ => (format!("{} ", env.user), PROMPT_ICON.to_string()),
+        // a locked session resumes only with its password
+        if env.locked() {
+            unlock_session(&mut env);
+            continue;
+        }
+
+        // generate prompt string, showing how deep in su sessions the user is
+        let (p_username, p_icon) = match (env.user(), env.depth()) {
+            (NULLUSER, _) => ("".to_string(), env.config.prompt_icon.clone()),
+            (_, 0) => (format!("{} ", env.user()), env.config.prompt_icon.clone()),
+            (_, depth) => (
+                format!("[{}] {} ", depth, env.user()),
+                env.config.prompt_icon.clone(),
+            ),
         };
 
         // determine color
-        let prompt_color = match env.user.as_str() {
-            ROOT => USERNAME_ROOT_COLOR,
-            _ => USERNAME_COLOR,
+        let prompt_color = match env.user() {
+            ROOT => &env.config.root_color,
+            _ => &env.config.user_color,
         };
 
         // possibly apply color
-        let prompt = match COLOR_MODE {
+        let prompt = match env.config.color {
             true => format!("\x1b[{}m{}\x1b[0m{}", prompt_color, p_username, p_icon),
             false => format!("{}{}", p_username, p_icon),
         };
 
-        // take commandline input and create argv list
-        let argv: Vec<String> = inline_input(&prompt)
+        // take commandline input and create argv list, unless the session times out first
+        let line = match timed_input(&prompt, env.deadline()) {
+            Some(line) => line,
+            None => {
+                on_timeout(&mut env);
+                continue;
+            }
+        };
+        let argv: Vec<String> = line
             .split_whitespace()
-            .map(|s| s.to_string())
+            .map(|s| expand_var(&env.vars, s))
             .collect();
         let argc: u8 = argv.len() as u8;
 
@@ -120,21 +259,31 @@ fn main() {
             continue;
         }
 
-        // harcoded exit command
+        // harcoded exit command, leaving a su session returns to the one beneath it
         if argv[0] == "exit" {
-            break;
+            if env.depth() == 0 {
+                env.end_session();
+                break;
+            }
+            let user = env.user().to_string();
+            env.pop_session();
+            let detail = format!("returned to {}", env.user());
+            env.audit
+                .record(&user, "su", env.user(), AuditOutcome::Success, &detail);
+            println!("{}", detail);
+            continue;
+        }
+
+        // a session may have timed out or been revoked by another shell since the last command
+        if let Err((user, e)) = env.validate_session() {
+            report_session_end(&env, &user, &e);
+            continue;
         }
 
         // search commandlist
         if let Some(cmd) = env.commands.iter().copied().find(|c| c.name == argv[0]) {
-            // check permissions for command
-            if env.permissions < cmd.permissions {
-                println!("permission denied: {}", cmd.name);
-                continue;
-            }
-
-            // run command w/ args
-            let ret_code = (cmd.handler)(&mut env, argc, &argv);
+            // run command w/ args, if the policy and its access allow it
+            let ret_code = run_command(&mut env, cmd, argc, &argv);
         } else {
             println!("unknown command: {}. try 'help'", argv[0])
         }
@@ -0,0 +1,916 @@
+// src/policy.rs
+//! This module contains the authorization policy, rules checked before every command:
+//! * `permit` and `forbid` rules over a principal, an action and a resource, like Cedar
+//! * `when { ... }` and `unless { ... }` conditions on the request and its context: the time,
+//!   the session's authentication factors, whether it runs through sudo
+//! * a forbid that applies beats any permit, a permit beats the command's access level,
+//!   and a command no rule applies to falls back to its access level
+//! * a permit whose condition can't be evaluated (say it compares a number with a string) is
+//!   skipped, like Cedar does, but such a forbid applies, so a mistake in it can't open a hole
+//! * evaluation keeps a trace of every rule, to explain the decision
+//! * a policy file writable by anyone but its owner is refused, like the sudoers file
+
+// ==================== IMPORTS ====================
+
+use std::fmt;
+use std::fs::{metadata, read_to_string};
+use std::os::unix::fs::PermissionsExt;
+
+// ==================== CONSTANTS ====================
+
+/// fields of `context`, as written in conditions
+pub const CONTEXT_FIELDS: &[&str] = &[
+    "time",
+    "hour",
+    "minute",
+    "weekday",
+    "factors",
+    "elevated",
+    "session_age",
+];
+
+/// names of the days of the week, `context.weekday`, from sunday
+pub const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// permission bits that make a policy file unsafe to trust, group and world write
+const UNSAFE_MODE_BITS: u32 = 0o022;
+
+/// operators and punctuation, longest first so `==` is not read as two `=`
+const SYMBOLS: &[&str] = &[
+    "==", "!=", "<=", ">=", "&&", "||", "(", ")", "[", "]", "{", "}", ",", ";", ".", "<", ">", "!",
+];
+
+// ==================== STRUCTURES ====================
+
+/// whether a rule allows or refuses what it applies to
+/// # Variants
+/// * `Permit` - allows the command, even without the capability it needs
+/// * `Forbid` - refuses the command, whatever else allows it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Effect {
+    Permit,
+    Forbid,
+}
+
+impl fmt::Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Effect::Permit => write!(f, "permit"),
+            Effect::Forbid => write!(f, "forbid"),
+        }
+    }
+}
+
+/// which principals, actions or resources a rule applies to
+/// # Variants
+/// * `Any` - all of them
+/// * `Is` - the named one
+/// * `InGroup` - accounts in the named group, for principals and resources
+/// * `OneOf` - any of the named ones, for actions
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Scope {
+    Any,
+    Is(String),
+    InGroup(String),
+    OneOf(Vec<String>),
+}
+
+/// a value conditions are evaluated to
+/// # Variants
+/// * `Bool` - `true` or `false`
+/// * `Int` - a whole number
+/// * `Str` - a string, compared by its characters
+/// * `List` - a list of values, for `in`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    Str(String),
+    List(Vec<Value>),
+}
+
+/// Displays the value the way it is written in a policy
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Str(s) => write!(f, "{:?}", s),
+            Value::List(items) => {
+                let items: Vec<String> = items.iter().map(|v| v.to_string()).collect();
+                write!(f, "[{}]", items.join(", "))
+            }
+        }
+    }
+}
+
+/// the parts of a request a condition can refer to
+/// # Variants
+/// * `Principal` - the user running the command
+/// * `Action` - the command
+/// * `Resource` - the account or object the command acts on
+/// * `Context` - a field of the context, one of `CONTEXT_FIELDS`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Var {
+    Principal,
+    Action,
+    Resource,
+    Context(String),
+}
+
+/// comparison operators
+/// # Variants
+/// * `Eq`, `Ne` - equality of values of the same type
+/// * `Lt`, `Le`, `Gt`, `Ge` - order of numbers, or of strings such as `"08:00"`
+/// * `In` - membership of a list, or of an account in a group
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    In,
+}
+
+/// a condition, as parsed
+/// # Variants
+/// * `Lit` - a literal value
+/// * `Var` - a part of the request
+/// * `List` - a list of expressions
+/// * `Not`, `And`, `Or` - boolean logic, `&&` and `||` short-circuit
+/// * `Cmp` - a comparison
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    Lit(Value),
+    Var(Var),
+    List(Vec<Expr>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Cmp(Op, Box<Expr>, Box<Expr>),
+}
+
+/// a `when` or `unless` clause
+/// # Fields
+/// * `unless` - whether the clause is `unless`, which must evaluate to false
+/// * `expr` - the condition
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Condition {
+    pub unless: bool,
+    pub expr: Expr,
+}
+
+/// a rule of the policy
+/// # Fields
+/// * `line` - line of the file the rule starts on
+/// * `text` - the rule as written, on one line
+/// * `effect` - permit or forbid
+/// * `principal`, `action`, `resource` - what the rule applies to
+/// * `conditions` - clauses that must all hold for the rule to apply
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    pub line: usize,
+    pub text: String,
+    pub effect: Effect,
+    pub principal: Scope,
+    pub action: Scope,
+    pub resource: Scope,
+    pub conditions: Vec<Condition>,
+}
+
+/// facts about a request besides who, what and on what
+/// # Fields
+/// * `hour`, `minute` - local time of day, `context.time` gives both as `HH:MM`
+/// * `weekday` - day of the week, one of `WEEKDAYS`
+/// * `factors` - authentication factors of the session, empty when logged out
+/// * `elevated` - whether the command runs through sudo
+/// * `session_age` - seconds since the session started, 0 when logged out
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Context {
+    pub hour: i64,
+    pub minute: i64,
+    pub weekday: &'static str,
+    pub factors: Vec<String>,
+    pub elevated: bool,
+    pub session_age: u64,
+}
+
+/// Methods for the struct
+impl Context {
+    /// the context of a request at a point in time
+    /// # Arguments
+    /// * `time` - seconds since the unix epoch
+    /// * `utc_offset` - minutes local time is ahead of UTC
+    /// * `factors` - authentication factors of the session
+    /// * `elevated` - whether the command runs through sudo
+    /// * `session_age` - seconds since the session started
+    pub fn at(
+        time: u64,
+        utc_offset: i64,
+        factors: Vec<String>,
+        elevated: bool,
+        session_age: u64,
+    ) -> Self {
+        let local = time as i64 + utc_offset * 60;
+        let seconds = local.rem_euclid(86400);
+        // 1970-01-01 was a thursday
+        let weekday = (local.div_euclid(86400) + 4).rem_euclid(7) as usize;
+        Context {
+            hour: seconds / 3600,
+            minute: seconds % 3600 / 60,
+            weekday: WEEKDAYS[weekday],
+            factors,
+            elevated,
+            session_age,
+        }
+    }
+
+    /// look a field up by name
+    /// # Arguments
+    /// * `field` - one of `CONTEXT_FIELDS`
+    pub fn get(&self, field: &str) -> Option<Value> {
+        Some(match field {
+            "time" => Value::Str(format!("{:02}:{:02}", self.hour, self.minute)),
+            "hour" => Value::Int(self.hour),
+            "minute" => Value::Int(self.minute),
+            "weekday" => Value::Str(self.weekday.to_string()),
+            "factors" => Value::List(self.factors.iter().cloned().map(Value::Str).collect()),
+            "elevated" => Value::Bool(self.elevated),
+            "session_age" => Value::Int(self.session_age as i64),
+            _ => return None,
+        })
+    }
+}
+
+/// a command about to run, as the policy sees it
+/// # Fields
+/// * `principal` - user running the command, empty when logged out
+/// * `action` - command name
+/// * `resource` - account or object the command acts on
+/// * `context` - time and session facts
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Request {
+    pub principal: String,
+    pub action: String,
+    pub resource: String,
+    pub context: Context,
+}
+
+/// what became of a rule while evaluating a request
+/// # Variants
+/// * `Applies` - the rule applies, and takes part in the decision
+/// * `OutOfScope` - the rule is not about this principal, action or resource, which is named
+/// * `ConditionFailed` - the numbered clause does not hold
+/// * `Error` - a clause could not be evaluated, with why, which skips a permit and applies a
+///   forbid
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Applies,
+    OutOfScope(&'static str),
+    ConditionFailed(usize),
+    Error(String),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Applies => write!(f, "applies"),
+            Outcome::OutOfScope(part) => write!(f, "{} does not match", part),
+            Outcome::ConditionFailed(clause) => write!(f, "clause #{} does not hold", clause),
+            Outcome::Error(reason) => write!(f, "can't be evaluated, {}", reason),
+        }
+    }
+}
+
+/// the policy's answer to a request
+/// # Variants
+/// * `Forbidden` - a forbid rule applies, with its line
+/// * `Permitted` - a permit rule applies and no forbid rule does, with its line
+/// * `NotCovered` - no rule applies, the command's access level decides
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Forbidden(usize),
+    Permitted(usize),
+    NotCovered,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Verdict::Forbidden(line) => write!(f, "forbidden by the rule on line #{}", line),
+            Verdict::Permitted(line) => write!(f, "permitted by the rule on line #{}", line),
+            Verdict::NotCovered => write!(f, "no rule applies"),
+        }
+    }
+}
+
+/// reasons a policy can't be used
+/// # Variants
+/// * `Io` - the policy file could not be read
+/// * `Insecure` - the policy file is writable by its group or everyone, with its mode
+/// * `Syntax` - the file is not a policy, with the line and the reason
+#[derive(Debug)]
+pub enum PolicyError {
+    Io(std::io::Error),
+    Insecure(u32),
+    Syntax(usize, String),
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PolicyError::Io(e) => write!(f, "{}", e),
+            PolicyError::Insecure(mode) => {
+                write!(f, "policy file is writable by others (mode {:o})", mode)
+            }
+            PolicyError::Syntax(line, reason) => write!(f, "line #{}: {}", line, reason),
+        }
+    }
+}
+
+impl std::error::Error for PolicyError {}
+
+impl From<std::io::Error> for PolicyError {
+    fn from(e: std::io::Error) -> Self {
+        PolicyError::Io(e)
+    }
+}
+
+/// the rules of a policy, in file order
+/// # Fields
+/// * `rules` - rules in the order they appear
+/// # Methods
+/// * `parse` - parses policy text
+/// * `load` - reads and parses a policy file, which may be missing
+/// * `evaluate` - decides a request, tracing every rule
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Policy {
+    pub rules: Vec<Rule>,
+}
+
+/// Methods for the struct
+impl Policy {
+    /// parse policy text, failing on the first error
+    /// # Arguments
+    /// * `text` - contents of a policy file
+    pub fn parse(text: &str) -> Result<Self, PolicyError> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser {
+            text,
+            tokens: &tokens,
+            pos: 0,
+        };
+        let mut rules: Vec<Rule> = Vec::new();
+        while parser.peek().is_some() {
+            rules.push(parser.rule()?);
+        }
+        Ok(Policy { rules })
+    }
+
+    /// read and parse a policy file, a missing file is a policy without rules
+    /// # Arguments
+    /// * `filepath` - policy file
+    pub fn load(filepath: &str) -> Result<Self, PolicyError> {
+        let mode = match metadata(filepath) {
+            Ok(meta) => meta.permissions().mode(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Policy::default()),
+            Err(e) => return Err(e.into()),
+        };
+        if mode & UNSAFE_MODE_BITS != 0 {
+            return Err(PolicyError::Insecure(mode & 0o777));
+        }
+        Self::parse(&read_to_string(filepath)?)
+    }
+
+    /// decide a request
+    /// # Arguments
+    /// * `request` - the command about to run
+    /// * `in_group` - whether an account is in a group
+    /// # Return
+    /// * the verdict, and what became of each rule
+    pub fn evaluate<G: Fn(&str, &str) -> bool>(
+        &self,
+        request: &Request,
+        in_group: G,
+    ) -> (Verdict, Vec<Outcome>) {
+        let outcomes: Vec<Outcome> = self
+            .rules
+            .iter()
+            .map(|rule| rule.evaluate(request, &in_group))
+            .collect();
+        // a forbid fails closed: one whose condition errors still applies
+        let applying = |effect: Effect| {
+            self.rules
+                .iter()
+                .zip(&outcomes)
+                .find(|(rule, outcome)| {
+                    rule.effect == effect
+                        && match outcome {
+                            Outcome::Applies => true,
+                            Outcome::Error(_) => effect == Effect::Forbid,
+                            _ => false,
+                        }
+                })
+                .map(|(rule, _)| rule.line)
+        };
+        let verdict = match (applying(Effect::Forbid), applying(Effect::Permit)) {
+            (Some(line), _) => Verdict::Forbidden(line),
+            (None, Some(line)) => Verdict::Permitted(line),
+            (None, None) => Verdict::NotCovered,
+        };
+        (verdict, outcomes)
+    }
+}
+
+/// Methods for the struct
+impl Rule {
+    /// check whether the rule applies to a request
+    /// # Arguments
+    /// * `request` - the command about to run
+    /// * `in_group` - whether an account is in a group
+    pub fn evaluate<G: Fn(&str, &str) -> bool>(&self, request: &Request, in_group: G) -> Outcome {
+        let scopes = [
+            ("principal", &self.principal, &request.principal),
+            ("action", &self.action, &request.action),
+            ("resource", &self.resource, &request.resource),
+        ];
+        for (part, scope, value) in scopes {
+            let matches = match scope {
+                Scope::Any => true,
+                Scope::Is(name) => name == value,
+                Scope::InGroup(group) => in_group(value, group),
+                Scope::OneOf(names) => names.contains(value),
+            };
+            if !matches {
+                return Outcome::OutOfScope(part);
+            }
+        }
+        for (index, condition) in self.conditions.iter().enumerate() {
+            match condition.expr.eval(request, &in_group) {
+                Ok(Value::Bool(holds)) if holds != condition.unless => {}
+                Ok(Value::Bool(_)) => return Outcome::ConditionFailed(index + 1),
+                Ok(other) => {
+                    return Outcome::Error(format!(
+                        "clause #{} is {}, not true or false",
+                        index + 1,
+                        other
+                    ));
+                }
+                Err(reason) => return Outcome::Error(format!("clause #{}: {}", index + 1, reason)),
+            }
+        }
+        Outcome::Applies
+    }
+}
+
+/// Methods for the enum
+impl Expr {
+    /// evaluate the expression for a request
+    /// # Arguments
+    /// * `request` - the command about to run
+    /// * `in_group` - whether an account is in a group
+    /// # Return
+    /// * the value, or why it has none
+    pub fn eval<G: Fn(&str, &str) -> bool>(
+        &self,
+        request: &Request,
+        in_group: &G,
+    ) -> Result<Value, String> {
+        let boolean = |expr: &Expr| match expr.eval(request, in_group)? {
+            Value::Bool(b) => Ok(b),
+            other => Err(format!("{} is not true or false", other)),
+        };
+        Ok(match self {
+            Expr::Lit(value) => value.clone(),
+            Expr::Var(Var::Principal) => Value::Str(request.principal.clone()),
+            Expr::Var(Var::Action) => Value::Str(request.action.clone()),
+            Expr::Var(Var::Resource) => Value::Str(request.resource.clone()),
+            Expr::Var(Var::Context(field)) => request
+                .context
+                .get(field)
+                .ok_or_else(|| format!("no context.{}", field))?,
+            Expr::List(items) => Value::List(
+                items
+                    .iter()
+                    .map(|item| item.eval(request, in_group))
+                    .collect::<Result<Vec<Value>, String>>()?,
+            ),
+            Expr::Not(expr) => Value::Bool(!boolean(expr)?),
+            Expr::And(a, b) => Value::Bool(boolean(a)? && boolean(b)?),
+            Expr::Or(a, b) => Value::Bool(boolean(a)? || boolean(b)?),
+            Expr::Cmp(op, a, b) => {
+                let (a, b) = (a.eval(request, in_group)?, b.eval(request, in_group)?);
+                Value::Bool(compare(*op, &a, &b, in_group)?)
+            }
+        })
+    }
+}
+
+/// a token of policy text
+/// # Variants
+/// * `Ident` - a keyword or name
+/// * `Str` - a string literal, unescaped
+/// * `Int` - a number
+/// * `Sym` - an operator or punctuation, one of `SYMBOLS`
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Int(i64),
+    Sym(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "{}", name),
+            Token::Str(s) => write!(f, "{:?}", s),
+            Token::Int(i) => write!(f, "{}", i),
+            Token::Sym(sym) => write!(f, "{}", sym),
+        }
+    }
+}
+
+/// a token and where it is
+/// # Fields
+/// * `token` - the token
+/// * `line` - line it is on
+/// * `start`, `end` - byte offsets in the text
+#[derive(Clone, Debug)]
+struct Spanned {
+    token: Token,
+    line: usize,
+    start: usize,
+    end: usize,
+}
+
+/// recursive descent parser over the tokens of a policy
+/// # Fields
+/// * `text` - the policy text, to copy each rule's source
+/// * `tokens` - the tokens
+/// * `pos` - index of the next token
+struct Parser<'a> {
+    text: &'a str,
+    tokens: &'a [Spanned],
+    pos: usize,
+}
+
+/// Methods for the struct
+impl Parser<'_> {
+    /// internal method to look at the next token
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|s| &s.token)
+    }
+
+    /// internal method to build a syntax error at the next token
+    fn error(&self, reason: &str) -> PolicyError {
+        match self.tokens.get(self.pos) {
+            Some(spanned) => {
+                PolicyError::Syntax(spanned.line, format!("{}, found {}", reason, spanned.token))
+            }
+            None => PolicyError::Syntax(
+                self.tokens.last().map_or(1, |s| s.line),
+                format!("{}, found the end of the policy", reason),
+            ),
+        }
+    }
+
+    /// internal method to take the next token if it is a symbol
+    fn eat(&mut self, sym: &str) -> bool {
+        match self.peek() {
+            Some(Token::Sym(s)) if *s == sym => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// internal method to take a symbol that must come next
+    fn expect(&mut self, sym: &str) -> Result<(), PolicyError> {
+        match self.eat(sym) {
+            true => Ok(()),
+            false => Err(self.error(&format!("expected '{}'", sym))),
+        }
+    }
+
+    /// internal method to take the next token if it is a keyword
+    fn keyword(&mut self, word: &str) -> bool {
+        match self.peek() {
+            Some(Token::Ident(name)) if name == word => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// internal method to take a string literal that must come next
+    fn string(&mut self) -> Result<String, PolicyError> {
+        match self.peek() {
+            Some(Token::Str(s)) => {
+                let s = s.clone();
+                self.pos += 1;
+                Ok(s)
+            }
+            _ => Err(self.error("expected a string")),
+        }
+    }
+
+    /// internal method to parse `<effect> (<scopes>) <conditions>;`
+    fn rule(&mut self) -> Result<Rule, PolicyError> {
+        let first = &self.tokens[self.pos];
+        let (line, start) = (first.line, first.start);
+        let effect = if self.keyword("permit") {
+            Effect::Permit
+        } else if self.keyword("forbid") {
+            Effect::Forbid
+        } else {
+            return Err(self.error("expected 'permit' or 'forbid'"));
+        };
+        self.expect("(")?;
+        let principal = self.scope("principal", false)?;
+        self.expect(",")?;
+        let action = self.scope("action", true)?;
+        self.expect(",")?;
+        let resource = self.scope("resource", false)?;
+        self.expect(")")?;
+        let mut conditions: Vec<Condition> = Vec::new();
+        loop {
+            let unless = if self.keyword("when") {
+                false
+            } else if self.keyword("unless") {
+                true
+            } else {
+                break;
+            };
+            self.expect("{")?;
+            let expr = self.or()?;
+            self.expect("}")?;
+            conditions.push(Condition { unless, expr });
+        }
+        self.expect(";")?;
+        let end = self.tokens[self.pos - 1].end;
+        Ok(Rule {
+            line,
+            text: self.text[start..end]
+                .split_whitespace()
+                .collect::<Vec<&str>>()
+                .join(" "),
+            effect,
+            principal,
+            action,
+            resource,
+            conditions,
+        })
+    }
+
+    /// internal method to parse a scope, `<part>`, `<part> == "name"`, `<part> in "group"`,
+    /// or for actions `action in ["name", ...]`
+    fn scope(&mut self, part: &str, is_action: bool) -> Result<Scope, PolicyError> {
+        if !self.keyword(part) {
+            return Err(self.error(&format!("expected '{}'", part)));
+        }
+        if self.eat("==") {
+            return Ok(Scope::Is(self.string()?));
+        }
+        if !self.keyword("in") {
+            return Ok(Scope::Any);
+        }
+        if !is_action {
+            return Ok(Scope::InGroup(self.string()?));
+        }
+        self.expect("[")?;
+        let mut names = vec![self.string()?];
+        while self.eat(",") {
+            names.push(self.string()?);
+        }
+        self.expect("]")?;
+        Ok(Scope::OneOf(names))
+    }
+
+    /// internal method to parse `a || b || ...`
+    fn or(&mut self) -> Result<Expr, PolicyError> {
+        let mut expr = self.and()?;
+        while self.eat("||") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    /// internal method to parse `a && b && ...`
+    fn and(&mut self) -> Result<Expr, PolicyError> {
+        let mut expr = self.unary()?;
+        while self.eat("&&") {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    /// internal method to parse `!a` or a comparison
+    fn unary(&mut self) -> Result<Expr, PolicyError> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        let left = self.primary()?;
+        let op = match self.peek() {
+            Some(Token::Sym("==")) => Op::Eq,
+            Some(Token::Sym("!=")) => Op::Ne,
+            Some(Token::Sym("<")) => Op::Lt,
+            Some(Token::Sym("<=")) => Op::Le,
+            Some(Token::Sym(">")) => Op::Gt,
+            Some(Token::Sym(">=")) => Op::Ge,
+            Some(Token::Ident(word)) if word == "in" => Op::In,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        Ok(Expr::Cmp(op, Box::new(left), Box::new(self.primary()?)))
+    }
+
+    /// internal method to parse a literal, a list, a variable or a parenthesized expression
+    fn primary(&mut self) -> Result<Expr, PolicyError> {
+        let token = match self.peek() {
+            Some(token) => token.clone(),
+            None => return Err(self.error("expected a value")),
+        };
+        self.pos += 1;
+        Ok(match token {
+            Token::Str(s) => Expr::Lit(Value::Str(s)),
+            Token::Int(i) => Expr::Lit(Value::Int(i)),
+            Token::Sym("(") => {
+                let expr = self.or()?;
+                self.expect(")")?;
+                expr
+            }
+            Token::Sym("[") => {
+                let mut items: Vec<Expr> = Vec::new();
+                if !self.eat("]") {
+                    items.push(self.or()?);
+                    while self.eat(",") {
+                        items.push(self.or()?);
+                    }
+                    self.expect("]")?;
+                }
+                Expr::List(items)
+            }
+            Token::Ident(word) => match word.as_str() {
+                "true" => Expr::Lit(Value::Bool(true)),
+                "false" => Expr::Lit(Value::Bool(false)),
+                "principal" => Expr::Var(Var::Principal),
+                "action" => Expr::Var(Var::Action),
+                "resource" => Expr::Var(Var::Resource),
+                "context" => {
+                    self.expect(".")?;
+                    match self.peek() {
+                        Some(Token::Ident(field)) if CONTEXT_FIELDS.contains(&field.as_str()) => {
+                            let field = field.clone();
+                            self.pos += 1;
+                            Expr::Var(Var::Context(field))
+                        }
+                        _ => {
+                            return Err(self.error(&format!(
+                                "expected one of context.{}",
+                                CONTEXT_FIELDS.join(", context.")
+                            )));
+                        }
+                    }
+                }
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.error("expected a value"));
+                }
+            },
+            Token::Sym(_) => {
+                self.pos -= 1;
+                return Err(self.error("expected a value"));
+            }
+        })
+    }
+}
+
+// ==================== FUNCTIONS ====================
+
+/// internal function to split policy text into tokens, skipping whitespace and `#` comments
+/// # Arguments
+/// * `text` - contents of a policy file
+fn tokenize(text: &str) -> Result<Vec<Spanned>, PolicyError> {
+    let mut tokens: Vec<Spanned> = Vec::new();
+    let mut line: usize = 1;
+    let mut chars = text.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c == '\n' {
+            line += 1;
+            chars.next();
+        } else if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+        } else if c == '"' {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, c @ ('"' | '\\'))) => s.push(c),
+                        _ => {
+                            return Err(PolicyError::Syntax(
+                                line,
+                                "only \\\" and \\\\ may be escaped".to_string(),
+                            ));
+                        }
+                    },
+                    Some((_, '\n')) | None => {
+                        return Err(PolicyError::Syntax(line, "unterminated string".to_string()));
+                    }
+                    Some((_, c)) => s.push(c),
+                }
+            }
+            let end = chars.peek().map_or(text.len(), |&(i, _)| i);
+            tokens.push(Spanned {
+                token: Token::Str(s),
+                line,
+                start,
+                end,
+            });
+        } else if c.is_ascii_digit() {
+            let mut end = start;
+            while let Some((i, c)) = chars.next_if(|&(_, c)| c.is_ascii_digit()) {
+                end = i + c.len_utf8();
+            }
+            let number = text[start..end]
+                .parse()
+                .map_err(|_| PolicyError::Syntax(line, "number is too large".to_string()))?;
+            tokens.push(Spanned {
+                token: Token::Int(number),
+                line,
+                start,
+                end,
+            });
+        } else if c.is_alphabetic() || c == '_' {
+            let mut end = start;
+            while let Some((i, c)) = chars.next_if(|&(_, c)| c.is_alphanumeric() || c == '_') {
+                end = i + c.len_utf8();
+            }
+            tokens.push(Spanned {
+                token: Token::Ident(text[start..end].to_string()),
+                line,
+                start,
+                end,
+            });
+        } else {
+            let sym = SYMBOLS
+                .iter()
+                .find(|sym| text[start..].starts_with(**sym))
+                .ok_or_else(|| PolicyError::Syntax(line, format!("unexpected {:?}", c)))?;
+            for _ in 0..sym.len() {
+                chars.next();
+            }
+            tokens.push(Spanned {
+                token: Token::Sym(sym),
+                line,
+                start,
+                end: start + sym.len(),
+            });
+        }
+    }
+    Ok(tokens)
+}
+
+/// internal function to compare two values
+/// # Arguments
+/// * `op` - comparison operator
+/// * `a`, `b` - left and right values
+/// * `in_group` - whether an account is in a group, for `"account" in "group"`
+/// # Return
+/// * the result, or why the values can't be compared
+fn compare<G: Fn(&str, &str) -> bool>(
+    op: Op,
+    a: &Value,
+    b: &Value,
+    in_group: &G,
+) -> Result<bool, String> {
+    use std::cmp::Ordering;
+    let order = match (a, b) {
+        (Value::Int(x), Value::Int(y)) => Some(x.cmp(y)),
+        (Value::Str(x), Value::Str(y)) => Some(x.cmp(y)),
+        _ => None,
+    };
+    let same_type = std::mem::discriminant(a) == std::mem::discriminant(b);
+    match (op, order) {
+        (Op::Eq, _) if same_type => Ok(a == b),
+        (Op::Ne, _) if same_type => Ok(a != b),
+        (Op::Lt, Some(order)) => Ok(order == Ordering::Less),
+        (Op::Le, Some(order)) => Ok(order != Ordering::Greater),
+        (Op::Gt, Some(order)) => Ok(order == Ordering::Greater),
+        (Op::Ge, Some(order)) => Ok(order != Ordering::Less),
+        (Op::In, _) => match (a, b) {
+            (_, Value::List(items)) => Ok(items.contains(a)),
+            (Value::Str(account), Value::Str(group)) => Ok(in_group(account, group)),
+            _ => Err(format!("can't check whether {} is in {}", a, b)),
+        },
+        _ => Err(format!("can't compare {} with {}", a, b)),
+    }
+}
@@ -0,0 +1,793 @@
+// src/scram.rs
+//! This module contains the SCRAM-SHA-256 challenge-response mechanism (RFC 5802, RFC 7677):
+//! * salted credentials whose StoredKey and ServerKey are derived with PBKDF2, so the server
+//!   keeps nothing password-equivalent and never sees the plaintext
+//! * client and server state machines exchanging the four SASL messages
+//! * mutual authentication, the client checks the server's signature too
+//! * channel binding as an extension point, through the `ChannelBinding` trait
+//! * an unknown account gets made-up credentials whose salt is derived from its name and a
+//!   server secret, so asking twice gives the same answer, as for a real account
+
+// ==================== IMPORTS ====================
+
+use crate::auth_utils::*;
+use rand_core::{OsRng, TryRngCore};
+use std::fmt;
+use unicode_normalization::UnicodeNormalization;
+
+// ==================== CONSTANTS ====================
+
+/// SASL mechanism name, without channel binding
+pub const SCRAM_MECHANISM: &str = "SCRAM-SHA-256";
+
+/// SASL mechanism name a server with channel binding advertises
+pub const SCRAM_PLUS_MECHANISM: &str = "SCRAM-SHA-256-PLUS";
+
+/// default PBKDF2 iterations of new credentials
+pub const DEF_SCRAM_ITERATIONS: u32 = 4096;
+
+/// fewest PBKDF2 iterations credentials are made with or a client accepts, as RFC 7677 asks
+pub const MIN_SCRAM_ITERATIONS: u32 = 4096;
+
+/// length of the salt of new credentials, in bytes
+const SCRAM_SALT_LEN: usize = 16;
+
+/// length of a nonce, in random bytes before base64
+const SCRAM_NONCE_LEN: usize = 18;
+
+/// length of the keys, proofs and signatures, the sha-256 output length
+const SCRAM_KEY_LEN: usize = 32;
+
+/// label the salts of made-up credentials are derived under, so the server secret can key
+/// other things too
+const FAKE_SALT_LABEL: &str = "scram-sha-256 salt:";
+
+/// characters SASLprep maps to nothing (RFC 3454 table B.1)
+const MAPPED_TO_NOTHING: &[char] = &[
+    '\u{00AD}', '\u{034F}', '\u{1806}', '\u{180B}', '\u{180C}', '\u{180D}', '\u{200B}', '\u{200C}',
+    '\u{200D}', '\u{2060}', '\u{FEFF}',
+];
+
+// ==================== STRUCTURES ====================
+
+/// what a server stores to check a password with SCRAM, in place of a password hash
+/// # Fields
+/// * `salt` - random salt
+/// * `iterations` - PBKDF2 iterations
+/// * `stored_key` - H(ClientKey), checks the client's proof
+/// * `server_key` - signs the exchange, proving the server holds the credentials
+/// # Methods
+/// * `derive` - derives credentials from a password and salt
+/// * `new` - derives credentials with a random salt
+/// * `parse` - reads credentials as `Display` writes them,
+///   `SCRAM-SHA-256$<iterations>:<salt>$<StoredKey>:<ServerKey>` in base64
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScramCredentials {
+    pub salt: Vec<u8>,
+    pub iterations: u32,
+    pub stored_key: Vec<u8>,
+    pub server_key: Vec<u8>,
+}
+
+/// channel binding of the secure channel SCRAM runs over (RFC 5929), so an attacker who
+/// terminates the channel can't relay the exchange
+/// nothing here implements it, a TLS transport would
+/// # Methods
+/// * `kind` - binding type, like `tls-server-end-point` or `tls-exporter`
+/// * `data` - binding data of this channel
+pub trait ChannelBinding {
+    fn kind(&self) -> &str;
+    fn data(&self) -> Vec<u8>;
+}
+
+/// reasons a SCRAM exchange fails
+/// # Variants
+/// * `Malformed` - a message or stored credentials could not be parsed, with why
+/// * `Prohibited` - the username or password holds characters SASLprep prohibits
+/// * `OutOfOrder` - a step was taken out of turn or after a failure, naming the step
+/// * `Nonce` - the server's nonce does not extend the client's, or the client changed it
+/// * `Iterations` - the server asked for fewer than `MIN_SCRAM_ITERATIONS`, with how many
+/// * `ChannelBinding` - channel binding was refused, with the RFC 5802 error value
+/// * `InvalidProof` - the client's proof is wrong, so it doesn't know the password
+/// * `ServerSignature` - the server's signature is wrong, so it doesn't hold the credentials
+/// * `Server` - the server ended the exchange with an error value
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScramError {
+    Malformed(String),
+    Prohibited,
+    OutOfOrder(&'static str),
+    Nonce,
+    Iterations(u32),
+    ChannelBinding(&'static str),
+    InvalidProof,
+    ServerSignature,
+    Server(String),
+}
+
+/// SCRAM client, taking the four steps of the exchange in order
+/// # Fields
+/// * `username` - account to authenticate as
+/// * `password` - its password
+/// * `nonce` - client nonce
+/// * `binding` - channel binding to use, `None` when the client has none
+/// * `state` - progress of the exchange
+/// # Methods
+/// * `new`, `with_nonce` - start an exchange with a random or given nonce, and the server
+///   secret made-up credentials are derived from
+/// * `gs2_header` - header announcing channel binding
+/// * `client_first` - writes the client-first message
+/// * `client_final` - answers the server-first message with a proof of the password
+/// * `verify_server` - checks the server-final message
+pub struct ScramClient {
+    username: String,
+    password: String,
+    nonce: String,
+    binding: Option<Box<dyn ChannelBinding>>,
+    state: ClientState,
+}
+
+/// progress of a client's exchange
+/// # Variants
+/// * `Start` - nothing sent yet
+/// * `SentFirst` - client-first sent, with its bare part
+/// * `SentFinal` - client-final sent, with the server signature to expect
+/// * `Done` - the server proved itself
+/// * `Failed` - a step failed, the exchange is over
+enum ClientState {
+    Start,
+    SentFirst(String),
+    SentFinal(Vec<u8>),
+    Done,
+    Failed,
+}
+
+/// SCRAM server, answering a client's messages
+/// # Fields
+/// * `nonce` - server part of the nonce
+/// * `binding` - channel binding of the connection, `None` when it has none
+/// * `secret` - server secret the salts of made-up credentials are derived from
+/// * `iterations` - PBKDF2 iterations of made-up credentials
+/// * `state` - progress of the exchange
+/// # Methods
+/// * `new`, `with_nonce` - start an exchange with a random or given nonce, and the server
+///   secret made-up credentials are derived from
+/// * `mechanism` - mechanism name to advertise
+/// * `server_first` - answers the client-first message with the salt and iterations
+/// * `server_final` - checks the client's proof and signs the exchange
+/// * `username` - account that authenticated, once the exchange succeeded
+pub struct ScramServer {
+    nonce: String,
+    binding: Option<Box<dyn ChannelBinding>>,
+    secret: Vec<u8>,
+    iterations: u32,
+    state: ServerState,
+}
+
+/// what a server remembers between its two messages
+/// # Fields
+/// * `username` - account the client asked for
+/// * `cbind_input` - GS2 header and binding data the client must echo
+/// * `first_bare` - client-first message without its GS2 header
+/// * `server_first` - server-first message
+/// * `nonce` - combined client and server nonce
+/// * `credentials` - the account's credentials, made up if it has none
+struct Pending {
+    username: String,
+    cbind_input: Vec<u8>,
+    first_bare: String,
+    server_first: String,
+    nonce: String,
+    credentials: ScramCredentials,
+}
+
+/// progress of a server's exchange
+/// # Variants
+/// * `Start` - nothing received yet
+/// * `SentFirst` - server-first sent
+/// * `Done` - the client authenticated as the account
+/// * `Failed` - a step failed, the exchange is over
+enum ServerState {
+    Start,
+    SentFirst(Box<Pending>),
+    Done(String),
+    Failed,
+}
+
+/// Methods for the struct
+impl ScramCredentials {
+    /// derive credentials from a password
+    /// # Arguments
+    /// * `password` - password, prepared with SASLprep
+    /// * `salt` - salt
+    /// * `iterations` - PBKDF2 iterations
+    /// # Return
+    /// * the credentials, `Prohibited` if the password can't be prepared
+    pub fn derive(password: &str, salt: &[u8], iterations: u32) -> Result<Self, ScramError> {
+        let salted = salted_password(password, salt, iterations)?;
+        Ok(ScramCredentials {
+            salt: salt.to_vec(),
+            iterations,
+            stored_key: sha256(client_key(&salted)),
+            server_key: server_key(&salted),
+        })
+    }
+
+    /// derive credentials from a password with a random salt
+    /// # Arguments
+    /// * `password` - password, prepared with SASLprep
+    /// * `iterations` - PBKDF2 iterations, at least `MIN_SCRAM_ITERATIONS`
+    pub fn new(password: &str, iterations: u32) -> Result<Self, ScramError> {
+        Self::derive(
+            password,
+            &random_bytes(SCRAM_SALT_LEN),
+            iterations.max(MIN_SCRAM_ITERATIONS),
+        )
+    }
+
+    /// read credentials as `Display` writes them
+    /// # Arguments
+    /// * `text` - `SCRAM-SHA-256$<iterations>:<salt>$<StoredKey>:<ServerKey>`
+    pub fn parse(text: &str) -> Result<Self, ScramError> {
+        let malformed = || ScramError::Malformed("stored credentials".to_string());
+        let rest = text
+            .strip_prefix(SCRAM_MECHANISM)
+            .and_then(|rest| rest.strip_prefix('$'))
+            .ok_or_else(malformed)?;
+        let (params, keys) = rest.split_once('$').ok_or_else(malformed)?;
+        let (iterations, salt) = params.split_once(':').ok_or_else(malformed)?;
+        let (stored_key, server_key) = keys.split_once(':').ok_or_else(malformed)?;
+        let credentials = ScramCredentials {
+            salt: base64_decode(salt).map_err(|_| malformed())?,
+            iterations: iterations.parse().map_err(|_| malformed())?,
+            stored_key: base64_decode(stored_key).map_err(|_| malformed())?,
+            server_key: base64_decode(server_key).map_err(|_| malformed())?,
+        };
+        if credentials.stored_key.len() != SCRAM_KEY_LEN
+            || credentials.server_key.len() != SCRAM_KEY_LEN
+        {
+            return Err(malformed());
+        }
+        Ok(credentials)
+    }
+}
+
+impl fmt::Display for ScramCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}${}:{}${}:{}",
+            SCRAM_MECHANISM,
+            self.iterations,
+            base64_encode(&self.salt),
+            base64_encode(&self.stored_key),
+            base64_encode(&self.server_key)
+        )
+    }
+}
+
+/// Methods for the enum
+impl ScramError {
+    /// server-final message reporting the error to the client, `e=<value>` (RFC 5802)
+    pub fn server_final(&self) -> String {
+        let value = match self {
+            ScramError::Malformed(_) => "invalid-encoding",
+            ScramError::Prohibited => "invalid-username-encoding",
+            ScramError::ChannelBinding(value) => value,
+            ScramError::InvalidProof => "invalid-proof",
+            _ => "other-error",
+        };
+        format!("e={}", value)
+    }
+}
+
+impl fmt::Display for ScramError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScramError::Malformed(what) => write!(f, "malformed {}", what),
+            ScramError::Prohibited => write!(f, "prohibited characters in username or password"),
+            ScramError::OutOfOrder(step) => write!(f, "{} taken out of turn", step),
+            ScramError::Nonce => write!(f, "nonce mismatch"),
+            ScramError::Iterations(n) => write!(
+                f,
+                "server asked for {} iterations, fewer than {}",
+                n, MIN_SCRAM_ITERATIONS
+            ),
+            ScramError::ChannelBinding(value) => write!(f, "channel binding refused: {}", value),
+            ScramError::InvalidProof => write!(f, "invalid proof"),
+            ScramError::ServerSignature => write!(f, "server signature mismatch"),
+            ScramError::Server(value) => write!(f, "server error: {}", value),
+        }
+    }
+}
+
+/// Methods for the struct
+impl ScramClient {
+    /// start an exchange with a random nonce
+    /// # Arguments
+    /// * `username` - account to authenticate as
+    /// * `password` - its password
+    /// * `binding` - channel binding to use, `None` when the client has none
+    pub fn new(username: &str, password: &str, binding: Option<Box<dyn ChannelBinding>>) -> Self {
+        Self::with_nonce(username, password, binding, &random_nonce())
+    }
+
+    /// start an exchange with a given nonce, e.g. to replay a test exchange
+    /// # Arguments
+    /// * `username` - account to authenticate as
+    /// * `password` - its password
+    /// * `binding` - channel binding to use, `None` when the client has none
+    /// * `nonce` - client nonce, printable ASCII other than `,`
+    pub fn with_nonce(
+        username: &str,
+        password: &str,
+        binding: Option<Box<dyn ChannelBinding>>,
+        nonce: &str,
+    ) -> Self {
+        ScramClient {
+            username: username.to_string(),
+            password: password.to_string(),
+            nonce: nonce.to_string(),
+            binding,
+            state: ClientState::Start,
+        }
+    }
+
+    /// GS2 header announcing channel binding, `n,,` without it and `p=<type>,,` with it
+    pub fn gs2_header(&self) -> String {
+        match &self.binding {
+            Some(binding) => format!("p={},,", binding.kind()),
+            None => "n,,".to_string(),
+        }
+    }
+
+    /// write the client-first message, `<gs2 header>n=<username>,r=<client nonce>`
+    pub fn client_first(&mut self) -> Result<String, ScramError> {
+        if !matches!(self.state, ClientState::Start) {
+            return Err(self.fail(ScramError::OutOfOrder("client-first")));
+        }
+        let username = match saslprep(&self.username) {
+            Ok(username) => username,
+            Err(e) => return Err(self.fail(e)),
+        };
+        let first_bare = format!("n={},r={}", escape_username(&username), self.nonce);
+        self.state = ClientState::SentFirst(first_bare.clone());
+        Ok(format!("{}{}", self.gs2_header(), first_bare))
+    }
+
+    /// answer the server-first message with a proof of the password,
+    /// `c=<channel binding>,r=<nonce>,p=<proof>`
+    /// # Arguments
+    /// * `server_first` - `r=<nonce>,s=<salt>,i=<iterations>` from the server
+    pub fn client_final(&mut self, server_first: &str) -> Result<String, ScramError> {
+        let first_bare = match &self.state {
+            ClientState::SentFirst(first_bare) => first_bare.clone(),
+            _ => return Err(self.fail(ScramError::OutOfOrder("client-final"))),
+        };
+        match self.prove(&first_bare, server_first) {
+            Ok((client_final, server_signature)) => {
+                self.state = ClientState::SentFinal(server_signature);
+                Ok(client_final)
+            }
+            Err(e) => Err(self.fail(e)),
+        }
+    }
+
+    /// internal method to compute the client-final message and the server signature to expect
+    fn prove(&self, first_bare: &str, server_first: &str) -> Result<(String, Vec<u8>), ScramError> {
+        let attrs = attributes(server_first)?;
+        let nonce = attribute(&attrs, 0, 'r', "server-first")?;
+        let salt = base64_decode(attribute(&attrs, 1, 's', "server-first")?)
+            .map_err(|_| ScramError::Malformed("salt".to_string()))?;
+        let iterations: u32 = attribute(&attrs, 2, 'i', "server-first")?
+            .parse()
+            .map_err(|_| ScramError::Malformed("iteration count".to_string()))?;
+        if !nonce.starts_with(&self.nonce) || nonce.len() == self.nonce.len() {
+            return Err(ScramError::Nonce);
+        }
+        if iterations < MIN_SCRAM_ITERATIONS {
+            return Err(ScramError::Iterations(iterations));
+        }
+
+        let mut cbind_input = self.gs2_header().into_bytes();
+        if let Some(binding) = &self.binding {
+            cbind_input.extend(binding.data());
+        }
+        let without_proof = format!("c={},r={}", base64_encode(&cbind_input), nonce);
+        let auth_message = format!("{},{},{}", first_bare, server_first, without_proof);
+
+        let salted = salted_password(&self.password, &salt, iterations)?;
+        let client_key = client_key(&salted);
+        let client_signature = hmac_sha256(&sha256(client_key.clone()), auth_message.as_bytes());
+        let proof = xor(&client_key, &client_signature);
+        let server_signature = hmac_sha256(&server_key(&salted), auth_message.as_bytes());
+        Ok((
+            format!("{},p={}", without_proof, base64_encode(&proof)),
+            server_signature,
+        ))
+    }
+
+    /// check the server-final message, `v=<server signature>` or `e=<error>`
+    /// # Arguments
+    /// * `server_final` - message from the server
+    /// # Return
+    /// * `Ok` if the server proved it holds the credentials
+    pub fn verify_server(&mut self, server_final: &str) -> Result<(), ScramError> {
+        let expected = match &self.state {
+            ClientState::SentFinal(expected) => expected.clone(),
+            _ => return Err(self.fail(ScramError::OutOfOrder("server-final check"))),
+        };
+        let result = attributes(server_final).and_then(|attrs| match attrs.first() {
+            Some(('e', value)) => Err(ScramError::Server(value.to_string())),
+            Some(('v', value)) => match base64_decode(value) {
+                Ok(signature) if constant_time_eq(&signature, &expected) => Ok(()),
+                Ok(_) => Err(ScramError::ServerSignature),
+                Err(_) => Err(ScramError::Malformed("server signature".to_string())),
+            },
+            _ => Err(ScramError::Malformed("server-final".to_string())),
+        });
+        match result {
+            Ok(()) => {
+                self.state = ClientState::Done;
+                Ok(())
+            }
+            Err(e) => Err(self.fail(e)),
+        }
+    }
+
+    /// internal method to end the exchange after a failed step
+    fn fail(&mut self, e: ScramError) -> ScramError {
+        self.state = ClientState::Failed;
+        e
+    }
+}
+
+/// Methods for the struct
+impl ScramServer {
+    /// start an exchange with a random nonce
+    /// # Arguments
+    /// * `binding` - channel binding of the connection, `None` when it has none
+    /// * `secret` - server secret, kept across exchanges, the salts of made-up credentials
+    ///   are derived from
+    /// * `iterations` - PBKDF2 iterations of made-up credentials, those new credentials get
+    pub fn new(binding: Option<Box<dyn ChannelBinding>>, secret: &[u8], iterations: u32) -> Self {
+        Self::with_nonce(binding, secret, iterations, &random_nonce())
+    }
+
+    /// start an exchange with a given nonce, e.g. to replay a test exchange
+    /// # Arguments
+    /// * `binding` - channel binding of the connection, `None` when it has none
+    /// * `secret` - server secret the salts of made-up credentials are derived from
+    /// * `iterations` - PBKDF2 iterations of made-up credentials
+    /// * `nonce` - server part of the nonce, printable ASCII other than `,`
+    pub fn with_nonce(
+        binding: Option<Box<dyn ChannelBinding>>,
+        secret: &[u8],
+        iterations: u32,
+        nonce: &str,
+    ) -> Self {
+        ScramServer {
+            nonce: nonce.to_string(),
+            binding,
+            secret: secret.to_vec(),
+            iterations,
+            state: ServerState::Start,
+        }
+    }
+
+    /// mechanism name to advertise, `-PLUS` when the connection offers channel binding
+    pub fn mechanism(&self) -> &'static str {
+        match self.binding {
+            Some(_) => SCRAM_PLUS_MECHANISM,
+            None => SCRAM_MECHANISM,
+        }
+    }
+
+    /// answer the client-first message with the account's salt and iterations
+    /// an account without credentials gets made-up ones, so the exchange fails like a wrong
+    /// password instead of telling the client the account doesn't exist, and their salt is
+    /// the same every time, as a real account's is (RFC 5802 section 5.1)
+    /// # Arguments
+    /// * `client_first` - `<gs2 header>n=<username>,r=<client nonce>` from the client
+    /// * `lookup` - finds the credentials of an account
+    /// # Return
+    /// * the server-first message, `r=<nonce>,s=<salt>,i=<iterations>`
+    pub fn server_first<F>(&mut self, client_first: &str, lookup: F) -> Result<String, ScramError>
+    where
+        F: FnOnce(&str) -> Option<ScramCredentials>,
+    {
+        if !matches!(self.state, ServerState::Start) {
+            return Err(self.fail(ScramError::OutOfOrder("server-first")));
+        }
+        match self.challenge(client_first, lookup) {
+            Ok(pending) => {
+                let server_first = pending.server_first.clone();
+                self.state = ServerState::SentFirst(Box::new(pending));
+                Ok(server_first)
+            }
+            Err(e) => Err(self.fail(e)),
+        }
+    }
+
+    /// internal method to parse the client-first message and pick the server-first one
+    fn challenge<F>(&self, client_first: &str, lookup: F) -> Result<Pending, ScramError>
+    where
+        F: FnOnce(&str) -> Option<ScramCredentials>,
+    {
+        let malformed = || ScramError::Malformed("client-first".to_string());
+        let mut parts = client_first.splitn(3, ',');
+        let (flag, authzid, first_bare) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(flag), Some(authzid), Some(first_bare)) => (flag, authzid, first_bare),
+            _ => return Err(malformed()),
+        };
+
+        // the client binds only to a channel of the type this connection offers
+        let mut cbind_input = format!("{},{},", flag, authzid).into_bytes();
+        match (flag, &self.binding) {
+            ("n", _) => {}
+            ("y", None) => {}
+            ("y", Some(_)) => {
+                return Err(ScramError::ChannelBinding(
+                    "server-does-support-channel-binding",
+                ));
+            }
+            (flag, None) if flag.starts_with("p=") => {
+                return Err(ScramError::ChannelBinding("channel-binding-not-supported"));
+            }
+            (flag, Some(binding)) if flag.strip_prefix("p=") == Some(binding.kind()) => {
+                cbind_input.extend(binding.data())
+            }
+            (flag, Some(_)) if flag.starts_with("p=") => {
+                return Err(ScramError::ChannelBinding(
+                    "unsupported-channel-binding-type",
+                ));
+            }
+            _ => return Err(malformed()),
+        }
+
+        let attrs = attributes(first_bare)?;
+        let username = unescape_username(attribute(&attrs, 0, 'n', "client-first")?)?;
+        let client_nonce = attribute(&attrs, 1, 'r', "client-first")?;
+        if client_nonce.is_empty() {
+            return Err(malformed());
+        }
+        // acting for another account would need authorization this mechanism can't give
+        match authzid.strip_prefix("a=") {
+            Some(authzid) if unescape_username(authzid)? != username => {
+                return Err(ScramError::Malformed(
+                    "authorization identity other than the username".to_string(),
+                ));
+            }
+            None if !authzid.is_empty() => return Err(malformed()),
+            _ => {}
+        }
+
+        let credentials = lookup(&username).unwrap_or_else(|| {
+            let label = format!("{}{}", FAKE_SALT_LABEL, username);
+            let mut salt = hmac_sha256(&self.secret, label.as_bytes());
+            salt.truncate(SCRAM_SALT_LEN);
+            ScramCredentials {
+                salt,
+                iterations: self.iterations,
+                stored_key: random_bytes(SCRAM_KEY_LEN),
+                server_key: random_bytes(SCRAM_KEY_LEN),
+            }
+        });
+        let nonce = format!("{}{}", client_nonce, self.nonce);
+        let server_first = format!(
+            "r={},s={},i={}",
+            nonce,
+            base64_encode(&credentials.salt),
+            credentials.iterations
+        );
+        Ok(Pending {
+            username,
+            cbind_input,
+            first_bare: first_bare.to_string(),
+            server_first,
+            nonce,
+            credentials,
+        })
+    }
+
+    /// check the client's proof and sign the exchange
+    /// # Arguments
+    /// * `client_final` - `c=<channel binding>,r=<nonce>,p=<proof>` from the client
+    /// # Return
+    /// * the server-final message, `v=<server signature>`, or the error to answer with
+    ///   through `ScramError::server_final`
+    pub fn server_final(&mut self, client_final: &str) -> Result<String, ScramError> {
+        let pending = match std::mem::replace(&mut self.state, ServerState::Failed) {
+            ServerState::SentFirst(pending) => pending,
+            _ => return Err(ScramError::OutOfOrder("server-final")),
+        };
+        let server_final = Self::verify(&pending, client_final)?;
+        self.state = ServerState::Done(pending.username);
+        Ok(server_final)
+    }
+
+    /// internal method to check the client-final message against the pending exchange
+    fn verify(pending: &Pending, client_final: &str) -> Result<String, ScramError> {
+        let malformed = || ScramError::Malformed("client-final".to_string());
+        let (without_proof, proof) = client_final.rsplit_once(",p=").ok_or_else(malformed)?;
+        let attrs = attributes(without_proof)?;
+        let cbind_input =
+            base64_decode(attribute(&attrs, 0, 'c', "client-final")?).map_err(|_| malformed())?;
+        if cbind_input != pending.cbind_input {
+            return Err(ScramError::ChannelBinding("channel-bindings-dont-match"));
+        }
+        if attribute(&attrs, 1, 'r', "client-final")? != pending.nonce {
+            return Err(ScramError::Nonce);
+        }
+        let proof = base64_decode(proof).map_err(|_| malformed())?;
+        if proof.len() != SCRAM_KEY_LEN {
+            return Err(malformed());
+        }
+
+        // ClientKey = ClientProof ^ HMAC(StoredKey, AuthMessage), and H(ClientKey) = StoredKey
+        let auth_message = format!(
+            "{},{},{}",
+            pending.first_bare, pending.server_first, without_proof
+        );
+        let credentials = &pending.credentials;
+        let client_signature = hmac_sha256(&credentials.stored_key, auth_message.as_bytes());
+        let client_key = xor(&proof, &client_signature);
+        if !constant_time_eq(&sha256(client_key), &credentials.stored_key) {
+            return Err(ScramError::InvalidProof);
+        }
+        let server_signature = hmac_sha256(&credentials.server_key, auth_message.as_bytes());
+        Ok(format!("v={}", base64_encode(&server_signature)))
+    }
+
+    /// account that authenticated, `None` until the exchange succeeded
+    pub fn username(&self) -> Option<&str> {
+        match &self.state {
+            ServerState::Done(username) => Some(username),
+            _ => None,
+        }
+    }
+
+    /// internal method to end the exchange after a failed step
+    fn fail(&mut self, e: ScramError) -> ScramError {
+        self.state = ServerState::Failed;
+        e
+    }
+}
+
+// ==================== FUNCTIONS ====================
+
+/// prepare a username or password with SASLprep (RFC 4013): non-ASCII spaces become spaces,
+/// characters mapped to nothing are dropped and the result is NFKC normalized
+/// the bidirectional text checks are not made
+/// # Arguments
+/// * `text` - username or password
+/// # Return
+/// * the prepared text, `Prohibited` if it holds control or private use characters
+pub fn saslprep(text: &str) -> Result<String, ScramError> {
+    let prepared: String = text
+        .chars()
+        .filter(|c| !MAPPED_TO_NOTHING.contains(c))
+        .map(|c| match c != ' ' && c.is_whitespace() && !c.is_control() {
+            true => ' ',
+            false => c,
+        })
+        .nfkc()
+        .collect();
+    match prepared
+        .chars()
+        .any(|c| c.is_control() || ('\u{E000}'..='\u{F8FF}').contains(&c))
+    {
+        true => Err(ScramError::Prohibited),
+        false => Ok(prepared),
+    }
+}
+
+/// SaltedPassword = Hi(password, salt, iterations), PBKDF2 with HMAC-SHA256
+/// # Arguments
+/// * `password` - password, prepared with SASLprep first
+/// * `salt` - salt
+/// * `iterations` - PBKDF2 iterations
+pub fn salted_password(
+    password: &str,
+    salt: &[u8],
+    iterations: u32,
+) -> Result<Vec<u8>, ScramError> {
+    Ok(pbkdf2_sha256(
+        saslprep(password)?.as_bytes(),
+        salt,
+        iterations,
+        SCRAM_KEY_LEN,
+    ))
+}
+
+/// ClientKey = HMAC(SaltedPassword, "Client Key"), whose hash is the StoredKey
+/// # Arguments
+/// * `salted` - SaltedPassword
+pub fn client_key(salted: &[u8]) -> Vec<u8> {
+    hmac_sha256(salted, b"Client Key")
+}
+
+/// ServerKey = HMAC(SaltedPassword, "Server Key")
+/// # Arguments
+/// * `salted` - SaltedPassword
+pub fn server_key(salted: &[u8]) -> Vec<u8> {
+    hmac_sha256(salted, b"Server Key")
+}
+
+/// internal function to xor two byte strings of the same length
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.iter().zip(b).map(|(a, b)| a ^ b).collect()
+}
+
+/// internal function to read random bytes from the OS
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    OsRng
+        .try_fill_bytes(&mut bytes)
+        .expect("OS random number generator failed");
+    bytes
+}
+
+/// internal function to make a random nonce, base64 never holds a `,`
+fn random_nonce() -> String {
+    base64_encode(&random_bytes(SCRAM_NONCE_LEN))
+}
+
+/// internal function to escape a username for a message, `,` as `=2C` and `=` as `=3D`
+fn escape_username(username: &str) -> String {
+    username.replace('=', "=3D").replace(',', "=2C")
+}
+
+/// internal function to undo `escape_username`
+fn unescape_username(saslname: &str) -> Result<String, ScramError> {
+    let mut username = String::new();
+    let mut rest = saslname;
+    while let Some(index) = rest.find('=') {
+        username.push_str(&rest[..index]);
+        rest = &rest[index..];
+        if let Some(after) = rest.strip_prefix("=2C") {
+            username.push(',');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("=3D") {
+            username.push('=');
+            rest = after;
+        } else {
+            return Err(ScramError::Malformed("username".to_string()));
+        }
+    }
+    username.push_str(rest);
+    Ok(username)
+}
+
+/// internal function to split a message into its `<letter>=<value>` attributes
+/// a mandatory extension, `m=`, is refused since none is supported
+fn attributes(message: &str) -> Result<Vec<(char, &str)>, ScramError> {
+    message
+        .split(',')
+        .map(|attr| {
+            let mut chars = attr.chars();
+            match (chars.next(), chars.next()) {
+                (Some('m'), Some('=')) => Err(ScramError::Malformed(
+                    "message, mandatory extensions are not supported".to_string(),
+                )),
+                (Some(name), Some('=')) if name.is_ascii_alphabetic() => Ok((name, &attr[2..])),
+                _ => Err(ScramError::Malformed(format!("attribute '{}'", attr))),
+            }
+        })
+        .collect()
+}
+
+/// internal function to get the attribute a message must have at a position
+fn attribute<'a>(
+    attrs: &[(char, &'a str)],
+    index: usize,
+    name: char,
+    message: &str,
+) -> Result<&'a str, ScramError> {
+    match attrs.get(index) {
+        Some((found, value)) if *found == name => Ok(value),
+        _ => Err(ScramError::Malformed(format!(
+            "{}, expected {}= attribute",
+            message, name
+        ))),
+    }
+}
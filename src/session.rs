@@ -0,0 +1,414 @@
+// src/session.rs
+//! This module contains login sessions:
+//! * random session ids from the OS
+//! * creation and last activity timestamps, checked against idle and absolute timeouts
+//! * the authentication factors a session was established with
+//! * locking, which stops the idle timeout until the password is given
+//! * a session table shared by every shell using the same data directory, so sessions can
+//!   be listed and revoked from another shell
+
+// ==================== IMPORTS ====================
+
+use crate::audit::format_time;
+use crate::auth_utils::*;
+use crate::authz::Capability;
+use rand_core::{OsRng, TryRngCore};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::read_to_string;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// ==================== CONSTANTS ====================
+
+/// length of a session id, in random bytes
+pub const SESSION_ID_LEN: usize = 16;
+
+/// default seconds without a command before a session expires
+pub const DEF_IDLE_TIMEOUT: u64 = 15 * 60;
+
+/// default seconds after login before a session expires, however active it is
+pub const DEF_SESSION_LIFETIME: u64 = 8 * 60 * 60;
+
+/// what happens to a session left idle at the prompt for its idle timeout
+pub const IDLE_ACTIONS: &[&str] = &["logout", "lock"];
+
+/// default idle action, logging out like `TMOUT`
+pub const DEF_IDLE_ACTION: &str = "logout";
+
+/// file mode of the session table, readable and writable by the owner only
+const SESSIONS_MODE: u32 = 0o600;
+
+// ==================== STRUCTURES ====================
+
+/// ways a user proved who they are
+/// # Variants
+/// * `Password` - the account password
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthFactor {
+    Password,
+}
+
+impl fmt::Display for AuthFactor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuthFactor::Password => write!(f, "password"),
+        }
+    }
+}
+
+/// reasons a session is no longer valid
+/// # Variants
+/// * `Io` - the session table could not be read or written
+/// * `Corrupt` - the session table is not a list of sessions
+/// * `Revoked` - the session is not in the session table
+/// * `Mismatch` - the session table names another user or other limits for the session
+/// * `Idle` - no command was run within the idle timeout, in seconds
+/// * `Expired` - the session outlived its lifetime, in seconds
+#[derive(Debug)]
+pub enum SessionError {
+    Io(std::io::Error),
+    Corrupt(String),
+    Revoked,
+    Mismatch,
+    Idle(u64),
+    Expired(u64),
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SessionError::Io(e) => write!(f, "{}", e),
+            SessionError::Corrupt(reason) => write!(f, "session table is corrupt: {}", reason),
+            SessionError::Revoked => write!(f, "session was revoked"),
+            SessionError::Mismatch => write!(f, "session table was changed behind the session"),
+            SessionError::Idle(timeout) => {
+                write!(f, "session was idle for more than {} seconds", timeout)
+            }
+            SessionError::Expired(lifetime) => {
+                write!(f, "session is older than {} seconds", lifetime)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SessionError {}
+
+impl From<std::io::Error> for SessionError {
+    fn from(e: std::io::Error) -> Self {
+        SessionError::Io(e)
+    }
+}
+
+/// a logged in user
+/// # Fields
+/// * `id` - hex encoded random id
+/// * `user` - account name
+/// * `created` - seconds since the unix epoch at login
+/// * `last_active` - seconds since the unix epoch at the last command
+/// * `idle_timeout` - seconds without a command before the session expires, 0 for never
+/// * `lifetime` - seconds after login before the session expires, 0 for never
+/// * `factors` - authentication factors used to log in
+/// * `sudo_until` - seconds since the unix epoch until which sudo needs no password
+/// * `locked` - whether the session waits for a password to resume, which stops the idle timeout
+/// * `elevated` - whether a command is running through sudo, never stored
+/// * `granted` - capability a policy permit lends the running command, never stored
+/// # Methods
+/// * `new` - starts a session with a fresh id
+/// * `check` - checks the timeouts
+/// * `same_identity` - compares who a session belongs to and its limits
+/// * `deadline` - finds when the first timeout passes
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub user: String,
+    pub created: u64,
+    pub last_active: u64,
+    pub idle_timeout: u64,
+    pub lifetime: u64,
+    pub factors: Vec<AuthFactor>,
+    #[serde(default)]
+    pub sudo_until: u64,
+    #[serde(default)]
+    pub locked: bool,
+    #[serde(skip)]
+    pub elevated: bool,
+    #[serde(skip)]
+    pub granted: Option<Capability>,
+}
+
+/// Methods for the struct
+impl Session {
+    /// start a session now, with a fresh random id
+    /// # Arguments
+    /// * `user` - account name
+    /// * `factors` - authentication factors used to log in
+    /// * `idle_timeout` - seconds without a command before the session expires, 0 for never
+    /// * `lifetime` - seconds after login before the session expires, 0 for never
+    /// # Return
+    /// * the session, or an error if the OS could not provide random bytes
+    pub fn new(
+        user: &str,
+        factors: &[AuthFactor],
+        idle_timeout: u64,
+        lifetime: u64,
+    ) -> std::io::Result<Self> {
+        let mut id = [0u8; SESSION_ID_LEN];
+        OsRng
+            .try_fill_bytes(&mut id)
+            .map_err(std::io::Error::other)?;
+        let now = now();
+        Ok(Session {
+            id: hex::encode(id),
+            user: user.to_string(),
+            created: now,
+            last_active: now,
+            idle_timeout,
+            lifetime,
+            factors: factors.to_vec(),
+            sudo_until: 0,
+            locked: false,
+            elevated: false,
+            granted: None,
+        })
+    }
+
+    /// check the session against its timeouts
+    /// # Arguments
+    /// * `now` - seconds since the unix epoch
+    /// # Return
+    /// * `Expired` or `Idle` if a timeout has passed, a locked session is never idle
+    pub fn check(&self, now: u64) -> Result<(), SessionError> {
+        if self.lifetime > 0 && now.saturating_sub(self.created) > self.lifetime {
+            return Err(SessionError::Expired(self.lifetime));
+        }
+        if !self.locked
+            && self.idle_timeout > 0
+            && now.saturating_sub(self.last_active) > self.idle_timeout
+        {
+            return Err(SessionError::Idle(self.idle_timeout));
+        }
+        Ok(())
+    }
+
+    /// compare the fields that say who the session belongs to and how long it may last
+    /// # Arguments
+    /// * `other` - another copy of the session
+    /// # Return
+    /// * whether or not both copies agree on them
+    pub fn same_identity(&self, other: &Session) -> bool {
+        self.id == other.id
+            && self.user == other.user
+            && self.created == other.created
+            && self.idle_timeout == other.idle_timeout
+            && self.lifetime == other.lifetime
+            && self.factors == other.factors
+    }
+
+    /// find when the session first fails `check`, unless a command is run before then
+    /// # Return
+    /// * seconds since the unix epoch, `None` if the session never times out
+    pub fn deadline(&self) -> Option<u64> {
+        let expiry = (self.lifetime > 0)
+            .then(|| self.created.saturating_add(self.lifetime).saturating_add(1));
+        let idle = (!self.locked && self.idle_timeout > 0).then(|| {
+            self.last_active
+                .saturating_add(self.idle_timeout)
+                .saturating_add(1)
+        });
+        expiry.into_iter().chain(idle).min()
+    }
+}
+
+/// Displays the session as one human readable line
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let factors: Vec<String> = self.factors.iter().map(|f| f.to_string()).collect();
+        write!(
+            f,
+            "{} {:<10} since {} last active {} via {}{}",
+            self.id,
+            self.user,
+            format_time(self.created),
+            format_time(self.last_active),
+            factors.join("+"),
+            if self.locked { ", locked" } else { "" }
+        )
+    }
+}
+
+/// sessions of every shell using the same data directory, kept in a JSON file
+/// # Fields
+/// * `location` - filepath of the table, locked with `<table>.lock` while changing it
+/// # Methods
+/// * `new` - refers to a table, which is created on the first session
+/// * `insert` - adds a session
+/// * `validate` - checks a session is still valid and marks it active
+/// * `save` - replaces a session, e.g. after its user was renamed
+/// * `remove` - ends a session
+/// * `list` - lists every valid session
+/// * `revoke` - ends a session by id
+/// * `revoke_user` - ends every session of an account
+pub struct SessionTable {
+    location: String,
+}
+
+/// Methods for the struct
+impl SessionTable {
+    /// refer to a session table, creating nothing until the first session
+    /// # Arguments
+    /// * `filepath` - filepath of the table
+    pub fn new(filepath: &str) -> Self {
+        SessionTable {
+            location: filepath.to_string(),
+        }
+    }
+
+    /// internal method to read the table
+    fn read(&self) -> Result<Vec<Session>, SessionError> {
+        match read_to_string(&self.location) {
+            Ok(data) => {
+                serde_json::from_str(&data).map_err(|e| SessionError::Corrupt(e.to_string()))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// internal method to change the table under its lock
+    /// # Arguments
+    /// * `change` - edits the sessions, returning whether anything changed and a result
+    fn update<T, F>(&self, change: F) -> Result<T, SessionError>
+    where
+        F: FnOnce(&mut Vec<Session>) -> (bool, T),
+    {
+        let lock_path = format!("{}.lock", self.location);
+        let _lock = lock_file(Path::new(&lock_path), true)?;
+        let mut sessions = self.read()?;
+        let (changed, result) = change(&mut sessions);
+        if changed {
+            let data = serde_json::to_vec_pretty(&sessions)
+                .map_err(|e| SessionError::Corrupt(e.to_string()))?;
+            atomic_write(&self.location, &data, SESSIONS_MODE)?;
+        }
+        Ok(result)
+    }
+
+    /// add a session, dropping any that have expired
+    /// # Arguments
+    /// * `session` - newly started session
+    pub fn insert(&self, session: &Session) -> Result<(), SessionError> {
+        let now = now();
+        self.update(|sessions| {
+            sessions.retain(|s| s.check(now).is_ok());
+            sessions.push(session.clone());
+            (true, ())
+        })
+    }
+
+    /// check a session is still in the table and within its timeouts, and mark it active
+    /// the table is not sealed, so it is only trusted to say whether the session still exists;
+    /// who it belongs to and its timeouts come from the caller's copy, and a stored session
+    /// that disagrees with it is removed like an expired one
+    /// # Arguments
+    /// * `session` - the caller's copy of the session
+    /// # Return
+    /// * the caller's copy marked active, or why it is no longer valid
+    pub fn validate(&self, session: &Session) -> Result<Session, SessionError> {
+        let now = now();
+        self.update(|sessions| {
+            let index = match sessions.iter().position(|s| s.id == session.id) {
+                Some(index) => index,
+                None => return (false, Err(SessionError::Revoked)),
+            };
+            let result = match sessions[index].same_identity(session) {
+                true => session.check(now),
+                false => Err(SessionError::Mismatch),
+            };
+            if let Err(e) = result {
+                sessions.remove(index);
+                return (true, Err(e));
+            }
+            sessions[index].last_active = now;
+            let mut validated = session.clone();
+            validated.last_active = now;
+            (true, Ok(validated))
+        })?
+    }
+
+    /// replace the stored session with the same id
+    /// # Arguments
+    /// * `session` - session with its changes
+    /// # Return
+    /// * `Revoked` if the session is no longer in the table
+    pub fn save(&self, session: &Session) -> Result<(), SessionError> {
+        self.update(
+            |sessions| match sessions.iter_mut().find(|s| s.id == session.id) {
+                Some(stored) => {
+                    *stored = session.clone();
+                    (true, Ok(()))
+                }
+                None => (false, Err(SessionError::Revoked)),
+            },
+        )?
+    }
+
+    /// end a session, e.g. on logout
+    /// # Arguments
+    /// * `id` - session id
+    /// # Return
+    /// * whether or not the session was in the table
+    pub fn remove(&self, id: &str) -> Result<bool, SessionError> {
+        Ok(self.revoke(id)?.is_some())
+    }
+
+    /// list every session that has not expired, dropping the rest
+    /// # Return
+    /// * sessions in the order they were started
+    pub fn list(&self) -> Result<Vec<Session>, SessionError> {
+        let now = now();
+        self.update(|sessions| {
+            let before = sessions.len();
+            sessions.retain(|s| s.check(now).is_ok());
+            (sessions.len() != before, sessions.clone())
+        })
+    }
+
+    /// end a session by id
+    /// # Arguments
+    /// * `id` - session id
+    /// # Return
+    /// * the session that was ended, if it was in the table
+    pub fn revoke(&self, id: &str) -> Result<Option<Session>, SessionError> {
+        self.update(|sessions| match sessions.iter().position(|s| s.id == id) {
+            Some(index) => (true, Some(sessions.remove(index))),
+            None => (false, None),
+        })
+    }
+
+    /// end every session of an account, e.g. when it is deleted
+    /// # Arguments
+    /// * `user` - account name
+    /// # Return
+    /// * number of sessions ended
+    pub fn revoke_user(&self, user: &str) -> Result<usize, SessionError> {
+        self.update(|sessions| {
+            let before = sessions.len();
+            sessions.retain(|s| s.user != user);
+            let revoked = before - sessions.len();
+            (revoked > 0, revoked)
+        })
+    }
+}
+
+// ==================== FUNCTIONS ====================
+
+/// This function gets the current time
+/// # Return
+/// * seconds since the unix epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
@@ -1,21 +1,27 @@
 // ==================== IMPORTS ====================
 
+use crate::audit::*;
 use crate::auth_utils::*;
+use crate::authz::*;
+use crate::bignum::BigUint;
+use crate::config::*;
+use crate::policy::*;
+use crate::scram::*;
+use crate::session::*;
+use crate::srp::*;
+use crate::sudo::*;
+use crate::token::*;
+use crate::transfer::*;
+use crate::username::normalize;
+use crate::verifier::*;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
 
 // ==================== CONSTANTS ====================
 
 /// predefined nulluser name
 pub const NULLUSER: &str = "";
 
-/// predefined root user name
-pub const ROOT: &str = "root";
-
-/// Permission levels
-pub const P_NONE: u8 = 0;
-pub const P_USER: u8 = 1;
-// pub const P_SUDO: u8 = 2;
-pub const P_ROOT: u8 = 3;
-
 // ==================== STRUCTURES ====================
 
 /// structure to hold information about shell a command
@@ -23,27 +29,1078 @@ pub const P_ROOT: u8 = 3;
 /// * name - name as called from the shell
 /// * usage - for help messsage, command args/flags
 /// * description - for help message, describes functionality
-/// * permissions - permissions level, i.e. who can run command
+/// * access - who can run command, anyone, logged in users, or holders of a capability
+/// * other - capability the command needs to act on another account than the caller's, if it
+///   takes one besides its access
+/// * resource - finds what a command line acts on, the resource policy rules see
 /// * handler - function handler that actually does the command
 pub struct Command {
     pub name: &'static str,
     pub usage: &'static str,
     pub description: &'static str,
-    pub permissions: u8,
+    pub access: Access,
+    pub other: Option<Capability>,
+    pub resource: fn(&Environment, &[String]) -> String,
     pub handler: fn(&mut Environment, u8, &[String]) -> i8,
 }
 
+/// decision on running a command, from the policy and then the command's access
+/// # Variants
+/// * `Access` - no rule applies, so the command's access decided, with whether it allowed it
+/// * `Permitted` - a permit rule applies, with its line, the command runs holding the
+///   capability the permit lends it, see `Environment::lent`
+/// * `Forbidden` - a forbid rule applies, with its line
+/// * `Unusable` - the policy can't be used, with why and whether the command may run anyway,
+///   which only root and commands open to anyone may
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Ruling {
+    Access(bool),
+    Permitted(usize),
+    Forbidden(usize),
+    Unusable(String, bool),
+}
+
+/// Methods for the enum
+impl Ruling {
+    /// whether the command may run
+    pub fn allows(&self) -> bool {
+        match self {
+            Ruling::Access(allowed) | Ruling::Unusable(_, allowed) => *allowed,
+            Ruling::Permitted(_) => true,
+            Ruling::Forbidden(_) => false,
+        }
+    }
+}
+
+impl std::fmt::Display for Ruling {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Ruling::Access(true) => write!(f, "no rule applies, allowed by the command's access"),
+            Ruling::Access(false) => write!(f, "no rule applies, denied by the command's access"),
+            Ruling::Permitted(line) => write!(f, "permitted by the rule on line #{}", line),
+            Ruling::Forbidden(line) => write!(f, "forbidden by the rule on line #{}", line),
+            Ruling::Unusable(reason, true) => {
+                write!(
+                    f,
+                    "policy can't be used ({}), allowed by the command's access",
+                    reason
+                )
+            }
+            Ruling::Unusable(reason, false) => write!(f, "policy can't be used ({})", reason),
+        }
+    }
+}
+
+/// a session suspended by `su`, resumed by `exit`
+/// # Fields
+/// * `session` - the suspended session
+/// * `vars` - its environment variables
+pub struct Frame {
+    pub session: Session,
+    pub vars: BTreeMap<String, String>,
+}
+
 /// structure for environment variables
 /// Fields
-/// * user - username of active user
-/// * permissions - permissions level of active user
+/// * session - session of the active user, `None` when logged out
+/// * vars - environment variables of the active session
+/// * stack - sessions suspended by `su`, innermost last
+/// * sessions - table of every shell's sessions
 /// * commands - vector of registered shell commands
-/// * database - struct managing credentials
+/// * database - credential store backend
+/// * groups - group database
+/// * roles - role table
+/// * verifiers - verifiers of challenge-response logins
+/// * audit - log of security events
+/// * config - effective configuration
 pub struct Environment {
-    pub user: String,
-    pub permissions: u8,
+    pub session: Option<Session>,
+    pub vars: BTreeMap<String, String>,
+    pub stack: Vec<Frame>,
+    pub sessions: SessionTable,
     pub commands: Vec<&'static Command>,
-    pub database: UserCredentials,
+    pub database: Box<dyn CredentialStore>,
+    pub groups: GroupDatabase,
+    pub roles: RoleTable,
+    pub verifiers: VerifierTable,
+    pub audit: AuditLog,
+    pub config: Config,
+}
+
+/// Methods for the struct
+impl Environment {
+    /// username of the active user, `NULLUSER` when logged out
+    pub fn user(&self) -> &str {
+        self.session.as_ref().map_or(NULLUSER, |s| s.user.as_str())
+    }
+
+    /// whether the active user is running a command through sudo
+    pub fn elevated(&self) -> bool {
+        self.session.as_ref().is_some_and(|s| s.elevated)
+    }
+
+    /// capability a policy rule lent the command the active user is running, if any
+    pub fn granted(&self) -> Option<Capability> {
+        self.session.as_ref().and_then(|s| s.granted)
+    }
+
+    /// capabilities of the active user, every one for root and under sudo, those of their roles
+    /// and the one a policy permit lent the running command otherwise, none when logged out
+    pub fn capabilities(&self) -> Vec<Capability> {
+        if self.user() == NULLUSER {
+            return Vec::new();
+        }
+        if self.user() == ROOT || self.elevated() {
+            return Capability::ALL.to_vec();
+        }
+        let mut capabilities = self
+            .roles
+            .capabilities_of(self.user(), |g| self.in_group(g));
+        if let Some(capability) = self.granted()
+            && !capabilities.contains(&capability)
+        {
+            capabilities.push(capability);
+            capabilities.sort();
+        }
+        capabilities
+    }
+
+    /// whether the active user holds a capability
+    /// # Arguments
+    /// * `capability` - capability to check
+    pub fn can(&self, capability: Capability) -> bool {
+        self.capabilities().contains(&capability)
+    }
+
+    /// whether the active user is in a group, as their primary group or as a member
+    /// # Arguments
+    /// * `group` - group name
+    pub fn in_group(&self, group: &str) -> bool {
+        self.account_in_group(self.user(), group)
+    }
+
+    /// whether an account is in a group, as its primary group or as a member
+    /// # Arguments
+    /// * `account` - account name
+    /// * `group` - group name
+    pub fn account_in_group(&self, account: &str, group: &str) -> bool {
+        self.database
+            .get(account)
+            .is_some_and(|record| self.groups.is_member(&record, group))
+    }
+
+    /// describe a command line to the policy
+    /// # Arguments
+    /// * `cmd` - shell command
+    /// * `argv` - command and its arguments
+    /// # Return
+    /// * the request, its resource found by the command's own extractor
+    pub fn policy_request(&self, cmd: &Command, argv: &[String]) -> Request {
+        let resource = (cmd.resource)(self, argv);
+        let (factors, created) = match &self.session {
+            Some(session) => (
+                session.factors.iter().map(|f| f.to_string()).collect(),
+                session.created,
+            ),
+            None => (Vec::new(), now()),
+        };
+        Request {
+            principal: self.user().to_string(),
+            action: cmd.name.to_string(),
+            resource,
+            context: Context::at(
+                now(),
+                self.config.utc_offset,
+                factors,
+                self.elevated(),
+                now().saturating_sub(created),
+            ),
+        }
+    }
+
+    /// decide whether the active user may run a command line, first by the policy, then by the
+    /// command's access
+    /// # Arguments
+    /// * `cmd` - shell command
+    /// * `argv` - command and its arguments
+    pub fn authorize(&self, cmd: &Command, argv: &[String]) -> Ruling {
+        let allowed = match cmd.access {
+            Access::Public => true,
+            Access::LoggedIn => self.user() != NULLUSER,
+            Access::Requires(capability) => self.can(capability),
+        };
+        let policy = match Policy::load(&self.config.policy_path()) {
+            Ok(policy) => policy,
+            Err(e) => {
+                let fallback = self.user() == ROOT || cmd.access == Access::Public;
+                return Ruling::Unusable(e.to_string(), allowed && fallback);
+            }
+        };
+        let request = self.policy_request(cmd, argv);
+        match policy
+            .evaluate(&request, |a, g| self.account_in_group(a, g))
+            .0
+        {
+            Verdict::Forbidden(line) => Ruling::Forbidden(line),
+            Verdict::Permitted(line) => Ruling::Permitted(line),
+            Verdict::NotCovered => Ruling::Access(allowed),
+        }
+    }
+
+    /// the capability a permit rule lends a command line: the one its access names, or its
+    /// `other` capability when it acts on another account than the active user's
+    /// # Arguments
+    /// * `cmd` - shell command
+    /// * `argv` - command and its arguments
+    pub fn lent(&self, cmd: &Command, argv: &[String]) -> Option<Capability> {
+        match cmd.access {
+            Access::Requires(capability) => Some(capability),
+            _ => cmd
+                .other
+                .filter(|_| (cmd.resource)(self, argv) != self.user()),
+        }
+    }
+
+    /// whether the active user may run a command without arguments, to list it in `help`
+    /// # Arguments
+    /// * `cmd` - shell command
+    pub fn may_run(&self, cmd: &Command) -> bool {
+        self.authorize(cmd, &[cmd.name.to_string()]).allows()
+    }
+
+    /// whether the active session waits for a password to resume
+    pub fn locked(&self) -> bool {
+        self.session.as_ref().is_some_and(|s| s.locked)
+    }
+
+    /// when the first of the active session and those suspended beneath it times out, unless a
+    /// command is run before then
+    /// # Return
+    /// * seconds since the unix epoch, `None` if logged out or no session times out
+    pub fn deadline(&self) -> Option<u64> {
+        self.stack
+            .iter()
+            .map(|frame| &frame.session)
+            .chain(&self.session)
+            .filter_map(Session::deadline)
+            .min()
+    }
+
+    /// how many sessions `su` has suspended beneath the active one
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// environment variables a login as a user starts with
+    /// # Arguments
+    /// * `user` - account name
+    pub fn login_vars(&self, user: &str) -> BTreeMap<String, String> {
+        let mut vars = BTreeMap::from([
+            ("USER".to_string(), user.to_string()),
+            ("LOGNAME".to_string(), user.to_string()),
+        ]);
+        if let Some(record) = self.database.get(user) {
+            vars.insert("HOME".to_string(), record.home().to_string());
+            vars.insert("SHELL".to_string(), record.shell().to_string());
+        }
+        vars
+    }
+
+    /// internal method to create a session and add it to the session table
+    /// # Return
+    /// * the session, or `None` after printing why there is none
+    fn new_session(&self, user: &str, factors: &[AuthFactor]) -> Option<Session> {
+        let session = match Session::new(
+            user,
+            factors,
+            self.config.idle_timeout,
+            self.config.session_lifetime,
+        ) {
+            Ok(session) => session,
+            Err(e) => {
+                eprintln!("\x1b[91mFailed to start a session. Error: {}\x1b[0m", e);
+                return None;
+            }
+        };
+        if let Err(e) = self.sessions.insert(&session) {
+            eprintln!("\x1b[91mFailed to start a session. Error: {}\x1b[0m", e);
+            return None;
+        }
+        Some(session)
+    }
+
+    /// start a session for a user who just authenticated, ending the current one
+    /// # Arguments
+    /// * `user` - account name
+    /// * `factors` - authentication factors the user passed
+    /// # Return
+    /// * whether or not the session was started, an error is printed if not
+    pub fn start_session(&mut self, user: &str, factors: &[AuthFactor]) -> bool {
+        self.end_session();
+        match self.new_session(user, factors) {
+            Some(session) => {
+                self.session = Some(session);
+                self.vars = self.login_vars(user);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// suspend the current session and start one for another user on top of it, like `su`
+    /// # Arguments
+    /// * `user` - account name
+    /// * `factors` - authentication factors of the new session
+    /// * `fresh` - whether the new session starts with login variables only, like `su -`,
+    ///   rather than the current ones with the user's login variables set
+    /// # Return
+    /// * whether or not the session was started, an error is printed if not
+    pub fn push_session(&mut self, user: &str, factors: &[AuthFactor], fresh: bool) -> bool {
+        let session = match (self.session.take(), self.new_session(user, factors)) {
+            (Some(current), Some(session)) => {
+                self.stack.push(Frame {
+                    session: current,
+                    vars: self.vars.clone(),
+                });
+                session
+            }
+            (current, _) => {
+                self.session = current;
+                return false;
+            }
+        };
+        if fresh {
+            self.vars.clear();
+        }
+        self.vars.extend(self.login_vars(user));
+        self.session = Some(session);
+        true
+    }
+
+    /// end the current session and resume the one `su` suspended beneath it
+    /// # Return
+    /// * whether or not there was a suspended session to resume
+    pub fn pop_session(&mut self) -> bool {
+        let frame = match self.stack.pop() {
+            Some(frame) => frame,
+            None => return false,
+        };
+        self.end_session();
+        self.session = Some(frame.session);
+        self.vars = frame.vars;
+        true
+    }
+
+    /// end the current session, if any
+    pub fn end_session(&mut self) {
+        self.vars.clear();
+        if let Some(session) = self.session.take()
+            && let Err(e) = self.sessions.remove(&session.id)
+        {
+            eprintln!(
+                "\x1b[91mFailed to remove session from '{}'. Error: {}\x1b[0m",
+                self.config.sessions_path(),
+                e
+            );
+        }
+    }
+
+    /// end the current session and every one suspended beneath it
+    pub fn end_all_sessions(&mut self) {
+        while self.pop_session() {}
+        self.end_session();
+    }
+
+    /// check the current session and those suspended beneath it were not revoked and have not
+    /// timed out, and mark them active
+    /// an invalid session is ended along with every session started on top of it, and the one
+    /// beneath it resumed
+    /// # Return
+    /// * the user whose session is no longer valid and why, `Ok` when logged out
+    pub fn validate_session(&mut self) -> Result<(), (String, SessionError)> {
+        for index in 0..self.stack.len() {
+            let suspended = &self.stack[index].session;
+            let result = self.sessions.validate(suspended);
+            if let Err(e) = result {
+                let user = suspended.user.clone();
+                while self.stack.len() > index {
+                    self.pop_session();
+                }
+                self.end_session();
+                if let Some(frame) = self.stack.pop() {
+                    self.session = Some(frame.session);
+                    self.vars = frame.vars;
+                }
+                return Err((user, e));
+            }
+            if let Ok(validated) = result {
+                self.stack[index].session = validated;
+            }
+        }
+        let result = match &self.session {
+            Some(session) => self.sessions.validate(session),
+            None => return Ok(()),
+        };
+        match result {
+            Ok(session) => {
+                self.session = Some(session);
+                Ok(())
+            }
+            Err(e) => {
+                let user = self.user().to_string();
+                self.session = None;
+                self.vars.clear();
+                if let Some(frame) = self.stack.pop() {
+                    self.session = Some(frame.session);
+                    self.vars = frame.vars;
+                }
+                Err((user, e))
+            }
+        }
+    }
+}
+
+// ==================== HELPERS ====================
+
+/// function to get inline input from the user
+/// # Arguments
+/// * `prompt` - &str with which to prompt the user for input
+/// # Return
+/// * input given by the user
+pub fn inline_input(prompt: &str) -> String {
+    let mut input_buffer: String = String::new();
+    print!("{}", prompt);
+    io::stdout().flush().expect("stdout.flush() failed");
+    io::stdin()
+        .read_line(&mut input_buffer)
+        .expect("stdin.read_line() failed");
+    input_buffer.trim().into()
+}
+
+/// function to get inline input from the user, giving up at a deadline
+/// only a terminal is waited on, since buffered lines of piped input would not wake `poll`
+/// # Arguments
+/// * `prompt` - &str with which to prompt the user for input
+/// * `deadline` - seconds since the unix epoch to wait until, `None` to wait forever
+/// # Return
+/// * input given by the user, `None` if the deadline passed first
+pub fn timed_input(prompt: &str, deadline: Option<u64>) -> Option<String> {
+    // SAFETY: isatty only inspects the descriptor
+    let deadline = match deadline {
+        Some(deadline) if unsafe { libc::isatty(libc::STDIN_FILENO) } == 1 => deadline,
+        _ => return Some(inline_input(prompt)),
+    };
+    print!("{}", prompt);
+    io::stdout().flush().expect("stdout.flush() failed");
+    loop {
+        let now_ms = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis());
+        let remaining = (deadline as u128 * 1000).saturating_sub(now_ms);
+        if remaining == 0 {
+            println!();
+            return None;
+        }
+        let mut stdin = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: stdin is a single valid pollfd that outlives the call
+        let ready = unsafe { libc::poll(&mut stdin, 1, remaining.min(i32::MAX as u128) as i32) };
+        if ready > 0
+            || (ready < 0 && io::Error::last_os_error().kind() != io::ErrorKind::Interrupted)
+        {
+            break;
+        }
+    }
+    let mut input_buffer: String = String::new();
+    io::stdin()
+        .read_line(&mut input_buffer)
+        .expect("stdin.read_line() failed");
+    Some(input_buffer.trim().into())
+}
+
+/// function to report a session that ended because it timed out or was revoked
+/// # Arguments
+/// * `env` - shell environment
+/// * `user` - user whose session ended
+/// * `e` - why it ended
+pub fn report_session_end(env: &Environment, user: &str, e: &SessionError) {
+    env.audit
+        .record(user, "session", user, AuditOutcome::Failure, &e.to_string());
+    println!("logged out of {}: {}", user, e);
+}
+
+/// function to lock the active session and those suspended beneath it until the password of
+/// the user who logged in is given, which stops their idle timeouts
+/// # Arguments
+/// * `env` - shell environment
+/// * `detail` - why the session was locked, for the audit log
+pub fn lock_session(env: &mut Environment, detail: &str) {
+    let sessions = env
+        .stack
+        .iter_mut()
+        .map(|frame| &mut frame.session)
+        .chain(env.session.as_mut());
+    for session in sessions {
+        session.locked = true;
+        if let Err(e) = env.sessions.save(session) {
+            eprintln!(
+                "\x1b[91mFailed to lock the session of {}. Error: {}\x1b[0m",
+                session.user, e
+            );
+        }
+    }
+    audit(env, "lock", env.user(), AuditOutcome::Success, detail);
+    println!("session locked");
+}
+
+/// function to ask for the password that resumes a locked session
+/// the session can still expire while locked, and is ended if it does
+/// # Arguments
+/// * `env` - shell environment
+pub fn unlock_session(env: &mut Environment) {
+    if timed_input("locked, press enter to unlock ", env.deadline()).is_none() {
+        if let Err((user, e)) = env.validate_session() {
+            report_session_end(env, &user, &e);
+        }
+        return;
+    }
+    // the user who logged in unlocks, not whoever they switched to with su
+    let owner = env
+        .stack
+        .first()
+        .map_or(env.user(), |frame| frame.session.user.as_str())
+        .to_string();
+    let password = password_input(&format!("password for {}: ", owner), false);
+    if !env.database.authenticate(&owner, &password) {
+        audit(
+            env,
+            "unlock",
+            &owner,
+            AuditOutcome::Failure,
+            "wrong password",
+        );
+        eprintln!("\x1b[91mWrong password\x1b[0m");
+        return;
+    }
+    let now = now();
+    let sessions = env
+        .stack
+        .iter_mut()
+        .map(|frame| &mut frame.session)
+        .chain(env.session.as_mut());
+    for session in sessions {
+        session.locked = false;
+        session.last_active = now;
+        // a session revoked while locked is caught by the next validation
+        let _ = env.sessions.save(session);
+    }
+    audit(env, "unlock", &owner, AuditOutcome::Success, "unlocked");
+    println!("session unlocked");
+}
+
+/// function to act on a session that timed out while waiting at the prompt
+/// an idle session is locked or logged out as `session.idle_action` says, an expired one is
+/// logged out
+/// # Arguments
+/// * `env` - shell environment
+pub fn on_timeout(env: &mut Environment) {
+    let now = now();
+    let idle = env
+        .stack
+        .iter()
+        .map(|frame| &frame.session)
+        .chain(&env.session)
+        .find_map(|session| session.check(now).err());
+    if let Some(SessionError::Idle(timeout)) = idle
+        && env.config.idle_action == "lock"
+    {
+        lock_session(env, &format!("locked after {} seconds idle", timeout));
+        return;
+    }
+    if let Err((user, e)) = env.validate_session() {
+        report_session_end(env, &user, &e);
+    }
+}
+
+/// function to substitute an environment variable for a `$NAME` word of a command line
+/// # Arguments
+/// * `vars` - environment variables
+/// * `word` - word of the command line
+/// # Return
+/// * the variable's value, or the word as it is if it names no variable
+pub fn expand_var(vars: &BTreeMap<String, String>, word: &str) -> String {
+    word.strip_prefix('$')
+        .and_then(|name| vars.get(name))
+        .map_or(word, |value| value.as_str())
+        .to_string()
+}
+
+/// function to confirm an administrative action by asking the active user for their password
+/// under sudo the user has just re-authenticated, so no password is asked for
+/// # Arguments
+/// * `env` - shell environment
+/// # Return
+/// * whether or not the action may go ahead
+pub fn reauthenticate(env: &Environment) -> bool {
+    env.elevated() || reauthenticate_always(env)
+}
+
+/// function to confirm an action that wipes, replaces or reseals the database by asking the
+/// active user for their password, even under sudo, since a `NOPASSWD` rule or a remembered
+/// sudo password only shows the user was at the keyboard some time ago
+/// # Arguments
+/// * `env` - shell environment
+/// # Return
+/// * whether or not the action may go ahead
+pub fn reauthenticate_always(env: &Environment) -> bool {
+    env.database.authenticate(
+        env.user(),
+        &password_input(&format!("password for {}: ", env.user()), false),
+    )
+}
+
+/// function to end every session of an account and revoke its tokens, e.g. after it was
+/// deleted or renamed
+/// # Arguments
+/// * `env` - shell environment
+/// * `user` - account name
+pub fn revoke_sessions(env: &Environment, user: &str) {
+    match env.sessions.revoke_user(user) {
+        Ok(0) => {}
+        Ok(count) => println!("ended {} session(s) of {}", count, user),
+        Err(e) => eprintln!(
+            "\x1b[91mFailed to end the sessions of {}. Error: {}\x1b[0m",
+            user, e
+        ),
+    }
+    revoke_tokens(env, user);
+}
+
+/// function to keep anyone but root from changing root's password or details, whatever
+/// capabilities they hold, since root can undo anything they could not
+/// # Arguments
+/// * `env` - shell environment
+/// * `event` - command name, for the audit log
+/// * `target` - account about to be changed
+/// # Return
+/// * whether the change may go ahead, the refusal is audited and printed otherwise
+pub fn may_change(env: &Environment, event: &str, target: &str) -> bool {
+    if target != ROOT || env.user() == ROOT {
+        return true;
+    }
+    let detail = format!("only {} may change the {} account", ROOT, ROOT);
+    audit(env, event, target, AuditOutcome::Denied, &detail);
+    println!("{}", detail);
+    false
+}
+
+/// function to revoke every bearer token issued to an account so far, e.g. after its
+/// password changed
+/// # Arguments
+/// * `env` - shell environment
+/// * `user` - account name
+pub fn revoke_tokens(env: &Environment, user: &str) {
+    let authority = TokenAuthority::new(
+        &env.config.token_key_path(),
+        &env.config.revoked_tokens_path(),
+    );
+    if let Err(e) = authority.revoke_subject(user) {
+        eprintln!(
+            "\x1b[91mFailed to revoke the tokens of {}. Error: {}\x1b[0m",
+            user, e
+        );
+    }
+}
+
+/// function to carry an account's group memberships and roles over to its new name
+/// # Arguments
+/// * `env` - shell environment
+/// * `old` - previous account name
+/// * `new` - new account name, as stored
+pub fn rename_memberships(env: &mut Environment, old: &str, new: &str) {
+    if let Err(e) = env.groups.rename_member(old, new) {
+        eprintln!(
+            "\x1b[91mFailed to rename {} in its groups. Error: {}\x1b[0m",
+            old, e
+        );
+    }
+    if let Err(e) = env.roles.rename_user(old, new) {
+        eprintln!(
+            "\x1b[91mFailed to rename {} in its roles. Error: {}\x1b[0m",
+            old, e
+        );
+    }
+    if let Err(e) = env.verifiers.rename(old, new) {
+        eprintln!(
+            "\x1b[91mFailed to rename the verifiers of {}. Error: {}\x1b[0m",
+            old, e
+        );
+    }
+}
+
+/// function to remove a deleted account from every group it is a member of and every role
+/// # Arguments
+/// * `env` - shell environment
+/// * `user` - account name
+pub fn drop_memberships(env: &mut Environment, user: &str) {
+    if let Err(e) = env.groups.remove_user(user) {
+        eprintln!(
+            "\x1b[91mFailed to remove {} from its groups. Error: {}\x1b[0m",
+            user, e
+        );
+    }
+    if let Err(e) = env
+        .roles
+        .remove_principal(&Principal::User(user.to_string()))
+    {
+        eprintln!(
+            "\x1b[91mFailed to remove {} from its roles. Error: {}\x1b[0m",
+            user, e
+        );
+    }
+    if let Err(e) = env.verifiers.remove(user) {
+        eprintln!(
+            "\x1b[91mFailed to remove the verifiers of {}. Error: {}\x1b[0m",
+            user, e
+        );
+    }
+}
+
+/// function to issue a bearer token to the active user and print it
+/// # Arguments
+/// * `env` - shell environment
+/// # Return
+/// * whether or not a token was issued
+pub fn issue_token(env: &Environment) -> bool {
+    let authority = TokenAuthority::new(
+        &env.config.token_key_path(),
+        &env.config.revoked_tokens_path(),
+    );
+    match authority.issue(env.user(), &env.capabilities(), env.config.token_lifetime) {
+        Ok((token, claims)) => {
+            audit(
+                env,
+                "token",
+                env.user(),
+                AuditOutcome::Success,
+                &format!("issued token {}", claims.jti),
+            );
+            println!("{}", token);
+            println!("expires {}", format_time(claims.exp));
+            true
+        }
+        Err(e) => {
+            audit(
+                env,
+                "token",
+                env.user(),
+                AuditOutcome::Failure,
+                &e.to_string(),
+            );
+            println!("failed to issue token: {}", e);
+            false
+        }
+    }
+}
+
+/// resource of a command that acts on the active user
+/// # Arguments
+/// * `env` - shell environment
+/// * `argv` - command and its arguments
+#[allow(unused_variables)]
+pub fn on_self(env: &Environment, argv: &[String]) -> String {
+    env.user().to_string()
+}
+
+/// resource of a command that acts on its first argument that is not a flag, such as the
+/// command `sudo` runs, or on the active user without one
+/// # Arguments
+/// * `env` - shell environment
+/// * `argv` - command and its arguments
+pub fn on_first(env: &Environment, argv: &[String]) -> String {
+    argv.iter()
+        .skip(1)
+        .find(|arg| !arg.starts_with('-'))
+        .map_or(env.user(), |arg| arg.as_str())
+        .to_string()
+}
+
+/// resource of a command that acts on its last argument that is not a flag, such as the group
+/// of `gpasswd -a <username> <group>`, or on the active user without one
+/// # Arguments
+/// * `env` - shell environment
+/// * `argv` - command and its arguments
+pub fn on_last(env: &Environment, argv: &[String]) -> String {
+    argv.iter()
+        .skip(1)
+        .rfind(|arg| !arg.starts_with('-'))
+        .map_or(env.user(), |arg| arg.as_str())
+        .to_string()
+}
+
+/// resource of a command with subcommands, the argument after the subcommand, such as the role
+/// of `role assign <role> <principal>`, or the active user without one
+/// # Arguments
+/// * `env` - shell environment
+/// * `argv` - command and its arguments
+pub fn on_object(env: &Environment, argv: &[String]) -> String {
+    argv.get(2)
+        .map_or(env.user(), |arg| arg.as_str())
+        .to_string()
+}
+
+/// resource of `chname [old] <new>`, the account renamed
+/// # Arguments
+/// * `env` - shell environment
+/// * `argv` - command and its arguments
+pub fn on_renamed(env: &Environment, argv: &[String]) -> String {
+    match argv.len() {
+        3 => argv[1].clone(),
+        _ => env.user().to_string(),
+    }
+}
+
+/// resource of `su [-] [username]`, the account switched to, root without one
+/// # Arguments
+/// * `env` - shell environment
+/// * `argv` - command and its arguments
+#[allow(unused_variables)]
+pub fn on_su_target(env: &Environment, argv: &[String]) -> String {
+    argv.iter()
+        .skip(1)
+        .find(|arg| *arg != "-")
+        .map_or(ROOT, |arg| arg.as_str())
+        .to_string()
+}
+
+/// function to run a command line if the policy and the command's access allow it
+/// a command a policy rule permitted runs holding the capability the permit lends it
+/// # Arguments
+/// * `env` - shell environment
+/// * `cmd` - shell command
+/// * `argc` - number of arguments
+/// * `argv` - command and its arguments
+/// # Return
+/// * the command's return code, 1 if it was not allowed to run
+pub fn run_command(env: &mut Environment, cmd: &Command, argc: u8, argv: &[String]) -> i8 {
+    let ruling = env.authorize(cmd, argv);
+    if !ruling.allows() {
+        let detail = match &ruling {
+            Ruling::Access(false) => format!("permission denied, requires {}", cmd.access),
+            other => format!("permission denied, {}", other),
+        };
+        audit(env, cmd.name, "", AuditOutcome::Denied, &detail);
+        println!("permission denied: {}", cmd.name);
+        return 1;
+    }
+    let capability = match (&ruling, env.lent(cmd, argv)) {
+        (Ruling::Permitted(_), Some(capability)) => capability,
+        _ => return (cmd.handler)(env, argc, argv),
+    };
+
+    // run on the permit, then drop it unless the command ended the session
+    let id = match env.session.as_mut() {
+        Some(session) => {
+            session.granted = Some(capability);
+            session.id.clone()
+        }
+        None => return (cmd.handler)(env, argc, argv),
+    };
+    let ret_code = (cmd.handler)(env, argc, argv);
+    if let Some(session) = env.session.as_mut()
+        && session.id == id
+    {
+        session.granted = None;
+    }
+    ret_code
+}
+
+/// function to record an event in the audit log as the active user
+/// # Arguments
+/// * `env` - shell environment
+/// * `event` - what happened, usually the command name
+/// * `target` - account acted upon, empty if none
+/// * `outcome` - how the action ended
+/// * `detail` - free-form description
+pub fn audit(env: &Environment, event: &str, target: &str, outcome: AuditOutcome, detail: &str) {
+    env.audit.record(env.user(), event, target, outcome, detail);
+}
+
+/// function to store an account's verifiers after its password was set
+/// # Arguments
+/// * `env` - shell environment
+/// * `user` - account name
+/// * `verifiers` - verifiers of the new password
+pub fn store_verifiers(env: &mut Environment, user: &str, verifiers: Verifiers) {
+    if let Err(e) = env.verifiers.set(user, verifiers) {
+        eprintln!(
+            "\x1b[91mFailed to store the verifiers of {}, challenge-response logins will fail. Error: {}\x1b[0m",
+            user, e
+        );
+    }
+}
+
+/// function to make an account's verifiers when it has none for its current password, e.g.
+/// at login with the password at hand, so accounts made before them or changed by an import
+/// catch up
+/// # Arguments
+/// * `env` - shell environment
+/// * `user` - account name
+/// * `password` - password the account just authenticated with
+pub fn upgrade_verifiers(env: &mut Environment, user: &str, password: &str) {
+    let hash = match env.database.get(user) {
+        Some(record) => record.hash().to_string(),
+        None => return,
+    };
+    if env
+        .verifiers
+        .get(user, &hash)
+        .is_some_and(|verifiers| verifiers.is_complete())
+    {
+        return;
+    }
+    if let Ok(verifiers) = Verifiers::derive(
+        user,
+        password,
+        &hash,
+        env.config.scram_iterations,
+        env.config.srp_group,
+    ) {
+        store_verifiers(env, user, verifiers);
+    }
+}
+
+/// function to prompt for a new password and hash it with the configured algorithm and cost
+/// # Arguments
+/// * `config` - effective configuration
+/// * `user` - account the password is for, which the SRP verifier is bound to
+/// * `prompt` - &str with which to prompt the user for the password
+/// # Return
+/// * hash of the new password and its verifiers, or `None` after printing why it can't be used
+pub fn new_password_hash(config: &Config, user: &str, prompt: &str) -> Option<(String, Verifiers)> {
+    let password = password_input(prompt, true);
+    match hash_new_password(config, user, &password) {
+        Ok(new) => Some(new),
+        Err(reason) => {
+            println!("{}", reason);
+            None
+        }
+    }
+}
+
+/// function to hash a new password with the configured algorithm and cost, and make its
+/// verifiers
+/// # Arguments
+/// * `config` - effective configuration
+/// * `user` - account the password is for, which the SRP verifier is bound to
+/// * `password` - the new password
+/// # Return
+/// * hash of the new password and its verifiers, or why it can't be used: it is shorter than
+///   the policy allows or holds characters SASLprep prohibits
+pub fn hash_new_password(
+    config: &Config,
+    user: &str,
+    password: &str,
+) -> Result<(String, Verifiers), String> {
+    if password.chars().count() < config.min_password_length {
+        return Err(format!(
+            "password must be at least {} characters",
+            config.min_password_length
+        ));
+    }
+    let salt = get_salt(None);
+    let hash = match config.hash_algorithm.as_str() {
+        ONION_HASH_VERSION => onion_hash_password(password, &salt, config.hash_cost),
+        _ => hash_password(password, &salt, config.hash_cost),
+    };
+    match Verifiers::derive(
+        user,
+        password,
+        &hash,
+        config.scram_iterations,
+        config.srp_group,
+    ) {
+        Ok(verifiers) => Ok((hash, verifiers)),
+        Err(e) => Err(format!("password can't be used: {}", e)),
+    }
+}
+
+/// function to prompt for a new value of a GECOS subfield
+/// # Arguments
+/// * `label` - name of the field shown to the user
+/// * `current` - current value, kept if the user enters nothing
+/// # Return
+/// * new value, or `None` if the input contains characters not allowed in GECOS
+fn gecos_input(label: &str, current: &str) -> Option<String> {
+    let input = inline_input(&format!("{} [{}]: ", label, current));
+    if input.is_empty() {
+        Some(current.to_string())
+    } else if Gecos::is_valid_field(&input) {
+        Some(input)
+    } else {
+        println!("{} may not contain ':', ',' or control characters", label);
+        None
+    }
+}
+
+/// function to find the account `groups` and `id` report on
+/// # Arguments
+/// * `env` - shell environment
+/// * `argc` - number of arguments
+/// * `argv` - command and an optional username, the active user if there is none
+/// # Return
+/// * the account record, or `None` after printing why there is none
+fn id_target(env: &Environment, argc: u8, argv: &[String]) -> Option<UserRecord> {
+    let username = match argc {
+        1 if env.user() == NULLUSER => {
+            println!("not logged in");
+            return None;
+        }
+        1 => env.user(),
+        2 => argv[1].as_str(),
+        _ => {
+            println!("invalid arguments for {}", argv[0]);
+            return None;
+        }
+    };
+    let record = env.database.get(username);
+    if record.is_none() {
+        println!("account {} not found", username);
+    }
+    record
+}
+
+/// function to read the server secret challenge-response logins derive the made-up salts of
+/// unknown accounts from, the seal key
+/// # Arguments
+/// * `env` - shell environment
+/// # Return
+/// * the secret, `None` after reporting why it could not be read
+fn server_secret(env: &Environment) -> Option<Vec<u8>> {
+    match read_seal_key(std::path::Path::new(&env.config.seal_key_path())) {
+        Ok(secret) => Some(secret),
+        Err(e) => {
+            eprintln!(
+                "\x1b[91mFailed to read the server secret. Error: {}\x1b[0m",
+                e
+            );
+            None
+        }
+    }
+}
+
+/// function to shorten a big number for display, its leading hex digits and its size
+/// # Arguments
+/// * `n` - number to show
+fn short_hex(n: &BigUint) -> String {
+    let hex = n.to_string();
+    match hex.len() > 32 {
+        true => format!("{}... ({} bits)", &hex[..32], n.bits()),
+        false => hex,
+    }
 }
 
 // ==================== COMMANDS ====================
@@ -56,7 +1113,7 @@ fn f_help(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
         .commands
         .iter()
         .copied()
-        .filter(|c| env.permissions >= c.permissions)
+        .filter(|c| env.may_run(c))
         .collect();
 
     // filter commands by permissions
@@ -79,17 +1136,19 @@ pub static HELP: Command = Command {
     name: "help",
     usage: "help",
     description: "display this helpful message",
-    permissions: P_NONE,
+    access: Access::Public,
+    other: None,
+    resource: on_self,
     handler: f_help,
 };
 
 // ==== WHOAMI ====
 #[allow(unused_variables)]
 fn f_whoami(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
-    if env.user == NULLUSER {
+    if env.user() == NULLUSER {
         println!("not logged in");
     } else {
-        println!("{}", env.user);
+        println!("{}", env.user());
     }
     0
 }
@@ -98,7 +1157,9 @@ pub static WHOAMI: Command = Command {
     name: "whoami",
     usage: "whoami",
     description: "print username",
-    permissions: P_NONE,
+    access: Access::Public,
+    other: None,
+    resource: on_self,
     handler: f_whoami,
 };
 
@@ -115,16 +1176,35 @@ fn f_mkuser(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
             1
         } else {
             // create user
-            env.database.set(
-                &argv[1],
-                &hash_password(
-                    &password_input("Password: ", true),
-                    &get_salt(None),
-                    DEF_HASH_COST,
-                ),
-            );
-            println!("created account {}", argv[1]);
-            0
+            let (hash, verifiers) = match new_password_hash(&env.config, &argv[1], "Password: ") {
+                Some(new) => new,
+                None => return 1,
+            };
+            match env.database.transaction().set(&argv[1], &hash).commit() {
+                Ok(()) => {
+                    store_verifiers(env, &argv[1], verifiers);
+                    audit(
+                        env,
+                        "mkuser",
+                        &argv[1],
+                        AuditOutcome::Success,
+                        "created account",
+                    );
+                    println!("created account {}", argv[1]);
+                    0
+                }
+                Err(e) => {
+                    audit(
+                        env,
+                        "mkuser",
+                        &argv[1],
+                        AuditOutcome::Failure,
+                        &e.to_string(),
+                    );
+                    println!("failed to create {}: {}", argv[1], e);
+                    1
+                }
+            }
         }
     }
 }
@@ -133,7 +1213,9 @@ pub static MKUSER: Command = Command {
     name: "mkuser",
     usage: "mkuser <username>",
     description: "create a user account",
-    permissions: P_ROOT,
+    access: Access::Requires(Capability::UserCreate),
+    other: None,
+    resource: on_last,
     handler: f_mkuser,
 };
 
@@ -150,7 +1232,9 @@ pub static USERS: Command = Command {
     name: "users",
     usage: "users",
     description: "list all users",
-    permissions: P_NONE,
+    access: Access::Public,
+    other: None,
+    resource: on_self,
     handler: f_users,
 };
 
@@ -165,23 +1249,26 @@ pub static CLEAR: Command = Command {
     name: "clear",
     usage: "clear",
     description: "clear the screen",
-    permissions: P_NONE,
+    access: Access::Public,
+    other: None,
+    resource: on_self,
     handler: f_clear,
 };
 
 // ==== CHNAME ====
 #[allow(unused_variables)]
 fn f_chname(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
-    if env.permissions >= P_ROOT {
-        // root path: change another account name
+    if env.can(Capability::UserRenameOther) {
+        // admin path: change another account name
         if argc != 3 {
-            println!("invalid arguments for {} as root", argv[0]);
+            println!("invalid arguments for {}", argv[0]);
+            1
+        } else if argv[1] == ROOT {
+            // a renamed root would be created again on the next start, with a new password
+            println!("cannot rename root account");
             1
         } else {
-            if env
-                .database
-                .authenticate(ROOT, &password_input("root password: ", false))
-            {
+            if reauthenticate(env) {
                 let old_name = &argv[1];
                 let new_name = &argv[2];
                 // ensure account
@@ -194,18 +1281,36 @@ fn f_chname(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
                     println!("account {} not found", old_name);
                     return 1;
                 }
-                // fetch credential string
-                let hashword = env
-                    .database
-                    .get(old_name)
-                    .expect("failed to retrieve hashed password")
-                    .clone();
                 // change account
-                env.database.remove(old_name);
-                env.database.set(new_name, &hashword);
-                0
+                match env
+                    .database
+                    .transaction()
+                    .rename(old_name, new_name)
+                    .commit()
+                {
+                    Ok(()) => {
+                        let detail = format!("renamed to {}", new_name);
+                        audit(env, "chname", old_name, AuditOutcome::Success, &detail);
+                        rename_memberships(env, old_name, &normalize(new_name));
+                        revoke_sessions(env, old_name);
+                        0
+                    }
+                    Err(e) => {
+                        audit(
+                            env,
+                            "chname",
+                            old_name,
+                            AuditOutcome::Failure,
+                            &e.to_string(),
+                        );
+                        println!("failed to rename {}: {}", old_name, e);
+                        1
+                    }
+                }
             } else {
-                println!("could not authenticate as root");
+                let detail = "re-authentication failed";
+                audit(env, "chname", &argv[1], AuditOutcome::Failure, detail);
+                println!("failed to authenticate as {}", env.user());
                 1
             }
         }
@@ -217,9 +1322,9 @@ fn f_chname(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
         } else {
             if env
                 .database
-                .authenticate(&env.user, &password_input("password: ", false))
+                .authenticate(env.user(), &password_input("password: ", false))
             {
-                let old_name = &env.user;
+                let old_name = env.user().to_string();
                 let new_name = &argv[1];
                 // prevent collisions
                 if env.database.contains(new_name) {
@@ -227,16 +1332,40 @@ fn f_chname(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
                     return 1;
                 }
                 // change account name
-                let hashword = env
+                match env
                     .database
-                    .get(old_name)
-                    .expect("failed to reitreive hashed password")
-                    .clone();
-                env.database.remove(old_name);
-                env.database.set(new_name, &hashword);
-                env.user = new_name.clone();
-                0
+                    .transaction()
+                    .rename(&old_name, new_name)
+                    .commit()
+                {
+                    Ok(()) => {
+                        let detail = format!("renamed to {}", new_name);
+                        audit(env, "chname", &old_name, AuditOutcome::Success, &detail);
+                        rename_memberships(env, &old_name, &normalize(new_name));
+                        revoke_tokens(env, &old_name);
+                        if let Some(session) = env.session.as_mut() {
+                            session.user = normalize(new_name);
+                            if let Err(e) = env.sessions.save(session) {
+                                eprintln!("\x1b[91mFailed to update session. Error: {}\x1b[0m", e);
+                            }
+                        }
+                        0
+                    }
+                    Err(e) => {
+                        audit(
+                            env,
+                            "chname",
+                            &old_name,
+                            AuditOutcome::Failure,
+                            &e.to_string(),
+                        );
+                        println!("failed to rename {}: {}", old_name, e);
+                        1
+                    }
+                }
             } else {
+                let detail = "authentication failed";
+                audit(env, "chname", env.user(), AuditOutcome::Failure, detail);
                 println!("failed authentication");
                 1
             }
@@ -248,7 +1377,9 @@ pub static CHNAME: Command = Command {
     name: "chname",
     usage: "chname [old] <new>",
     description: "change account username",
-    permissions: P_USER,
+    access: Access::LoggedIn,
+    other: Some(Capability::UserRenameOther),
+    resource: on_renamed,
     handler: f_chname,
 };
 
@@ -257,50 +1388,96 @@ pub static CHNAME: Command = Command {
 fn f_chpass(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
     if argc == 1 {
         // change own password
+        let user = env.user().to_string();
         if env
             .database
-            .authenticate(&env.user, &password_input("current password: ", false))
+            .authenticate(&user, &password_input("current password: ", false))
         {
             // change to new password
-            env.database.set(
-                &env.user,
-                &hash_password(
-                    &password_input("new password: ", true),
-                    &get_salt(None),
-                    DEF_HASH_COST,
-                ),
-            );
-            println!("changed password for {}", env.user);
-            0
+            let (hash, verifiers) = match new_password_hash(&env.config, &user, "new password: ") {
+                Some(new) => new,
+                None => return 1,
+            };
+            match env.database.transaction().set(&user, &hash).commit() {
+                Ok(()) => {
+                    store_verifiers(env, &user, verifiers);
+                    revoke_tokens(env, &user);
+                    audit(
+                        env,
+                        "chpass",
+                        env.user(),
+                        AuditOutcome::Success,
+                        "changed password",
+                    );
+                    println!("changed password for {}", env.user());
+                    0
+                }
+                Err(e) => {
+                    audit(
+                        env,
+                        "chpass",
+                        env.user(),
+                        AuditOutcome::Failure,
+                        &e.to_string(),
+                    );
+                    println!("failed to change password for {}: {}", env.user(), e);
+                    1
+                }
+            }
         } else {
+            let detail = "authentication failed";
+            audit(env, "chpass", env.user(), AuditOutcome::Failure, detail);
             println!("failed to authenticate");
             1
         }
-    } else if argc == 2 && env.permissions >= P_ROOT {
-        // root path: change other account password
-        if env
-            .database
-            .authenticate(ROOT, &password_input("root password: ", false))
-        {
+    } else if argc == 2 && env.can(Capability::UserPasswdOther) {
+        // admin path: change other account password
+        if !may_change(env, "chpass", &argv[1]) {
+            return 1;
+        }
+        if reauthenticate(env) {
             // ensure account exists
             let target_user = &argv[1];
-            if !env.database.contains(&target_user) {
+            if !env.database.contains(target_user) {
                 println!("account {} not found", target_user);
                 return 1;
             }
             // change password
-            env.database.set(
-                target_user,
-                &hash_password(
-                    &password_input("new account password: ", true),
-                    &get_salt(None),
-                    DEF_HASH_COST,
-                ),
-            );
-            println!("changed {}'s password", target_user);
-            0
+            let (hash, verifiers) =
+                match new_password_hash(&env.config, target_user, "new account password: ") {
+                    Some(new) => new,
+                    None => return 1,
+                };
+            match env.database.transaction().set(target_user, &hash).commit() {
+                Ok(()) => {
+                    store_verifiers(env, target_user, verifiers);
+                    revoke_tokens(env, target_user);
+                    audit(
+                        env,
+                        "chpass",
+                        target_user,
+                        AuditOutcome::Success,
+                        "changed password",
+                    );
+                    println!("changed {}'s password", target_user);
+                    0
+                }
+                Err(e) => {
+                    audit(
+                        env,
+                        "chpass",
+                        target_user,
+                        AuditOutcome::Failure,
+                        &e.to_string(),
+                    );
+                    println!("failed to change {}'s password: {}", target_user, e);
+                    1
+                }
+            }
         } else {
-            println!("failed to authenticate as root");
+            let detail = "re-authentication failed";
+            audit(env, "chpass", &argv[1], AuditOutcome::Failure, detail);
+            println!("failed to authenticate as {}", env.user());
             1
         }
     } else {
@@ -313,34 +1490,143 @@ pub static CHPASS: Command = Command {
     name: "chpass",
     usage: "chpass [username]",
     description: "change account password",
-    permissions: P_USER,
+    access: Access::LoggedIn,
+    other: Some(Capability::UserPasswdOther),
+    resource: on_last,
     handler: f_chpass,
 };
 
-// ==== SWITCHUSER ====
+// ==== CHFN ====
 #[allow(unused_variables)]
-fn f_switchuser(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
-    if env.user == NULLUSER {
-        println!("not logged in");
-        return 1;
-    }
-    if argc != 2 {
+fn f_chfn(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    // determine target account, the caller always authenticates as themselves
+    let target_user = if argc == 1 {
+        env.user().to_string()
+    } else if argc == 2 && env.can(Capability::UserChfnOther) {
+        argv[1].clone()
+    } else {
         println!("invalid arguments for {}", argv[0]);
         return 1;
+    };
+    if !may_change(env, "chfn", &target_user) {
+        return 1;
     }
-    if env
-        .database
-        .authenticate(&argv[1], &password_input("Password: ", false))
-    {
-        env.user = argv[1].clone();
-        if env.user == ROOT {
-            env.permissions = P_ROOT;
-        } else {
-            env.permissions = P_USER;
+    let mut record = match env.database.get(&target_user) {
+        Some(record) => record,
+        None => {
+            println!("account {} not found", target_user);
+            return 1;
         }
-        println!("logged in as {}", env.user);
-        return 0;
-    } else {
+    };
+    if !reauthenticate(env) {
+        let detail = format!("{} authentication failed", env.user());
+        audit(env, "chfn", &target_user, AuditOutcome::Failure, &detail);
+        println!("failed to authenticate as {}", env.user());
+        return 1;
+    }
+
+    // prompt for each field, keeping the current value on empty input
+    println!("changing user information for {}", target_user);
+    println!("enter the new value, or press enter for the default");
+    let current = record.gecos().clone();
+    let gecos = Gecos {
+        full_name: match gecos_input("full name", &current.full_name) {
+            Some(v) => v,
+            None => return 1,
+        },
+        room: match gecos_input("room number", &current.room) {
+            Some(v) => v,
+            None => return 1,
+        },
+        work_phone: match gecos_input("work phone", &current.work_phone) {
+            Some(v) => v,
+            None => return 1,
+        },
+        home_phone: match gecos_input("home phone", &current.home_phone) {
+            Some(v) => v,
+            None => return 1,
+        },
+        other: match gecos_input("other", &current.other) {
+            Some(v) => v,
+            None => return 1,
+        },
+    };
+    record.set_gecos(gecos);
+    match env.database.transaction().set_record(record).commit() {
+        Ok(()) => {
+            let detail = "changed user information";
+            audit(env, "chfn", &target_user, AuditOutcome::Success, detail);
+            println!("changed user information for {}", target_user);
+            0
+        }
+        Err(e) => {
+            audit(
+                env,
+                "chfn",
+                &target_user,
+                AuditOutcome::Failure,
+                &e.to_string(),
+            );
+            println!(
+                "failed to change user information for {}: {}",
+                target_user, e
+            );
+            1
+        }
+    }
+}
+
+pub static CHFN: Command = Command {
+    name: "chfn",
+    usage: "chfn [username]",
+    description: "change account user information",
+    access: Access::LoggedIn,
+    other: Some(Capability::UserChfnOther),
+    resource: on_last,
+    handler: f_chfn,
+};
+
+// ==== SWITCHUSER ====
+#[allow(unused_variables)]
+fn f_switchuser(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    if env.user() == NULLUSER {
+        println!("not logged in");
+        return 1;
+    }
+    if env.depth() > 0 {
+        println!("not a login session: use exit to leave the su session");
+        return 1;
+    }
+    if argc != 2 {
+        println!("invalid arguments for {}", argv[0]);
+        return 1;
+    }
+    let password = password_input("Password: ", false);
+    if env.database.authenticate(&argv[1], &password) {
+        upgrade_verifiers(env, &argv[1], &password);
+        audit(
+            env,
+            "switchuser",
+            &argv[1],
+            AuditOutcome::Success,
+            "switched user",
+        );
+        if !env.start_session(&argv[1], &[AuthFactor::Password]) {
+            return 1;
+        }
+        println!("logged in as {}", env.user());
+        if env.config.token_on_login {
+            issue_token(env);
+        }
+        return 0;
+    } else {
+        audit(
+            env,
+            "switchuser",
+            &argv[1],
+            AuditOutcome::Failure,
+            "authentication failed",
+        );
         println!("failed to authenticate as {}", argv[1]);
     }
     1
@@ -350,16 +1636,96 @@ pub static SWITCHUSER: Command = Command {
     name: "switchuser",
     usage: "switchuser <username>",
     description: "logout and login as another user",
-    permissions: P_USER,
+    access: Access::LoggedIn,
+    other: None,
+    resource: on_last,
     handler: f_switchuser,
 };
 
+// ==== SU ====
+#[allow(unused_variables)]
+fn f_su(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    // su [-] [username], root by default
+    let fresh = argv.get(1).is_some_and(|a| a == "-");
+    let args = &argv[if fresh { 2 } else { 1 }..];
+    let target = match args {
+        [] => ROOT.to_string(),
+        [user] => user.clone(),
+        _ => {
+            println!("invalid arguments for {}", argv[0]);
+            return 1;
+        }
+    };
+    if !env.database.contains(&target) {
+        println!("account {} not found", target);
+        return 1;
+    }
+
+    // root may become anyone, and the new session carries root's factors
+    let factors = match env.user() == ROOT {
+        true => env
+            .session
+            .as_ref()
+            .map_or(Vec::new(), |s| s.factors.clone()),
+        false => {
+            if !env
+                .database
+                .authenticate(&target, &password_input("Password: ", false))
+            {
+                audit(
+                    env,
+                    "su",
+                    &target,
+                    AuditOutcome::Failure,
+                    "authentication failed",
+                );
+                println!("failed to authenticate as {}", target);
+                return 1;
+            }
+            vec![AuthFactor::Password]
+        }
+    };
+    let from = env.user().to_string();
+    if !env.push_session(&target, &factors, fresh) {
+        return 1;
+    }
+    let detail = format!(
+        "switched from {} at depth {}{}",
+        from,
+        env.depth(),
+        if fresh { ", fresh environment" } else { "" }
+    );
+    audit(env, "su", &target, AuditOutcome::Success, &detail);
+    println!("logged in as {}, exit to return to {}", target, from);
+    0
+}
+
+pub static SU: Command = Command {
+    name: "su",
+    usage: "su [-] [username]",
+    description: "start a nested session as another user, root by default, exit returns",
+    access: Access::LoggedIn,
+    other: None,
+    resource: on_su_target,
+    handler: f_su,
+};
+
 // ==== LOGOUT ====
 #[allow(unused_variables)]
 fn f_logout(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
-    println!("logged out of {}", env.user);
-    env.user = NULLUSER.to_string();
-    env.permissions = P_NONE;
+    if env.depth() > 0 {
+        println!("not a login session: use exit to leave the su session");
+        return 1;
+    }
+    audit(
+        env,
+        "logout",
+        env.user(),
+        AuditOutcome::Success,
+        "logged out",
+    );
+    println!("logged out of {}", env.user());
+    env.end_session();
     0
 }
 
@@ -367,15 +1733,34 @@ pub static LOGOUT: Command = Command {
     name: "logout",
     usage: "logout",
     description: "logout of account",
-    permissions: P_USER,
+    access: Access::LoggedIn,
+    other: None,
+    resource: on_self,
     handler: f_logout,
 };
 
+// ==== LOCK ====
+#[allow(unused_variables)]
+fn f_lock(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    lock_session(env, "locked");
+    0
+}
+
+pub static LOCK: Command = Command {
+    name: "lock",
+    usage: "lock",
+    description: "lock the session until the password is given",
+    access: Access::LoggedIn,
+    other: None,
+    resource: on_self,
+    handler: f_lock,
+};
+
 // ==== LOGIN ====
 #[allow(unused_variables)]
 fn f_login(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
-    if env.user != NULLUSER {
-        println!("already logged in: {}", env.user);
+    if env.user() != NULLUSER {
+        println!("already logged in: {}", env.user());
         return 1;
     }
 
@@ -384,19 +1769,26 @@ fn f_login(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
         return 1;
     }
 
-    if env
-        .database
-        .authenticate(&argv[1], &password_input("Password: ", false))
-    {
-        env.user = argv[1].clone();
-        if env.user == ROOT {
-            env.permissions = P_ROOT;
-        } else {
-            env.permissions = P_USER;
+    let password = password_input("Password: ", false);
+    if env.database.authenticate(&argv[1], &password) {
+        upgrade_verifiers(env, &argv[1], &password);
+        if !env.start_session(&argv[1], &[AuthFactor::Password]) {
+            return 1;
         }
+        audit(env, "login", &argv[1], AuditOutcome::Success, "logged in");
         println!("logged in as {}", argv[1]);
+        if env.config.token_on_login {
+            issue_token(env);
+        }
         return 0;
     } else {
+        audit(
+            env,
+            "login",
+            &argv[1],
+            AuditOutcome::Failure,
+            "authentication failed",
+        );
         println!("failed to authenticate as {}", argv[1]);
     }
     1
@@ -406,7 +1798,9 @@ pub static LOGIN: Command = Command {
     name: "login",
     usage: "login <username>",
     description: "login to an account",
-    permissions: P_NONE,
+    access: Access::Public,
+    other: None,
+    resource: on_last,
     handler: f_login,
 };
 
@@ -417,20 +1811,42 @@ fn f_rmuser(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
         println!("invalid arguments for {}", argv[1]);
         1
     } else {
-        if env
-            .database
-            .authenticate(&ROOT, &password_input("root password: ", false))
-        {
+        if reauthenticate(env) {
             if argv[1] == ROOT {
                 println!("cannot delete root account");
                 1
             } else {
-                env.database.remove(&argv[1]);
-                println!("deleted account {}", argv[1]);
-                0
+                match env.database.transaction().remove(&argv[1]).commit() {
+                    Ok(()) => {
+                        audit(
+                            env,
+                            "rmuser",
+                            &argv[1],
+                            AuditOutcome::Success,
+                            "deleted account",
+                        );
+                        println!("deleted account {}", argv[1]);
+                        drop_memberships(env, &argv[1]);
+                        revoke_sessions(env, &argv[1]);
+                        0
+                    }
+                    Err(e) => {
+                        audit(
+                            env,
+                            "rmuser",
+                            &argv[1],
+                            AuditOutcome::Failure,
+                            &e.to_string(),
+                        );
+                        println!("failed to delete {}: {}", argv[1], e);
+                        1
+                    }
+                }
             }
         } else {
-            println!("failed to authenticate as root");
+            let detail = "re-authentication failed";
+            audit(env, "rmuser", &argv[1], AuditOutcome::Failure, detail);
+            println!("failed to authenticate as {}", env.user());
             1
         }
     }
@@ -440,7 +1856,9 @@ pub static RMUSER: Command = Command {
     name: "rmuser",
     usage: "rmuser <username>",
     description: "delete an account",
-    permissions: P_ROOT,
+    access: Access::Requires(Capability::UserDelete),
+    other: None,
+    resource: on_last,
     handler: f_rmuser,
 };
 
@@ -452,20 +1870,38 @@ fn f_reset(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
         1
     } else {
         println!("this action will destroy all accounts.");
-        if env
-            .database
-            .authenticate(ROOT, &password_input("enter password to proceed: ", false))
-        {
+        if reauthenticate_always(env) {
+            // delete every account in a single write
+            let usernames = env.database.list_users();
+            let count = usernames.len();
+            let mut transaction = env.database.transaction();
+            for username in &usernames {
+                transaction = transaction.remove(username);
+            }
+            if let Err(e) = transaction.commit() {
+                audit(env, "reset", "", AuditOutcome::Failure, &e.to_string());
+                println!("failed to delete accounts: {}", e);
+                return 1;
+            }
+            let detail = format!("deleted {} accounts", count);
+            audit(env, "reset", "", AuditOutcome::Success, &detail);
             print!("\x1bc"); // ANSI escape code to clear terminal screen
-            for username in env.database.list_users() {
-                env.database.remove(&username);
+            env.end_all_sessions();
+            for username in usernames {
+                drop_memberships(env, &username);
+                revoke_sessions(env, &username);
             }
-            env.user = NULLUSER.to_string();
-            env.permissions = P_NONE;
             println!("all accounts deleted\n");
             0
         } else {
-            println!("failed to authenticate as root");
+            audit(
+                env,
+                "reset",
+                "",
+                AuditOutcome::Failure,
+                "re-authentication failed",
+            );
+            println!("failed to authenticate as {}", env.user());
             1
         }
     }
@@ -475,10 +1911,1720 @@ pub static RESET: Command = Command {
     name: "reset",
     usage: "reset",
     description: "delete all accounts",
-    permissions: P_ROOT,
+    access: Access::Requires(Capability::DbReset),
+    other: None,
+    resource: on_self,
     handler: f_reset,
 };
 
+// ==== GROUPADD ====
+#[allow(unused_variables)]
+fn f_groupadd(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    let (name, gid) = match argc {
+        2 => match env.groups.next_gid() {
+            Ok(gid) => (&argv[1], gid),
+            Err(e) => {
+                println!("failed to create group {}: {}", argv[1], e);
+                return 1;
+            }
+        },
+        4 if argv[1] == "-g" => match argv[2].parse() {
+            Ok(gid) => (&argv[3], gid),
+            Err(_) => {
+                println!("invalid gid {}", argv[2]);
+                return 1;
+            }
+        },
+        _ => {
+            println!("invalid arguments for {}", argv[0]);
+            return 1;
+        }
+    };
+    match env.groups.add(name, gid) {
+        Ok(group) => {
+            let detail = format!("created group with gid {}", group.gid());
+            audit(env, "groupadd", name, AuditOutcome::Success, &detail);
+            println!("created group {} with gid {}", name, group.gid());
+            0
+        }
+        Err(e) => {
+            audit(env, "groupadd", name, AuditOutcome::Failure, &e.to_string());
+            println!("failed to create group {}: {}", name, e);
+            1
+        }
+    }
+}
+
+pub static GROUPADD: Command = Command {
+    name: "groupadd",
+    usage: "groupadd [-g <gid>] <group>",
+    description: "create a group",
+    access: Access::Requires(Capability::GroupCreate),
+    other: None,
+    resource: on_last,
+    handler: f_groupadd,
+};
+
+// ==== GROUPDEL ====
+#[allow(unused_variables)]
+fn f_groupdel(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    if argc != 2 {
+        println!("invalid arguments for {}", argv[0]);
+        return 1;
+    }
+
+    // like groupdel, refuse to leave accounts without their primary group
+    if let Some(group) = env.groups.get(&argv[1]) {
+        let gid = group.gid();
+        let owner = env
+            .database
+            .list_users()
+            .into_iter()
+            .find(|u| env.database.get(u).is_some_and(|r| r.gid() == gid));
+        if let Some(owner) = owner {
+            let e = GroupError::PrimaryGroup(argv[1].clone(), owner);
+            audit(
+                env,
+                "groupdel",
+                &argv[1],
+                AuditOutcome::Failure,
+                &e.to_string(),
+            );
+            println!("failed to delete group {}: {}", argv[1], e);
+            return 1;
+        }
+    }
+    match env.groups.remove(&argv[1]) {
+        Ok(group) => {
+            audit(
+                env,
+                "groupdel",
+                &argv[1],
+                AuditOutcome::Success,
+                "deleted group",
+            );
+            let principal = Principal::Group(group.name().to_string());
+            if let Err(e) = env.roles.remove_principal(&principal) {
+                eprintln!(
+                    "\x1b[91mFailed to remove {} from its roles. Error: {}\x1b[0m",
+                    principal, e
+                );
+            }
+            println!("deleted group {}", group.name());
+            0
+        }
+        Err(e) => {
+            audit(
+                env,
+                "groupdel",
+                &argv[1],
+                AuditOutcome::Failure,
+                &e.to_string(),
+            );
+            println!("failed to delete group {}: {}", argv[1], e);
+            1
+        }
+    }
+}
+
+pub static GROUPDEL: Command = Command {
+    name: "groupdel",
+    usage: "groupdel <group>",
+    description: "delete a group",
+    access: Access::Requires(Capability::GroupDelete),
+    other: None,
+    resource: on_last,
+    handler: f_groupdel,
+};
+
+// ==== GPASSWD ====
+#[allow(unused_variables)]
+fn f_gpasswd(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    if argc != 4 || (argv[1] != "-a" && argv[1] != "-d") {
+        println!("invalid arguments for {}", argv[0]);
+        return 1;
+    }
+    let (user, group) = (&argv[2], &argv[3]);
+    let result = match argv[1].as_str() {
+        "-a" if !env.database.contains(user) => {
+            println!("account {} not found", user);
+            return 1;
+        }
+        "-a" => env
+            .groups
+            .add_member(group, user)
+            .map(|()| format!("added {} to {}", user, group)),
+        _ => env
+            .groups
+            .remove_member(group, user)
+            .map(|()| format!("removed {} from {}", user, group)),
+    };
+    match result {
+        Ok(detail) => {
+            audit(env, "gpasswd", user, AuditOutcome::Success, &detail);
+            println!("{}", detail);
+            0
+        }
+        Err(e) => {
+            audit(env, "gpasswd", user, AuditOutcome::Failure, &e.to_string());
+            println!("failed to change {}: {}", group, e);
+            1
+        }
+    }
+}
+
+pub static GPASSWD: Command = Command {
+    name: "gpasswd",
+    usage: "gpasswd <-a | -d> <username> <group>",
+    description: "add a user to or remove a user from a group",
+    access: Access::Requires(Capability::GroupMembers),
+    other: None,
+    resource: on_last,
+    handler: f_gpasswd,
+};
+
+// ==== GROUPS ====
+#[allow(unused_variables)]
+fn f_groups(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    let record = match id_target(env, argc, argv) {
+        Some(record) => record,
+        None => return 1,
+    };
+    let names: Vec<String> = env
+        .groups
+        .groups_of(&record)
+        .iter()
+        .map(|g| g.name().to_string())
+        .collect();
+    println!("{}", names.join(" "));
+    0
+}
+
+pub static GROUPS: Command = Command {
+    name: "groups",
+    usage: "groups [username]",
+    description: "list the groups a user is in",
+    access: Access::Public,
+    other: None,
+    resource: on_last,
+    handler: f_groups,
+};
+
+// ==== ID ====
+#[allow(unused_variables)]
+fn f_id(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    let record = match id_target(env, argc, argv) {
+        Some(record) => record,
+        None => return 1,
+    };
+
+    // the primary group comes first, by number only if it is not in the group file
+    let describe = |gid: u32| match env.groups.by_gid(gid) {
+        Some(group) => format!("{}({})", gid, group.name()),
+        None => gid.to_string(),
+    };
+    let mut gids: Vec<u32> = vec![record.gid()];
+    for group in env.groups.groups_of(&record) {
+        if !gids.contains(&group.gid()) {
+            gids.push(group.gid());
+        }
+    }
+    let groups: Vec<String> = gids.iter().map(|gid| describe(*gid)).collect();
+    println!(
+        "uid={}({}) gid={} groups={}",
+        record.uid(),
+        record.name(),
+        describe(record.gid()),
+        groups.join(",")
+    );
+    0
+}
+
+pub static ID: Command = Command {
+    name: "id",
+    usage: "id [username]",
+    description: "print the uid, gid and groups of a user",
+    access: Access::Public,
+    other: None,
+    resource: on_last,
+    handler: f_id,
+};
+
+// ==== EXPORT ====
+#[allow(unused_variables)]
+fn f_export(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    if argc != 2 {
+        println!("invalid arguments for {}", argv[0]);
+        return 1;
+    }
+    // the file holds every hash, so ask for the password as import does
+    if !reauthenticate(env) {
+        audit(
+            env,
+            "export",
+            "",
+            AuditOutcome::Failure,
+            "re-authentication failed",
+        );
+        println!("failed to authenticate as {}", env.user());
+        return 1;
+    }
+    match export_database(env.database.as_ref(), &argv[1]) {
+        Ok(count) => {
+            let detail = format!("exported {} accounts to {}", count, argv[1]);
+            audit(env, "export", "", AuditOutcome::Success, &detail);
+            println!("{}", detail);
+            0
+        }
+        Err(e) => {
+            let detail = format!("failed to export to {}: {}", argv[1], e);
+            audit(env, "export", "", AuditOutcome::Failure, &detail);
+            println!("{}", detail);
+            1
+        }
+    }
+}
+
+pub static EXPORT: Command = Command {
+    name: "export",
+    usage: "export <file.json|file.toml>",
+    description: "export all accounts",
+    access: Access::Requires(Capability::DbExport),
+    other: None,
+    resource: on_last,
+    handler: f_export,
+};
+
+// ==== IMPORT ====
+#[allow(unused_variables)]
+fn f_import(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    // parse merge mode flag
+    let (mode, path) = match argv {
+        [_, path] => (MergeMode::FailOnConflict, path),
+        [_, flag, path] if flag == "--overwrite" => (MergeMode::Overwrite, path),
+        [_, flag, path] if flag == "--skip" => (MergeMode::SkipExisting, path),
+        _ => {
+            println!("invalid arguments for {}", argv[0]);
+            return 1;
+        }
+    };
+    if !reauthenticate_always(env) {
+        audit(
+            env,
+            "import",
+            "",
+            AuditOutcome::Failure,
+            "re-authentication failed",
+        );
+        println!("failed to authenticate as {}", env.user());
+        return 1;
+    }
+    match import_database(env.database.as_mut(), &env.groups, path, mode) {
+        Ok(summary) => {
+            let detail = format!(
+                "imported {}: {} added, {} replaced, {} skipped",
+                path, summary.added, summary.replaced, summary.skipped
+            );
+            audit(env, "import", "", AuditOutcome::Success, &detail);
+            println!("{}", detail);
+            0
+        }
+        Err(e) => {
+            let detail = format!("failed to import {}: {}", path, e);
+            audit(env, "import", "", AuditOutcome::Failure, &detail);
+            println!("failed to import {}, no accounts changed: {}", path, e);
+            1
+        }
+    }
+}
+
+pub static IMPORT: Command = Command {
+    name: "import",
+    usage: "import [--overwrite|--skip] <file>",
+    description: "import accounts from an export",
+    access: Access::Requires(Capability::DbImport),
+    other: None,
+    resource: on_last,
+    handler: f_import,
+};
+
+// ==== REKEY ====
+#[allow(unused_variables)]
+fn f_rekey(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    // read both secrets from key files, or prompt for passphrases
+    let (current, new) = match argv {
+        [_] => (
+            password_input("current master passphrase: ", false).into_bytes(),
+            password_input("new master passphrase: ", true).into_bytes(),
+        ),
+        [_, flag, current, new] if flag == "--keyfile" => {
+            match (std::fs::read(current), std::fs::read(new)) {
+                (Ok(current), Ok(new)) => (current, new),
+                (Err(e), _) | (_, Err(e)) => {
+                    println!("failed to read key file: {}", e);
+                    return 1;
+                }
+            }
+        }
+        _ => {
+            println!("invalid arguments for {}", argv[0]);
+            return 1;
+        }
+    };
+    match env.database.rotate_master_key(&current, &new) {
+        Ok(()) => {
+            audit(
+                env,
+                "rekey",
+                "",
+                AuditOutcome::Success,
+                "master key rotated",
+            );
+            println!("master key rotated");
+            0
+        }
+        Err(e) => {
+            audit(env, "rekey", "", AuditOutcome::Failure, &e.to_string());
+            println!("failed to rotate master key: {}", e);
+            1
+        }
+    }
+}
+
+pub static REKEY: Command = Command {
+    name: "rekey",
+    usage: "rekey [--keyfile <current> <new>]",
+    description: "re-encrypt the database under a new master secret",
+    access: Access::Requires(Capability::DbRekey),
+    other: None,
+    resource: on_self,
+    handler: f_rekey,
+};
+
+// ==== RESEAL ====
+#[allow(unused_variables)]
+fn f_reseal(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    if argc != 1 {
+        println!("invalid arguments for {}", argv[0]);
+        return 1;
+    }
+    if !env.database.tampered()
+        && inline_input("database seal is intact, reseal anyway? [y/N] ") != "y"
+    {
+        return 1;
+    }
+    if !reauthenticate_always(env) {
+        audit(
+            env,
+            "reseal",
+            "",
+            AuditOutcome::Failure,
+            "re-authentication failed",
+        );
+        println!("failed to authenticate as {}", env.user());
+        return 1;
+    }
+    match env.database.reseal() {
+        Ok(()) => {
+            audit(
+                env,
+                "reseal",
+                "",
+                AuditOutcome::Success,
+                "database resealed",
+            );
+            println!("database resealed");
+            0
+        }
+        Err(e) => {
+            audit(env, "reseal", "", AuditOutcome::Failure, &e.to_string());
+            println!("failed to reseal database: {}", e);
+            1
+        }
+    }
+}
+
+pub static RESEAL: Command = Command {
+    name: "reseal",
+    usage: "reseal",
+    description: "accept manual edits to the database files",
+    access: Access::Requires(Capability::DbReseal),
+    other: None,
+    resource: on_self,
+    handler: f_reseal,
+};
+
+// ==== DBCHECK ====
+#[allow(unused_variables)]
+fn f_dbcheck(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    let repair = match argv.get(1).map(|s| s.as_str()) {
+        None => false,
+        Some("--repair") if argc == 2 => true,
+        _ => {
+            println!("invalid arguments for {}", argv[0]);
+            return 1;
+        }
+    };
+
+    if repair {
+        return match env.database.repair() {
+            Ok(report) if report.repaired.is_empty() => {
+                println!("nothing to repair");
+                0
+            }
+            Ok(report) => {
+                for diagnostic in &report.repaired {
+                    println!("repaired: {}", diagnostic);
+                }
+                if let Some(quarantine) = &report.quarantine {
+                    println!("offending records were moved to '{}'", quarantine);
+                }
+                audit(
+                    env,
+                    "dbcheck",
+                    "",
+                    AuditOutcome::Success,
+                    &format!("repaired {} problem(s)", report.repaired.len()),
+                );
+                0
+            }
+            Err(e) => {
+                audit(env, "dbcheck", "", AuditOutcome::Failure, &e.to_string());
+                println!("failed to repair database: {}", e);
+                1
+            }
+        };
+    }
+
+    match env.database.check() {
+        Ok(diagnostics) if diagnostics.is_empty() => {
+            println!("no problems found");
+            0
+        }
+        Ok(diagnostics) => {
+            for diagnostic in &diagnostics {
+                println!("{}", diagnostic);
+            }
+            let repairable = diagnostics.iter().filter(|d| d.repairable()).count();
+            println!(
+                "{} problem(s) found, {} can be repaired with dbcheck --repair",
+                diagnostics.len(),
+                repairable
+            );
+            1
+        }
+        Err(e) => {
+            println!("failed to check database: {}", e);
+            1
+        }
+    }
+}
+
+pub static DBCHECK: Command = Command {
+    name: "dbcheck",
+    usage: "dbcheck [--repair]",
+    description: "check the database for problems, quarantining bad records with --repair",
+    access: Access::Requires(Capability::DbCheck),
+    other: None,
+    resource: on_self,
+    handler: f_dbcheck,
+};
+
+// ==== REHASH ====
+#[allow(unused_variables)]
+fn f_rehash(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    if argc != 1 {
+        println!("invalid arguments for {}", argv[0]);
+        return 1;
+    }
+
+    // only well formed hashes of the old version can be wrapped
+    env.database.refresh();
+    let mut records: Vec<UserRecord> = env
+        .database
+        .list_users()
+        .iter()
+        .filter_map(|username| env.database.get(username))
+        .filter(|r| r.hash().starts_with(&format!("${}$", HASH_VERSION)))
+        .filter(|r| validate_hash(r.hash()).is_ok())
+        .collect();
+    records.sort_by_key(|r| r.uid());
+    if records.is_empty() {
+        println!("no {} hashes to migrate", HASH_VERSION);
+        return 0;
+    }
+    let question = format!(
+        "wrap {} {} hash(es) in {}? this can't be undone [y/N] ",
+        records.len(),
+        HASH_VERSION,
+        ONION_HASH_VERSION
+    );
+    if inline_input(&question) != "y" {
+        return 1;
+    }
+    if !reauthenticate(env) {
+        audit(
+            env,
+            "rehash",
+            "",
+            AuditOutcome::Failure,
+            "re-authentication failed",
+        );
+        println!("failed to authenticate as {}", env.user());
+        return 1;
+    }
+
+    // wrap every hash, then store them all at once
+    let total = records.len();
+    let cost = env.config.hash_cost.min(MAX_ONION_COST);
+    let mut wrapped: usize = 0;
+    let mut transaction = env.database.transaction();
+    for (i, mut record) in records.into_iter().enumerate() {
+        match wrap_hash(record.hash(), &get_salt(None), cost) {
+            Ok(hash) => {
+                println!(
+                    "[{}/{}] wrapped the hash of {}",
+                    i + 1,
+                    total,
+                    record.name()
+                );
+                record.set_hash(&hash);
+                transaction = transaction.set_record(record);
+                wrapped += 1;
+            }
+            Err(e) => println!("[{}/{}] skipping {}: {}", i + 1, total, record.name(), e),
+        }
+    }
+    match transaction.commit() {
+        Ok(()) => {
+            audit(
+                env,
+                "rehash",
+                "",
+                AuditOutcome::Success,
+                &format!("wrapped {} hash(es) in {}", wrapped, ONION_HASH_VERSION),
+            );
+            println!("migrated {} account(s) to {}", wrapped, ONION_HASH_VERSION);
+            if env.config.hash_algorithm != ONION_HASH_VERSION {
+                println!(
+                    "set hash.algorithm = \"{}\" so new passwords use it too",
+                    ONION_HASH_VERSION
+                );
+            }
+            0
+        }
+        Err(e) => {
+            audit(env, "rehash", "", AuditOutcome::Failure, &e.to_string());
+            println!("failed to migrate hashes, nothing was changed: {}", e);
+            1
+        }
+    }
+}
+
+pub static REHASH: Command = Command {
+    name: "rehash",
+    usage: "rehash",
+    description: "wrap every sha256iter-1 hash in pbkdf2, without needing the passwords",
+    access: Access::Requires(Capability::DbRehash),
+    other: None,
+    resource: on_self,
+    handler: f_rehash,
+};
+
+// ==== AUDIT ====
+#[allow(unused_variables)]
+fn f_audit(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    // verify the whole chain
+    if argc == 2 && argv[1] == "verify" {
+        return match env.audit.verify() {
+            Ok(count) => {
+                println!("audit log intact, {} entries verified", count);
+                0
+            }
+            Err(e) => {
+                audit(env, "audit", "", AuditOutcome::Failure, &e.to_string());
+                println!("audit log failed verification: {}", e);
+                1
+            }
+        };
+    }
+
+    // parse filters
+    let mut user: Option<&str> = None;
+    let mut event: Option<&str> = None;
+    let mut failures = false;
+    let mut last: Option<usize> = None;
+    let mut args = argv[1..].iter();
+    while let Some(arg) = args.next() {
+        let valid = match arg.as_str() {
+            "--user" => {
+                user = args.next().map(String::as_str);
+                user.is_some()
+            }
+            "--event" => {
+                event = args.next().map(String::as_str);
+                event.is_some()
+            }
+            "--last" => {
+                last = args.next().and_then(|n| n.parse().ok());
+                last.is_some()
+            }
+            "--failures" => {
+                failures = true;
+                true
+            }
+            _ => false,
+        };
+        if !valid {
+            println!("invalid arguments for {}", argv[0]);
+            return 1;
+        }
+    }
+
+    let entries = match env.audit.entries() {
+        Ok(entries) => entries,
+        Err(e) => {
+            println!("failed to read audit log: {}", e);
+            return 1;
+        }
+    };
+    let matching: Vec<&AuditEntry> = entries
+        .iter()
+        .filter(|e| user.is_none_or(|u| e.actor == u || e.target == u))
+        .filter(|e| event.is_none_or(|ev| e.event == ev))
+        .filter(|e| !failures || e.outcome != AuditOutcome::Success)
+        .collect();
+    let skip = last.map_or(0, |n| matching.len().saturating_sub(n));
+    for entry in &matching[skip..] {
+        println!("{}", entry);
+    }
+    0
+}
+
+pub static AUDIT: Command = Command {
+    name: "audit",
+    usage: "audit [verify | --user <name> --event <event> --failures --last <n>]",
+    description: "view or verify the audit log",
+    access: Access::Requires(Capability::AuditRead),
+    other: None,
+    resource: on_self,
+    handler: f_audit,
+};
+
+// ==== SESSION ====
+#[allow(unused_variables)]
+fn f_session(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    match (argc, argv.get(1).map(|s| s.as_str())) {
+        // current session
+        (1, _) => {
+            if let Some(session) = &env.session {
+                println!("{}", session);
+            }
+            0
+        }
+
+        // session.other sees every session, users their own
+        (2, Some("list")) => match env.sessions.list() {
+            Ok(sessions) => {
+                for session in sessions
+                    .iter()
+                    .filter(|s| env.can(Capability::SessionOther) || s.user == env.user())
+                {
+                    let current = env.session.as_ref().is_some_and(|s| s.id == session.id);
+                    println!("{} {}", if current { "*" } else { " " }, session);
+                }
+                0
+            }
+            Err(e) => {
+                println!("failed to list sessions: {}", e);
+                1
+            }
+        },
+
+        // session.other may revoke any session, users their own
+        (3, Some("revoke")) => {
+            let id = &argv[2];
+            let owner = match env.sessions.list() {
+                Ok(sessions) => sessions.into_iter().find(|s| s.id == *id).map(|s| s.user),
+                Err(e) => {
+                    println!("failed to list sessions: {}", e);
+                    return 1;
+                }
+            };
+            match owner {
+                Some(owner) if env.can(Capability::SessionOther) || owner == env.user() => {}
+                _ => {
+                    println!("no session {}", id);
+                    return 1;
+                }
+            }
+            match env.sessions.revoke(id) {
+                Ok(Some(session)) => {
+                    audit(
+                        env,
+                        "session",
+                        &session.user,
+                        AuditOutcome::Success,
+                        &format!("revoked session {}", session.id),
+                    );
+                    println!("revoked session {} of {}", session.id, session.user);
+
+                    // revoking the current session logs out entirely, rather than handing the
+                    // terminal to the suspended session beneath it
+                    if env.session.as_ref().is_some_and(|s| s.id == session.id) {
+                        if env.depth() > 0 {
+                            println!("also ended the {} suspended session(s)", env.depth());
+                        }
+                        env.end_all_sessions();
+                    }
+                    0
+                }
+                Ok(None) => {
+                    println!("no session {}", id);
+                    1
+                }
+                Err(e) => {
+                    println!("failed to revoke session: {}", e);
+                    1
+                }
+            }
+        }
+        _ => {
+            println!("invalid arguments for {}", argv[0]);
+            1
+        }
+    }
+}
+
+pub static SESSION: Command = Command {
+    name: "session",
+    usage: "session [list | revoke <id>]",
+    description: "show the current session, list sessions, or end one",
+    access: Access::LoggedIn,
+    other: None,
+    resource: on_object,
+    handler: f_session,
+};
+
+// ==== TOKEN ====
+#[allow(unused_variables)]
+fn f_token(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    let authority = TokenAuthority::new(
+        &env.config.token_key_path(),
+        &env.config.revoked_tokens_path(),
+    );
+    match (argc, argv.get(1).map(|s| s.as_str())) {
+        (2, Some("issue")) => {
+            if env.user() == NULLUSER {
+                println!("not logged in");
+                return 1;
+            }
+            match issue_token(env) {
+                true => 0,
+                false => 1,
+            }
+        }
+
+        // anyone may verify, the signing key is all that is needed
+        (3, Some("verify")) => match authority.verify(&argv[2]) {
+            Ok(claims) => {
+                println!(
+                    "valid token for {} (capabilities {}), issued {}, expires {}, id {}",
+                    claims.sub,
+                    format_capabilities(&claims.caps),
+                    format_time(claims.iat),
+                    format_time(claims.exp),
+                    claims.jti
+                );
+                0
+            }
+            Err(e) => {
+                println!("invalid token: {}", e);
+                1
+            }
+        },
+
+        // token.revoke.other may revoke any token, users their own
+        (3, Some("revoke")) => {
+            let claims = match authority.verify(&argv[2]) {
+                Ok(claims) => claims,
+                Err(e) => {
+                    println!("invalid token: {}", e);
+                    return 1;
+                }
+            };
+            if !env.can(Capability::TokenRevokeOther) && claims.sub != env.user() {
+                println!("token was issued to {}, not you", claims.sub);
+                return 1;
+            }
+            match authority.revoke(&claims) {
+                Ok(()) => {
+                    audit(
+                        env,
+                        "token",
+                        &claims.sub,
+                        AuditOutcome::Success,
+                        &format!("revoked token {}", claims.jti),
+                    );
+                    println!("revoked token {}", claims.jti);
+                    0
+                }
+                Err(e) => {
+                    audit(
+                        env,
+                        "token",
+                        &claims.sub,
+                        AuditOutcome::Failure,
+                        &e.to_string(),
+                    );
+                    println!("failed to revoke token: {}", e);
+                    1
+                }
+            }
+        }
+        _ => {
+            println!("invalid arguments for {}", argv[0]);
+            1
+        }
+    }
+}
+
+pub static TOKEN: Command = Command {
+    name: "token",
+    usage: "token <issue | verify <jwt> | revoke <jwt>>",
+    description: "issue, verify or revoke signed bearer tokens",
+    access: Access::Public,
+    other: None,
+    resource: on_self,
+    handler: f_token,
+};
+
+// ==== SUDO ====
+#[allow(unused_variables)]
+fn f_sudo(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    let user = env.user().to_string();
+    match (argc, argv.get(1).map(|s| s.as_str())) {
+        // list what the user may run
+        (2, Some("-l")) => {
+            if user == ROOT {
+                println!("{} may run every command", user);
+                return 0;
+            }
+            let sudoers = match load_sudoers(env) {
+                Some(sudoers) => sudoers,
+                None => return 1,
+            };
+            let rules = sudoers.rules_for(&user, |g| env.in_group(g));
+            if rules.is_empty() {
+                println!("{} may not run sudo", user);
+                return 0;
+            }
+            println!("{} may run the following commands with sudo:", user);
+            for rule in rules {
+                println!("    {}", rule);
+            }
+            0
+        }
+
+        // forget the cached authentication
+        (2, Some("-k")) => {
+            if let Some(session) = env.session.as_mut() {
+                session.sudo_until = 0;
+                if let Err(e) = env.sessions.save(session) {
+                    eprintln!("\x1b[91mFailed to update session. Error: {}\x1b[0m", e);
+                    return 1;
+                }
+            }
+            0
+        }
+        (1, _) => {
+            println!("invalid arguments for {}", argv[0]);
+            1
+        }
+        _ => run_sudo(env, argc, argv),
+    }
+}
+
+/// function to run a command through sudo, once the policy allows it and the user has
+/// re-authenticated
+/// # Arguments
+/// * `env` - shell environment
+/// * `argc` - number of arguments, `sudo` included
+/// * `argv` - `sudo` followed by the command and its arguments
+/// # Return
+/// * exit code of the command, or 1 if it was not run
+fn run_sudo(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    let user = env.user().to_string();
+    let args = &argv[1..];
+    let detail = args.join(" ");
+    let cmd = match env.commands.iter().copied().find(|c| c.name == args[0]) {
+        Some(cmd) => cmd,
+        None => {
+            println!("unknown command: {}. try 'help'", args[0]);
+            return 1;
+        }
+    };
+
+    // commands that change who is logged in would outlive the elevation
+    if matches!(cmd.name, "sudo" | "su" | "login" | "logout" | "switchuser") {
+        println!("{} can't be run with sudo", cmd.name);
+        return 1;
+    }
+
+    // forbid rules hold under sudo too, which the policy sees in `context.elevated`
+    if let Some(session) = env.session.as_mut() {
+        session.elevated = true;
+    }
+    let ruling = env.authorize(cmd, args);
+    if let Some(session) = env.session.as_mut() {
+        session.elevated = false;
+    }
+    if !ruling.allows() {
+        let reason = format!("{}: {}", ruling, detail);
+        audit(env, "sudo", "", AuditOutcome::Denied, &reason);
+        println!("{} may not run {}: {}", user, cmd.name, ruling);
+        return 1;
+    }
+
+    // root needs neither a rule nor a password
+    if env.user() == ROOT {
+        return (cmd.handler)(env, argc - 1, args);
+    }
+
+    // check the policy
+    let sudoers = match load_sudoers(env) {
+        Some(sudoers) => sudoers,
+        None => return 1,
+    };
+    let nopasswd = match sudoers.check(&user, |g| env.in_group(g), cmd.name) {
+        Some(rule) => rule.nopasswd,
+        None => {
+            audit(env, "sudo", "", AuditOutcome::Denied, &detail);
+            println!("{} is not allowed to run {} with sudo", user, cmd.name);
+            return 1;
+        }
+    };
+
+    // re-authenticate, unless the rule says not to or the user did so recently
+    let now = now();
+    let cached = env.session.as_ref().is_some_and(|s| now < s.sudo_until);
+    if !nopasswd && !cached {
+        if !sudo_authenticate(env, &user) {
+            let reason = format!("authentication failed: {}", detail);
+            audit(env, "sudo", "", AuditOutcome::Failure, &reason);
+            println!("sudo: {} incorrect password attempts", SUDO_ATTEMPTS);
+            return 1;
+        }
+        if let Some(session) = env.session.as_mut() {
+            session.sudo_until = now.saturating_add(env.config.sudo_timeout);
+            if let Err(e) = env.sessions.save(session) {
+                eprintln!("\x1b[91mFailed to update session. Error: {}\x1b[0m", e);
+            }
+        }
+    }
+    audit(env, "sudo", "", AuditOutcome::Success, &detail);
+
+    // run elevated, then drop back unless the command ended the session
+    let id = match &env.session {
+        Some(session) => session.id.clone(),
+        None => return 1,
+    };
+    if let Some(session) = env.session.as_mut() {
+        session.elevated = true;
+    }
+    let ret_code = (cmd.handler)(env, argc - 1, args);
+    if let Some(session) = env.session.as_mut()
+        && session.id == id
+    {
+        session.elevated = false;
+    }
+    ret_code
+}
+
+/// function to load the sudoers policy, printing why if it can't be used
+/// # Arguments
+/// * `env` - shell environment
+/// # Return
+/// * the policy, `None` if nobody may use sudo
+fn load_sudoers(env: &Environment) -> Option<Sudoers> {
+    match Sudoers::load(&env.config.sudoers_path()) {
+        Ok(sudoers) => Some(sudoers),
+        Err(e) => {
+            eprintln!(
+                "\x1b[91msudo is disabled, '{}' can't be used. Error: {}\x1b[0m",
+                env.config.sudoers_path(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// function to ask the active user for their own password, a few times like sudo does
+/// # Arguments
+/// * `env` - shell environment
+/// * `user` - account name
+/// # Return
+/// * whether or not the user entered their password
+fn sudo_authenticate(env: &Environment, user: &str) -> bool {
+    let prompt = format!("[sudo] password for {}: ", user);
+    for attempt in 1..=SUDO_ATTEMPTS {
+        if env
+            .database
+            .authenticate(user, &password_input(&prompt, false))
+        {
+            return true;
+        }
+        if attempt < SUDO_ATTEMPTS {
+            println!("sorry, try again");
+        }
+    }
+    false
+}
+
+pub static SUDO: Command = Command {
+    name: "sudo",
+    usage: "sudo <-l | -k | command [args]>",
+    description: "run a command as an administrator, list what you may run, or forget the cached password",
+    access: Access::LoggedIn,
+    other: None,
+    resource: on_first,
+    handler: f_sudo,
+};
+
+// ==== ROLE ====
+#[allow(unused_variables)]
+fn f_role(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    let action = argv.get(1).map(|s| s.as_str());
+
+    // reading roles is open to every user, changing them needs role.manage
+    if matches!(action, Some("create" | "delete" | "assign" | "unassign"))
+        && !env.can(Capability::RoleManage)
+    {
+        audit(
+            env,
+            "role",
+            "",
+            AuditOutcome::Denied,
+            &format!("{} requires {}", argv[1], Capability::RoleManage),
+        );
+        println!("{} requires {}", argv[1], Capability::RoleManage);
+        return 1;
+    }
+    let (target, result) = match (argc, action) {
+        (2, Some("list")) => {
+            let set = env.roles.roles();
+            for (role, capabilities) in &set.roles {
+                let holders: Vec<String> = set
+                    .assignments
+                    .iter()
+                    .filter(|a| a.role == *role)
+                    .map(|a| a.principal.to_string())
+                    .collect();
+                println!(
+                    "{}: {} (assigned to {})",
+                    role,
+                    format_capabilities(capabilities),
+                    match holders.is_empty() {
+                        true => "nobody".to_string(),
+                        false => holders.join(", "),
+                    }
+                );
+            }
+            return 0;
+        }
+        (2 | 3, Some("show")) => {
+            let user = argv.get(2).map_or(env.user(), |s| s.as_str()).to_string();
+            let record = match env.database.get(&user) {
+                Some(record) => record,
+                None => {
+                    println!("account {} not found", user);
+                    return 1;
+                }
+            };
+            let capabilities = match user == ROOT {
+                true => Capability::ALL.to_vec(),
+                false => env
+                    .roles
+                    .capabilities_of(&user, |g| env.groups.is_member(&record, g)),
+            };
+            println!("{}: {}", user, format_capabilities(&capabilities));
+            return 0;
+        }
+        (4, Some("create")) => {
+            let mut capabilities: Vec<Capability> = Vec::new();
+            for name in argv[3].split(',') {
+                match Capability::parse(name) {
+                    Some(capability) => capabilities.push(capability),
+                    None => {
+                        println!("unknown capability {:?}", name);
+                        return 1;
+                    }
+                }
+            }
+            (
+                argv[2].clone(),
+                env.roles.create(&argv[2], &capabilities).map(|()| {
+                    format!(
+                        "created role {} with {}",
+                        argv[2],
+                        format_capabilities(&capabilities)
+                    )
+                }),
+            )
+        }
+        (3, Some("delete")) => (
+            argv[2].clone(),
+            env.roles
+                .delete(&argv[2])
+                .map(|()| format!("deleted role {}", argv[2])),
+        ),
+        (4, Some("assign" | "unassign")) => {
+            let principal = match Principal::parse(&argv[3]) {
+                Ok(principal) => principal,
+                Err(e) => {
+                    println!("invalid principal: {}", e);
+                    return 1;
+                }
+            };
+            let result = match argv[1].as_str() {
+                "assign" => env
+                    .roles
+                    .assign(&argv[2], &principal)
+                    .map(|()| format!("assigned role {} to {}", argv[2], principal)),
+                _ => env
+                    .roles
+                    .unassign(&argv[2], &principal)
+                    .map(|()| format!("took role {} from {}", argv[2], principal)),
+            };
+            (argv[2].clone(), result)
+        }
+        _ => {
+            println!("invalid arguments for {}", argv[0]);
+            return 1;
+        }
+    };
+    match result {
+        Ok(detail) => {
+            audit(env, "role", &target, AuditOutcome::Success, &detail);
+            println!("{}", detail);
+            0
+        }
+        Err(e) => {
+            audit(env, "role", &target, AuditOutcome::Failure, &e.to_string());
+            println!("failed to {} role {}: {}", argv[1], target, e);
+            1
+        }
+    }
+}
+
+pub static ROLE: Command = Command {
+    name: "role",
+    usage: "role <list | show | create | delete | assign | unassign> [args]",
+    description: "list capabilities and roles, or manage them",
+    access: Access::LoggedIn,
+    other: None,
+    resource: on_object,
+    handler: f_role,
+};
+
+// ==== AUTHZ ====
+#[allow(unused_variables)]
+fn f_authz(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    if argc < 3 || argv[1] != "explain" {
+        println!("invalid arguments for {}", argv[0]);
+        return 1;
+    }
+    let args = &argv[2..];
+    let cmd = match env.commands.iter().copied().find(|c| c.name == args[0]) {
+        Some(cmd) => cmd,
+        None => {
+            println!("unknown command: {}. try 'help'", args[0]);
+            return 1;
+        }
+    };
+
+    // show the request as the policy sees it
+    let request = env.policy_request(cmd, args);
+    let context = &request.context;
+    println!(
+        "principal {:?}, action {:?}, resource {:?}",
+        request.principal, request.action, request.resource
+    );
+    let fields: Vec<String> = CONTEXT_FIELDS
+        .iter()
+        .filter_map(|field| context.get(field).map(|v| format!("{} {}", field, v)))
+        .collect();
+    println!("context {}", fields.join(", "));
+
+    // trace every rule, unless the policy can't be used at all
+    let policy_path = env.config.policy_path();
+    match Policy::load(&policy_path) {
+        Ok(policy) if policy.rules.is_empty() => println!("no rules in {}", policy_path),
+        Ok(policy) => {
+            let (_, outcomes) = policy.evaluate(&request, |a, g| env.account_in_group(a, g));
+            for (rule, outcome) in policy.rules.iter().zip(outcomes) {
+                println!("line #{}: {}", rule.line, rule.text);
+                println!("    {}", outcome);
+            }
+        }
+        Err(e) => println!("{}: {}", policy_path, e),
+    }
+    if let Access::Requires(capability) = cmd.access {
+        let held = match env.can(capability) {
+            true => "held",
+            false => "not held",
+        };
+        println!("{} requires {}, {}", cmd.name, capability, held);
+    }
+    let ruling = env.authorize(cmd, args);
+    println!(
+        "{}: {}",
+        match ruling.allows() {
+            true => "allowed",
+            false => "denied",
+        },
+        ruling
+    );
+    0
+}
+
+pub static AUTHZ: Command = Command {
+    name: "authz",
+    usage: "authz explain <command> [args]",
+    description: "show which policy rule or capability allows or denies a command",
+    access: Access::Public,
+    other: None,
+    resource: on_object,
+    handler: f_authz,
+};
+
+// ==== SCRAM ====
+#[allow(unused_variables)]
+fn f_scram(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    let user = match argc {
+        2 => argv[1].clone(),
+        1 => env.user().to_string(),
+        _ => {
+            println!("invalid arguments for {}", argv[0]);
+            return 1;
+        }
+    };
+
+    // the server side holds the account's credentials, never its password
+    let own = user == env.user();
+    let stored = env
+        .database
+        .get(&user)
+        .and_then(|record| env.verifiers.get(&user, record.hash()).cloned())
+        .map(|verifiers| verifiers.scram());
+    let stored = match stored {
+        Some(Ok(credentials)) if own => {
+            println!("server holds for {}:", user);
+            println!("    salt      {}", base64_encode(&credentials.salt));
+            println!("    i         {}", credentials.iterations);
+            println!("    StoredKey {}", base64_encode(&credentials.stored_key));
+            println!("    ServerKey {}", base64_encode(&credentials.server_key));
+            Some(credentials)
+        }
+        Some(Ok(credentials)) => Some(credentials),
+        Some(Err(e)) => {
+            eprintln!(
+                "\x1b[91mFailed to read the credentials of {}. Error: {}\x1b[0m",
+                user, e
+            );
+            return 1;
+        }
+        None if own => {
+            println!(
+                "no SCRAM credentials for {} yet, they are made the next time its password is set or used to log in",
+                user
+            );
+            return 1;
+        }
+        // whether another account exists is not given away, both fail like a wrong password
+        None => None,
+    };
+    let secret = match server_secret(env) {
+        Some(secret) => secret,
+        None => return 1,
+    };
+    let password = password_input(&format!("password for {}: ", user), false);
+    let mut client = ScramClient::new(&user, &password, None);
+    let mut server = ScramServer::new(None, &secret, env.config.scram_iterations);
+
+    // 1. the client names the account and sends a fresh nonce
+    let client_first = match client.client_first() {
+        Ok(message) => message,
+        Err(e) => {
+            println!("client gives up: {}", e);
+            return 1;
+        }
+    };
+    println!("\n1. client-first  C: {}", client_first);
+    println!(
+        "   gs2 header {:?}, no channel binding",
+        client.gs2_header()
+    );
+
+    // 2. the server extends the nonce and sends the salt and iterations
+    let server_first = match server.server_first(&client_first, |_| stored.clone()) {
+        Ok(message) => message,
+        Err(e) => {
+            println!("2. server-final  S: {}", e.server_final());
+            return 1;
+        }
+    };
+    println!("2. server-first  S: {}", server_first);
+
+    // 3. the client proves it knows the password without sending it
+    let client_final = match client.client_final(&server_first) {
+        Ok(message) => message,
+        Err(e) => {
+            println!("client gives up: {}", e);
+            return 1;
+        }
+    };
+    if let Some(credentials) = &stored
+        && let Ok(salted) = salted_password(&password, &credentials.salt, credentials.iterations)
+    {
+        let client_key = client_key(&salted);
+        println!(
+            "   SaltedPassword  = Hi(password, salt, i) = {}",
+            base64_encode(&salted)
+        );
+        println!(
+            "   ClientKey       = HMAC(SaltedPassword, \"Client Key\") = {}",
+            base64_encode(&client_key)
+        );
+        println!(
+            "   StoredKey       = H(ClientKey) = {}",
+            base64_encode(&sha256(client_key))
+        );
+    }
+    let without_proof = client_final
+        .rsplit_once(",p=")
+        .map_or(client_final.as_str(), |(without_proof, _)| without_proof);
+    let first_bare = client_first.splitn(3, ',').nth(2).unwrap_or_default();
+    println!(
+        "   AuthMessage     = {},{},{}",
+        first_bare, server_first, without_proof
+    );
+    println!("   ClientProof     = ClientKey XOR HMAC(StoredKey, AuthMessage)");
+    println!("3. client-final  C: {}", client_final);
+
+    // 4. the server recovers ClientKey from the proof and checks it hashes to StoredKey
+    println!("   server checks H(ClientProof XOR HMAC(StoredKey, AuthMessage)) = StoredKey");
+    let server_final = match server.server_final(&client_final) {
+        Ok(message) => message,
+        Err(e) => {
+            println!("4. server-final  S: {}", e.server_final());
+            audit(env, "scram", &user, AuditOutcome::Failure, &e.to_string());
+            println!("failed to authenticate as {}: {}", user, e);
+            return 1;
+        }
+    };
+    println!("4. server-final  S: {}", server_final);
+
+    // 5. the client checks the server holds the credentials too
+    println!("   client checks ServerSignature = HMAC(ServerKey, AuthMessage)");
+    match client.verify_server(&server_final) {
+        Ok(()) => {
+            audit(env, "scram", &user, AuditOutcome::Success, "authenticated");
+            println!("authenticated as {}, and the server proved itself", user);
+            0
+        }
+        Err(e) => {
+            println!("server is not trusted: {}", e);
+            1
+        }
+    }
+}
+
+pub static SCRAM: Command = Command {
+    name: "scram",
+    usage: "scram [username]",
+    description: "walk through a SCRAM-SHA-256 handshake step by step",
+    access: Access::LoggedIn,
+    other: None,
+    resource: on_last,
+    handler: f_scram,
+};
+
+// ==== SRP ====
+#[allow(unused_variables)]
+fn f_srp(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    let user = match argc {
+        2 => argv[1].clone(),
+        1 => env.user().to_string(),
+        _ => {
+            println!("invalid arguments for {}", argv[0]);
+            return 1;
+        }
+    };
+
+    // the server side holds the account's verifier, never its password
+    let own = user == env.user();
+    let stored = env
+        .database
+        .get(&user)
+        .and_then(|record| env.verifiers.get(&user, record.hash()).cloned())
+        .map(|verifiers| verifiers.srp());
+    let stored = match stored {
+        Some(Ok(Some(verifier))) if own => {
+            println!("server holds for {}:", user);
+            println!(
+                "    group {} bits, H = {}",
+                verifier.group,
+                verifier.hash.name()
+            );
+            println!("    s     {}", base64_encode(&verifier.salt));
+            println!("    v     {}", short_hex(&verifier.verifier));
+            Some(verifier)
+        }
+        Some(Ok(verifier)) if !own => verifier,
+        Some(Err(e)) => {
+            eprintln!(
+                "\x1b[91mFailed to read the verifier of {}. Error: {}\x1b[0m",
+                user, e
+            );
+            return 1;
+        }
+        _ if own => {
+            println!(
+                "no SRP verifier for {} yet, it is made the next time its password is set or used to log in",
+                user
+            );
+            return 1;
+        }
+        // whether another account exists is not given away, both fail like a wrong password
+        _ => None,
+    };
+    let key = match server_secret(env) {
+        Some(key) => key,
+        None => return 1,
+    };
+    let password = password_input(&format!("password for {}: ", user), false);
+    let mut client = SrpClient::new(&user, &password);
+    let mut server = SrpServer::new(&key, env.config.srp_group);
+
+    // 1. the client names the account
+    let username = match client.client_hello() {
+        Ok(username) => username,
+        Err(e) => {
+            println!("client gives up: {}", e);
+            return 1;
+        }
+    };
+    println!("\n1. hello      C: I = {}", username);
+
+    // 2. the server sends the salt and its public value, blinded by the verifier
+    let challenge = match server.server_challenge(&username, |_| stored.clone()) {
+        Ok(challenge) => challenge,
+        Err(e) => {
+            println!("server gives up: {}", e);
+            return 1;
+        }
+    };
+    let group = match SrpGroup::rfc5054(challenge.group) {
+        Ok(group) => group,
+        Err(e) => {
+            println!("client gives up: {}", e);
+            return 1;
+        }
+    };
+    println!(
+        "   N = RFC 5054 {}-bit group, g = {}, H = {}",
+        challenge.group,
+        group.g,
+        challenge.hash.name()
+    );
+    println!(
+        "   k = H(N | PAD(g)) = {}",
+        short_hex(&multiplier(challenge.hash, &group))
+    );
+    println!("   B = k*v + g^b mod N");
+    println!(
+        "2. challenge  S: s = {}, B = {}",
+        base64_encode(&challenge.salt),
+        short_hex(&challenge.b_pub)
+    );
+
+    // 3. the client derives the session key and proves it did, without sending the password
+    let proof = match client.client_proof(&challenge) {
+        Ok(proof) => proof,
+        Err(e) => {
+            println!("client gives up: {}", e);
+            return 1;
+        }
+    };
+    println!("   A = g^a mod N");
+    println!(
+        "   u = H(PAD(A) | PAD(B)) = {}",
+        short_hex(&scrambler(
+            challenge.hash,
+            &group,
+            &proof.a_pub,
+            &challenge.b_pub
+        ))
+    );
+    println!("   x = H(s | H(I | \":\" | P))");
+    println!("   S = (B - k*g^x)^(a + u*x) mod N, K = H(PAD(S))");
+    println!("   M1 = H(H(N) XOR H(g) | H(I) | s | A | B | K)");
+    println!(
+        "3. proof      C: A = {}, M1 = {}",
+        short_hex(&proof.a_pub),
+        hex::encode(&proof.m1)
+    );
+
+    // 4. the server derives the same key from the verifier and checks the proof
+    println!("   server computes S = (A * v^u)^b mod N and checks M1");
+    let m2 = match server.server_verify(&proof) {
+        Ok(m2) => m2,
+        Err(e) => {
+            println!("4. server rejects the proof");
+            audit(env, "srp", &user, AuditOutcome::Failure, &e.to_string());
+            println!("failed to authenticate as {}: {}", user, e);
+            return 1;
+        }
+    };
+    println!("4. proof      S: M2 = H(A | M1 | K) = {}", hex::encode(&m2));
+
+    // 5. the client checks the server holds the verifier too
+    if let Err(e) = client.verify_server(&m2) {
+        println!("server is not trusted: {}", e);
+        return 1;
+    }
+    let keys = (client.session_key(), server.session_key());
+    if let (Some(client_side), Some(server_side)) = keys {
+        println!("   client K = {}", hex::encode(client_side));
+        println!("   server K = {}", hex::encode(server_side));
+    }
+    audit(env, "srp", &user, AuditOutcome::Success, "authenticated");
+    println!(
+        "authenticated as {}, the server proved itself and both sides hold the same session key",
+        user
+    );
+    0
+}
+
+pub static SRP: Command = Command {
+    name: "srp",
+    usage: "srp [username]",
+    description: "walk through an SRP-6a key exchange step by step",
+    access: Access::LoggedIn,
+    other: None,
+    resource: on_last,
+    handler: f_srp,
+};
+
+// ==== ENV ====
+#[allow(unused_variables)]
+fn f_env(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    if argc != 1 {
+        println!("invalid arguments for {}", argv[0]);
+        return 1;
+    }
+    for (name, value) in &env.vars {
+        println!("{}={}", name, value);
+    }
+    0
+}
+
+pub static ENV: Command = Command {
+    name: "env",
+    usage: "env",
+    description: "print the environment variables of the session",
+    access: Access::LoggedIn,
+    other: None,
+    resource: on_self,
+    handler: f_env,
+};
+
+// ==== SETENV ====
+#[allow(unused_variables)]
+fn f_setenv(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    if !(2..=3).contains(&argc) {
+        println!("invalid arguments for {}", argv[0]);
+        return 1;
+    }
+    let name = &argv[1];
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        println!("invalid variable name {:?}", name);
+        return 1;
+    }
+    let value = argv.get(2).cloned().unwrap_or_default();
+    env.vars.insert(name.clone(), value);
+    0
+}
+
+pub static SETENV: Command = Command {
+    name: "setenv",
+    usage: "setenv <name> [value]",
+    description: "set an environment variable of the session, used as $name",
+    access: Access::LoggedIn,
+    other: None,
+    resource: on_first,
+    handler: f_setenv,
+};
+
+// ==== UNSETENV ====
+#[allow(unused_variables)]
+fn f_unsetenv(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    if argc != 2 {
+        println!("invalid arguments for {}", argv[0]);
+        return 1;
+    }
+    match env.vars.remove(&argv[1]) {
+        Some(_) => 0,
+        None => {
+            println!("no variable {}", argv[1]);
+            1
+        }
+    }
+}
+
+pub static UNSETENV: Command = Command {
+    name: "unsetenv",
+    usage: "unsetenv <name>",
+    description: "remove an environment variable of the session",
+    access: Access::LoggedIn,
+    other: None,
+    resource: on_last,
+    handler: f_unsetenv,
+};
+
+// ==== CONFIG ====
+#[allow(unused_variables)]
+fn f_config(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
+    if argc != 2 || argv[1] != "show" {
+        println!("invalid arguments for {}", argv[0]);
+        return 1;
+    }
+    let entries = env.config.entries();
+    let key_width = entries.iter().map(|(k, _, _)| k.len()).max().unwrap_or(0);
+    let value_width = entries.iter().map(|(_, v, _)| v.len()).max().unwrap_or(0);
+    for (key, value, source) in entries {
+        println!(
+            "{:<key_width$} = {:<value_width$}  ({})",
+            key, value, source
+        );
+    }
+    0
+}
+
+pub static CONFIG: Command = Command {
+    name: "config",
+    usage: "config show",
+    description: "print the effective configuration and where it came from",
+    access: Access::LoggedIn,
+    other: None,
+    resource: on_self,
+    handler: f_config,
+};
+
 // ==== EXIT ====
 #[allow(unused_variables)]
 fn f_exit(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
@@ -488,7 +3634,9 @@ fn f_exit(env: &mut Environment, argc: u8, argv: &[String]) -> i8 {
 pub static EXIT: Command = Command {
     name: "exit",
     usage: "exit",
-    description: "exit shell",
-    permissions: P_NONE,
+    description: "leave a su session, or exit the shell",
+    access: Access::Public,
+    other: None,
+    resource: on_self,
     handler: f_exit,
 };
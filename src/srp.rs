@@ -0,0 +1,816 @@
+// src/srp.rs
+//! This module contains the SRP-6a password-authenticated key exchange (RFC 2945, RFC 5054):
+//! * verifiers `v = g^x mod N`, from which the password can't be recovered without guessing it
+//! * the RFC 5054 groups, with `k = H(N | PAD(g))` and `u = H(PAD(A) | PAD(B))`
+//! * client and server state machines that agree on a session key and prove it to each other,
+//!   so neither the password nor anything equivalent to it crosses the wire
+//! * SHA-1 only to replay the RFC 5054 test vectors, new verifiers use SHA-256
+//! * an unknown account gets a made-up verifier whose salt is derived from its name and a
+//!   server key, so asking twice gives the same salt, as for a real account
+//!
+//! The big number arithmetic is not constant time, so this is a demonstration only
+
+// ==================== IMPORTS ====================
+
+use crate::auth_utils::*;
+use crate::bignum::BigUint;
+use rand_core::{OsRng, TryRngCore};
+use std::fmt;
+
+// ==================== CONSTANTS ====================
+
+/// prefix of stored verifiers
+pub const SRP_PREFIX: &str = "SRP-6a";
+
+/// sizes of the RFC 5054 groups supported, in bits
+pub const SRP_GROUPS: [u32; 4] = [1024, 1536, 2048, 3072];
+
+/// group of new verifiers, in bits
+pub const DEF_SRP_GROUP: u32 = 2048;
+
+/// length of the salt of new verifiers, in bytes
+const SRP_SALT_LEN: usize = 16;
+
+/// length of the private values `a` and `b`, in bytes, at least 256 bits as RFC 5054 asks
+const SRP_SECRET_LEN: usize = 32;
+
+/// label the salts of made-up verifiers are derived under, so the server key can key other
+/// things too
+const FAKE_SALT_LABEL: &str = "srp-6a salt:";
+
+/// RFC 5054 appendix A, 1024-bit group, generator 2
+const GROUP_1024: &str = "
+    EEAF0AB9 ADB38DD6 9C33F80A FA8FC5E8 60726187 75FF3C0B 9EA2314C
+    9C256576 D674DF74 96EA81D3 383B4813 D692C6E0 E0D5D8E2 50B98BE4
+    8E495C1D 6089DAD1 5DC7D7B4 6154D6B6 CE8EF4AD 69B15D49 82559B29
+    7BCF1885 C529F566 660E57EC 68EDBC3C 05726CC0 2FD4CBF4 976EAA9A
+    FD5138FE 8376435B 9FC61D2F C0EB06E3";
+
+/// RFC 5054 appendix A, 1536-bit group, generator 2
+const GROUP_1536: &str = "
+    9DEF3CAF B939277A B1F12A86 17A47BBB DBA51DF4 99AC4C80 BEEEA961
+    4B19CC4D 5F4F5F55 6E27CBDE 51C6A94B E4607A29 1558903B A0D0F843
+    80B655BB 9A22E8DC DF028A7C EC67F0D0 8134B1C8 B9798914 9B609E0B
+    E3BAB63D 47548381 DBC5B1FC 764E3F4B 53DD9DA1 158BFD3E 2B9C8CF5
+    6EDF0195 39349627 DB2FD53D 24B7C486 65772E43 7D6C7F8C E442734A
+    F7CCB7AE 837C264A E3A9BEB8 7F8A2FE9 B8B5292E 5A021FFF 5E91479E
+    8CE7A28C 2442C6F3 15180F93 499A234D CF76E3FE D135F9BB";
+
+/// RFC 5054 appendix A, 2048-bit group, generator 2
+const GROUP_2048: &str = "
+    AC6BDB41 324A9A9B F166DE5E 1389582F AF72B665 1987EE07 FC319294
+    3DB56050 A37329CB B4A099ED 8193E075 7767A13D D52312AB 4B03310D
+    CD7F48A9 DA04FD50 E8083969 EDB767B0 CF609517 9A163AB3 661A05FB
+    D5FAAAE8 2918A996 2F0B93B8 55F97993 EC975EEA A80D740A DBF4FF74
+    7359D041 D5C33EA7 1D281E44 6B14773B CA97B43A 23FB8016 76BD207A
+    436C6481 F1D2B907 8717461A 5B9D32E6 88F87748 544523B5 24B0D57D
+    5EA77A27 75D2ECFA 032CFBDB F52FB378 61602790 04E57AE6 AF874E73
+    03CE5329 9CCC041C 7BC308D8 2A5698F3 A8D0C382 71AE35F8 E9DBFBB6
+    94B5C803 D89F7AE4 35DE236D 525F5475 9B65E372 FCD68EF2 0FA7111F
+    9E4AFF73";
+
+/// RFC 5054 appendix A, 3072-bit group (the RFC 3526 prime), generator 5
+const GROUP_3072: &str = "
+    FFFFFFFF FFFFFFFF C90FDAA2 2168C234 C4C6628B 80DC1CD1 29024E08
+    8A67CC74 020BBEA6 3B139B22 514A0879 8E3404DD EF9519B3 CD3A431B
+    302B0A6D F25F1437 4FE1356D 6D51C245 E485B576 625E7EC6 F44C42E9
+    A637ED6B 0BFF5CB6 F406B7ED EE386BFB 5A899FA5 AE9F2411 7C4B1FE6
+    49286651 ECE45B3D C2007CB8 A163BF05 98DA4836 1C55D39A 69163FA8
+    FD24CF5F 83655D23 DCA3AD96 1C62F356 208552BB 9ED52907 7096966D
+    670C354E 4ABC9804 F1746C08 CA18217C 32905E46 2E36CE3B E39E772C
+    180E8603 9B2783A2 EC07A28F B5C55DF0 6F4C52C9 DE2BCBF6 95581718
+    3995497C EA956AE5 15D22618 98FA0510 15728E5A 8AAAC42D AD33170D
+    04507A33 A85521AB DF1CBA64 ECFB8504 58DBEF0A 8AEA7157 5D060C7D
+    B3970F85 A6E1E4C7 ABF5AE8C DB0933D7 1E8C94E0 4A25619D CEE3D226
+    1AD2EE6B F12FFA06 D98A0864 D8760273 3EC86A64 521F2B18 177B200C
+    BBE11757 7A615D6C 770988C0 BAD946E2 08E24FA0 74E5AB31 43DB5BFC
+    E0FD108E 4B82D120 A93AD2CA FFFFFFFF FFFFFFFF";
+
+// ==================== STRUCTURES ====================
+
+/// hash function `H` of an exchange
+/// # Variants
+/// * `Sha1` - as RFC 5054's test vectors use, **NOT SECURE**
+/// * `Sha256` - used for new verifiers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SrpHash {
+    Sha1,
+    Sha256,
+}
+
+/// an RFC 5054 group, the safe prime modulus `N` and its generator `g`
+/// # Fields
+/// * `bits` - size of `N`
+/// * `n` - modulus
+/// * `g` - generator
+/// # Methods
+/// * `rfc5054` - looks a group up by its size
+/// * `byte_len` - length of `N` in bytes, what `PAD` pads to
+/// * `pad` - writes a number as `PAD` does, left padded to the length of `N`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SrpGroup {
+    pub bits: u32,
+    pub n: BigUint,
+    pub g: BigUint,
+}
+
+/// what a server stores to check a password with SRP, in place of a password hash
+/// # Fields
+/// * `hash` - hash function of the exchange
+/// * `group` - size of the group
+/// * `salt` - random salt
+/// * `verifier` - `v = g^x mod N`
+/// # Methods
+/// * `derive` - derives a verifier from a username, password and salt
+/// * `new` - derives a verifier with a random salt
+/// * `parse` - reads a verifier as `Display` writes it,
+///   `SRP-6a$<hash>$<bits>$<salt>$<v>` with the salt and `v` in base64
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SrpVerifier {
+    pub hash: SrpHash,
+    pub group: u32,
+    pub salt: Vec<u8>,
+    pub verifier: BigUint,
+}
+
+/// the server's answer to a client naming its account
+/// # Fields
+/// * `hash` - hash function of the exchange
+/// * `group` - size of the group
+/// * `salt` - the account's salt
+/// * `b_pub` - the server's public value `B = k*v + g^b mod N`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SrpChallenge {
+    pub hash: SrpHash,
+    pub group: u32,
+    pub salt: Vec<u8>,
+    pub b_pub: BigUint,
+}
+
+/// the client's answer to a challenge
+/// # Fields
+/// * `a_pub` - the client's public value `A = g^a mod N`
+/// * `m1` - proof it derived the session key, `H(H(N) XOR H(g) | H(I) | s | A | B | K)`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SrpProof {
+    pub a_pub: BigUint,
+    pub m1: Vec<u8>,
+}
+
+/// reasons an SRP exchange fails
+/// # Variants
+/// * `Malformed` - a stored verifier or a message could not be parsed, with why
+/// * `UnknownGroup` - not one of `SRP_GROUPS`, with its size
+/// * `UnknownHash` - not a supported hash function, with its name
+/// * `OutOfOrder` - a step was taken out of turn or after a failure, naming the step
+/// * `IllegalParameter` - `A` or `B` is zero modulo `N`, or `u` is zero, naming which
+/// * `InvalidProof` - the client's proof is wrong, so it doesn't know the password
+/// * `ServerProof` - the server's proof is wrong, so it doesn't hold the verifier
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SrpError {
+    Malformed(String),
+    UnknownGroup(u32),
+    UnknownHash(String),
+    OutOfOrder(&'static str),
+    IllegalParameter(&'static str),
+    InvalidProof,
+    ServerProof,
+}
+
+/// SRP client, taking the three steps of the exchange in order
+/// # Fields
+/// * `username` - account to authenticate as, `I`
+/// * `password` - its password, `P`
+/// * `secret` - private value `a`
+/// * `state` - progress of the exchange
+/// # Methods
+/// * `new`, `with_secret` - start an exchange with a random or given `a`
+/// * `client_hello` - names the account
+/// * `client_proof` - answers the challenge with `A` and the proof `M1`
+/// * `verify_server` - checks the server's proof `M2`
+/// * `session_key` - the shared key `K`, once the server proved itself
+pub struct SrpClient {
+    username: String,
+    password: String,
+    secret: BigUint,
+    state: ClientState,
+}
+
+/// progress of a client's exchange
+/// # Variants
+/// * `Start` - nothing sent yet
+/// * `SentHello` - account named
+/// * `SentProof` - proof sent, with the `M2` to expect and the session key
+/// * `Done` - the server proved itself, with the session key
+/// * `Failed` - a step failed, the exchange is over
+enum ClientState {
+    Start,
+    SentHello,
+    SentProof(Vec<u8>, Vec<u8>),
+    Done(Vec<u8>),
+    Failed,
+}
+
+/// SRP server, answering a client's messages
+/// # Fields
+/// * `key` - server key the salts of made-up verifiers are derived from
+/// * `group` - group of made-up verifiers
+/// * `secret` - private value `b`
+/// * `state` - progress of the exchange
+/// # Methods
+/// * `new`, `with_secret` - start an exchange with a random or given `b`, and the server key
+///   made-up verifiers are derived from
+/// * `server_challenge` - answers a client naming its account with the salt and `B`
+/// * `server_verify` - checks the client's proof and answers with `M2`
+/// * `username` - account that authenticated, once the exchange succeeded
+/// * `session_key` - the shared key `K`, once the exchange succeeded
+pub struct SrpServer {
+    key: Vec<u8>,
+    group: u32,
+    secret: BigUint,
+    state: ServerState,
+}
+
+/// what a server remembers between its two messages
+/// # Fields
+/// * `username` - account the client asked for
+/// * `verifier` - the account's verifier, made up if it has none
+/// * `group` - the verifier's group
+/// * `b_pub` - the server's public value `B`
+struct Pending {
+    username: String,
+    verifier: SrpVerifier,
+    group: SrpGroup,
+    b_pub: BigUint,
+}
+
+/// progress of a server's exchange
+/// # Variants
+/// * `Start` - nothing received yet
+/// * `SentChallenge` - challenge sent
+/// * `Done` - the client authenticated as the account, with the session key
+/// * `Failed` - a step failed, the exchange is over
+enum ServerState {
+    Start,
+    SentChallenge(Box<Pending>),
+    Done(String, Vec<u8>),
+    Failed,
+}
+
+/// Methods for the enum
+impl SrpHash {
+    /// hash the concatenation of some byte strings
+    /// # Arguments
+    /// * `parts` - byte strings, hashed in order
+    pub fn digest(&self, parts: &[&[u8]]) -> Vec<u8> {
+        let message = parts.concat();
+        match self {
+            SrpHash::Sha1 => sha1(message),
+            SrpHash::Sha256 => sha256(message),
+        }
+    }
+
+    /// name of the hash function, as stored verifiers write it
+    pub fn name(&self) -> &'static str {
+        match self {
+            SrpHash::Sha1 => "sha1",
+            SrpHash::Sha256 => "sha256",
+        }
+    }
+
+    /// look a hash function up by name
+    /// # Arguments
+    /// * `name` - `sha1` or `sha256`
+    pub fn parse(name: &str) -> Result<Self, SrpError> {
+        match name {
+            "sha1" => Ok(SrpHash::Sha1),
+            "sha256" => Ok(SrpHash::Sha256),
+            _ => Err(SrpError::UnknownHash(name.to_string())),
+        }
+    }
+}
+
+/// Methods for the struct
+impl SrpGroup {
+    /// look an RFC 5054 group up by its size
+    /// # Arguments
+    /// * `bits` - one of `SRP_GROUPS`
+    pub fn rfc5054(bits: u32) -> Result<Self, SrpError> {
+        let (hex, g) = match bits {
+            1024 => (GROUP_1024, 2),
+            1536 => (GROUP_1536, 2),
+            2048 => (GROUP_2048, 2),
+            3072 => (GROUP_3072, 5),
+            _ => return Err(SrpError::UnknownGroup(bits)),
+        };
+        Ok(SrpGroup {
+            bits,
+            n: BigUint::from_hex(hex).expect("RFC 5054 group constants are hex"),
+            g: BigUint::from_u32(g),
+        })
+    }
+
+    /// length of `N` in bytes
+    pub fn byte_len(&self) -> usize {
+        self.bits as usize / 8
+    }
+
+    /// `PAD(x)`, a number left padded with zeros to the length of `N`
+    /// # Arguments
+    /// * `x` - number below `N`
+    pub fn pad(&self, x: &BigUint) -> Vec<u8> {
+        x.to_bytes_be_padded(self.byte_len())
+    }
+}
+
+/// Methods for the struct
+impl SrpVerifier {
+    /// derive a verifier from a password
+    /// # Arguments
+    /// * `hash` - hash function of the exchange
+    /// * `group` - size of the group, one of `SRP_GROUPS`
+    /// * `username` - account name, `I`, part of `x` so the verifier is bound to it
+    /// * `password` - password, `P`
+    /// * `salt` - salt, `s`
+    pub fn derive(
+        hash: SrpHash,
+        group: u32,
+        username: &str,
+        password: &str,
+        salt: &[u8],
+    ) -> Result<Self, SrpError> {
+        let srp_group = SrpGroup::rfc5054(group)?;
+        let x = private_key(hash, salt, username, password);
+        Ok(SrpVerifier {
+            hash,
+            group,
+            salt: salt.to_vec(),
+            verifier: srp_group.g.mod_pow(&x, &srp_group.n),
+        })
+    }
+
+    /// derive a SHA-256 verifier from a password with a random salt
+    /// # Arguments
+    /// * `group` - size of the group, one of `SRP_GROUPS`
+    /// * `username` - account name
+    /// * `password` - password
+    pub fn new(group: u32, username: &str, password: &str) -> Result<Self, SrpError> {
+        Self::derive(
+            SrpHash::Sha256,
+            group,
+            username,
+            password,
+            &random_bytes(SRP_SALT_LEN),
+        )
+    }
+
+    /// read a verifier as `Display` writes it
+    /// # Arguments
+    /// * `text` - `SRP-6a$<hash>$<bits>$<salt>$<v>`
+    pub fn parse(text: &str) -> Result<Self, SrpError> {
+        let malformed = || SrpError::Malformed("stored verifier".to_string());
+        let fields: Vec<&str> = text.split('$').collect();
+        let [prefix, hash, group, salt, verifier] = fields[..] else {
+            return Err(malformed());
+        };
+        if prefix != SRP_PREFIX {
+            return Err(malformed());
+        }
+        let group: u32 = group.parse().map_err(|_| malformed())?;
+        let srp_group = SrpGroup::rfc5054(group)?;
+        let verifier = BigUint::from_bytes_be(&base64_decode(verifier).map_err(|_| malformed())?);
+        if verifier.is_zero() || verifier >= srp_group.n {
+            return Err(malformed());
+        }
+        Ok(SrpVerifier {
+            hash: SrpHash::parse(hash)?,
+            group,
+            salt: base64_decode(salt).map_err(|_| malformed())?,
+            verifier,
+        })
+    }
+}
+
+impl fmt::Display for SrpVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}${}${}${}${}",
+            SRP_PREFIX,
+            self.hash.name(),
+            self.group,
+            base64_encode(&self.salt),
+            base64_encode(&self.verifier.to_bytes_be())
+        )
+    }
+}
+
+impl fmt::Display for SrpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SrpError::Malformed(what) => write!(f, "malformed {}", what),
+            SrpError::UnknownGroup(bits) => write!(f, "no {}-bit SRP group", bits),
+            SrpError::UnknownHash(name) => write!(f, "unsupported hash function '{}'", name),
+            SrpError::OutOfOrder(step) => write!(f, "{} taken out of turn", step),
+            SrpError::IllegalParameter(what) => write!(f, "illegal parameter {}", what),
+            SrpError::InvalidProof => write!(f, "invalid proof"),
+            SrpError::ServerProof => write!(f, "server proof mismatch"),
+        }
+    }
+}
+
+impl std::error::Error for SrpError {}
+
+/// Methods for the struct
+impl SrpClient {
+    /// start an exchange with a random private value
+    /// # Arguments
+    /// * `username` - account to authenticate as
+    /// * `password` - its password
+    pub fn new(username: &str, password: &str) -> Self {
+        Self::with_secret(username, password, random_secret())
+    }
+
+    /// start an exchange with a given private value, e.g. to replay a test exchange
+    /// # Arguments
+    /// * `username` - account to authenticate as
+    /// * `password` - its password
+    /// * `secret` - private value `a`
+    pub fn with_secret(username: &str, password: &str, secret: BigUint) -> Self {
+        SrpClient {
+            username: username.to_string(),
+            password: password.to_string(),
+            secret,
+            state: ClientState::Start,
+        }
+    }
+
+    /// name the account to authenticate as, `I`
+    pub fn client_hello(&mut self) -> Result<String, SrpError> {
+        if !matches!(self.state, ClientState::Start) {
+            return Err(self.fail(SrpError::OutOfOrder("client hello")));
+        }
+        self.state = ClientState::SentHello;
+        Ok(self.username.clone())
+    }
+
+    /// answer the server's challenge with `A` and the proof `M1`
+    /// # Arguments
+    /// * `challenge` - salt, group and `B` from the server
+    pub fn client_proof(&mut self, challenge: &SrpChallenge) -> Result<SrpProof, SrpError> {
+        if !matches!(self.state, ClientState::SentHello) {
+            return Err(self.fail(SrpError::OutOfOrder("client proof")));
+        }
+        match self.prove(challenge) {
+            Ok((proof, m2, key)) => {
+                self.state = ClientState::SentProof(m2, key);
+                Ok(proof)
+            }
+            Err(e) => Err(self.fail(e)),
+        }
+    }
+
+    /// internal method to compute the proof, the `M2` to expect and the session key
+    fn prove(&self, challenge: &SrpChallenge) -> Result<(SrpProof, Vec<u8>, Vec<u8>), SrpError> {
+        let hash = challenge.hash;
+        let group = SrpGroup::rfc5054(challenge.group)?;
+        if challenge.b_pub.rem(&group.n).is_zero() {
+            return Err(SrpError::IllegalParameter("B"));
+        }
+        let a_pub = group.g.mod_pow(&self.secret, &group.n);
+        let u = scrambler(hash, &group, &a_pub, &challenge.b_pub);
+        if u.is_zero() {
+            return Err(SrpError::IllegalParameter("u"));
+        }
+        let x = private_key(hash, &challenge.salt, &self.username, &self.password);
+        let premaster = client_premaster(hash, &group, &challenge.b_pub, &self.secret, &x, &u);
+        let key = session_key(hash, &group, &premaster);
+        let m1 = client_evidence(
+            hash,
+            &group,
+            &self.username,
+            &challenge.salt,
+            &a_pub,
+            &challenge.b_pub,
+            &key,
+        );
+        let m2 = server_evidence(hash, &a_pub, &m1, &key);
+        Ok((SrpProof { a_pub, m1 }, m2, key))
+    }
+
+    /// check the server's proof `M2 = H(A | M1 | K)`
+    /// # Arguments
+    /// * `m2` - proof from the server
+    /// # Return
+    /// * `Ok` if the server proved it holds the verifier
+    pub fn verify_server(&mut self, m2: &[u8]) -> Result<(), SrpError> {
+        let (expected, key) = match &self.state {
+            ClientState::SentProof(expected, key) => (expected.clone(), key.clone()),
+            _ => return Err(self.fail(SrpError::OutOfOrder("server proof check"))),
+        };
+        if !constant_time_eq(m2, &expected) {
+            return Err(self.fail(SrpError::ServerProof));
+        }
+        self.state = ClientState::Done(key);
+        Ok(())
+    }
+
+    /// shared session key `K`, `None` until the server proved itself
+    pub fn session_key(&self) -> Option<&[u8]> {
+        match &self.state {
+            ClientState::Done(key) => Some(key),
+            _ => None,
+        }
+    }
+
+    /// internal method to end the exchange after a failed step
+    fn fail(&mut self, e: SrpError) -> SrpError {
+        self.state = ClientState::Failed;
+        e
+    }
+}
+
+/// Methods for the struct
+impl SrpServer {
+    /// start an exchange with a random private value
+    /// # Arguments
+    /// * `key` - server key, kept across exchanges, the salts of made-up verifiers are
+    ///   derived from
+    /// * `group` - group of made-up verifiers, that of new verifiers
+    pub fn new(key: &[u8], group: u32) -> Self {
+        Self::with_secret(key, group, random_secret())
+    }
+
+    /// start an exchange with a given private value, e.g. to replay a test exchange
+    /// # Arguments
+    /// * `key` - server key the salts of made-up verifiers are derived from
+    /// * `group` - group of made-up verifiers
+    /// * `secret` - private value `b`
+    pub fn with_secret(key: &[u8], group: u32, secret: BigUint) -> Self {
+        SrpServer {
+            key: key.to_vec(),
+            group,
+            secret,
+            state: ServerState::Start,
+        }
+    }
+
+    /// answer a client naming its account with the salt and `B`
+    /// an account without a verifier gets a made-up one, so the exchange fails like a wrong
+    /// password instead of telling the client the account doesn't exist, and its salt is the
+    /// same every time, as a real account's is (RFC 5054 section 2.5.1.3)
+    /// # Arguments
+    /// * `username` - account the client named
+    /// * `lookup` - finds the verifier of an account
+    pub fn server_challenge<F>(
+        &mut self,
+        username: &str,
+        lookup: F,
+    ) -> Result<SrpChallenge, SrpError>
+    where
+        F: FnOnce(&str) -> Option<SrpVerifier>,
+    {
+        if !matches!(self.state, ServerState::Start) {
+            return Err(self.fail(SrpError::OutOfOrder("server challenge")));
+        }
+        let verifier = match lookup(username) {
+            Some(verifier) => verifier,
+            None => {
+                let label = format!("{}{}", FAKE_SALT_LABEL, username);
+                let mut salt = hmac_sha256(&self.key, label.as_bytes());
+                salt.truncate(SRP_SALT_LEN);
+                let password = random_secret().to_string();
+                match SrpVerifier::derive(SrpHash::Sha256, self.group, username, &password, &salt) {
+                    Ok(verifier) => verifier,
+                    Err(e) => return Err(self.fail(e)),
+                }
+            }
+        };
+        let group = match SrpGroup::rfc5054(verifier.group) {
+            Ok(group) => group,
+            Err(e) => return Err(self.fail(e)),
+        };
+
+        // B = k*v + g^b mod N
+        let k = multiplier(verifier.hash, &group);
+        let b_pub = k
+            .mul(&verifier.verifier)
+            .add(&group.g.mod_pow(&self.secret, &group.n))
+            .rem(&group.n);
+        let challenge = SrpChallenge {
+            hash: verifier.hash,
+            group: verifier.group,
+            salt: verifier.salt.clone(),
+            b_pub: b_pub.clone(),
+        };
+        self.state = ServerState::SentChallenge(Box::new(Pending {
+            username: username.to_string(),
+            verifier,
+            group,
+            b_pub,
+        }));
+        Ok(challenge)
+    }
+
+    /// check the client's proof and answer with `M2`
+    /// # Arguments
+    /// * `proof` - `A` and `M1` from the client
+    /// # Return
+    /// * the server's proof `M2 = H(A | M1 | K)`
+    pub fn server_verify(&mut self, proof: &SrpProof) -> Result<Vec<u8>, SrpError> {
+        let pending = match std::mem::replace(&mut self.state, ServerState::Failed) {
+            ServerState::SentChallenge(pending) => pending,
+            _ => return Err(SrpError::OutOfOrder("server verify")),
+        };
+        let hash = pending.verifier.hash;
+        let group = &pending.group;
+        if proof.a_pub.rem(&group.n).is_zero() {
+            return Err(SrpError::IllegalParameter("A"));
+        }
+        let u = scrambler(hash, group, &proof.a_pub, &pending.b_pub);
+        if u.is_zero() {
+            return Err(SrpError::IllegalParameter("u"));
+        }
+        let premaster = server_premaster(
+            group,
+            &proof.a_pub,
+            &pending.verifier.verifier,
+            &u,
+            &self.secret,
+        );
+        let key = session_key(hash, group, &premaster);
+        let m1 = client_evidence(
+            hash,
+            group,
+            &pending.username,
+            &pending.verifier.salt,
+            &proof.a_pub,
+            &pending.b_pub,
+            &key,
+        );
+        if !constant_time_eq(&proof.m1, &m1) {
+            return Err(SrpError::InvalidProof);
+        }
+        let m2 = server_evidence(hash, &proof.a_pub, &m1, &key);
+        self.state = ServerState::Done(pending.username, key);
+        Ok(m2)
+    }
+
+    /// account that authenticated, `None` until the exchange succeeded
+    pub fn username(&self) -> Option<&str> {
+        match &self.state {
+            ServerState::Done(username, _) => Some(username),
+            _ => None,
+        }
+    }
+
+    /// shared session key `K`, `None` until the exchange succeeded
+    pub fn session_key(&self) -> Option<&[u8]> {
+        match &self.state {
+            ServerState::Done(_, key) => Some(key),
+            _ => None,
+        }
+    }
+
+    /// internal method to end the exchange after a failed step
+    fn fail(&mut self, e: SrpError) -> SrpError {
+        self.state = ServerState::Failed;
+        e
+    }
+}
+
+// ==================== FUNCTIONS ====================
+
+/// multiplier parameter `k = H(N | PAD(g))`
+/// # Arguments
+/// * `hash` - hash function of the exchange
+/// * `group` - group of the exchange
+pub fn multiplier(hash: SrpHash, group: &SrpGroup) -> BigUint {
+    BigUint::from_bytes_be(&hash.digest(&[&group.n.to_bytes_be(), &group.pad(&group.g)]))
+}
+
+/// private key `x = H(s | H(I | ":" | P))`
+/// # Arguments
+/// * `hash` - hash function of the exchange
+/// * `salt` - salt, `s`
+/// * `username` - account name, `I`
+/// * `password` - password, `P`
+pub fn private_key(hash: SrpHash, salt: &[u8], username: &str, password: &str) -> BigUint {
+    let inner = hash.digest(&[username.as_bytes(), b":", password.as_bytes()]);
+    BigUint::from_bytes_be(&hash.digest(&[salt, &inner]))
+}
+
+/// random scrambling parameter `u = H(PAD(A) | PAD(B))`
+/// # Arguments
+/// * `hash` - hash function of the exchange
+/// * `group` - group of the exchange
+/// * `a_pub` - client's public value `A`
+/// * `b_pub` - server's public value `B`
+pub fn scrambler(hash: SrpHash, group: &SrpGroup, a_pub: &BigUint, b_pub: &BigUint) -> BigUint {
+    BigUint::from_bytes_be(&hash.digest(&[&group.pad(a_pub), &group.pad(b_pub)]))
+}
+
+/// premaster secret as the client computes it, `S = (B - k*g^x)^(a + u*x) mod N`
+/// # Arguments
+/// * `hash` - hash function of the exchange
+/// * `group` - group of the exchange
+/// * `b_pub` - server's public value `B`
+/// * `a` - client's private value
+/// * `x` - private key
+/// * `u` - scrambling parameter
+pub fn client_premaster(
+    hash: SrpHash,
+    group: &SrpGroup,
+    b_pub: &BigUint,
+    a: &BigUint,
+    x: &BigUint,
+    u: &BigUint,
+) -> BigUint {
+    let n = &group.n;
+    let kgx = multiplier(hash, group).mul(&group.g.mod_pow(x, n)).rem(n);
+    // add N first so the difference stays positive
+    let base = b_pub.rem(n).add(n).sub(&kgx).rem(n);
+    base.mod_pow(&a.add(&u.mul(x)), n)
+}
+
+/// premaster secret as the server computes it, `S = (A * v^u)^b mod N`
+/// # Arguments
+/// * `group` - group of the exchange
+/// * `a_pub` - client's public value `A`
+/// * `v` - verifier
+/// * `u` - scrambling parameter
+/// * `b` - server's private value
+pub fn server_premaster(
+    group: &SrpGroup,
+    a_pub: &BigUint,
+    v: &BigUint,
+    u: &BigUint,
+    b: &BigUint,
+) -> BigUint {
+    let n = &group.n;
+    a_pub.mul(&v.mod_pow(u, n)).rem(n).mod_pow(b, n)
+}
+
+/// session key `K = H(PAD(S))`
+/// # Arguments
+/// * `hash` - hash function of the exchange
+/// * `group` - group of the exchange
+/// * `premaster` - premaster secret `S`
+pub fn session_key(hash: SrpHash, group: &SrpGroup, premaster: &BigUint) -> Vec<u8> {
+    hash.digest(&[&group.pad(premaster)])
+}
+
+/// client's proof `M1 = H(H(N) XOR H(g) | H(I) | s | A | B | K)` (RFC 2945)
+/// # Arguments
+/// * `hash` - hash function of the exchange
+/// * `group` - group of the exchange
+/// * `username` - account name, `I`
+/// * `salt` - salt, `s`
+/// * `a_pub` - client's public value `A`
+/// * `b_pub` - server's public value `B`
+/// * `key` - session key `K`
+pub fn client_evidence(
+    hash: SrpHash,
+    group: &SrpGroup,
+    username: &str,
+    salt: &[u8],
+    a_pub: &BigUint,
+    b_pub: &BigUint,
+    key: &[u8],
+) -> Vec<u8> {
+    let hn = hash.digest(&[&group.n.to_bytes_be()]);
+    let hg = hash.digest(&[&group.g.to_bytes_be()]);
+    let hn_xor_hg: Vec<u8> = hn.iter().zip(&hg).map(|(n, g)| n ^ g).collect();
+    hash.digest(&[
+        &hn_xor_hg,
+        &hash.digest(&[username.as_bytes()]),
+        salt,
+        &a_pub.to_bytes_be(),
+        &b_pub.to_bytes_be(),
+        key,
+    ])
+}
+
+/// server's proof `M2 = H(A | M1 | K)`
+/// # Arguments
+/// * `hash` - hash function of the exchange
+/// * `a_pub` - client's public value `A`
+/// * `m1` - client's proof
+/// * `key` - session key `K`
+pub fn server_evidence(hash: SrpHash, a_pub: &BigUint, m1: &[u8], key: &[u8]) -> Vec<u8> {
+    hash.digest(&[&a_pub.to_bytes_be(), m1, key])
+}
+
+/// internal function to read random bytes from the OS
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    OsRng
+        .try_fill_bytes(&mut bytes)
+        .expect("OS random number generator failed");
+    bytes
+}
+
+/// internal function to pick a random private value `a` or `b`
+fn random_secret() -> BigUint {
+    BigUint::from_bytes_be(&random_bytes(SRP_SECRET_LEN))
+}
@@ -0,0 +1,280 @@
+// src/sudo.rs
+//! This module contains the sudoers policy deciding who may run which commands with `sudo`:
+//! * one rule per line, `<user | %group> [NOPASSWD:] <command | ALL>[, <command>...]`
+//! * `ALL` in place of a user applies the rule to everyone
+//! * `#` comments and blank lines are ignored
+//! * the last rule matching a user and command wins, like sudoers
+//! * a policy file writable by anyone but its owner is refused, like sudo does
+
+// ==================== IMPORTS ====================
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{metadata, read_to_string};
+use std::os::unix::fs::PermissionsExt;
+
+// ==================== CONSTANTS ====================
+
+/// command list entry matching every command
+pub const SUDO_ALL: &str = "ALL";
+
+/// tag that lets a rule's commands run without re-authenticating
+pub const NOPASSWD_TAG: &str = "NOPASSWD:";
+
+/// prefix marking a rule's principal as a group
+pub const GROUP_PREFIX: char = '%';
+
+/// password attempts before sudo gives up
+pub const SUDO_ATTEMPTS: u8 = 3;
+
+/// default seconds a successful sudo authentication is remembered for
+pub const DEF_SUDO_TIMEOUT: u64 = 5 * 60;
+
+/// permission bits that make a policy file unsafe to trust, group and world write
+const UNSAFE_MODE_BITS: u32 = 0o022;
+
+// ==================== STRUCTURES ====================
+
+/// who a rule applies to, written `user`, `%group` or `ALL`
+/// # Variants
+/// * `User` - the named account, or everyone for `ALL`
+/// * `Group` - every member of the named group
+/// # Methods
+/// * `parse` - reads the written form
+/// * `matches` - checks whether it covers a user
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Principal {
+    User(String),
+    Group(String),
+}
+
+/// Methods for the enum
+impl Principal {
+    /// read a principal as written in a policy
+    /// # Arguments
+    /// * `text` - `user`, `%group` or `ALL`
+    /// # Return
+    /// * the principal, or why it is not one
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text.strip_prefix(GROUP_PREFIX) {
+            _ if text.is_empty() => Err("empty user name".to_string()),
+            Some("") => Err("empty group name".to_string()),
+            Some(group) => Ok(Principal::Group(group.to_string())),
+            None => Ok(Principal::User(text.to_string())),
+        }
+    }
+
+    /// whether the principal covers a user
+    /// # Arguments
+    /// * `user` - account name
+    /// * `in_group` - whether the user is in a group
+    pub fn matches<G: Fn(&str) -> bool>(&self, user: &str, in_group: G) -> bool {
+        match self {
+            Principal::User(name) => name == user || name == SUDO_ALL,
+            Principal::Group(group) => in_group(group),
+        }
+    }
+}
+
+impl TryFrom<String> for Principal {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        Principal::parse(&text)
+    }
+}
+
+impl From<Principal> for String {
+    fn from(principal: Principal) -> Self {
+        principal.to_string()
+    }
+}
+
+impl fmt::Display for Principal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Principal::User(user) => write!(f, "{}", user),
+            Principal::Group(group) => write!(f, "{}{}", GROUP_PREFIX, group),
+        }
+    }
+}
+
+/// a single line of the policy
+/// # Fields
+/// * `principal` - who the rule applies to
+/// * `nopasswd` - whether the commands run without re-authenticating
+/// * `commands` - command names, or `SUDO_ALL`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SudoRule {
+    pub principal: Principal,
+    pub nopasswd: bool,
+    pub commands: Vec<String>,
+}
+
+/// Methods for the struct
+impl SudoRule {
+    /// parse a policy line
+    /// # Arguments
+    /// * `line` - line without its comment
+    /// # Return
+    /// * the rule, or why the line is not one
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let (principal, rest) = line
+            .trim()
+            .split_once(char::is_whitespace)
+            .ok_or("expected a user or %group followed by commands")?;
+        let principal = Principal::parse(principal)?;
+        let rest = rest.trim();
+        let (nopasswd, rest) = match rest.strip_prefix(NOPASSWD_TAG) {
+            Some(rest) => (true, rest),
+            None => (false, rest),
+        };
+        let commands: Vec<String> = rest.split(',').map(|c| c.trim().to_string()).collect();
+        if let Some(bad) = commands
+            .iter()
+            .find(|c| c.is_empty() || c.contains(char::is_whitespace))
+        {
+            return Err(format!("invalid command {:?}", bad));
+        }
+        Ok(SudoRule {
+            principal,
+            nopasswd,
+            commands,
+        })
+    }
+
+    /// whether the rule applies to a user
+    /// # Arguments
+    /// * `user` - account name
+    /// * `in_group` - whether the user is in a group
+    pub fn applies_to<G: Fn(&str) -> bool>(&self, user: &str, in_group: G) -> bool {
+        self.principal.matches(user, in_group)
+    }
+
+    /// whether the rule covers a command
+    /// # Arguments
+    /// * `command` - command name
+    pub fn covers(&self, command: &str) -> bool {
+        self.commands.iter().any(|c| c == command || c == SUDO_ALL)
+    }
+}
+
+/// Displays the rule the way it is written in the policy file
+impl fmt::Display for SudoRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tag = match self.nopasswd {
+            true => format!("{} ", NOPASSWD_TAG),
+            false => String::new(),
+        };
+        write!(f, "{} {}{}", self.principal, tag, self.commands.join(", "))
+    }
+}
+
+/// reasons a policy can't be used, in which case nobody may use sudo
+/// # Variants
+/// * `Io` - the policy file could not be read
+/// * `Insecure` - the policy file is writable by its group or everyone, with its mode
+/// * `Syntax` - a line is not a rule, with its line number and the reason
+#[derive(Debug)]
+pub enum SudoersError {
+    Io(std::io::Error),
+    Insecure(u32),
+    Syntax(usize, String),
+}
+
+impl fmt::Display for SudoersError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SudoersError::Io(e) => write!(f, "{}", e),
+            SudoersError::Insecure(mode) => {
+                write!(f, "policy file is writable by others (mode {:o})", mode)
+            }
+            SudoersError::Syntax(line, reason) => write!(f, "line #{}: {}", line, reason),
+        }
+    }
+}
+
+impl std::error::Error for SudoersError {}
+
+impl From<std::io::Error> for SudoersError {
+    fn from(e: std::io::Error) -> Self {
+        SudoersError::Io(e)
+    }
+}
+
+/// the rules of a sudoers policy, in file order
+/// # Fields
+/// * `rules` - rules in the order they appear
+/// # Methods
+/// * `parse` - parses policy text
+/// * `load` - reads and parses a policy file, which may be missing
+/// * `check` - finds the rule deciding whether a user may run a command
+/// * `rules_for` - lists the rules that apply to a user
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Sudoers {
+    pub rules: Vec<SudoRule>,
+}
+
+/// Methods for the struct
+impl Sudoers {
+    /// parse policy text, failing on the first line that is not a rule
+    /// # Arguments
+    /// * `text` - contents of a policy file
+    pub fn parse(text: &str) -> Result<Self, SudoersError> {
+        let mut rules: Vec<SudoRule> = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            if line.trim().is_empty() {
+                continue;
+            }
+            rules.push(SudoRule::parse(line).map_err(|e| SudoersError::Syntax(index + 1, e))?);
+        }
+        Ok(Sudoers { rules })
+    }
+
+    /// read and parse a policy file, a missing file is a policy that allows nothing
+    /// # Arguments
+    /// * `filepath` - policy file
+    pub fn load(filepath: &str) -> Result<Self, SudoersError> {
+        let mode = match metadata(filepath) {
+            Ok(meta) => meta.permissions().mode(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Sudoers::default()),
+            Err(e) => return Err(e.into()),
+        };
+        if mode & UNSAFE_MODE_BITS != 0 {
+            return Err(SudoersError::Insecure(mode & 0o777));
+        }
+        Self::parse(&read_to_string(filepath)?)
+    }
+
+    /// find the rule deciding whether a user may run a command, the last one that matches
+    /// # Arguments
+    /// * `user` - account name
+    /// * `in_group` - whether the user is in a group
+    /// * `command` - command name
+    /// # Return
+    /// * the deciding rule, `None` if the user may not run the command
+    pub fn check<G: Fn(&str) -> bool>(
+        &self,
+        user: &str,
+        in_group: G,
+        command: &str,
+    ) -> Option<&SudoRule> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|r| r.applies_to(user, &in_group) && r.covers(command))
+    }
+
+    /// list the rules that apply to a user
+    /// # Arguments
+    /// * `user` - account name
+    /// * `in_group` - whether the user is in a group
+    pub fn rules_for<G: Fn(&str) -> bool>(&self, user: &str, in_group: G) -> Vec<&SudoRule> {
+        self.rules
+            .iter()
+            .filter(|r| r.applies_to(user, &in_group))
+            .collect()
+    }
+}
@@ -0,0 +1,328 @@
+// src/token.rs
+//! This module contains signed bearer tokens, so other tools can trust a login without the
+//! passwd file:
+//! * compact JWS/JWT (RFC 7515/7519) signed with HMAC-SHA256 (`HS256`)
+//! * `sub`, `iat`, `exp` and `jti` claims plus the capabilities held at issue
+//! * a random signing key kept next to the database
+//! * a revocation list keyed by `jti`, plus a "not before" time per account, so changing or
+//!   deleting an account revokes every token issued to it
+
+// ==================== IMPORTS ====================
+
+use crate::auth_utils::*;
+use crate::authz::Capability;
+use crate::session::now;
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use rand_core::{OsRng, TryRngCore};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::read_to_string;
+use std::path::Path;
+
+// ==================== CONSTANTS ====================
+
+/// the only signing algorithm accepted, anything else (including `none`) is rejected
+pub const TOKEN_ALG: &str = "HS256";
+
+/// default seconds a token is valid for
+pub const DEF_TOKEN_LIFETIME: u64 = 60 * 60;
+
+/// seconds a token's `iat` may be ahead of this clock
+pub const CLOCK_SKEW: u64 = 60;
+
+/// length of the signing key, in bytes
+const TOKEN_KEY_LEN: usize = 32;
+
+/// length of a token id, in random bytes
+const JTI_LEN: usize = 16;
+
+/// prefix of revocation list entries that revoke every token of an account issued until then
+const SUBJECT_PREFIX: &str = "sub:";
+
+/// file mode of the signing key and revocation list, readable and writable by the owner only
+const TOKEN_MODE: u32 = 0o600;
+
+// ==================== STRUCTURES ====================
+
+/// JOSE header of a token
+/// # Fields
+/// * `alg` - signing algorithm, always `HS256` when issued here
+/// * `typ` - media type, `JWT`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Header {
+    alg: String,
+    #[serde(default)]
+    typ: String,
+}
+
+/// claims carried by a token
+/// # Fields
+/// * `sub` - account the token was issued to
+/// * `iat` - seconds since the unix epoch at issue
+/// * `exp` - seconds since the unix epoch the token expires at
+/// * `jti` - hex encoded random token id, used to revoke it
+/// * `caps` - capabilities the account held at issue
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub iat: u64,
+    pub exp: u64,
+    pub jti: String,
+    pub caps: Vec<Capability>,
+}
+
+/// reasons a token is rejected
+/// # Variants
+/// * `Io` - the signing key or revocation list could not be read or written
+/// * `Malformed` - not three base64url parts holding JSON, with a reason
+/// * `Algorithm` - signed with an algorithm other than `HS256`, which is named
+/// * `Signature` - the signature does not match
+/// * `Expired` - `exp` has passed, at the given time
+/// * `NotYetValid` - `iat` is in the future, at the given time
+/// * `Revoked` - the `jti` is on the revocation list
+#[derive(Debug)]
+pub enum TokenError {
+    Io(std::io::Error),
+    Malformed(String),
+    Algorithm(String),
+    Signature,
+    Expired(u64),
+    NotYetValid(u64),
+    Revoked,
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenError::Io(e) => write!(f, "{}", e),
+            TokenError::Malformed(reason) => write!(f, "malformed token: {}", reason),
+            TokenError::Algorithm(alg) => {
+                write!(
+                    f,
+                    "algorithm '{}' is not accepted, expected {}",
+                    alg, TOKEN_ALG
+                )
+            }
+            TokenError::Signature => write!(f, "signature does not match"),
+            TokenError::Expired(exp) => write!(f, "token expired at {}", exp),
+            TokenError::NotYetValid(iat) => write!(f, "token is issued in the future, at {}", iat),
+            TokenError::Revoked => write!(f, "token was revoked"),
+        }
+    }
+}
+
+impl std::error::Error for TokenError {}
+
+impl From<std::io::Error> for TokenError {
+    fn from(e: std::io::Error) -> Self {
+        TokenError::Io(e)
+    }
+}
+
+/// issues and verifies tokens with a signing key, and keeps the revocation list
+/// # Fields
+/// * `key_location` - filepath of the signing key, generated on first use
+/// * `revoked_location` - filepath of the revocation list, `<jti> <exp>` and
+///   `sub:<account> <not before>` lines
+/// # Methods
+/// * `new` - refers to a key and revocation list, creating nothing yet
+/// * `issue` - signs a token for an account
+/// * `verify` - checks a token's signature, times and revocation
+/// * `revoke` - adds a token to the revocation list
+/// * `revoke_subject` - revokes every token issued to an account so far
+pub struct TokenAuthority {
+    key_location: String,
+    revoked_location: String,
+}
+
+/// Methods for the struct
+impl TokenAuthority {
+    /// refer to a signing key and revocation list
+    /// # Arguments
+    /// * `key_path` - filepath of the signing key, which anyone verifying tokens needs too
+    /// * `revoked_path` - filepath of the revocation list
+    pub fn new(key_path: &str, revoked_path: &str) -> Self {
+        TokenAuthority {
+            key_location: key_path.to_string(),
+            revoked_location: revoked_path.to_string(),
+        }
+    }
+
+    /// internal method to read the signing key, generating one if there is none yet
+    fn key(&self) -> std::io::Result<Vec<u8>> {
+        read_or_create_key(Path::new(&self.key_location), TOKEN_KEY_LEN, TOKEN_MODE)
+    }
+
+    /// sign a token for an account, valid from now
+    /// # Arguments
+    /// * `sub` - account name
+    /// * `caps` - capabilities the account holds
+    /// * `lifetime` - seconds the token is valid for
+    /// # Return
+    /// * the token and its claims
+    pub fn issue(
+        &self,
+        sub: &str,
+        caps: &[Capability],
+        lifetime: u64,
+    ) -> Result<(String, Claims), TokenError> {
+        let mut jti = [0u8; JTI_LEN];
+        OsRng
+            .try_fill_bytes(&mut jti)
+            .map_err(std::io::Error::other)?;
+        let iat = now();
+        let claims = Claims {
+            sub: sub.to_string(),
+            iat,
+            exp: iat.saturating_add(lifetime),
+            jti: hex::encode(jti),
+            caps: caps.to_vec(),
+        };
+        Ok((sign(&self.key()?, &claims), claims))
+    }
+
+    /// check a token's signature, that it is within its validity period, and not revoked
+    /// a token issued in the same second its account was changed counts as issued before
+    /// # Arguments
+    /// * `token` - compact serialized token
+    /// # Return
+    /// * the token's claims, or why it was rejected
+    pub fn verify(&self, token: &str) -> Result<Claims, TokenError> {
+        let claims = verify(&self.key()?, token, now())?;
+        let subject = format!("{}{}", SUBJECT_PREFIX, claims.sub);
+        if self
+            .read_revoked()?
+            .iter()
+            .any(|(key, time)| *key == claims.jti || (*key == subject && claims.iat <= *time))
+        {
+            return Err(TokenError::Revoked);
+        }
+        Ok(claims)
+    }
+
+    /// add a token to the revocation list, dropping entries of tokens that have expired anyway
+    /// # Arguments
+    /// * `claims` - claims of the token
+    pub fn revoke(&self, claims: &Claims) -> Result<(), TokenError> {
+        let now = now();
+        self.update_revoked(|revoked| {
+            revoked.retain(|(key, exp)| {
+                (key.starts_with(SUBJECT_PREFIX) || *exp >= now) && *key != claims.jti
+            });
+            revoked.push((claims.jti.clone(), claims.exp));
+        })
+    }
+
+    /// revoke every token issued to an account until now, e.g. when it is deleted, renamed
+    /// or its password changes, so a new account under the same name doesn't inherit them
+    /// # Arguments
+    /// * `sub` - account name
+    pub fn revoke_subject(&self, sub: &str) -> Result<(), TokenError> {
+        let subject = format!("{}{}", SUBJECT_PREFIX, sub);
+        let now = now();
+        self.update_revoked(|revoked| {
+            revoked.retain(|(key, _)| *key != subject);
+            revoked.push((subject.clone(), now));
+        })
+    }
+
+    /// internal method to change the revocation list under its lock
+    /// # Arguments
+    /// * `change` - edits the entries
+    fn update_revoked<F>(&self, change: F) -> Result<(), TokenError>
+    where
+        F: FnOnce(&mut Vec<(String, u64)>),
+    {
+        let lock_path = format!("{}.lock", self.revoked_location);
+        let _lock = lock_file(Path::new(&lock_path), true)?;
+        let mut revoked = self.read_revoked()?;
+        change(&mut revoked);
+        let data: String = revoked
+            .iter()
+            .map(|(key, time)| format!("{} {}\n", key, time))
+            .collect();
+        atomic_write(&self.revoked_location, data.as_bytes(), TOKEN_MODE)?;
+        Ok(())
+    }
+
+    /// internal method to read the revocation list
+    /// # Return
+    /// * `jti` and `exp` of every revoked token and `sub:<account>` and the time until which
+    ///   its tokens are revoked, empty if there is no list yet
+    fn read_revoked(&self) -> Result<Vec<(String, u64)>, TokenError> {
+        let data = match read_to_string(&self.revoked_location) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(data
+            .lines()
+            .filter_map(|line| line.rsplit_once(' '))
+            .map(|(key, time)| (key.to_string(), time.parse().unwrap_or(u64::MAX)))
+            .collect())
+    }
+}
+
+// ==================== FUNCTIONS ====================
+
+/// This function signs claims as a compact `HS256` token
+/// # Arguments
+/// * `key` - signing key
+/// * `claims` - claims to sign
+/// # Return
+/// * `<header>.<claims>.<signature>`, each part base64url encoded without padding
+pub fn sign(key: &[u8], claims: &Claims) -> String {
+    let header = Header {
+        alg: TOKEN_ALG.to_string(),
+        typ: "JWT".to_string(),
+    };
+    let signing_input = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header).expect("headers always serialize")),
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims).expect("claims always serialize"))
+    );
+    let signature = hmac_sha256(key, signing_input.as_bytes());
+    format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(signature))
+}
+
+/// This function checks a compact `HS256` token's signature and validity period
+/// the revocation list is not consulted, see `TokenAuthority::verify`
+/// # Arguments
+/// * `key` - signing key
+/// * `token` - compact serialized token
+/// * `now` - seconds since the unix epoch
+/// # Return
+/// * the token's claims, or why it was rejected
+pub fn verify(key: &[u8], token: &str, now: u64) -> Result<Claims, TokenError> {
+    let parts: Vec<&str> = token.trim().split('.').collect();
+    if parts.len() != 3 {
+        return Err(TokenError::Malformed("expected three parts".to_string()));
+    }
+    let decode = |part: &str, name: &str| {
+        URL_SAFE_NO_PAD
+            .decode(part)
+            .map_err(|_| TokenError::Malformed(format!("{} is not base64url", name)))
+    };
+
+    // check the algorithm before trusting anything else in the token
+    let header: Header = serde_json::from_slice(&decode(parts[0], "header")?)
+        .map_err(|e| TokenError::Malformed(format!("header: {}", e)))?;
+    if header.alg != TOKEN_ALG {
+        return Err(TokenError::Algorithm(header.alg));
+    }
+    let signature = decode(parts[2], "signature")?;
+    let signing_input = format!("{}.{}", parts[0], parts[1]);
+    if !constant_time_eq(&hmac_sha256(key, signing_input.as_bytes()), &signature) {
+        return Err(TokenError::Signature);
+    }
+
+    let claims: Claims = serde_json::from_slice(&decode(parts[1], "claims")?)
+        .map_err(|e| TokenError::Malformed(format!("claims: {}", e)))?;
+    if now >= claims.exp {
+        return Err(TokenError::Expired(claims.exp));
+    }
+    if claims.iat > now.saturating_add(CLOCK_SKEW) {
+        return Err(TokenError::NotYetValid(claims.iat));
+    }
+    Ok(claims)
+}
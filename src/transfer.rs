@@ -0,0 +1,336 @@
+// src/transfer.rs
+//! This module contains export and import of the whole credential database:
+//! * JSON and TOML formats, picked by file extension
+//! * merge modes for accounts that already exist
+//! * validation of every imported record, including its uid and primary group
+
+// ==================== IMPORTS ====================
+
+use crate::auth_utils::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::read_to_string;
+
+// ==================== CONSTANTS ====================
+
+/// version of the export format, bumped whenever a field changes meaning
+pub const EXPORT_VERSION: u32 = 1;
+
+// ==================== STRUCTURES ====================
+
+/// file formats the database can be exported to
+/// # Variants
+/// * `Json` - `.json` files
+/// * `Toml` - `.toml` files
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Json,
+    Toml,
+}
+
+/// Methods for the enum
+impl ExportFormat {
+    /// pick a format from a file's extension
+    /// # Arguments
+    /// * `path` - filepath ending in `.json` or `.toml`
+    /// # Return
+    /// * the format, or `None` for any other extension
+    pub fn from_path(path: &str) -> Option<Self> {
+        match path.rsplit_once('.') {
+            Some((_, ext)) if ext.eq_ignore_ascii_case("json") => Some(ExportFormat::Json),
+            Some((_, ext)) if ext.eq_ignore_ascii_case("toml") => Some(ExportFormat::Toml),
+            _ => None,
+        }
+    }
+}
+
+/// contents of an export file
+/// # Fields
+/// * `version` - export format version, see `EXPORT_VERSION`
+/// * `users` - every account record, including its hash and metadata
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DatabaseExport {
+    pub version: u32,
+    #[serde(default)]
+    pub users: Vec<UserRecord>,
+}
+
+/// what to do with imported accounts that already exist
+/// # Variants
+/// * `Overwrite` - replace the existing record
+/// * `SkipExisting` - keep the existing record
+/// * `FailOnConflict` - abort the whole import
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeMode {
+    Overwrite,
+    SkipExisting,
+    FailOnConflict,
+}
+
+/// counts of what an import did
+/// # Fields
+/// * `added` - accounts that did not exist before
+/// * `replaced` - existing accounts that were overwritten
+/// * `skipped` - existing accounts that were left alone
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub added: usize,
+    pub replaced: usize,
+    pub skipped: usize,
+}
+
+/// errors returned by export and import
+/// # Variants
+/// * `UnknownFormat` - the file extension is not `.json` or `.toml`
+/// * `Io` - the file could not be read or written
+/// * `Parse` - the file contents could not be (de)serialized
+/// * `Invalid` - an imported record failed validation
+/// * `Conflict` - an imported account already exists and the merge mode forbids it
+/// * `Store` - the credential store refused the change
+#[derive(Debug)]
+pub enum TransferError {
+    UnknownFormat(String),
+    Io(std::io::Error),
+    Parse(String),
+    Invalid(String, String),
+    Conflict(String),
+    Store(StoreError),
+}
+
+impl fmt::Display for TransferError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransferError::UnknownFormat(path) => {
+                write!(f, "'{}' does not end in .json or .toml", path)
+            }
+            TransferError::Io(e) => write!(f, "{}", e),
+            TransferError::Parse(e) => write!(f, "{}", e),
+            TransferError::Invalid(username, reason) => {
+                write!(f, "invalid record for '{}': {}", username, reason)
+            }
+            TransferError::Conflict(username) => write!(f, "account {} already exists", username),
+            TransferError::Store(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for TransferError {}
+
+impl From<std::io::Error> for TransferError {
+    fn from(e: std::io::Error) -> Self {
+        TransferError::Io(e)
+    }
+}
+
+impl From<StoreError> for TransferError {
+    fn from(e: StoreError) -> Self {
+        TransferError::Store(e)
+    }
+}
+
+// ==================== FUNCTIONS ====================
+
+/// internal function to check a passwd field that may hold anything but separators
+fn is_valid_field(value: &str) -> bool {
+    !value.chars().any(|c| c == ':' || c.is_control())
+}
+
+/// This function checks that a record can be stored without corrupting the database
+/// # Arguments
+/// * `record` - imported account record
+/// # Return
+/// * description of the first problem found
+pub fn validate_record(record: &UserRecord) -> Result<(), String> {
+    if record.name().is_empty() || !is_valid_field(record.name()) {
+        return Err("name is empty or contains ':' or control characters".to_string());
+    }
+    validate_hash(record.hash()).map_err(|e| e.to_string())?;
+    let gecos = record.gecos();
+    for field in [
+        &gecos.full_name,
+        &gecos.room,
+        &gecos.work_phone,
+        &gecos.home_phone,
+        &gecos.other,
+    ] {
+        if !Gecos::is_valid_field(field) {
+            return Err("gecos contains ':', ',' or control characters".to_string());
+        }
+    }
+    if !is_valid_field(record.home()) || !is_valid_field(record.shell()) {
+        return Err("home or shell contains ':' or control characters".to_string());
+    }
+    match record.name() == ROOT {
+        true if record.uid() != ROOT_UID || record.gid() != ROOT_UID => {
+            return Err(format!("{} must have uid and gid {}", ROOT, ROOT_UID));
+        }
+        false if record.uid() == ROOT_UID || record.gid() == ROOT_UID => {
+            return Err(format!("only {} may have uid or gid {}", ROOT, ROOT_UID));
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// internal function to check the uid and primary group of the accounts an import stages
+/// against each other and the accounts that stay, since a shared uid or a primary gid that
+/// belongs to a group like `adm` would hand out another account's files or privileges
+/// # Arguments
+/// * `database` - credential store being imported into
+/// * `groups` - group file of the store
+/// * `staged` - records about to be stored
+/// # Return
+/// * the first record that fails, and why
+fn validate_ids(
+    database: &dyn CredentialStore,
+    groups: &GroupDatabase,
+    staged: &[UserRecord],
+) -> Result<(), TransferError> {
+    // uids of the accounts the import leaves alone
+    let mut uids: HashMap<u32, String> = database
+        .list_users()
+        .iter()
+        .filter(|u| !staged.iter().any(|r| r.name() == u.as_str()))
+        .filter_map(|u| database.get(u))
+        .map(|r| (r.uid(), r.name().to_string()))
+        .collect();
+    for record in staged {
+        let invalid = |reason: String| TransferError::Invalid(record.name().to_string(), reason);
+        if let Some(owner) = uids.insert(record.uid(), record.name().to_string()) {
+            return Err(invalid(format!(
+                "uid {} is taken by {}",
+                record.uid(),
+                owner
+            )));
+        }
+
+        // the account's own group, or the primary group it already has
+        let kept = database
+            .get(record.name())
+            .is_some_and(|r| r.gid() == record.gid());
+        let own = record.gid() == record.uid() && groups.by_gid(record.gid()).is_none();
+        if !kept && !own {
+            return Err(invalid(format!(
+                "gid {} is neither the account's own group nor its current one",
+                record.gid()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// This function writes every account in the database to a JSON or TOML file
+/// the file holds password hashes, so it is created readable by the owner only
+/// records are validated like on import, so an export can always be imported again: the
+/// passwd file keeps commas in the last GECOS subfield, which import refuses
+/// # Arguments
+/// * `database` - credential store to export
+/// * `path` - destination file, its extension picks the format
+/// # Return
+/// * number of accounts exported, nothing is written if any record is invalid
+pub fn export_database(database: &dyn CredentialStore, path: &str) -> Result<usize, TransferError> {
+    let format =
+        ExportFormat::from_path(path).ok_or(TransferError::UnknownFormat(path.to_string()))?;
+
+    // collect records, ordered by uid like /etc/passwd
+    let mut users: Vec<UserRecord> = database
+        .list_users()
+        .iter()
+        .filter_map(|u| database.get(u))
+        .collect();
+    users.sort_by_key(|r| (r.uid(), r.name().to_string()));
+    for record in &users {
+        validate_record(record)
+            .map_err(|reason| TransferError::Invalid(record.name().to_string(), reason))?;
+    }
+    let export = DatabaseExport {
+        version: EXPORT_VERSION,
+        users,
+    };
+
+    let contents = match format {
+        ExportFormat::Json => serde_json::to_string_pretty(&export)
+            .map_err(|e| TransferError::Parse(e.to_string()))?,
+        ExportFormat::Toml => {
+            toml::to_string_pretty(&export).map_err(|e| TransferError::Parse(e.to_string()))?
+        }
+    };
+    atomic_write(path, contents.as_bytes(), SHADOW_MODE)?;
+    Ok(export.users.len())
+}
+
+/// This function reads accounts from a JSON or TOML export and adds them to the database
+/// every record is validated first, and all changes are committed in one transaction
+/// # Arguments
+/// * `database` - credential store to import into
+/// * `groups` - group file of the store, primary gids must not point into it
+/// * `path` - export file, its extension picks the format
+/// * `mode` - what to do with accounts that already exist
+/// # Return
+/// * counts of added, replaced and skipped accounts, nothing is changed on error
+pub fn import_database(
+    database: &mut dyn CredentialStore,
+    groups: &GroupDatabase,
+    path: &str,
+    mode: MergeMode,
+) -> Result<ImportSummary, TransferError> {
+    let format =
+        ExportFormat::from_path(path).ok_or(TransferError::UnknownFormat(path.to_string()))?;
+    let contents = read_to_string(path)?;
+    let export: DatabaseExport = match format {
+        ExportFormat::Json => {
+            serde_json::from_str(&contents).map_err(|e| TransferError::Parse(e.to_string()))?
+        }
+        ExportFormat::Toml => {
+            toml::from_str(&contents).map_err(|e| TransferError::Parse(e.to_string()))?
+        }
+    };
+    if export.version != EXPORT_VERSION {
+        return Err(TransferError::Parse(format!(
+            "unsupported export version {}, expected {}",
+            export.version, EXPORT_VERSION
+        )));
+    }
+
+    // validate everything before staging anything
+    let mut seen: HashSet<String> = HashSet::new();
+    for record in &export.users {
+        validate_record(record)
+            .map_err(|reason| TransferError::Invalid(record.name().to_string(), reason))?;
+        if !seen.insert(record.name().to_string()) {
+            return Err(TransferError::Invalid(
+                record.name().to_string(),
+                "listed more than once".to_string(),
+            ));
+        }
+    }
+
+    // stage changes according to the merge mode
+    let mut summary = ImportSummary::default();
+    let mut staged: Vec<UserRecord> = Vec::new();
+    for record in export.users {
+        if database.contains(record.name()) {
+            match mode {
+                MergeMode::Overwrite => summary.replaced += 1,
+                MergeMode::SkipExisting => {
+                    summary.skipped += 1;
+                    continue;
+                }
+                MergeMode::FailOnConflict => {
+                    return Err(TransferError::Conflict(record.name().to_string()));
+                }
+            }
+        } else {
+            summary.added += 1;
+        }
+        staged.push(record);
+    }
+    validate_ids(database, groups, &staged)?;
+    let mut transaction = database.transaction();
+    for record in staged {
+        transaction = transaction.set_record(record);
+    }
+    transaction.commit()?;
+    Ok(summary)
+}
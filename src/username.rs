@@ -0,0 +1,173 @@
+// src/username.rs
+//! This module contains the username policy enforced by every credential store:
+//! * Unicode NFKC normalization
+//! * characters that would corrupt a passwd record
+//! * a configurable character-set regex and length limits
+//! * reserved names
+//! * names confusable with, or differing only in case from, existing accounts
+
+// ==================== IMPORTS ====================
+
+use regex::Regex;
+use std::fmt;
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::skeleton;
+
+// ==================== CONSTANTS ====================
+
+/// POSIX portable filename character set, not starting with a hyphen
+pub const DEF_USERNAME_PATTERN: &str = "^[A-Za-z0-9._][A-Za-z0-9._-]*$";
+
+/// shortest username accepted, in characters
+pub const DEF_USERNAME_MIN: usize = 1;
+
+/// longest username accepted, in characters, like `LOGIN_NAME_MAX` minus the terminator
+pub const DEF_USERNAME_MAX: usize = 32;
+
+/// names of system accounts that can't be registered
+pub const DEF_RESERVED_NAMES: [&str; 9] = [
+    "adm", "bin", "daemon", "nobody", "nogroup", "operator", "sudo", "sys", "wheel",
+];
+
+// ==================== STRUCTURES ====================
+
+/// reasons a username is rejected
+/// # Variants
+/// * `Unsafe` - contains `:`, `,`, whitespace or control characters, which break the passwd format
+/// * `Length` - shorter or longer than the policy allows
+/// * `Pattern` - does not match the policy's regex
+/// * `Reserved` - is a reserved name
+/// * `CaseConflict` - differs only in case from an existing account, which is named
+/// * `Confusable` - looks like an existing account, which is named
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UsernameError {
+    Unsafe,
+    Length(usize, usize),
+    Pattern(String),
+    Reserved,
+    CaseConflict(String),
+    Confusable(String),
+}
+
+impl fmt::Display for UsernameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UsernameError::Unsafe => write!(
+                f,
+                "must not contain ':', ',', whitespace or control characters"
+            ),
+            UsernameError::Length(min, max) => {
+                write!(f, "must be {} to {} characters long", min, max)
+            }
+            UsernameError::Pattern(pattern) => write!(f, "must match {}", pattern),
+            UsernameError::Reserved => write!(f, "is reserved"),
+            UsernameError::CaseConflict(existing) => {
+                write!(f, "differs only in case from account {}", existing)
+            }
+            UsernameError::Confusable(existing) => {
+                write!(f, "is easily confused with account {}", existing)
+            }
+        }
+    }
+}
+
+impl std::error::Error for UsernameError {}
+
+/// rules new account names must follow
+/// # Fields
+/// * `pattern` - regex the NFKC normalized name must match
+/// * `min_length` - shortest name, in characters
+/// * `max_length` - longest name, in characters
+/// * `reserved` - names that can't be registered, compared case-insensitively
+/// * `case_insensitive` - whether names differing only in case count as the same name
+/// * `reject_confusables` - whether names that look like an existing name are rejected
+/// # Methods
+/// * `check` - normalizes and validates a new name against the existing ones
+#[derive(Clone, Debug)]
+pub struct UsernamePolicy {
+    pub pattern: Regex,
+    pub min_length: usize,
+    pub max_length: usize,
+    pub reserved: Vec<String>,
+    pub case_insensitive: bool,
+    pub reject_confusables: bool,
+}
+
+/// the portable character set, 1 to 32 characters, system names reserved, confusables rejected
+impl Default for UsernamePolicy {
+    fn default() -> Self {
+        UsernamePolicy {
+            pattern: Regex::new(DEF_USERNAME_PATTERN).expect("default pattern compiles"),
+            min_length: DEF_USERNAME_MIN,
+            max_length: DEF_USERNAME_MAX,
+            reserved: DEF_RESERVED_NAMES.iter().map(|s| s.to_string()).collect(),
+            case_insensitive: false,
+            reject_confusables: true,
+        }
+    }
+}
+
+/// Methods for the struct
+impl UsernamePolicy {
+    /// normalize a new account name and check it against the policy
+    /// # Arguments
+    /// * `name` - requested account name
+    /// * `existing` - names of the other accounts
+    /// # Return
+    /// * the NFKC normalized name to store, or why it was rejected
+    pub fn check<'a, I>(&self, name: &str, existing: I) -> Result<String, UsernameError>
+    where
+        I: IntoIterator<Item = &'a String>,
+    {
+        let name = normalize(name);
+
+        // separators and control characters are never allowed, whatever the pattern says
+        if name
+            .chars()
+            .any(|c| c == ':' || c == ',' || c.is_whitespace() || c.is_control())
+        {
+            return Err(UsernameError::Unsafe);
+        }
+        let length = name.chars().count();
+        if length < self.min_length || length > self.max_length {
+            return Err(UsernameError::Length(self.min_length, self.max_length));
+        }
+        if !self.pattern.is_match(&name) {
+            return Err(UsernameError::Pattern(self.pattern.to_string()));
+        }
+        let lowercase = name.to_lowercase();
+        if self.reserved.iter().any(|r| r.to_lowercase() == lowercase) {
+            return Err(UsernameError::Reserved);
+        }
+
+        // compare against every other account
+        let name_skeleton: String = skeleton(&lowercase).collect();
+        for other in existing {
+            if *other == name {
+                continue;
+            }
+            let other_lowercase = other.to_lowercase();
+            if self.case_insensitive && other_lowercase == lowercase {
+                return Err(UsernameError::CaseConflict(other.clone()));
+            }
+            if self.reject_confusables
+                && other_lowercase != lowercase
+                && skeleton(&other_lowercase).eq(name_skeleton.chars())
+            {
+                return Err(UsernameError::Confusable(other.clone()));
+            }
+        }
+        Ok(name)
+    }
+}
+
+// ==================== FUNCTIONS ====================
+
+/// This function normalizes a username the way the policy stores it
+/// # Arguments
+/// * `name` - username as typed
+/// # Return
+/// * the NFKC normal form of the name
+pub fn normalize(name: &str) -> String {
+    name.nfkc().collect()
+}
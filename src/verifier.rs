@@ -0,0 +1,286 @@
+// src/verifier.rs
+//! This module contains the verifiers challenge-response mechanisms check passwords with:
+//! * SCRAM-SHA-256 credentials of every account, made whenever its password is set or used
+//! * an SRP-6a verifier alongside them, bound to the account name, so a rename leaves it
+//!   stale until the next login
+//! * a digest of the password hash they were made alongside, so a password changed any other
+//!   way leaves them stale instead of wrong
+//! * a table shared by every shell using the same data directory
+
+// ==================== IMPORTS ====================
+
+use crate::auth_utils::*;
+use crate::scram::*;
+use crate::srp::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::read;
+use std::path::Path;
+
+// ==================== CONSTANTS ====================
+
+/// file mode of the verifier table, readable and writable by the owner only
+const VERIFIERS_MODE: u32 = 0o600;
+
+// ==================== STRUCTURES ====================
+
+/// verifiers of one account's password
+/// # Fields
+/// * `hash` - hex sha-256 of the password hash the verifiers were made alongside
+/// * `scram` - SCRAM-SHA-256 credentials, as `ScramCredentials` writes them
+/// * `srp` - SRP-6a verifier, as `SrpVerifier` writes it, empty if it was never made or
+///   the account was renamed since
+/// # Methods
+/// * `derive` - makes the verifiers of a password
+/// * `is_current` - checks they were made from the account's current password
+/// * `is_complete` - checks none is missing
+/// * `scram` - reads the SCRAM-SHA-256 credentials
+/// * `srp` - reads the SRP-6a verifier
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Verifiers {
+    pub hash: String,
+    pub scram: String,
+    #[serde(default)]
+    pub srp: String,
+}
+
+/// errors returned when reading or changing the verifier table
+/// # Variants
+/// * `Io` - the table could not be locked, read or written
+/// * `Corrupt` - the table is not a map of verifiers
+/// * `Scram` - the SCRAM credentials could not be made or read
+/// * `Srp` - the SRP verifier could not be made or read
+#[derive(Debug)]
+pub enum VerifierError {
+    Io(std::io::Error),
+    Corrupt(String),
+    Scram(ScramError),
+    Srp(SrpError),
+}
+
+/// verifiers of every shell using the same data directory, kept in a JSON file by account
+/// # Fields
+/// * `entries` - verifiers by account name, as last read or written
+/// * `location` - filepath of the table, locked with `<table>.lock` while changing it
+/// * `fingerprint` - digest of the file as last read or written, to notice outside changes
+/// # Methods
+/// * `new` - loads the table, which may not exist yet
+/// * `refresh` - reloads the table if another process changed it
+/// * `get` - gets an account's verifiers if they match its password hash
+/// * `set` - stores an account's verifiers
+/// * `rename`, `remove` - follow account renames and deletions, a rename drops the SRP
+///   verifier since it is bound to the old name
+pub struct VerifierTable {
+    entries: BTreeMap<String, Verifiers>,
+    location: String,
+    fingerprint: Option<Vec<u8>>,
+}
+
+/// Methods for the struct
+impl Verifiers {
+    /// make the verifiers of a password
+    /// # Arguments
+    /// * `user` - account name
+    /// * `password` - the password, just set or checked
+    /// * `hash` - the account's password hash
+    /// * `scram_iterations` - PBKDF2 iterations of the SCRAM credentials
+    /// * `srp_group` - size of the RFC 5054 group of the SRP verifier
+    pub fn derive(
+        user: &str,
+        password: &str,
+        hash: &str,
+        scram_iterations: u32,
+        srp_group: u32,
+    ) -> Result<Self, VerifierError> {
+        Ok(Verifiers {
+            hash: hash_digest(hash),
+            scram: ScramCredentials::new(password, scram_iterations)?.to_string(),
+            srp: SrpVerifier::new(srp_group, user, password)?.to_string(),
+        })
+    }
+
+    /// whether the verifiers were made from the password an account's hash is of
+    /// # Arguments
+    /// * `hash` - the account's password hash
+    pub fn is_current(&self, hash: &str) -> bool {
+        constant_time_eq(self.hash.as_bytes(), hash_digest(hash).as_bytes())
+    }
+
+    /// whether every kind of verifier is there, older entries and renamed accounts lack SRP
+    pub fn is_complete(&self) -> bool {
+        !self.srp.is_empty()
+    }
+
+    /// SCRAM-SHA-256 credentials
+    pub fn scram(&self) -> Result<ScramCredentials, VerifierError> {
+        Ok(ScramCredentials::parse(&self.scram)?)
+    }
+
+    /// SRP-6a verifier, `None` if there is none
+    pub fn srp(&self) -> Result<Option<SrpVerifier>, VerifierError> {
+        match self.srp.is_empty() {
+            true => Ok(None),
+            false => Ok(Some(SrpVerifier::parse(&self.srp)?)),
+        }
+    }
+}
+
+impl fmt::Display for VerifierError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VerifierError::Io(e) => write!(f, "{}", e),
+            VerifierError::Corrupt(reason) => write!(f, "verifier table is corrupt: {}", reason),
+            VerifierError::Scram(e) => write!(f, "{}", e),
+            VerifierError::Srp(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for VerifierError {}
+
+impl From<std::io::Error> for VerifierError {
+    fn from(e: std::io::Error) -> Self {
+        VerifierError::Io(e)
+    }
+}
+
+impl From<ScramError> for VerifierError {
+    fn from(e: ScramError) -> Self {
+        VerifierError::Scram(e)
+    }
+}
+
+impl From<SrpError> for VerifierError {
+    fn from(e: SrpError) -> Self {
+        VerifierError::Srp(e)
+    }
+}
+
+/// Methods for the struct
+impl VerifierTable {
+    /// load a verifier table, a missing table holds no verifiers until one is set
+    /// # Arguments
+    /// * `filepath` - filepath of the table
+    pub fn new(filepath: &str) -> Self {
+        let mut table = VerifierTable {
+            entries: BTreeMap::new(),
+            location: filepath.to_string(),
+            fingerprint: None,
+        };
+        if let Err(e) = table.load() {
+            eprintln!(
+                "\x1b[91mFailed to load verifiers from '{}'. Error: {}\x1b[0m",
+                filepath, e
+            );
+        }
+        table
+    }
+
+    /// internal method to read the table, leaving it empty if it is missing or unreadable
+    fn load(&mut self) -> Result<(), VerifierError> {
+        self.entries = BTreeMap::new();
+        let data = match read(&self.location) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                self.fingerprint = None;
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        self.fingerprint = Some(sha256(data.clone()));
+        self.entries =
+            serde_json::from_slice(&data).map_err(|e| VerifierError::Corrupt(e.to_string()))?;
+        Ok(())
+    }
+
+    /// reload the table if another process changed it since it was last read
+    /// # Return
+    /// * whether or not the table was reloaded
+    pub fn refresh(&mut self) -> bool {
+        let current = read(&self.location).ok().map(sha256);
+        if current == self.fingerprint {
+            return false;
+        }
+        if let Err(e) = self.load() {
+            eprintln!(
+                "\x1b[91mFailed to reload verifiers from '{}'. Error: {}\x1b[0m",
+                self.location, e
+            );
+        }
+        true
+    }
+
+    /// internal method to change the table under its lock and write it back
+    /// # Arguments
+    /// * `change` - edits the entries
+    fn update<F>(&mut self, change: F) -> Result<(), VerifierError>
+    where
+        F: FnOnce(&mut BTreeMap<String, Verifiers>),
+    {
+        let lock_path = format!("{}.lock", self.location);
+        let _lock = lock_file(Path::new(&lock_path), true)?;
+        self.refresh();
+        let mut entries = self.entries.clone();
+        change(&mut entries);
+        let data = serde_json::to_vec_pretty(&entries)
+            .map_err(|e| VerifierError::Corrupt(e.to_string()))?;
+        atomic_write(&self.location, &data, VERIFIERS_MODE)?;
+        self.fingerprint = Some(sha256(data));
+        self.entries = entries;
+        Ok(())
+    }
+
+    /// an account's verifiers, if they were made from its current password
+    /// # Arguments
+    /// * `user` - account name
+    /// * `hash` - the account's password hash
+    pub fn get(&self, user: &str, hash: &str) -> Option<&Verifiers> {
+        self.entries.get(user).filter(|v| v.is_current(hash))
+    }
+
+    /// store an account's verifiers, replacing any it had
+    /// # Arguments
+    /// * `user` - account name
+    /// * `verifiers` - its new verifiers
+    pub fn set(&mut self, user: &str, verifiers: Verifiers) -> Result<(), VerifierError> {
+        self.update(|entries| {
+            entries.insert(user.to_string(), verifiers);
+        })
+    }
+
+    /// move an account's verifiers to its new name, dropping the SRP verifier bound to the old
+    /// # Arguments
+    /// * `old` - previous account name
+    /// * `new` - new account name
+    pub fn rename(&mut self, old: &str, new: &str) -> Result<(), VerifierError> {
+        if !self.entries.contains_key(old) {
+            return Ok(());
+        }
+        self.update(|entries| {
+            if let Some(mut verifiers) = entries.remove(old) {
+                verifiers.srp.clear();
+                entries.insert(new.to_string(), verifiers);
+            }
+        })
+    }
+
+    /// drop an account's verifiers
+    /// # Arguments
+    /// * `user` - account name
+    pub fn remove(&mut self, user: &str) -> Result<(), VerifierError> {
+        if !self.entries.contains_key(user) {
+            return Ok(());
+        }
+        self.update(|entries| {
+            entries.remove(user);
+        })
+    }
+}
+
+// ==================== FUNCTIONS ====================
+
+/// internal function to fingerprint a password hash
+fn hash_digest(hash: &str) -> String {
+    hex::encode(sha256(hash.as_bytes().to_vec()))
+}
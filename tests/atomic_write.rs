@@ -0,0 +1,125 @@
+// tests/atomic_write.rs
+//! Crash-safety of database writes: interrupted writes must never truncate the live file
+
+use credential_playground::auth_utils::*;
+use std::fs::{create_dir_all, metadata, read_to_string, remove_dir_all, write};
+use std::io::{Error, ErrorKind, Write};
+use std::os::unix::fs::PermissionsExt;
+
+/// creates an empty scratch directory unique to a test
+fn scratch_dir(name: &str) -> String {
+    let dir = std::env::temp_dir()
+        .join(format!("credplay-{}-{}", name, std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    let _ = remove_dir_all(&dir);
+    create_dir_all(&dir).expect("failed to create scratch dir");
+    dir
+}
+
+#[test]
+fn write_replaces_file_and_keeps_backup() {
+    let dir = scratch_dir("replace");
+    let path = format!("{}/passwd", dir);
+
+    atomic_write(&path, b"first\n", PASSWD_MODE).unwrap();
+    atomic_write(&path, b"second\n", SHADOW_MODE).unwrap();
+
+    assert_eq!(read_to_string(&path).unwrap(), "second\n");
+    assert_eq!(read_to_string(format!("{}-", path)).unwrap(), "first\n");
+    assert_eq!(
+        metadata(&path).unwrap().permissions().mode() & 0o777,
+        SHADOW_MODE
+    );
+    assert!(metadata(format!("{}+", path)).is_err());
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn failed_write_leaves_original_untouched() {
+    let dir = scratch_dir("interrupted");
+    let path = format!("{}/passwd", dir);
+    atomic_write(&path, b"root:x:0:0::/root:/bin/sh\n", PASSWD_MODE).unwrap();
+
+    // simulate running out of disk space halfway through the new contents
+    let result = atomic_write_with(&path, PASSWD_MODE, |file| {
+        file.write_all(b"root:x:0:")?;
+        Err(Error::new(ErrorKind::StorageFull, "disk full"))
+    });
+
+    assert!(result.is_err());
+    assert_eq!(
+        read_to_string(&path).unwrap(),
+        "root:x:0:0::/root:/bin/sh\n"
+    );
+    assert!(metadata(format!("{}+", path)).is_err());
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unwritable_temp_file_leaves_original_untouched() {
+    let dir = scratch_dir("unwritable");
+    let path = format!("{}/passwd", dir);
+    atomic_write(&path, b"original\n", PASSWD_MODE).unwrap();
+
+    // a directory squatting on the temp path makes the write fail before anything is touched
+    create_dir_all(format!("{}+", path)).unwrap();
+    assert!(atomic_write(&path, b"replacement\n", PASSWD_MODE).is_err());
+    assert_eq!(read_to_string(&path).unwrap(), "original\n");
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn crash_before_rename_keeps_accounts() {
+    let dir = scratch_dir("crash");
+    let passwd = format!("{}/passwd", dir);
+    let shadow = format!("{}/shadow", dir);
+    let hash = hash_password("hunter2", &get_salt(None), 1);
+    UserCredentials::new(&passwd, &shadow)
+        .set_record(UserRecord::new(ROOT, &hash, ROOT_UID))
+        .set("alice", &hash);
+
+    // a process killed mid-write leaves truncated temp files behind
+    write(format!("{}+", passwd), "root:x:0").unwrap();
+    write(format!("{}+", shadow), "").unwrap();
+
+    let mut database = UserCredentials::new(&passwd, &shadow);
+    assert!(database.contains(ROOT));
+    assert!(database.authenticate("alice", "hunter2"));
+
+    // the next write recovers by overwriting the stale temp files
+    database.set("bob", &hash);
+    let database = UserCredentials::new(&passwd, &shadow);
+    assert_eq!(database.list_users().len(), 3);
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn failed_passwd_write_only_rolls_back_its_own_shadow_write() {
+    let dir = scratch_dir("rollback");
+    let passwd = format!("{}/passwd", dir);
+    let shadow = format!("{}/shadow", dir);
+    let hash = hash_password("hunter2", &get_salt(None), 1);
+
+    // a backup left by an earlier run, with no shadow file to go with it
+    write(format!("{}-", shadow), "stale:$old$\n").unwrap();
+    create_dir_all(format!("{}+", passwd)).unwrap();
+    let mut database = UserCredentials::new(&passwd, &shadow);
+    assert!(
+        database
+            .transaction()
+            .set_record(UserRecord::new(ROOT, &hash, ROOT_UID))
+            .commit()
+            .is_err()
+    );
+    assert!(metadata(&shadow).is_err());
+
+    // with a shadow file in place, the backup this write made is put back
+    remove_dir_all(format!("{}+", passwd)).unwrap();
+    database.set_record(UserRecord::new(ROOT, &hash, ROOT_UID));
+    let before = read_to_string(&shadow).unwrap();
+    create_dir_all(format!("{}+", passwd)).unwrap();
+    assert!(database.transaction().set("alice", &hash).commit().is_err());
+    assert_eq!(read_to_string(&shadow).unwrap(), before);
+    remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,68 @@
+// tests/audit.rs
+//! Audit log chain: crashes between an entry and its head recover, forged entries don't verify
+
+use credential_playground::audit::*;
+use std::fs::{OpenOptions, copy, create_dir_all, remove_dir_all};
+use std::io::Write;
+
+/// creates an empty scratch directory unique to a test
+fn scratch_dir(name: &str) -> String {
+    let dir = std::env::temp_dir()
+        .join(format!("credplay-audit-{}-{}", name, std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    let _ = remove_dir_all(&dir);
+    create_dir_all(&dir).expect("failed to create scratch dir");
+    dir
+}
+
+/// opens the log of a scratch directory, keyed with the seal key next to it
+fn open_log(dir: &str) -> AuditLog {
+    AuditLog::new(&format!("{}/audit.log", dir), &format!("{}/.pwd.key", dir))
+}
+
+#[test]
+fn head_missed_by_a_crash_is_recovered() {
+    let dir = scratch_dir("crash");
+    let log = open_log(&dir);
+    log.record("root", "login", "", AuditOutcome::Success, "first");
+    let head = format!("{}/audit.log{}", dir, HEAD_SUFFIX);
+    copy(&head, format!("{}.old", head)).unwrap();
+    log.record("root", "logout", "", AuditOutcome::Success, "second");
+
+    // a crash after the entry was written but before its head was
+    copy(format!("{}.old", head), &head).unwrap();
+    let log = open_log(&dir);
+    log.record("root", "login", "", AuditOutcome::Success, "third");
+
+    let seqs: Vec<u64> = log.entries().unwrap().iter().map(|e| e.seq).collect();
+    assert_eq!(seqs, vec![1, 2, 3]);
+    assert_eq!(log.verify().unwrap(), 3);
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn entry_forged_without_the_key_is_not_taken_up() {
+    let dir = scratch_dir("forged");
+    let log = open_log(&dir);
+    log.record("root", "login", "", AuditOutcome::Success, "first");
+    let first = log.entries().unwrap().pop().unwrap();
+
+    // an entry that chains on correctly but is hashed with a key of the attacker's own
+    let mut forged = AuditEntry {
+        seq: 2,
+        detail: "forged".to_string(),
+        prev: first.hash.clone(),
+        ..first
+    };
+    forged.hash = forged.digest(b"not the seal key");
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(format!("{}/audit.log", dir))
+        .unwrap();
+    writeln!(file, "{}", serde_json::to_string(&forged).unwrap()).unwrap();
+
+    let log = open_log(&dir);
+    assert!(matches!(log.verify(), Err(AuditError::Tampered(2, _))));
+    remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,122 @@
+// tests/chacha20poly1305.rs
+//! Known-answer tests for the primitives behind the encrypted store (RFC 8439, RFC 7914)
+
+use credential_playground::auth_utils::*;
+
+/// decodes a hex test vector
+fn unhex(s: &str) -> Vec<u8> {
+    hex::decode(s).expect("bad test vector")
+}
+
+/// the key 00 01 02 .. 1f used by most RFC 8439 vectors
+fn sequential_key(start: u8) -> [u8; CHACHA_KEY_LEN] {
+    std::array::from_fn(|i| start + i as u8)
+}
+
+/// the plaintext of RFC 8439 sections 2.4.2 and 2.8.2
+const SUNSCREEN: &[u8] = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+
+#[test]
+fn chacha20_block_function() {
+    // RFC 8439 section 2.3.2
+    let nonce: [u8; CHACHA_NONCE_LEN] = unhex("000000090000004a00000000").try_into().unwrap();
+    let block = chacha20_block(&sequential_key(0), 1, &nonce);
+    assert_eq!(
+        hex::encode(block),
+        "10f1e7e4d13b5915500fdd1fa32071c4c7d1f4c733c068030422aa9ac3d46c4e\
+         d2826446079faa0914c2d705d98b02a2b5129cd1de164eb9cbd083e8a2503c4e"
+    );
+}
+
+#[test]
+fn chacha20_encryption() {
+    // RFC 8439 section 2.4.2
+    let nonce: [u8; CHACHA_NONCE_LEN] = unhex("000000000000004a00000000").try_into().unwrap();
+    let ciphertext = chacha20_xor(&sequential_key(0), 1, &nonce, SUNSCREEN);
+    assert_eq!(
+        hex::encode(&ciphertext),
+        "6e2e359a2568f98041ba0728dd0d6981e97e7aec1d4360c20a27afccfd9fae0b\
+         f91b65c5524733ab8f593dabcd62b3571639d624e65152ab8f530c359f0861d8\
+         07ca0dbf500d6a6156a38e088a22b65e52bc514d16ccf806818ce91ab7793736\
+         5af90bbf74a35be6b40b8eedf2785e42874d"
+    );
+    assert_eq!(
+        chacha20_xor(&sequential_key(0), 1, &nonce, &ciphertext),
+        SUNSCREEN
+    );
+}
+
+#[test]
+fn poly1305_mac() {
+    // RFC 8439 section 2.5.2
+    let key: [u8; 32] = unhex("85d6be7857556d337f4452fe42d506a80103808afb0db2fd4abff6af4149f51b")
+        .try_into()
+        .unwrap();
+    let tag = poly1305(&key, b"Cryptographic Forum Research Group");
+    assert_eq!(hex::encode(tag), "a8061dc1305136c6c22b8baf0c0127a9");
+}
+
+#[test]
+fn aead_seal_and_open() {
+    // RFC 8439 section 2.8.2
+    let key = sequential_key(0x80);
+    let nonce: [u8; CHACHA_NONCE_LEN] = unhex("070000004041424344454647").try_into().unwrap();
+    let aad = unhex("50515253c0c1c2c3c4c5c6c7");
+    let sealed = chacha20poly1305_seal(&key, &nonce, &aad, SUNSCREEN);
+    let (ciphertext, tag) = sealed.split_at(sealed.len() - POLY1305_TAG_LEN);
+    assert_eq!(
+        hex::encode(ciphertext),
+        "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d6\
+         3dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b36\
+         92ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc\
+         3ff4def08e4b7a9de576d26586cec64b6116"
+    );
+    assert_eq!(hex::encode(tag), "1ae10b594f09e26a7e902ecbd0600691");
+    assert_eq!(
+        chacha20poly1305_open(&key, &nonce, &aad, &sealed).as_deref(),
+        Some(SUNSCREEN)
+    );
+}
+
+#[test]
+fn aead_rejects_tampering() {
+    let key = sequential_key(0x80);
+    let nonce: [u8; CHACHA_NONCE_LEN] = unhex("070000004041424344454647").try_into().unwrap();
+    let mut sealed = chacha20poly1305_seal(&key, &nonce, b"header", SUNSCREEN);
+    assert!(chacha20poly1305_open(&key, &nonce, b"other header", &sealed).is_none());
+    sealed[0] ^= 1;
+    assert!(chacha20poly1305_open(&key, &nonce, b"header", &sealed).is_none());
+    assert!(chacha20poly1305_open(&key, &nonce, b"header", &sealed[..4]).is_none());
+}
+
+#[test]
+fn pbkdf2_hmac_sha256() {
+    // RFC 7914 section 11
+    assert_eq!(
+        hex::encode(pbkdf2_sha256(b"passwd", b"salt", 1, 64)),
+        "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc\
+         49ca9cccf179b645991664b39d77ef317c71b845b1e30bd509112041d3a19783"
+    );
+}
+
+#[test]
+fn encrypted_store_refuses_excessive_kdf_iterations() {
+    let dir = std::env::temp_dir().join(format!("credplay-kdf-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("passwd.enc").to_string_lossy().to_string();
+
+    // the header is read before anything is authenticated, so it must not set the cost
+    let header = format!(
+        "credenc-1 pbkdf2-sha256 {} {} {}\n{}\n",
+        u32::MAX,
+        base64_encode(b"0123456789abcdef"),
+        base64_encode(&[0; CHACHA_NONCE_LEN]),
+        base64_encode(&[0; 32])
+    );
+    std::fs::write(&path, header).unwrap();
+    assert!(matches!(
+        EncryptedStore::open(&path, b"secret"),
+        Err(StoreError::Corrupt(_))
+    ));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,167 @@
+// tests/config.rs
+//! Configuration: files, environment and flags overriding each other, and refused values
+
+use credential_playground::config::*;
+use std::fs::{create_dir_all, remove_dir_all, write};
+
+/// creates an empty scratch directory unique to a test
+fn scratch_dir(name: &str) -> String {
+    let dir = std::env::temp_dir()
+        .join(format!("credplay-config-{}-{}", name, std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    let _ = remove_dir_all(&dir);
+    create_dir_all(&dir).expect("failed to create scratch dir");
+    dir
+}
+
+/// command-line arguments from string slices
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+// the only test touching the environment, nothing the others check depends on it
+#[test]
+fn later_layers_override_earlier_ones() {
+    let dir = scratch_dir("layers");
+    create_dir_all(format!("{}/credplay", dir)).unwrap();
+    write(
+        format!("{}/credplay/config.toml", dir),
+        "[hash]\ncost = 5\n[sudo]\ntimeout = 10\n[token]\nlifetime = 100\n[prompt]\nicon = \"user> \"\n",
+    )
+    .unwrap();
+    let extra = format!("{}/extra.toml", dir);
+    write(
+        &extra,
+        "[hash]\ncost = 6\n[sudo]\ntimeout = 20\n[token]\nlifetime = 200\n",
+    )
+    .unwrap();
+
+    // SAFETY: the tests only read the environment through std, which serializes access
+    unsafe {
+        std::env::set_var("XDG_CONFIG_HOME", &dir);
+        std::env::set_var("CREDPLAY_HASH_COST", "7");
+        std::env::set_var("CREDPLAY_SUDO_TIMEOUT", "30");
+    }
+    let config = Config::load(&args(&[
+        "--dir",
+        &dir,
+        "--config",
+        &extra,
+        "--hash-cost",
+        "8",
+    ]));
+    unsafe {
+        std::env::remove_var("XDG_CONFIG_HOME");
+        std::env::remove_var("CREDPLAY_HASH_COST");
+        std::env::remove_var("CREDPLAY_SUDO_TIMEOUT");
+    }
+    let config = config.unwrap();
+
+    let user_file = format!("{}/credplay/config.toml", dir);
+    assert_eq!(config.prompt_icon, "user> ");
+    assert_eq!(config.source("prompt.icon"), Source::File(user_file));
+    assert_eq!(config.token_lifetime, 200);
+    assert_eq!(config.source("token.lifetime"), Source::File(extra));
+    assert_eq!(config.sudo_timeout, 30);
+    assert_eq!(
+        config.source("sudo.timeout"),
+        Source::Env("CREDPLAY_SUDO_TIMEOUT".to_string())
+    );
+    assert_eq!(config.hash_cost, 8);
+    assert_eq!(
+        config.source("hash.cost"),
+        Source::Flag("--hash-cost".to_string())
+    );
+    assert_eq!(config.source("daemon.listen"), Source::Default);
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn paths_resolve_against_the_data_directory() {
+    let config = Config::load(&args(&[
+        "--dir",
+        "/srv/credplay",
+        "--set",
+        "storage.shadow=/secure/shadow",
+    ]))
+    .unwrap();
+    assert_eq!(config.passwd_path(), "/srv/credplay/passwd");
+    assert_eq!(config.shadow_path(), "/secure/shadow");
+    assert!(
+        config
+            .entries()
+            .iter()
+            .any(|(key, value, _)| *key == "storage.group" && value == "/srv/credplay/group")
+    );
+}
+
+#[test]
+fn invalid_values_name_the_setting_and_source() {
+    let mut config = Config::default();
+    let flag = Source::Flag("--set".to_string());
+    for (key, value) in [
+        ("hash.cost", "31"),
+        ("storage.backend", "sqlite"),
+        ("session.idle_action", "nap"),
+        ("token.lifetime", "0"),
+        ("authz.utc_offset", "900"),
+        ("prompt.user_color", "green"),
+        ("daemon.listen", "nowhere"),
+        ("daemon.max_connections", "0"),
+        ("policy.username_pattern", "["),
+    ] {
+        assert!(
+            matches!(
+                config.set(key, value, flag.clone()),
+                Err(ConfigError::Invalid(k, _, s)) if k == key && s == flag
+            ),
+            "{} = {} should be refused",
+            key,
+            value
+        );
+        assert_eq!(config.source(key), Source::Default);
+    }
+    assert!(matches!(
+        config.set("hash.colour", "1", flag.clone()),
+        Err(ConfigError::UnknownKey(key, _)) if key == "hash.colour"
+    ));
+    config.set("hash.cost", "4", flag.clone()).unwrap();
+    assert_eq!((config.hash_cost, config.source("hash.cost")), (4, flag));
+}
+
+#[test]
+fn bad_files_and_arguments_are_refused() {
+    let dir = scratch_dir("bad");
+    let (unknown, broken) = (
+        format!("{}/unknown.toml", dir),
+        format!("{}/broken.toml", dir),
+    );
+    write(&unknown, "[hash]\ncolour = 1\n").unwrap();
+    write(&broken, "[hash\n").unwrap();
+
+    let load = |extra: &[&str]| Config::load(&args(&[&["--dir", dir.as_str()], extra].concat()));
+    assert!(matches!(
+        load(&["--config", &unknown]),
+        Err(ConfigError::UnknownKey(key, Source::File(path))) if key == "hash.colour" && path == unknown
+    ));
+    assert!(matches!(
+        load(&["--config", &broken]),
+        Err(ConfigError::Parse(path, _)) if path == broken
+    ));
+    assert!(matches!(
+        load(&["--config", &format!("{}/missing.toml", dir)]),
+        Err(ConfigError::Io(_, _))
+    ));
+    assert!(matches!(
+        load(&["--frobnicate"]),
+        Err(ConfigError::Usage(_))
+    ));
+    assert!(matches!(load(&["--hash-cost"]), Err(ConfigError::Usage(_))));
+    assert!(matches!(
+        load(&["--set", "hash.cost"]),
+        Err(ConfigError::Usage(_))
+    ));
+    assert!(matches!(load(&["--help"]), Err(ConfigError::Help)));
+    remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,541 @@
+// tests/credplayd.rs
+//! The credplayd line protocol, spoken by a loopback client to a spawned daemon
+
+use credential_playground::auth_utils::*;
+use credential_playground::authz::*;
+use credential_playground::config::*;
+use credential_playground::daemon::*;
+use credential_playground::shell::*;
+use credential_playground::sudo::Principal;
+use std::fs::{create_dir_all, remove_dir_all};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::unix::net::UnixStream;
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+/// password of the root account every test starts with
+const ROOT_PASSWORD: &str = "rootpass1";
+
+/// settings keeping hashing cheap, so the tests stay fast in debug builds
+const FAST_HASHING: [&str; 6] = [
+    "--hash-cost",
+    "4",
+    "--set",
+    "hash.scram_iterations=4096",
+    "--set",
+    "hash.srp_group=1024",
+];
+
+/// creates an empty scratch directory unique to a test, holding a root account
+fn scratch_dir(name: &str) -> String {
+    let dir = std::env::temp_dir()
+        .join(format!("credplayd-{}-{}", name, std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    let _ = remove_dir_all(&dir);
+    create_dir_all(&dir).expect("failed to create scratch dir");
+
+    let mut config = Config::default();
+    config.dir = dir.clone();
+    config.hash_cost = 4;
+    config.srp_group = 1024;
+    let mut env = open_environment(&config).unwrap();
+    let (hash, verifiers) = hash_new_password(&config, ROOT, ROOT_PASSWORD).unwrap();
+    env.database
+        .set_record(UserRecord::new(ROOT, &hash, ROOT_UID));
+    store_verifiers(&mut env, ROOT, verifiers);
+    dir
+}
+
+/// a running daemon, killed when the test is done with it
+struct Daemon {
+    child: Child,
+    address: String,
+    _stdout: BufReader<ChildStdout>,
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// spawns the daemon over a scratch directory and waits until it listens
+fn spawn(dir: &str, listen: &str) -> Daemon {
+    spawn_with(dir, listen, &[])
+}
+
+/// spawns the daemon with extra arguments and waits until it listens
+fn spawn_with(dir: &str, listen: &str, args: &[&str]) -> Daemon {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_credplayd"))
+        .args(["--dir", dir, "--listen", listen])
+        .args(FAST_HASHING)
+        .args(args)
+        .env("XDG_CONFIG_HOME", dir)
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to spawn credplayd");
+    // keep stdout open, the daemon may still print to it
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    let address = line
+        .trim()
+        .strip_prefix("credplayd listening on ")
+        .unwrap_or_else(|| panic!("unexpected first line: {:?}", line))
+        .to_string();
+    Daemon {
+        child,
+        address,
+        _stdout: stdout,
+    }
+}
+
+/// a loopback client reading one reply line at a time
+struct Client<S: Read + Write> {
+    reader: BufReader<S>,
+    writer: S,
+}
+
+impl Client<TcpStream> {
+    /// connects over TCP and reads the greeting
+    fn tcp(daemon: &Daemon) -> Self {
+        let stream = TcpStream::connect(&daemon.address).unwrap();
+        Client::greeted(stream.try_clone().unwrap(), stream)
+    }
+}
+
+impl Client<UnixStream> {
+    /// connects to a Unix socket and reads the greeting
+    fn unix(path: &str) -> Self {
+        let stream = UnixStream::connect(path).unwrap();
+        Client::greeted(stream.try_clone().unwrap(), stream)
+    }
+}
+
+impl<S: Read + Write> Client<S> {
+    /// wraps a connection and checks the greeting
+    fn greeted(reader: S, writer: S) -> Self {
+        let mut client = Client {
+            reader: BufReader::new(reader),
+            writer,
+        };
+        assert_eq!(client.line(), GREETING);
+        client
+    }
+
+    /// reads one reply line, empty once the daemon closed the connection
+    fn line(&mut self) -> String {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).unwrap();
+        line.trim_end().to_string()
+    }
+
+    /// sends a request and reads its first reply line
+    fn send(&mut self, request: &str) -> String {
+        writeln!(self.writer, "{}", request).unwrap();
+        self.line()
+    }
+
+    /// sends `LIST` and reads the accounts it returns
+    fn list(&mut self) -> Vec<String> {
+        let count: usize = self
+            .send("LIST")
+            .strip_prefix("OK ")
+            .expect("LIST failed")
+            .parse()
+            .unwrap();
+        (0..count).map(|_| self.line()).collect()
+    }
+}
+
+#[test]
+fn authenticates_and_lists_accounts() {
+    let dir = scratch_dir("auth");
+    let daemon = spawn(&dir, "127.0.0.1:0");
+    let mut client = Client::tcp(&daemon);
+
+    assert_eq!(client.send("LIST"), "ERR permission denied");
+    assert_eq!(
+        client.send("AUTH root wrongpass"),
+        "ERR authentication failed"
+    );
+    assert_eq!(
+        client.send(&format!("AUTH root {}", ROOT_PASSWORD)),
+        "OK authenticated as root"
+    );
+    assert_eq!(
+        client.send(&format!("AUTH root {}", ROOT_PASSWORD)),
+        "ERR already authenticated as root"
+    );
+    assert_eq!(client.list(), vec!["root".to_string()]);
+    assert_eq!(client.send("QUIT"), "OK bye");
+    assert_eq!(client.line(), "");
+    drop(daemon);
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn passwords_may_contain_spaces() {
+    let dir = scratch_dir("spaces");
+    let daemon = spawn(&dir, "127.0.0.1:0");
+    let mut root = Client::tcp(&daemon);
+
+    assert!(
+        root.send(&format!("AUTH root {}", ROOT_PASSWORD))
+            .starts_with("OK")
+    );
+    assert_eq!(
+        root.send("USERADD alice correct horse battery"),
+        "OK created account alice"
+    );
+
+    let mut alice = Client::tcp(&daemon);
+    assert_eq!(
+        alice.send("AUTH alice correct horse"),
+        "ERR authentication failed"
+    );
+    assert_eq!(
+        alice.send("AUTH alice correct horse battery"),
+        "OK authenticated as alice"
+    );
+    drop(daemon);
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn checks_permissions_per_connection() {
+    let dir = scratch_dir("permissions");
+    let daemon = spawn(&dir, "127.0.0.1:0");
+
+    // nobody is authenticated yet
+    let mut root = Client::tcp(&daemon);
+    assert_eq!(
+        root.send("USERADD alice alicepass1"),
+        "ERR permission denied"
+    );
+    assert_eq!(root.send("PASSWD root newpass1"), "ERR permission denied");
+
+    // root may create accounts and change any password
+    assert!(
+        root.send(&format!("AUTH root {}", ROOT_PASSWORD))
+            .starts_with("OK")
+    );
+    assert_eq!(
+        root.send("USERADD alice alicepass1"),
+        "OK created account alice"
+    );
+    assert_eq!(
+        root.send("USERADD alice alicepass2"),
+        "ERR account alice already exists"
+    );
+    assert_eq!(root.send("USERADD bob bobpass99"), "OK created account bob");
+
+    // a regular account may only change its own password, on its own connection
+    let mut alice = Client::tcp(&daemon);
+    assert!(alice.send("AUTH alice alicepass1").starts_with("OK"));
+    assert_eq!(
+        alice.send("USERADD carol carolpass"),
+        "ERR permission denied"
+    );
+    assert_eq!(alice.send("PASSWD bob stolenpass"), "ERR permission denied");
+    assert_eq!(
+        alice.send("PASSWD alice alicepass2"),
+        "OK changed password for alice"
+    );
+    assert_eq!(
+        alice.list(),
+        vec!["alice".to_string(), "bob".to_string(), "root".to_string()]
+    );
+
+    // the other connection keeps root's session and its permissions
+    assert_eq!(
+        root.send("PASSWD bob bobpass100"),
+        "OK changed password for bob"
+    );
+
+    let mut check = Client::tcp(&daemon);
+    assert_eq!(
+        check.send("AUTH alice alicepass1"),
+        "ERR authentication failed"
+    );
+    assert!(check.send("AUTH alice alicepass2").starts_with("OK"));
+    let mut check = Client::tcp(&daemon);
+    assert!(check.send("AUTH bob bobpass100").starts_with("OK"));
+    drop(daemon);
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn only_root_changes_roots_password() {
+    let dir = scratch_dir("root-passwd");
+
+    // helpdesk may change any other password
+    let mut config = Config::default();
+    config.dir = dir.clone();
+    let mut roles = RoleTable::new(&config.roles_path());
+    roles
+        .create("helpdesk", &[Capability::UserPasswdOther])
+        .unwrap();
+    roles
+        .assign("helpdesk", &Principal::User("alice".to_string()))
+        .unwrap();
+
+    let daemon = spawn(&dir, "127.0.0.1:0");
+    let mut root = Client::tcp(&daemon);
+    assert!(
+        root.send(&format!("AUTH root {}", ROOT_PASSWORD))
+            .starts_with("OK")
+    );
+    assert!(root.send("USERADD alice alicepass1").starts_with("OK"));
+    assert!(root.send("USERADD bob bobpass99").starts_with("OK"));
+
+    let mut alice = Client::tcp(&daemon);
+    assert!(alice.send("AUTH alice alicepass1").starts_with("OK"));
+    assert_eq!(
+        alice.send("PASSWD bob bobpass100"),
+        "OK changed password for bob"
+    );
+    assert_eq!(alice.send("PASSWD root takenover"), "ERR permission denied");
+    assert_eq!(
+        root.send("PASSWD root rootpass2"),
+        "OK changed password for root"
+    );
+
+    let mut check = Client::tcp(&daemon);
+    assert_eq!(
+        check.send("AUTH root takenover"),
+        "ERR authentication failed"
+    );
+    assert!(check.send("AUTH root rootpass2").starts_with("OK"));
+    drop(daemon);
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn policy_permits_change_other_passwords() {
+    let dir = scratch_dir("permit");
+
+    // helpdesk holds no role, the policy alone lets it change passwords
+    let mut config = Config::default();
+    config.dir = dir.clone();
+    let mut groups = GroupDatabase::new(&config.group_path());
+    groups.add("helpdesk", FIRST_GID).unwrap();
+    groups.add_member("helpdesk", "alice").unwrap();
+    let policy = config.policy_path();
+    std::fs::write(
+        &policy,
+        r#"permit (principal in "helpdesk", action == "chpass", resource);"#,
+    )
+    .unwrap();
+    std::fs::set_permissions(&policy, std::os::unix::fs::PermissionsExt::from_mode(0o600)).unwrap();
+
+    let daemon = spawn(&dir, "127.0.0.1:0");
+    let mut root = Client::tcp(&daemon);
+    assert!(
+        root.send(&format!("AUTH root {}", ROOT_PASSWORD))
+            .starts_with("OK")
+    );
+    assert!(root.send("USERADD alice alicepass1").starts_with("OK"));
+    assert!(root.send("USERADD bob bobpass99").starts_with("OK"));
+
+    let mut alice = Client::tcp(&daemon);
+    assert!(alice.send("AUTH alice alicepass1").starts_with("OK"));
+    assert_eq!(
+        alice.send("PASSWD bob bobpass100"),
+        "OK changed password for bob"
+    );
+    assert_eq!(alice.send("PASSWD root takenover"), "ERR permission denied");
+
+    // bob isn't in helpdesk
+    let mut bob = Client::tcp(&daemon);
+    assert!(bob.send("AUTH bob bobpass100").starts_with("OK"));
+    assert_eq!(bob.send("PASSWD alice stolenpass"), "ERR permission denied");
+    drop(daemon);
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn closes_after_repeated_failures() {
+    let dir = scratch_dir("failures");
+    let daemon = spawn(&dir, "127.0.0.1:0");
+    let mut client = Client::tcp(&daemon);
+
+    for _ in 0..3 {
+        assert_eq!(client.send("AUTH root guess"), "ERR authentication failed");
+    }
+    assert_eq!(client.line(), "");
+    drop(daemon);
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn throttles_failures_across_connections() {
+    let dir = scratch_dir("throttle");
+    let daemon = spawn(&dir, "127.0.0.1:0");
+
+    // reconnecting doesn't reset the count, 10 failures from one address lock it out
+    for _ in 0..3 {
+        let mut client = Client::tcp(&daemon);
+        for _ in 0..3 {
+            assert_eq!(client.send("AUTH root guess"), "ERR authentication failed");
+        }
+    }
+    let mut client = Client::tcp(&daemon);
+    assert_eq!(client.send("AUTH root guess"), "ERR authentication failed");
+    assert_eq!(
+        client.send(&format!("AUTH root {}", ROOT_PASSWORD)),
+        "ERR too many failed logins, try again later"
+    );
+    drop(daemon);
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn throttles_each_client_on_its_own() {
+    let throttle = AuthThrottle::default();
+    for _ in 0..10 {
+        assert!(throttle.allows("uid 1000"));
+        throttle.record("uid 1000");
+    }
+    assert!(!throttle.allows("uid 1000"));
+    assert!(throttle.allows("uid 1001"));
+    assert!(throttle.allows("127.0.0.1"));
+}
+
+#[test]
+fn names_local_clients_by_uid() {
+    // SAFETY: getuid and getpid only read the process's ids
+    let (uid, pid) = unsafe { (libc::getuid(), libc::getpid()) };
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (accepted, peer) = listener.accept().unwrap();
+    assert_eq!(
+        tcp_client(&accepted.local_addr().unwrap(), &peer),
+        format!("uid {}", uid)
+    );
+
+    let (accepted, _client) = UnixStream::pair().unwrap();
+    assert_eq!(
+        unix_client(&accepted, "/run/credplayd.sock"),
+        (
+            format!("/run/credplayd.sock pid {} uid {}", pid, uid),
+            format!("uid {}", uid)
+        )
+    );
+}
+
+#[test]
+fn finds_socket_owners_in_kernel_tables() {
+    let header = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode";
+    let tcp = [
+        header,
+        "   0: 0100007F:1CFC 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 1 1 0 100 0 0 10 0",
+        "   1: 0100007F:1CFC 0100007F:D431 01 00000000:00000000 00:00000000 00000000     0        0 2 1 0 20 4 30 10 -1",
+        "   2: 0100007F:D431 0100007F:1CFC 01 00000000:00000000 00:00000000 00000000  1000        0 3 1 0 20 4 30 10 -1",
+    ]
+    .join("\n");
+    let local: SocketAddr = "127.0.0.1:7420".parse().unwrap();
+    let peer: SocketAddr = "127.0.0.1:54321".parse().unwrap();
+    assert_eq!(socket_owner(&tcp, &local, &peer), Some(1000));
+    // the daemon's own end goes the other way
+    assert_eq!(socket_owner(&tcp, &peer, &local), Some(0));
+    let other: SocketAddr = "127.0.0.1:54322".parse().unwrap();
+    assert_eq!(socket_owner(&tcp, &local, &other), None);
+
+    let tcp6 = [
+        header,
+        "   0: 00000000000000000000000001000000:D431 00000000000000000000000001000000:1CFC 01 00000000:00000000 00:00000000 00000000  1001        0 4 1 0 20 4 30 10 -1",
+    ]
+    .join("\n");
+    let local: SocketAddr = "[::1]:7420".parse().unwrap();
+    let peer: SocketAddr = "[::1]:54321".parse().unwrap();
+    assert_eq!(socket_owner(&tcp6, &local, &peer), Some(1001));
+}
+
+#[test]
+fn turns_away_connections_over_the_limit() {
+    let dir = scratch_dir("limit");
+    let daemon = spawn_with(&dir, "127.0.0.1:0", &["--set", "daemon.max_connections=2"]);
+    let mut first = Client::tcp(&daemon);
+    let _second = Client::tcp(&daemon);
+
+    let stream = TcpStream::connect(&daemon.address).unwrap();
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).unwrap();
+    assert_eq!(line.trim_end(), "ERR too many connections");
+
+    // a worker is free again once its connection is closed
+    assert_eq!(first.send("QUIT"), "OK bye");
+    let greeted = (0..50).any(|_| {
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let stream = TcpStream::connect(&daemon.address).unwrap();
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        line.trim_end() == GREETING
+    });
+    assert!(greeted);
+    drop(daemon);
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn rejects_malformed_requests() {
+    let dir = scratch_dir("malformed");
+    let daemon = spawn(&dir, "127.0.0.1:0");
+    let mut client = Client::tcp(&daemon);
+
+    assert_eq!(client.send(""), "ERR empty request");
+    assert_eq!(client.send("HELLO"), "ERR unknown command HELLO");
+    assert_eq!(
+        client.send("AUTH root"),
+        "ERR usage: AUTH <username> <password>"
+    );
+    assert_eq!(client.send("LIST all"), "ERR usage: LIST");
+    assert_eq!(client.send(&"A".repeat(5000)), "ERR line too long");
+    assert_eq!(client.send("QUIT"), "OK bye");
+    drop(daemon);
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn serves_a_unix_socket() {
+    let dir = scratch_dir("unix");
+    let path = format!("{}/credplayd.sock", dir);
+    let daemon = spawn(&dir, &format!("unix:{}", path));
+    assert_eq!(daemon.address, format!("unix:{}", path));
+
+    let mode =
+        std::os::unix::fs::PermissionsExt::mode(&std::fs::metadata(&path).unwrap().permissions());
+    assert_eq!(mode & 0o777, SOCKET_MODE);
+
+    let mut client = Client::unix(&path);
+    assert_eq!(
+        client.send(&format!("AUTH root {}", ROOT_PASSWORD)),
+        "OK authenticated as root"
+    );
+    assert_eq!(client.list(), vec!["root".to_string()]);
+    drop(daemon);
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn parses_listen_addresses() {
+    assert_eq!(
+        ListenAddr::parse("127.0.0.1:7420"),
+        Some(ListenAddr::Tcp("127.0.0.1:7420".to_string()))
+    );
+    assert_eq!(
+        ListenAddr::parse("[::1]:7420"),
+        Some(ListenAddr::Tcp("[::1]:7420".to_string()))
+    );
+    assert_eq!(
+        ListenAddr::parse("unix:/run/credplayd.sock"),
+        Some(ListenAddr::Unix("/run/credplayd.sock".to_string()))
+    );
+    assert_eq!(ListenAddr::parse("unix:"), None);
+    assert_eq!(ListenAddr::parse("localhost"), None);
+    assert_eq!(ListenAddr::parse("localhost:http"), None);
+    assert_eq!(ListenAddr::parse(":7420"), None);
+}
@@ -0,0 +1,121 @@
+// tests/dbcheck.rs
+//! Consistency checks: what each kind of damage is reported as, and what a repair does
+
+use credential_playground::auth_utils::*;
+use credential_playground::dbcheck::*;
+use std::fs::{create_dir_all, read_to_string, remove_dir_all, write};
+
+/// creates an empty scratch directory unique to a test
+fn scratch_dir(name: &str) -> String {
+    let dir = std::env::temp_dir()
+        .join(format!("credplay-dbcheck-{}-{}", name, std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    let _ = remove_dir_all(&dir);
+    create_dir_all(&dir).expect("failed to create scratch dir");
+    dir
+}
+
+/// a passwd line with the hash in the shadow file
+fn passwd_line(name: &str, uid: u32) -> String {
+    UserRecord::new(name, SHADOW_PLACEHOLDER, uid).record_line()
+}
+
+const BAD_HASH: &str = "$sha256iter-1$99$c2FsdA==$aGFzaA==$";
+
+#[test]
+fn damaged_files_are_reported_and_repaired() {
+    let dir = scratch_dir("files");
+    let (passwd, shadow) = (format!("{}/passwd", dir), format!("{}/shadow", dir));
+    let lines = [
+        passwd_line(ROOT, ROOT_UID),
+        "garbage".to_string(),
+        passwd_line("alice", 1000),
+        passwd_line("alice", 1001),
+        passwd_line("bob", 1002),
+        passwd_line("carol", 1003),
+    ];
+    write(&passwd, lines.join("\n") + "\n").unwrap();
+    write(
+        &shadow,
+        format!(
+            "root:{0}:::::::\nalice:{0}:::::::\ncarol:{1}:::::::\nghost:{0}:::::::\n",
+            LOCKED_HASH, BAD_HASH
+        ),
+    )
+    .unwrap();
+
+    let mut store = UserCredentials::new(&passwd, &shadow);
+    let diagnostics = store.check().unwrap();
+    let found = |problem: Problem| {
+        diagnostics
+            .iter()
+            .find(|d| d.problem == problem)
+            .unwrap_or_else(|| panic!("{:?} not found in {:?}", problem, diagnostics))
+            .clone()
+    };
+    assert_eq!(
+        found(Problem::Malformed).location,
+        Some((passwd.clone(), 2))
+    );
+    assert_eq!(
+        found(Problem::Duplicate("alice".to_string())).location,
+        Some((passwd.clone(), 4))
+    );
+    assert_eq!(
+        found(Problem::Orphaned("ghost".to_string())).record,
+        Some(format!("ghost:{}:::::::", LOCKED_HASH))
+    );
+    found(Problem::NoShadow("bob".to_string()));
+    assert!(diagnostics.iter().any(|d| matches!(
+        &d.problem, Problem::BadHash(username, HashError::Cost(_, _)) if username == "carol"
+    )));
+    assert!(!diagnostics.iter().any(|d| d.problem == Problem::NoRoot));
+    assert_eq!(diagnostics.len(), 5);
+
+    // bad lines go to the quarantine file, the account with a bad hash is locked
+    let report = store.repair().unwrap();
+    let quarantine = report.quarantine.unwrap();
+    assert_eq!(quarantine, format!("{}{}", passwd, QUARANTINE_SUFFIX));
+    let quarantined = read_to_string(&quarantine).unwrap();
+    for line in ["garbage", &passwd_line("alice", 1001), BAD_HASH] {
+        assert!(quarantined.contains(line), "{} not quarantined", line);
+    }
+    assert_eq!(store.get("carol").unwrap().hash(), LOCKED_HASH);
+    assert_eq!(store.get("alice").unwrap().uid(), 1000);
+    assert_eq!(store.check().unwrap(), Vec::new());
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn every_backend_reports_hashes_and_a_missing_root() {
+    let mut store = MemoryStore::new();
+    store
+        .transaction()
+        .set_record(UserRecord::new("bob", BAD_HASH, 1001))
+        .set_record(UserRecord::new(
+            "alice",
+            "$unknown-1$1$c2FsdA==$aGFzaA==$",
+            1000,
+        ))
+        .set("carol", LOCKED_HASH)
+        .commit()
+        .unwrap();
+
+    // ordered by uid, the missing root last
+    let diagnostics = check_records(&store);
+    let problems: Vec<&Problem> = diagnostics.iter().map(|d| &d.problem).collect();
+    assert!(matches!(
+        problems[..],
+        [
+            Problem::BadHash(alice, HashError::UnknownVersion(_)),
+            Problem::BadHash(bob, HashError::Cost(_, _)),
+            Problem::NoRoot,
+        ] if alice == "alice" && bob == "bob"
+    ));
+    assert!(!diagnostics[2].repairable());
+    assert_eq!(diagnostics[0].username(), Some("alice"));
+
+    // the memory store has nowhere to quarantine to
+    assert!(matches!(store.repair(), Err(StoreError::Unsupported(_))));
+}
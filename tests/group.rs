@@ -0,0 +1,128 @@
+// tests/group.rs
+//! Group file: membership through the primary gid or the member list, and following renames
+
+use credential_playground::auth_utils::*;
+use std::fs::{create_dir_all, read_to_string, remove_dir_all};
+
+/// creates an empty scratch directory unique to a test
+fn scratch_dir(name: &str) -> String {
+    let dir = std::env::temp_dir()
+        .join(format!("credplay-group-{}-{}", name, std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    let _ = remove_dir_all(&dir);
+    create_dir_all(&dir).expect("failed to create scratch dir");
+    dir
+}
+
+#[test]
+fn primary_groups_and_members_both_count() {
+    let dir = scratch_dir("membership");
+    let path = format!("{}/group", dir);
+    let mut groups = GroupDatabase::new(&path);
+    let alice = UserRecord::new("alice", LOCKED_HASH, 1000);
+    let bob = UserRecord::new("bob", LOCKED_HASH, 1001);
+
+    groups.add("alice", 1000).unwrap();
+    groups.add("wheel", FIRST_GID).unwrap();
+    groups.add_member("wheel", "bob").unwrap();
+    assert!(groups.is_member(&alice, "alice"));
+    assert!(!groups.is_member(&alice, "wheel"));
+    assert!(groups.is_member(&bob, "wheel"));
+    assert_eq!(
+        groups
+            .groups_of(&bob)
+            .iter()
+            .map(|g| g.name())
+            .collect::<Vec<_>>(),
+        vec!["wheel"]
+    );
+
+    assert!(matches!(
+        groups.add_member("wheel", "bob"),
+        Err(GroupError::AlreadyMember(_, _))
+    ));
+    assert!(matches!(
+        groups.remove_member("wheel", "alice"),
+        Err(GroupError::NotMember(_, _))
+    ));
+    assert!(matches!(
+        groups.add_member("staff", "bob"),
+        Err(GroupError::NotFound(_))
+    ));
+
+    // written ordered by gid, and read back the same
+    assert_eq!(
+        read_to_string(&path).unwrap(),
+        "alice:1000:\nwheel:10000:bob\n"
+    );
+    let reloaded = GroupDatabase::new(&path);
+    assert_eq!(reloaded.list(), groups.list());
+    groups.remove_member("wheel", "bob").unwrap();
+    assert!(!groups.is_member(&bob, "wheel"));
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn renames_and_deletions_follow_the_account() {
+    let dir = scratch_dir("rename");
+    let path = format!("{}/group", dir);
+    let mut groups = GroupDatabase::new(&path);
+    for name in ["wheel", "staff", "audit"] {
+        let gid = groups.next_gid().unwrap();
+        groups.add(name, gid).unwrap();
+    }
+    groups.add_member("wheel", "bob").unwrap();
+    groups.add_member("staff", "bob").unwrap();
+    groups.add_member("staff", "carol").unwrap();
+
+    assert_eq!(groups.rename_member("bob", "robert").unwrap(), 2);
+    assert_eq!(groups.rename_member("nobody", "somebody").unwrap(), 0);
+    let robert = UserRecord::new("robert", LOCKED_HASH, 1001);
+    assert_eq!(groups.groups_of(&robert).len(), 2);
+    assert_eq!(
+        GroupDatabase::new(&path).get("staff").unwrap().members(),
+        ["robert".to_string(), "carol".to_string()]
+    );
+
+    assert_eq!(groups.remove_user("robert").unwrap(), 2);
+    assert!(groups.groups_of(&robert).is_empty());
+    assert_eq!(
+        groups.get("staff").unwrap().members(),
+        ["carol".to_string()]
+    );
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn gids_and_names_are_checked() {
+    let dir = scratch_dir("gids");
+    let mut groups = GroupDatabase::new(&format!("{}/group", dir));
+    assert_eq!(groups.next_gid().unwrap(), FIRST_GID);
+    groups.add("wheel", FIRST_GID).unwrap();
+    assert_eq!(groups.next_gid().unwrap(), FIRST_GID + 1);
+
+    assert!(matches!(
+        groups.add("staff", FIRST_GID),
+        Err(GroupError::GidTaken(gid)) if gid == FIRST_GID
+    ));
+    assert!(matches!(
+        groups.add("wheel", FIRST_GID + 1),
+        Err(GroupError::AlreadyExists(_))
+    ));
+    for name in ["", "-wheel", "wh:eel", "wh,eel", "wh eel"] {
+        assert!(matches!(
+            groups.add(name, FIRST_GID + 1),
+            Err(GroupError::InvalidName(_))
+        ));
+    }
+
+    groups.add("last", u32::MAX).unwrap();
+    assert!(matches!(groups.next_gid(), Err(GroupError::NoFreeGid)));
+    groups.remove("last").unwrap();
+    assert!(matches!(
+        groups.remove("last"),
+        Err(GroupError::NotFound(_))
+    ));
+    remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,71 @@
+// tests/onion.rs
+//! Onion hash: wrapping stored hashes without the password, and the cap on its cost
+
+use credential_playground::auth_utils::*;
+
+/// base64 salts for the inner and outer layer
+const INNER_SALT: &str = "aW5uZXIgc2FsdA==";
+const OUTER_SALT: &str = "b3V0ZXIgc2FsdA==";
+
+#[test]
+fn wrapped_hashes_still_verify() {
+    let inner = hash_password("hunter2", INNER_SALT, 4);
+    let wrapped = wrap_hash(&inner, OUTER_SALT, 3).unwrap();
+    assert!(wrapped.starts_with(&format!("${}$3${}$", ONION_HASH_VERSION, OUTER_SALT)));
+    assert!(wrapped.ends_with(&format!("$4${}$", INNER_SALT)));
+    assert_eq!(validate_hash(&wrapped), Ok(()));
+
+    assert!(verify_password(&wrapped, "hunter2"));
+    assert!(!verify_password(&wrapped, "hunter3"));
+
+    // a password hashed with the onion hash directly verifies the same way
+    let direct = onion_hash_password("hunter2", INNER_SALT, 4);
+    assert!(verify_password(&direct, "hunter2"));
+    assert_ne!(direct, inner);
+}
+
+#[test]
+fn only_old_hashes_are_wrapped() {
+    let wrapped = wrap_hash(&hash_password("hunter2", INNER_SALT, 2), OUTER_SALT, 2).unwrap();
+    assert_eq!(
+        wrap_hash(&wrapped, OUTER_SALT, 2),
+        Err(HashError::UnknownVersion(ONION_HASH_VERSION.to_string()))
+    );
+    assert!(wrap_hash(LOCKED_HASH, OUTER_SALT, 2).is_err());
+    assert!(wrap_hash("$sha256iter-1$2$", OUTER_SALT, 2).is_err());
+}
+
+#[test]
+fn outer_cost_is_capped_at_2_to_the_20() {
+    assert_eq!(1u32 << MAX_ONION_COST, 1 << 20);
+    let inner = hash_password("hunter2", INNER_SALT, 2);
+    assert_eq!(
+        wrap_hash(&inner, OUTER_SALT, MAX_ONION_COST + 1),
+        Err(HashError::Cost(
+            (MAX_ONION_COST + 1).to_string(),
+            MAX_ONION_COST
+        ))
+    );
+
+    // a stored hash past the cap is refused before any work is done on it
+    let digest = base64_encode(&[0u8; 32]);
+    let onion = |outer: usize, inner: usize| {
+        format!(
+            "${}${}${}${}${}${}$",
+            ONION_HASH_VERSION, outer, OUTER_SALT, digest, inner, INNER_SALT
+        )
+    };
+    assert_eq!(validate_hash(&onion(MAX_ONION_COST, 2)), Ok(()));
+    assert_eq!(
+        validate_hash(&onion(MAX_ONION_COST + 1, 2)),
+        Err(HashError::Cost("21".to_string(), MAX_ONION_COST))
+    );
+    assert_eq!(
+        validate_hash(&onion(2, MAX_HASH_COST + 1)),
+        Err(HashError::Cost(
+            (MAX_HASH_COST + 1).to_string(),
+            MAX_HASH_COST
+        ))
+    );
+    assert!(!verify_password(&onion(u32::MAX as usize, 2), "hunter2"));
+}
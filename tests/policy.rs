@@ -0,0 +1,332 @@
+// tests/policy.rs
+//! Authorization policy: parsing, forbid beating permit, conditions on the context, loading the
+//! policy file, and what a permit lends the command it lets run
+
+use credential_playground::auth_utils::*;
+use credential_playground::authz::*;
+use credential_playground::config::Config;
+use credential_playground::daemon::open_environment;
+use credential_playground::policy::*;
+use credential_playground::session::AuthFactor;
+use credential_playground::shell::{CHPASS, Command, Environment, MKUSER, Ruling, run_command};
+use std::fs::{Permissions, create_dir_all, remove_dir_all, set_permissions, write};
+use std::os::unix::fs::PermissionsExt;
+
+/// creates an empty scratch directory unique to a test
+fn scratch_dir(name: &str) -> String {
+    let dir = std::env::temp_dir()
+        .join(format!("credplay-policy-{}-{}", name, std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    let _ = remove_dir_all(&dir);
+    create_dir_all(&dir).expect("failed to create scratch dir");
+    dir
+}
+
+/// writes a policy file with the given mode
+fn write_policy(path: &str, policy: &str, mode: u32) {
+    write(path, policy).unwrap();
+    set_permissions(path, Permissions::from_mode(mode)).unwrap();
+}
+
+/// an environment over a scratch directory holding root, alice, bob and carol, alice in helpdesk
+fn environment(dir: &str, policy: &str) -> Environment {
+    let mut config = Config::default();
+    config.dir = dir.to_string();
+    config.hash_cost = 4;
+    write_policy(&config.policy_path(), policy, 0o600);
+    let mut env = open_environment(&config).unwrap();
+    env.database
+        .transaction()
+        .set_record(UserRecord::new(ROOT, LOCKED_HASH, ROOT_UID))
+        .set("alice", LOCKED_HASH)
+        .set("bob", LOCKED_HASH)
+        .set("carol", LOCKED_HASH)
+        .commit()
+        .unwrap();
+    env.groups.add("helpdesk", FIRST_GID).unwrap();
+    env.groups.add_member("helpdesk", "alice").unwrap();
+    env
+}
+
+/// logs in as `user`, ending any session before
+fn login(env: &mut Environment, user: &str) {
+    assert!(env.start_session(user, &[AuthFactor::Password]));
+}
+
+/// a command line
+fn argv(words: &[&str]) -> Vec<String> {
+    words.iter().map(|word| word.to_string()).collect()
+}
+
+/// a handler succeeding only while the session holds `user.passwd.other`
+fn holds_passwd_other(env: &mut Environment, _argc: u8, _argv: &[String]) -> i8 {
+    match env.can(Capability::UserPasswdOther) {
+        true => 0,
+        false => 1,
+    }
+}
+
+const HELPDESK: &str = r#"
+permit (principal in "helpdesk", action == "chpass", resource) unless { resource == "root" };
+forbid (principal == "carol", action, resource);
+"#;
+
+/// monday 1970-01-05 09:00 UTC
+const MONDAY_MORNING: u64 = 4 * 86400 + 9 * 3600;
+
+/// saturday 1970-01-03 09:00 UTC
+const SATURDAY_MORNING: u64 = 2 * 86400 + 9 * 3600;
+
+/// alice is in helpdesk, nobody else in any group
+fn in_group(account: &str, group: &str) -> bool {
+    account == "alice" && group == "helpdesk"
+}
+
+/// a request at a point in time, from a session logged in with `factors`
+fn request(principal: &str, action: &str, resource: &str, time: u64, factors: &[&str]) -> Request {
+    Request {
+        principal: principal.to_string(),
+        action: action.to_string(),
+        resource: resource.to_string(),
+        context: Context::at(
+            time,
+            0,
+            factors.iter().map(|f| f.to_string()).collect(),
+            false,
+            60,
+        ),
+    }
+}
+
+/// the verdict of a policy on a request
+fn decide(policy: &str, request: &Request) -> Verdict {
+    Policy::parse(policy).unwrap().evaluate(request, in_group).0
+}
+
+const OFFICE_HOURS: &str = r#"
+# members of helpdesk may change passwords during office hours, except root's
+permit (principal in "helpdesk", action == "chpass", resource)
+  when { context.time >= "08:00" && context.time < "18:00" && !(context.weekday in ["sat", "sun"]) }
+  unless { resource == "root" };
+"#;
+
+#[test]
+fn parse_errors_name_the_line() {
+    for (text, line) in [
+        ("permit (principal, action, resource)", 1),
+        (
+            "\npermit (principal, action, resource);\nallow (principal, action, resource);",
+            3,
+        ),
+        ("forbid (principal, action == , resource);", 1),
+        (
+            "permit (principal, action, resource)\n  when { context.time >= };",
+            2,
+        ),
+        (
+            "permit (principal, action, resource) when { context.nothing };",
+            1,
+        ),
+    ] {
+        assert!(
+            matches!(Policy::parse(text), Err(PolicyError::Syntax(l, _)) if l == line),
+            "{:?} should fail on line {}",
+            text,
+            line
+        );
+    }
+    assert!(
+        Policy::parse("# no rules at all\n")
+            .unwrap()
+            .rules
+            .is_empty()
+    );
+}
+
+#[test]
+fn forbid_beats_permit() {
+    let policy = r#"
+permit (principal, action, resource);
+forbid (principal == "bob", action in ["export", "reset"], resource);
+"#;
+    let morning = MONDAY_MORNING;
+    assert_eq!(
+        decide(policy, &request("bob", "reset", "bob", morning, &[])),
+        Verdict::Forbidden(3)
+    );
+    assert_eq!(
+        decide(policy, &request("bob", "users", "bob", morning, &[])),
+        Verdict::Permitted(2)
+    );
+    assert_eq!(
+        decide(policy, &request("alice", "reset", "alice", morning, &[])),
+        Verdict::Permitted(2)
+    );
+}
+
+#[test]
+fn time_conditions_follow_the_clock() {
+    let chpass = |principal, resource, time| {
+        decide(
+            OFFICE_HOURS,
+            &request(principal, "chpass", resource, time, &["password"]),
+        )
+    };
+    assert_eq!(
+        chpass("alice", "bob", MONDAY_MORNING),
+        Verdict::Permitted(3)
+    );
+    assert_eq!(
+        chpass("alice", "bob", MONDAY_MORNING + 10 * 3600),
+        Verdict::NotCovered
+    );
+    assert_eq!(
+        chpass("alice", "bob", SATURDAY_MORNING),
+        Verdict::NotCovered
+    );
+    assert_eq!(chpass("alice", "root", MONDAY_MORNING), Verdict::NotCovered);
+    assert_eq!(chpass("bob", "carol", MONDAY_MORNING), Verdict::NotCovered);
+}
+
+#[test]
+fn factor_conditions_see_the_session() {
+    let policy = r#"
+forbid (principal, action == "export", resource)
+  unless { "password" in context.factors };
+"#;
+    assert_eq!(
+        decide(
+            policy,
+            &request("alice", "export", "out.json", MONDAY_MORNING, &["password"])
+        ),
+        Verdict::NotCovered
+    );
+    assert_eq!(
+        decide(
+            policy,
+            &request("alice", "export", "out.json", MONDAY_MORNING, &[])
+        ),
+        Verdict::Forbidden(2)
+    );
+}
+
+#[test]
+fn erroring_conditions_skip_permits_and_apply_forbids() {
+    // comparing a number with a string can't be evaluated
+    let permit = r#"permit (principal, action, resource) when { context.hour < "noon" };"#;
+    let forbid = r#"forbid (principal, action, resource) when { context.hour < "noon" };"#;
+    let req = request("alice", "users", "alice", MONDAY_MORNING, &[]);
+
+    let (verdict, outcomes) = Policy::parse(permit).unwrap().evaluate(&req, in_group);
+    assert_eq!(verdict, Verdict::NotCovered);
+    assert!(matches!(outcomes[0], Outcome::Error(_)));
+    assert_eq!(decide(forbid, &req), Verdict::Forbidden(1));
+}
+
+#[test]
+fn policy_files_must_not_be_writable_by_others() {
+    let dir = scratch_dir("load");
+    let path = format!("{}/authz.policy", dir);
+
+    // no file is a policy without rules
+    assert!(Policy::load(&path).unwrap().rules.is_empty());
+
+    write_policy(&path, HELPDESK, 0o640);
+    assert_eq!(Policy::load(&path).unwrap().rules.len(), 2);
+    for mode in [0o660, 0o646, 0o666] {
+        set_permissions(&path, Permissions::from_mode(mode)).unwrap();
+        assert!(matches!(
+            Policy::load(&path),
+            Err(PolicyError::Insecure(found)) if found == mode
+        ));
+    }
+    write_policy(&path, "permit (principal, action)", 0o600);
+    assert!(matches!(
+        Policy::load(&path),
+        Err(PolicyError::Syntax(1, _))
+    ));
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn the_environment_asks_the_policy_first() {
+    let dir = scratch_dir("authorize");
+    let mut env = environment(&dir, HELPDESK);
+
+    login(&mut env, "alice");
+    assert_eq!(
+        env.authorize(&CHPASS, &argv(&["chpass", "bob"])),
+        Ruling::Permitted(2)
+    );
+    // not covered, so the command's own access decides
+    assert_eq!(
+        env.authorize(&CHPASS, &argv(&["chpass", "root"])),
+        Ruling::Access(true)
+    );
+    assert_eq!(
+        env.authorize(&MKUSER, &argv(&["mkuser", "dave"])),
+        Ruling::Access(false)
+    );
+    login(&mut env, "carol");
+    assert_eq!(
+        env.authorize(&CHPASS, &argv(&["chpass"])),
+        Ruling::Forbidden(3)
+    );
+
+    // an unsafe policy file leaves only root and public commands
+    set_permissions(env.config.policy_path(), Permissions::from_mode(0o666)).unwrap();
+    login(&mut env, "alice");
+    assert!(matches!(
+        env.authorize(&CHPASS, &argv(&["chpass"])),
+        Ruling::Unusable(_, false)
+    ));
+    login(&mut env, ROOT);
+    assert!(matches!(
+        env.authorize(&CHPASS, &argv(&["chpass", "bob"])),
+        Ruling::Unusable(_, true)
+    ));
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn permits_lend_the_other_account_capability() {
+    let dir = scratch_dir("lend");
+    let mut env = environment(&dir, HELPDESK);
+    let probe = Command {
+        handler: holds_passwd_other,
+        ..CHPASS
+    };
+
+    login(&mut env, "alice");
+    assert_eq!(
+        env.lent(&CHPASS, &argv(&["chpass", "bob"])),
+        Some(Capability::UserPasswdOther)
+    );
+    assert_eq!(env.lent(&CHPASS, &argv(&["chpass"])), None);
+    assert_eq!(env.lent(&CHPASS, &argv(&["chpass", "alice"])), None);
+    assert_eq!(
+        env.lent(&MKUSER, &argv(&["mkuser", "dave"])),
+        Some(Capability::UserCreate)
+    );
+
+    // held while the command runs, and dropped afterwards
+    assert_eq!(
+        run_command(&mut env, &probe, 2, &argv(&["chpass", "bob"])),
+        0
+    );
+    assert_eq!(env.granted(), None);
+    assert!(!env.can(Capability::UserPasswdOther));
+
+    // nothing is lent without a permit
+    assert_eq!(
+        run_command(&mut env, &probe, 2, &argv(&["chpass", "root"])),
+        1
+    );
+    login(&mut env, "bob");
+    assert_eq!(
+        run_command(&mut env, &probe, 2, &argv(&["chpass", "alice"])),
+        1
+    );
+    remove_dir_all(&dir).unwrap();
+}
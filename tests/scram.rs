@@ -0,0 +1,276 @@
+// tests/scram.rs
+//! SCRAM-SHA-256 exchanges: the RFC 7677 example, failures and channel binding
+
+use credential_playground::auth_utils::base64_decode;
+use credential_playground::scram::*;
+
+/// the example exchange of RFC 7677 section 3
+const USER: &str = "user";
+const PASSWORD: &str = "pencil";
+const CLIENT_NONCE: &str = "rOprNGfwEbeRWgbNEkqO";
+const SERVER_NONCE: &str = "%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0";
+const SALT: &str = "W22ZaJ0SNY7soEsUEjb6gQ==";
+const CLIENT_FIRST: &str = "n,,n=user,r=rOprNGfwEbeRWgbNEkqO";
+const SERVER_FIRST: &str =
+    "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+const CLIENT_FINAL: &str = "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ=";
+const SERVER_FINAL: &str = "v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=";
+
+/// server secret made-up credentials are derived from
+const SECRET: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+/// the example user's credentials, as the server stores them
+fn credentials() -> ScramCredentials {
+    let salt = base64_decode(SALT).unwrap();
+    ScramCredentials::derive(PASSWORD, &salt, 4096).unwrap()
+}
+
+/// a fixed channel binding standing in for a TLS connection
+struct FakeTls(&'static str, &'static [u8]);
+
+impl ChannelBinding for FakeTls {
+    fn kind(&self) -> &str {
+        self.0
+    }
+    fn data(&self) -> Vec<u8> {
+        self.1.to_vec()
+    }
+}
+
+/// runs a whole exchange, returning the server's verdict and the client's
+fn exchange(
+    client: &mut ScramClient,
+    server: &mut ScramServer,
+    stored: Option<ScramCredentials>,
+) -> (Result<String, ScramError>, Result<(), ScramError>) {
+    let client_first = client.client_first().unwrap();
+    let server_first = match server.server_first(&client_first, |_| stored) {
+        Ok(message) => message,
+        Err(e) => return (Err(e.clone()), client.verify_server(&e.server_final())),
+    };
+    let client_final = client.client_final(&server_first).unwrap();
+    let server_final = server.server_final(&client_final);
+    let reply = match &server_final {
+        Ok(message) => message.clone(),
+        Err(e) => e.server_final(),
+    };
+    let verdict = client.verify_server(&reply);
+    (server_final, verdict)
+}
+
+#[test]
+fn rfc7677_client_messages() {
+    let mut client = ScramClient::with_nonce(USER, PASSWORD, None, CLIENT_NONCE);
+    assert_eq!(client.client_first().unwrap(), CLIENT_FIRST);
+    assert_eq!(client.client_final(SERVER_FIRST).unwrap(), CLIENT_FINAL);
+    assert_eq!(client.verify_server(SERVER_FINAL), Ok(()));
+}
+
+#[test]
+fn rfc7677_server_messages() {
+    let mut server = ScramServer::with_nonce(None, SECRET, 4096, SERVER_NONCE);
+    let server_first = server
+        .server_first(CLIENT_FIRST, |user| {
+            assert_eq!(user, USER);
+            Some(credentials())
+        })
+        .unwrap();
+    assert_eq!(server_first, SERVER_FIRST);
+    assert_eq!(server.username(), None);
+    assert_eq!(server.server_final(CLIENT_FINAL).unwrap(), SERVER_FINAL);
+    assert_eq!(server.username(), Some(USER));
+}
+
+#[test]
+fn stored_credentials_round_trip() {
+    let stored = credentials();
+    let text = stored.to_string();
+    assert!(text.starts_with("SCRAM-SHA-256$4096:W22ZaJ0SNY7soEsUEjb6gQ==$"));
+    assert_eq!(ScramCredentials::parse(&text), Ok(stored));
+    assert!(ScramCredentials::parse("SCRAM-SHA-256$4096:abc").is_err());
+}
+
+#[test]
+fn random_nonces_authenticate() {
+    let mut client = ScramClient::new(USER, PASSWORD, None);
+    let mut server = ScramServer::new(None, SECRET, 4096);
+    let stored = ScramCredentials::new(PASSWORD, DEF_SCRAM_ITERATIONS).unwrap();
+    let (server_final, verdict) = exchange(&mut client, &mut server, Some(stored));
+    assert!(server_final.is_ok());
+    assert_eq!(verdict, Ok(()));
+    assert_eq!(server.username(), Some(USER));
+}
+
+#[test]
+fn wrong_password_is_an_invalid_proof() {
+    let mut client = ScramClient::new(USER, "pen", None);
+    let mut server = ScramServer::new(None, SECRET, 4096);
+    let (server_final, verdict) = exchange(&mut client, &mut server, Some(credentials()));
+    assert_eq!(server_final, Err(ScramError::InvalidProof));
+    assert_eq!(
+        verdict,
+        Err(ScramError::Server("invalid-proof".to_string()))
+    );
+    assert_eq!(server.username(), None);
+}
+
+#[test]
+fn unknown_user_fails_like_a_wrong_password() {
+    let mut client = ScramClient::new("nobody", PASSWORD, None);
+    let mut server = ScramServer::new(None, SECRET, 4096);
+    let (server_final, _) = exchange(&mut client, &mut server, None);
+    assert_eq!(server_final, Err(ScramError::InvalidProof));
+}
+
+#[test]
+fn unknown_user_gets_the_same_salt_every_time() {
+    let challenge = |user: &str, secret: &[u8]| {
+        let client_first = format!("n,,n={},r={}", user, CLIENT_NONCE);
+        ScramServer::with_nonce(None, secret, 4096, SERVER_NONCE)
+            .server_first(&client_first, |_| None)
+            .unwrap()
+    };
+
+    // asking twice gives the same answer, as it does for a real account
+    let first = challenge("nobody", SECRET);
+    assert_eq!(first, challenge("nobody", SECRET));
+    assert_ne!(first, challenge("someone", SECRET));
+    assert_ne!(first, challenge("nobody", b"another server"));
+
+    // and the answer is shaped like a real account's
+    let salt = first
+        .split(",s=")
+        .nth(1)
+        .unwrap()
+        .split(',')
+        .next()
+        .unwrap();
+    assert_eq!(salt.len(), SALT.len());
+    assert!(first.ends_with(",i=4096"));
+}
+
+#[test]
+fn impostor_server_is_caught() {
+    // the server signature of the example, sent by a server that holds other credentials
+    let mut client = ScramClient::with_nonce(USER, "pen", None, CLIENT_NONCE);
+    client.client_first().unwrap();
+    client.client_final(SERVER_FIRST).unwrap();
+    assert_eq!(
+        client.verify_server(SERVER_FINAL),
+        Err(ScramError::ServerSignature)
+    );
+}
+
+#[test]
+fn client_checks_the_server_nonce_and_iterations() {
+    let mut client = ScramClient::with_nonce(USER, PASSWORD, None, CLIENT_NONCE);
+    client.client_first().unwrap();
+    assert_eq!(
+        client.client_final("r=somebodyelse,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096"),
+        Err(ScramError::Nonce)
+    );
+
+    let mut client = ScramClient::with_nonce(USER, PASSWORD, None, CLIENT_NONCE);
+    client.client_first().unwrap();
+    assert_eq!(
+        client.client_final("r=rOprNGfwEbeRWgbNEkqOx,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=1"),
+        Err(ScramError::Iterations(1))
+    );
+}
+
+#[test]
+fn steps_out_of_order_are_refused() {
+    let mut client = ScramClient::with_nonce(USER, PASSWORD, None, CLIENT_NONCE);
+    assert_eq!(
+        client.client_final(SERVER_FIRST),
+        Err(ScramError::OutOfOrder("client-final"))
+    );
+    // a failed exchange can't be resumed
+    assert!(client.client_first().is_err());
+
+    let mut server = ScramServer::with_nonce(None, SECRET, 4096, SERVER_NONCE);
+    assert!(server.server_final(CLIENT_FINAL).is_err());
+    assert!(
+        server
+            .server_first(CLIENT_FIRST, |_| Some(credentials()))
+            .is_err()
+    );
+}
+
+#[test]
+fn server_rejects_replayed_or_malformed_messages() {
+    // a replayed client-final carries the old nonce
+    let mut server = ScramServer::with_nonce(None, SECRET, 4096, "fresh");
+    server
+        .server_first(CLIENT_FIRST, |_| Some(credentials()))
+        .unwrap();
+    assert_eq!(server.server_final(CLIENT_FINAL), Err(ScramError::Nonce));
+
+    let mut server = ScramServer::new(None, SECRET, 4096);
+    let e = server
+        .server_first("n,,m=ext,n=user,r=abc", |_| Some(credentials()))
+        .unwrap_err();
+    assert_eq!(e.server_final(), "e=invalid-encoding");
+}
+
+#[test]
+fn usernames_are_escaped() {
+    let mut client = ScramClient::with_nonce("a,b=c", PASSWORD, None, CLIENT_NONCE);
+    let client_first = client.client_first().unwrap();
+    assert_eq!(client_first, "n,,n=a=2Cb=3Dc,r=rOprNGfwEbeRWgbNEkqO");
+    let mut server = ScramServer::new(None, SECRET, 4096);
+    server
+        .server_first(&client_first, |user| {
+            assert_eq!(user, "a,b=c");
+            None
+        })
+        .unwrap();
+}
+
+#[test]
+fn saslprep_maps_spaces_and_refuses_controls() {
+    assert_eq!(saslprep("a\u{00A0}b\u{00AD}c").unwrap(), "a bc");
+    assert_eq!(saslprep("\u{2168}").unwrap(), "IX");
+    assert_eq!(saslprep("a\u{0007}"), Err(ScramError::Prohibited));
+}
+
+#[test]
+fn channel_binding_is_checked() {
+    let binding = || Some(Box::new(FakeTls("tls-exporter", b"key")) as Box<dyn ChannelBinding>);
+
+    // both ends bound to the same channel
+    let mut client = ScramClient::new(USER, PASSWORD, binding());
+    let mut server = ScramServer::new(binding(), SECRET, 4096);
+    assert_eq!(server.mechanism(), SCRAM_PLUS_MECHANISM);
+    let (server_final, verdict) = exchange(&mut client, &mut server, Some(credentials()));
+    assert!(server_final.is_ok());
+    assert_eq!(verdict, Ok(()));
+
+    // a relay terminates the client's channel and opens its own to the server
+    let mut client = ScramClient::new(USER, PASSWORD, binding());
+    let relayed = Some(Box::new(FakeTls("tls-exporter", b"other")) as Box<dyn ChannelBinding>);
+    let mut server = ScramServer::new(relayed, SECRET, 4096);
+    let (server_final, _) = exchange(&mut client, &mut server, Some(credentials()));
+    assert_eq!(
+        server_final,
+        Err(ScramError::ChannelBinding("channel-bindings-dont-match"))
+    );
+
+    // a binding the server's connection doesn't have
+    let mut client = ScramClient::new(USER, PASSWORD, binding());
+    let mut server = ScramServer::new(None, SECRET, 4096);
+    let (server_final, _) = exchange(&mut client, &mut server, Some(credentials()));
+    assert_eq!(
+        server_final,
+        Err(ScramError::ChannelBinding("channel-binding-not-supported"))
+    );
+
+    // a client claiming the server offered no binding, when it did, was downgraded
+    let mut server = ScramServer::new(binding(), SECRET, 4096);
+    assert_eq!(
+        server.server_first("y,,n=user,r=abc", |_| Some(credentials())),
+        Err(ScramError::ChannelBinding(
+            "server-does-support-channel-binding"
+        ))
+    );
+}
@@ -0,0 +1,70 @@
+// tests/seal.rs
+//! Integrity seal of the passwd and shadow files: edits made behind the program's back lock it down
+
+use credential_playground::auth_utils::*;
+use std::fs::{create_dir_all, metadata, read_to_string, remove_dir_all, remove_file, write};
+
+/// creates an empty scratch directory unique to a test
+fn scratch_dir(name: &str) -> String {
+    let dir = std::env::temp_dir()
+        .join(format!("credplay-seal-{}-{}", name, std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    let _ = remove_dir_all(&dir);
+    create_dir_all(&dir).expect("failed to create scratch dir");
+    dir
+}
+
+#[test]
+fn tampered_files_only_let_root_in_with_its_sealed_password() {
+    let dir = scratch_dir("tampered");
+    let passwd = format!("{}/passwd", dir);
+    let shadow = format!("{}/shadow", dir);
+    UserCredentials::new(&passwd, &shadow)
+        .set_record(UserRecord::new(
+            ROOT,
+            &hash_password("rootpass", &get_salt(None), 1),
+            ROOT_UID,
+        ))
+        .set("alice", &hash_password("alicepass", &get_salt(None), 1));
+
+    // swap in a root hash for a password of the attacker's choosing
+    let forged = hash_password("forged", &get_salt(None), 1);
+    let contents = read_to_string(&shadow).unwrap();
+    let root_line = contents.lines().find(|l| l.starts_with("root:")).unwrap();
+    let hash = root_line.split(':').nth(1).unwrap();
+    write(&shadow, contents.replace(hash, &forged)).unwrap();
+
+    let database = UserCredentials::new(&passwd, &shadow);
+    assert!(database.tampered());
+    assert!(!database.authenticate(ROOT, "forged"));
+    assert!(!database.authenticate("alice", "alicepass"));
+    assert!(database.authenticate(ROOT, "rootpass"));
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn database_without_seal_or_key_is_sealed_on_first_load() {
+    let dir = scratch_dir("unsealed");
+    let passwd = format!("{}/passwd", dir);
+    let shadow = format!("{}/shadow", dir);
+    let hash = hash_password("hunter2", &get_salt(None), 1);
+    UserCredentials::new(&passwd, &shadow)
+        .set_record(UserRecord::new(ROOT, &hash, ROOT_UID))
+        .set("alice", &hash);
+
+    // an install from before sealing existed has neither file
+    remove_file(format!("{}{}", passwd, SEAL_SUFFIX)).unwrap();
+    remove_file(format!("{}/{}", dir, SEAL_KEY_FILE)).unwrap();
+    let database = UserCredentials::new(&passwd, &shadow);
+    assert!(!database.tampered());
+    assert!(database.authenticate("alice", "hunter2"));
+    assert!(metadata(format!("{}{}", passwd, SEAL_SUFFIX)).is_ok());
+
+    // losing the seal once it exists is still tampering
+    remove_file(format!("{}{}", passwd, SEAL_SUFFIX)).unwrap();
+    let database = UserCredentials::new(&passwd, &shadow);
+    assert!(database.tampered());
+    assert!(!database.authenticate("alice", "hunter2"));
+    remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,170 @@
+// tests/session.rs
+//! Session table: a session survives validation only as the shell that started it knows it,
+//! and times out when idle or too old, unless locked while idle
+
+use credential_playground::auth_utils::*;
+use credential_playground::config::Config;
+use credential_playground::daemon::open_environment;
+use credential_playground::session::*;
+use credential_playground::shell::{Environment, NULLUSER, on_timeout};
+use std::fs::{create_dir_all, read_to_string, remove_dir_all, write};
+
+/// creates an empty scratch directory unique to a test
+fn scratch_dir(name: &str) -> String {
+    let dir = std::env::temp_dir()
+        .join(format!("credplay-session-{}-{}", name, std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    let _ = remove_dir_all(&dir);
+    create_dir_all(&dir).expect("failed to create scratch dir");
+    dir
+}
+
+/// a session logged in at `created` and last active then, with the default timeouts
+fn session_at(created: u64) -> Session {
+    let mut session = Session::new("alice", &[AuthFactor::Password], 900, 28800).unwrap();
+    session.created = created;
+    session.last_active = created;
+    session
+}
+
+/// an environment logged in as root, then `su` to alice, both started `ago` seconds ago and idle
+/// since
+fn idle_stack(dir: &str, idle_action: &str, ago: u64) -> Environment {
+    let mut config = Config::default();
+    config.dir = dir.to_string();
+    config.idle_action = idle_action.to_string();
+    let mut env = open_environment(&config).unwrap();
+    env.database
+        .transaction()
+        .set_record(UserRecord::new(ROOT, LOCKED_HASH, ROOT_UID))
+        .set("alice", LOCKED_HASH)
+        .commit()
+        .unwrap();
+    assert!(env.start_session(ROOT, &[AuthFactor::Password]));
+    assert!(env.push_session("alice", &[AuthFactor::Password], false));
+    let sessions = env
+        .stack
+        .iter_mut()
+        .map(|frame| &mut frame.session)
+        .chain(env.session.as_mut());
+    for session in sessions {
+        session.created -= ago;
+        session.last_active -= ago;
+        env.sessions.save(session).unwrap();
+    }
+    env
+}
+
+#[test]
+fn validation_keeps_the_callers_identity() {
+    let dir = scratch_dir("validate");
+    let table = SessionTable::new(&format!("{}/sessions.json", dir));
+    let session = Session::new("alice", &[AuthFactor::Password], 900, 0).unwrap();
+    table.insert(&session).unwrap();
+
+    let validated = table.validate(&session).unwrap();
+    assert_eq!(validated.user, "alice");
+    assert!(validated.same_identity(&session));
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn edited_session_row_ends_the_session() {
+    let dir = scratch_dir("edited");
+    let path = format!("{}/sessions.json", dir);
+    let table = SessionTable::new(&path);
+    let session = Session::new("alice", &[AuthFactor::Password], 900, 0).unwrap();
+    table.insert(&session).unwrap();
+
+    // the table is not sealed, so anyone who can write it could claim to be root
+    let edited = read_to_string(&path)
+        .unwrap()
+        .replace("\"alice\"", "\"root\"");
+    write(&path, edited).unwrap();
+    assert!(matches!(
+        table.validate(&session),
+        Err(SessionError::Mismatch)
+    ));
+    assert!(matches!(
+        table.validate(&session),
+        Err(SessionError::Revoked)
+    ));
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn huge_timeouts_never_time_out() {
+    let mut session = Session::new("alice", &[AuthFactor::Password], u64::MAX, u64::MAX).unwrap();
+    assert_eq!(session.deadline(), Some(u64::MAX));
+    assert!(session.check(u64::MAX).is_ok());
+
+    session.lifetime = 60;
+    assert_eq!(session.deadline(), Some(session.created + 61));
+}
+
+#[test]
+fn the_first_timeout_is_the_deadline() {
+    // idle first
+    let mut session = session_at(1000);
+    assert_eq!(session.deadline(), Some(1901));
+    assert!(session.check(1900).is_ok());
+    assert!(matches!(session.check(1901), Err(SessionError::Idle(900))));
+
+    // a command just before the lifetime is up leaves the lifetime first
+    session.last_active = 29500;
+    assert_eq!(session.deadline(), Some(29801));
+    assert!(session.check(29800).is_ok());
+    assert!(matches!(
+        session.check(29801),
+        Err(SessionError::Expired(28800))
+    ));
+
+    // a locked session is never idle, but still expires
+    session.last_active = 1000;
+    session.locked = true;
+    assert_eq!(session.deadline(), Some(29801));
+    assert!(session.check(20000).is_ok());
+    assert!(matches!(
+        session.check(29801),
+        Err(SessionError::Expired(28800))
+    ));
+
+    session.lifetime = 0;
+    assert_eq!(session.deadline(), None);
+    session.locked = false;
+    session.idle_timeout = 0;
+    assert_eq!(session.deadline(), None);
+    assert!(session.check(u64::MAX).is_ok());
+}
+
+#[test]
+fn idle_stacks_are_locked_or_logged_out() {
+    let dir = scratch_dir("idle");
+    let mut env = idle_stack(&dir, "lock", 1000);
+    assert!(env.deadline().unwrap() <= now());
+
+    // every session of the stack is locked, in the table too, and stays logged in
+    on_timeout(&mut env);
+    assert_eq!((env.user(), env.depth()), ("alice", 1));
+    assert!(env.session.as_ref().unwrap().locked);
+    assert!(env.stack[0].session.locked);
+    assert!(env.sessions.list().unwrap().iter().all(|s| s.locked));
+    assert!(env.deadline().unwrap() > now());
+    assert!(env.validate_session().is_ok());
+
+    let mut env = idle_stack(&dir, "logout", 1000);
+    on_timeout(&mut env);
+    assert_eq!((env.user(), env.depth()), (NULLUSER, 0));
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn expired_stacks_are_logged_out_even_when_locking() {
+    let dir = scratch_dir("expired");
+    let mut env = idle_stack(&dir, "lock", 30000);
+    on_timeout(&mut env);
+    assert_eq!((env.user(), env.depth()), (NULLUSER, 0));
+    assert!(env.sessions.list().unwrap().is_empty());
+    remove_dir_all(&dir).unwrap();
+}
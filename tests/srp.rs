@@ -0,0 +1,306 @@
+// tests/srp.rs
+//! SRP-6a exchanges: the RFC 5054 appendix B vectors, the groups, failures and the big numbers
+
+use credential_playground::auth_utils::sha1;
+use credential_playground::bignum::BigUint;
+use credential_playground::srp::*;
+
+/// the example of RFC 5054 appendix B, with the 1024-bit group and SHA-1
+const USER: &str = "alice";
+const PASSWORD: &str = "password123";
+const SALT: &str = "BEB25379D1A8581EB5A727673A2441EE";
+const K: &str = "7556AA045AEF2CDD07ABAF0F665C3E818913186F";
+const X: &str = "94B7555AABE9127CC58CCF4993DB6CF84D16C124";
+const V: &str = "7E273DE8696FFC4F4E337D05B4B375BEB0DDE1569E8FA00A9886D812
+    9BADA1F1822223CA1A605B530E379BA4729FDC59F105B4787E5186F5
+    C671085A1447B52A48CF1970B4FB6F8400BBF4CEBFBB168152E08AB5
+    EA53D15C1AFF87B2B9DA6E04E058AD51CC72BFC9033B564E26480D78
+    E955A5E29E7AB245DB2BE315E2099AFB";
+const A_PRIV: &str = "60975527035CF2AD1989806F0407210BC81EDC04E2762A56AFD529DDDA2D4393";
+const B_PRIV: &str = "E487CB59D31AC550471E81F00F6928E01DDA08E974A004F49E61F5D105284D20";
+const A_PUB: &str = "61D5E490F6F1B79547B0704C436F523DD0E560F0C64115BB72557EC4
+    4352E8903211C04692272D8B2D1A5358A2CF1B6E0BFCF99F921530EC
+    8E39356179EAE45E42BA92AEACED825171E1E8B9AF6D9C03E1327F44
+    BE087EF06530E69F66615261EEF54073CA11CF5858F0EDFDFE15EFEA
+    B349EF5D76988A3672FAC47B0769447B";
+const B_PUB: &str = "BD0C61512C692C0CB6D041FA01BB152D4916A1E77AF46AE105393011
+    BAF38964DC46A0670DD125B95A981652236F99D9B681CBF87837EC99
+    6C6DA04453728610D0C6DDB58B318885D7D82C7F8DEB75CE7BD4FBAA
+    37089E6F9C6059F388838E7A00030B331EB76840910440B1B27AAEAE
+    EB4012B7D7665238A8E3FB004B117B58";
+const U: &str = "CE38B9593487DA98554ED47D70A7AE5F462EF019";
+const PREMASTER: &str = "B0DC82BABCF30674AE450C0287745E7990A3381F63B387AAF271A10D
+    233861E359B48220F7C4693C9AE12B0A6F67809F0876E2D013800D6C
+    41BB59B6D5979B5C00A172B4A2A5903A0BDCAF8A709585EB2AFAFA8F
+    3499B200210DCC1F10EB33943CD67FC88A2F39A4BE5BEC4EC0A3212D
+    C346D7E474B29EDE8A469FFECA686E5A";
+
+/// server key made-up verifiers are derived from
+const KEY: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+/// reads a hex test vector as a number
+fn num(hex: &str) -> BigUint {
+    BigUint::from_hex(hex).expect("bad test vector")
+}
+
+/// the example user's verifier, as the server stores it
+fn verifier() -> SrpVerifier {
+    let salt = hex::decode(SALT).unwrap();
+    SrpVerifier::derive(SrpHash::Sha1, 1024, USER, PASSWORD, &salt).unwrap()
+}
+
+/// runs a whole exchange, returning the server's verdict and the client's
+fn exchange(
+    client: &mut SrpClient,
+    server: &mut SrpServer,
+    stored: Option<SrpVerifier>,
+) -> (Result<Vec<u8>, SrpError>, Result<(), SrpError>) {
+    let username = client.client_hello().unwrap();
+    let challenge = server.server_challenge(&username, |_| stored).unwrap();
+    let proof = client.client_proof(&challenge).unwrap();
+    let m2 = server.server_verify(&proof);
+    let verdict = match &m2 {
+        Ok(m2) => client.verify_server(m2),
+        Err(e) => Err(e.clone()),
+    };
+    (m2, verdict)
+}
+
+#[test]
+fn rfc5054_parameters() {
+    let group = SrpGroup::rfc5054(1024).unwrap();
+    let salt = hex::decode(SALT).unwrap();
+    let x = private_key(SrpHash::Sha1, &salt, USER, PASSWORD);
+    assert_eq!(multiplier(SrpHash::Sha1, &group), num(K));
+    assert_eq!(x, num(X));
+    assert_eq!(verifier().verifier, num(V));
+
+    let u = scrambler(SrpHash::Sha1, &group, &num(A_PUB), &num(B_PUB));
+    assert_eq!(u, num(U));
+    let client = client_premaster(SrpHash::Sha1, &group, &num(B_PUB), &num(A_PRIV), &x, &u);
+    let server = server_premaster(&group, &num(A_PUB), &num(V), &u, &num(B_PRIV));
+    assert_eq!(client, num(PREMASTER));
+    assert_eq!(server, num(PREMASTER));
+}
+
+#[test]
+fn rfc5054_exchange() {
+    let mut client = SrpClient::with_secret(USER, PASSWORD, num(A_PRIV));
+    let mut server = SrpServer::with_secret(KEY, DEF_SRP_GROUP, num(B_PRIV));
+    assert_eq!(client.client_hello().unwrap(), USER);
+    let challenge = server
+        .server_challenge(USER, |user| {
+            assert_eq!(user, USER);
+            Some(verifier())
+        })
+        .unwrap();
+    assert_eq!(challenge.b_pub, num(B_PUB));
+    let proof = client.client_proof(&challenge).unwrap();
+    assert_eq!(proof.a_pub, num(A_PUB));
+
+    let m2 = server.server_verify(&proof).unwrap();
+    assert_eq!(client.verify_server(&m2), Ok(()));
+    assert_eq!(server.username(), Some(USER));
+
+    // K = H(PAD(S)) on both sides
+    let group = SrpGroup::rfc5054(1024).unwrap();
+    let key = session_key(SrpHash::Sha1, &group, &num(PREMASTER));
+    assert_eq!(client.session_key(), Some(key.as_slice()));
+    assert_eq!(server.session_key(), Some(key.as_slice()));
+}
+
+#[test]
+fn groups_are_safe_primes() {
+    // a single round is slow enough on these sizes, and catches a mistyped constant
+    for bits in SRP_GROUPS {
+        let group = SrpGroup::rfc5054(bits).unwrap();
+        assert_eq!(group.n.bits(), bits as usize);
+        assert!(group.n.is_probable_prime(1), "{}-bit N", bits);
+        let q = group
+            .n
+            .sub(&BigUint::from_u32(1))
+            .div_rem(&BigUint::from_u32(2))
+            .0;
+        assert!(q.is_probable_prime(1), "{}-bit (N-1)/2", bits);
+    }
+    assert_eq!(SrpGroup::rfc5054(512), Err(SrpError::UnknownGroup(512)));
+}
+
+#[test]
+fn random_secrets_agree_on_a_key() {
+    let stored = SrpVerifier::new(DEF_SRP_GROUP, USER, PASSWORD).unwrap();
+    let mut client = SrpClient::new(USER, PASSWORD);
+    let mut server = SrpServer::new(KEY, DEF_SRP_GROUP);
+    let (m2, verdict) = exchange(&mut client, &mut server, Some(stored));
+    assert!(m2.is_ok());
+    assert_eq!(verdict, Ok(()));
+    assert!(client.session_key().is_some());
+    assert_eq!(client.session_key(), server.session_key());
+}
+
+#[test]
+fn stored_verifier_round_trip() {
+    let stored = verifier();
+    let text = stored.to_string();
+    assert!(text.starts_with("SRP-6a$sha1$1024$vrJTedGoWB61pydnOiRB7g==$"));
+    assert_eq!(SrpVerifier::parse(&text), Ok(stored));
+    assert!(SrpVerifier::parse("SRP-6a$sha1$1024$abc").is_err());
+    assert_eq!(
+        SrpVerifier::parse("SRP-6a$md5$1024$vrJTedGoWB61pydnOiRB7g==$AQ=="),
+        Err(SrpError::UnknownHash("md5".to_string()))
+    );
+}
+
+#[test]
+fn wrong_password_is_an_invalid_proof() {
+    let mut client = SrpClient::new(USER, "password124");
+    let mut server = SrpServer::new(KEY, DEF_SRP_GROUP);
+    let (m2, _) = exchange(&mut client, &mut server, Some(verifier()));
+    assert_eq!(m2, Err(SrpError::InvalidProof));
+    assert_eq!(server.username(), None);
+    assert_eq!(server.session_key(), None);
+}
+
+#[test]
+fn verifier_is_bound_to_the_username() {
+    let mut client = SrpClient::new("bob", PASSWORD);
+    let mut server = SrpServer::new(KEY, DEF_SRP_GROUP);
+    let (m2, _) = exchange(&mut client, &mut server, Some(verifier()));
+    assert_eq!(m2, Err(SrpError::InvalidProof));
+}
+
+#[test]
+fn unknown_user_fails_like_a_wrong_password() {
+    let mut client = SrpClient::new("nobody", PASSWORD);
+    let mut server = SrpServer::new(KEY, DEF_SRP_GROUP);
+    let (m2, _) = exchange(&mut client, &mut server, None);
+    assert_eq!(m2, Err(SrpError::InvalidProof));
+}
+
+#[test]
+fn unknown_user_gets_the_same_salt_every_time() {
+    let challenge = |user: &str, key: &[u8]| {
+        SrpServer::new(key, 1024)
+            .server_challenge(user, |_| None)
+            .unwrap()
+    };
+
+    // asking twice gives the same salt, as it does for a real account, and a fresh B
+    let first = challenge("nobody", KEY);
+    let second = challenge("nobody", KEY);
+    assert_eq!(first.salt, second.salt);
+    assert_ne!(first.b_pub, second.b_pub);
+    assert_ne!(first.salt, challenge("someone", KEY).salt);
+    assert_ne!(first.salt, challenge("nobody", b"another server").salt);
+
+    // and the challenge is shaped like one for a real account
+    let real = SrpVerifier::new(1024, USER, PASSWORD).unwrap();
+    assert_eq!(first.salt.len(), real.salt.len());
+    assert_eq!((first.hash, first.group), (real.hash, real.group));
+}
+
+#[test]
+fn impostor_server_is_caught() {
+    let mut client = SrpClient::with_secret(USER, PASSWORD, num(A_PRIV));
+    let mut server = SrpServer::with_secret(KEY, DEF_SRP_GROUP, num(B_PRIV));
+    client.client_hello().unwrap();
+    let challenge = server.server_challenge(USER, |_| Some(verifier())).unwrap();
+    client.client_proof(&challenge).unwrap();
+    assert_eq!(client.verify_server(&[0u8; 20]), Err(SrpError::ServerProof));
+    assert_eq!(client.session_key(), None);
+}
+
+#[test]
+fn zero_public_values_are_refused() {
+    // B = N would make the client's premaster secret predictable
+    let group = SrpGroup::rfc5054(1024).unwrap();
+    let mut client = SrpClient::new(USER, PASSWORD);
+    client.client_hello().unwrap();
+    let challenge = SrpChallenge {
+        hash: SrpHash::Sha1,
+        group: 1024,
+        salt: hex::decode(SALT).unwrap(),
+        b_pub: group.n.clone(),
+    };
+    assert_eq!(
+        client.client_proof(&challenge),
+        Err(SrpError::IllegalParameter("B"))
+    );
+
+    // A = 0 makes S = 0 whatever the password, letting anyone in
+    let mut server = SrpServer::new(KEY, DEF_SRP_GROUP);
+    server.server_challenge(USER, |_| Some(verifier())).unwrap();
+    let forged = SrpProof {
+        a_pub: BigUint::zero(),
+        m1: vec![0u8; 20],
+    };
+    assert_eq!(
+        server.server_verify(&forged),
+        Err(SrpError::IllegalParameter("A"))
+    );
+}
+
+#[test]
+fn steps_out_of_order_are_refused() {
+    let mut client = SrpClient::new(USER, PASSWORD);
+    assert_eq!(
+        client.verify_server(&[0u8; 20]),
+        Err(SrpError::OutOfOrder("server proof check"))
+    );
+    // a failed exchange can't be resumed
+    assert!(client.client_hello().is_err());
+
+    let mut server = SrpServer::new(KEY, DEF_SRP_GROUP);
+    let proof = SrpProof {
+        a_pub: BigUint::from_u32(2),
+        m1: Vec::new(),
+    };
+    assert!(server.server_verify(&proof).is_err());
+    assert!(server.server_challenge(USER, |_| Some(verifier())).is_err());
+}
+
+#[test]
+fn big_number_arithmetic() {
+    let a = num(&"0123456789abcdef".repeat(9));
+    let d = num(&format!("{}1", "fedcba9876543210".repeat(3)));
+    let (q, r) = a.div_rem(&d);
+    assert_eq!(
+        q.to_string(),
+        "1249249249249237ec687d6343eb1a2093af64c20d6f34fdbd722a6f86e08bd34c464e5257c3ed0d3ae85005f4147f"
+    );
+    assert_eq!(
+        r.to_string(),
+        "613b667c85b07d90c064d744ba73c2f22e3b78b6294d65a70"
+    );
+    assert_eq!(q.mul(&d).add(&r), a);
+
+    let m = num("ffffffffffffffffffffffffffffff61");
+    let p = num("1234567890abcdef").mod_pow(&num("deadbeefcafebabe1234"), &m);
+    assert_eq!(p.to_string(), "2321ecf59b89f3912642cdc5ec40e613");
+
+    assert_eq!(
+        BigUint::from_bytes_be(&[0, 0, 1, 2]).to_bytes_be(),
+        vec![1, 2]
+    );
+    assert_eq!(
+        BigUint::from_u32(258).to_bytes_be_padded(4),
+        vec![0, 0, 1, 2]
+    );
+    assert_eq!(
+        BigUint::from_u32(5).checked_sub(&BigUint::from_u32(6)),
+        None
+    );
+    assert!(!num("ffffffffffffffffffffffffffffff63").is_probable_prime(8));
+}
+
+#[test]
+fn sha1_known_answers() {
+    assert_eq!(
+        hex::encode(sha1(b"abc".to_vec())),
+        "a9993e364706816aba3e25717850c26c9cd0d89d"
+    );
+    assert_eq!(
+        hex::encode(sha1(vec![b'a'; 1000])),
+        "291e9a6c66994949b57ba5e650361e98fc36b1ba"
+    );
+}
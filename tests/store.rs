@@ -0,0 +1,236 @@
+// tests/store.rs
+//! Credential stores: uid allocation, transactions and concurrent writers
+
+use credential_playground::auth_utils::*;
+use credential_playground::dbcheck::Problem;
+use std::fs::{create_dir_all, remove_dir_all, write};
+
+/// creates an empty scratch directory unique to a test
+fn scratch_dir(name: &str) -> String {
+    let dir = std::env::temp_dir()
+        .join(format!("credplay-store-{}-{}", name, std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    let _ = remove_dir_all(&dir);
+    create_dir_all(&dir).expect("failed to create scratch dir");
+    dir
+}
+
+#[test]
+fn uids_run_out_instead_of_wrapping() {
+    let mut store = MemoryStore::new();
+    store
+        .transaction()
+        .set_record(UserRecord::new("max", LOCKED_HASH, u32::MAX - 1))
+        .commit()
+        .unwrap();
+    assert_eq!(store.next_uid().unwrap(), u32::MAX);
+    store
+        .transaction()
+        .set("last", LOCKED_HASH)
+        .commit()
+        .unwrap();
+    assert_eq!(store.get("last").unwrap().uid(), u32::MAX);
+
+    assert!(matches!(store.next_uid(), Err(StoreError::NoFreeUid)));
+    assert!(matches!(
+        store.transaction().set("alice", LOCKED_HASH).commit(),
+        Err(StoreError::NoFreeUid)
+    ));
+    assert!(!store.contains("alice"));
+}
+
+#[test]
+fn legacy_records_past_the_last_uid_are_reported() {
+    let dir = scratch_dir("legacy-uid");
+    let (passwd, shadow) = (format!("{}/passwd", dir), format!("{}/shadow", dir));
+    write(
+        &passwd,
+        format!(
+            "{}\nold:{}\n",
+            UserRecord::new("max", SHADOW_PLACEHOLDER, u32::MAX).record_line(),
+            LOCKED_HASH
+        ),
+    )
+    .unwrap();
+    write(&shadow, format!("max:{}:::::::\n", LOCKED_HASH)).unwrap();
+
+    let mut store = UserCredentials::new(&passwd, &shadow);
+    assert!(store.contains("max"));
+    assert!(!store.contains("old"));
+    assert!(
+        store
+            .check()
+            .unwrap()
+            .iter()
+            .any(|d| d.problem == Problem::NoFreeUid("old".to_string()))
+    );
+    remove_dir_all(&dir).unwrap();
+}
+
+/// every record of a store as stored, sorted so stores can be compared
+fn snapshot<S: CredentialStore>(store: &S) -> Vec<String> {
+    let mut lines: Vec<String> = store
+        .list_users()
+        .iter()
+        .filter_map(|u| store.get(u))
+        .map(|u| u.record_line())
+        .collect();
+    lines.sort();
+    lines
+}
+
+/// runs failing and succeeding transactions against a store
+/// # Arguments
+/// * `store` - empty store to use
+/// * `reopen` - loads the store again from wherever it keeps its records
+fn check_transactions<S: CredentialStore>(mut store: S, reopen: impl Fn() -> S) {
+    store
+        .transaction()
+        .set(ROOT, LOCKED_HASH)
+        .set("alice", LOCKED_HASH)
+        .set("bob", LOCKED_HASH)
+        .commit()
+        .unwrap();
+    let before = snapshot(&store);
+    let alice = store.get("alice").unwrap().uid();
+
+    // a later op failing undoes the ones before it, in memory and on disk
+    assert!(matches!(
+        store
+            .transaction()
+            .set("carol", LOCKED_HASH)
+            .rename("alice", "dave")
+            .remove("nobody")
+            .commit(),
+        Err(StoreError::NotFound(username)) if username == "nobody"
+    ));
+    assert_eq!(snapshot(&store), before, "{}", store.backend());
+    assert_eq!(snapshot(&reopen()), before, "{}", store.backend());
+
+    // renaming onto an existing account fails and changes nothing
+    assert!(matches!(
+        store.transaction().rename("alice", "bob").commit(),
+        Err(StoreError::AlreadyExists(username)) if username == "bob"
+    ));
+    assert_eq!(snapshot(&store), before, "{}", store.backend());
+    assert_eq!(snapshot(&reopen()), before, "{}", store.backend());
+
+    // a rename on its own goes through and keeps the uid
+    store
+        .transaction()
+        .rename("alice", "dave")
+        .commit()
+        .unwrap();
+    let store = reopen();
+    assert!(!store.contains("alice"));
+    assert_eq!(store.get("dave").unwrap().uid(), alice);
+}
+
+#[test]
+fn failed_transactions_leave_every_backend_unchanged() {
+    let dir = scratch_dir("transactions");
+    let (passwd, shadow) = (format!("{}/passwd", dir), format!("{}/shadow", dir));
+    let (kv, enc) = (
+        format!("{}/accounts.kv", dir),
+        format!("{}/accounts.enc", dir),
+    );
+    let secret = b"correct horse battery staple";
+
+    check_transactions(UserCredentials::new(&passwd, &shadow), || {
+        UserCredentials::new(&passwd, &shadow)
+    });
+    check_transactions(KvStore::new(&kv), || KvStore::new(&kv));
+    check_transactions(EncryptedStore::open(&enc, secret).unwrap(), || {
+        EncryptedStore::open(&enc, secret).unwrap()
+    });
+    remove_dir_all(&dir).unwrap();
+
+    // the memory store has nothing to reload from, so each check looks at the store itself
+    let mut memory = MemoryStore::new();
+    memory
+        .transaction()
+        .set("alice", LOCKED_HASH)
+        .set("bob", LOCKED_HASH)
+        .commit()
+        .unwrap();
+    let before = snapshot(&memory);
+    assert!(
+        memory
+            .transaction()
+            .set("carol", LOCKED_HASH)
+            .remove("nobody")
+            .commit()
+            .is_err()
+    );
+    assert!(
+        memory
+            .transaction()
+            .rename("alice", "bob")
+            .commit()
+            .is_err()
+    );
+    assert_eq!(snapshot(&memory), before);
+}
+
+#[test]
+fn root_can_not_be_renamed() {
+    let mut store = MemoryStore::new();
+    store.transaction().set(ROOT, LOCKED_HASH).commit().unwrap();
+    assert!(matches!(
+        store.transaction().rename(ROOT, "toor").commit(),
+        Err(StoreError::Protected(username)) if username == ROOT
+    ));
+    assert!(store.contains(ROOT));
+    assert!(!store.contains("toor"));
+}
+
+#[test]
+fn concurrent_writers_keep_each_others_changes() {
+    let dir = scratch_dir("concurrent");
+    let (passwd, shadow) = (format!("{}/passwd", dir), format!("{}/shadow", dir));
+    UserCredentials::new(&passwd, &shadow)
+        .transaction()
+        .set(ROOT, LOCKED_HASH)
+        .commit()
+        .unwrap();
+
+    // both stores load the same files, then race to commit, each under the `.pwd.lock`
+    let barrier = std::sync::Arc::new(std::sync::Barrier::new(2));
+    let writers: Vec<_> = ["alice", "bob"]
+        .into_iter()
+        .map(|username| {
+            let (passwd, shadow, barrier) = (passwd.clone(), shadow.clone(), barrier.clone());
+            std::thread::spawn(move || {
+                let mut store = UserCredentials::new(&passwd, &shadow);
+                barrier.wait();
+                for n in 0..5 {
+                    store
+                        .transaction()
+                        .set(&format!("{}{}", username, n), LOCKED_HASH)
+                        .commit()
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    let store = UserCredentials::new(&passwd, &shadow);
+    assert!(!store.tampered());
+    for n in 0..5 {
+        assert!(store.contains(&format!("alice{}", n)));
+        assert!(store.contains(&format!("bob{}", n)));
+    }
+    // and no two of them were handed the same uid
+    let uids: std::collections::HashSet<u32> = store
+        .list_users()
+        .iter()
+        .filter_map(|u| store.get(u))
+        .map(|u| u.uid())
+        .collect();
+    assert_eq!(uids.len(), 11);
+    remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,124 @@
+// tests/su.rs
+//! Session stack: `su` suspending sessions, resuming them, and what a revoked session ends
+
+use credential_playground::auth_utils::*;
+use credential_playground::config::Config;
+use credential_playground::daemon::open_environment;
+use credential_playground::session::*;
+use credential_playground::shell::{Environment, NULLUSER, SESSION, run_command};
+use std::fs::{create_dir_all, remove_dir_all};
+
+/// creates an empty scratch directory unique to a test
+fn scratch_dir(name: &str) -> String {
+    let dir = std::env::temp_dir()
+        .join(format!("credplay-su-{}-{}", name, std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    let _ = remove_dir_all(&dir);
+    create_dir_all(&dir).expect("failed to create scratch dir");
+    dir
+}
+
+/// an environment logged in as root, then `su` to alice, then `su` to bob
+fn stacked(dir: &str) -> Environment {
+    let mut config = Config::default();
+    config.dir = dir.to_string();
+    let mut env = open_environment(&config).unwrap();
+    env.database
+        .transaction()
+        .set_record(UserRecord::new(ROOT, LOCKED_HASH, ROOT_UID))
+        .set("alice", LOCKED_HASH)
+        .set("bob", LOCKED_HASH)
+        .commit()
+        .unwrap();
+    assert!(env.start_session(ROOT, &[AuthFactor::Password]));
+    assert!(env.push_session("alice", &[AuthFactor::Password], false));
+    assert!(env.push_session("bob", &[AuthFactor::Password], false));
+    env
+}
+
+/// users of the sessions still in the session table
+fn users(env: &Environment) -> Vec<String> {
+    let mut users: Vec<String> = env
+        .sessions
+        .list()
+        .unwrap()
+        .into_iter()
+        .map(|s| s.user)
+        .collect();
+    users.sort();
+    users
+}
+
+#[test]
+fn su_suspends_and_exit_resumes() {
+    let dir = scratch_dir("stack");
+    let mut env = stacked(&dir);
+    assert_eq!((env.user(), env.depth()), ("bob", 2));
+    assert_eq!(users(&env), ["alice", "bob", "root"]);
+
+    // variables set in a session are gone once it ends
+    env.vars.insert("EDITOR".to_string(), "vi".to_string());
+    assert!(env.pop_session());
+    assert_eq!((env.user(), env.depth()), ("alice", 1));
+    assert_eq!(env.vars.get("USER").unwrap(), "alice");
+    assert_eq!(env.vars.get("EDITOR"), None);
+    assert_eq!(users(&env), ["alice", "root"]);
+
+    // `su -` starts from the login variables only, plain `su` keeps the others
+    env.vars.insert("EDITOR".to_string(), "vi".to_string());
+    assert!(env.push_session("bob", &[AuthFactor::Password], true));
+    assert_eq!(env.vars.get("EDITOR"), None);
+    assert!(env.pop_session());
+    assert!(env.push_session("bob", &[AuthFactor::Password], false));
+    assert_eq!(env.vars.get("EDITOR").unwrap(), "vi");
+    assert_eq!(env.vars.get("USER").unwrap(), "bob");
+
+    assert!(env.pop_session());
+    assert!(env.pop_session());
+    assert_eq!((env.user(), env.depth()), (ROOT, 0));
+    assert!(!env.pop_session());
+    assert_eq!(env.user(), ROOT);
+
+    env.push_session("alice", &[AuthFactor::Password], false);
+    env.end_all_sessions();
+    assert_eq!((env.user(), env.depth()), (NULLUSER, 0));
+    assert!(users(&env).is_empty());
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn revoking_a_suspended_session_ends_those_above_it() {
+    let dir = scratch_dir("revoke");
+    let mut env = stacked(&dir);
+    let alice = env.stack[1].session.id.clone();
+    env.sessions.revoke(&alice).unwrap();
+
+    assert!(matches!(
+        env.validate_session(),
+        Err((user, SessionError::Revoked)) if user == "alice"
+    ));
+    assert_eq!((env.user(), env.depth()), (ROOT, 0));
+    assert_eq!(env.vars.get("USER").unwrap(), ROOT);
+    assert_eq!(users(&env), [ROOT]);
+    assert!(env.validate_session().is_ok());
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn revoking_the_current_session_logs_out_of_the_stack() {
+    let dir = scratch_dir("current");
+    let mut env = stacked(&dir);
+    let bob = env.session.as_ref().unwrap().id.clone();
+
+    // bob may revoke only bob's own sessions
+    let alice = env.stack[1].session.id.clone();
+    let argv = |id: &str| ["session", "revoke", id].map(String::from);
+    assert_eq!(run_command(&mut env, &SESSION, 3, &argv(&alice)), 1);
+    assert_eq!(env.depth(), 2);
+
+    assert_eq!(run_command(&mut env, &SESSION, 3, &argv(&bob)), 0);
+    assert_eq!((env.user(), env.depth()), (NULLUSER, 0));
+    assert!(users(&env).is_empty());
+    remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,87 @@
+// tests/sudo.rs
+//! Sudoers policy: parsing, and the last matching rule deciding who may run what
+
+use credential_playground::sudo::*;
+
+/// alice is in wheel, bob in no group
+fn in_group(user: &str) -> impl Fn(&str) -> bool + '_ {
+    move |group| user == "alice" && group == "wheel"
+}
+
+const POLICY: &str = "
+# admins may run anything, after giving their password
+%wheel ALL
+
+ALL NOPASSWD: whoami, id
+bob NOPASSWD: dbcheck, audit   # trailing comment
+alice NOPASSWD: audit
+";
+
+#[test]
+fn parse_reads_rules_in_order() {
+    let sudoers = Sudoers::parse(POLICY).unwrap();
+    assert_eq!(sudoers.rules.len(), 4);
+    assert_eq!(
+        sudoers.rules[0],
+        SudoRule {
+            principal: Principal::Group("wheel".to_string()),
+            nopasswd: false,
+            commands: vec![SUDO_ALL.to_string()],
+        }
+    );
+    assert_eq!(
+        sudoers.rules[2].commands,
+        vec!["dbcheck".to_string(), "audit".to_string()]
+    );
+    assert_eq!(sudoers.rules[2].to_string(), "bob NOPASSWD: dbcheck, audit");
+}
+
+#[test]
+fn parse_refuses_malformed_lines() {
+    for (text, line) in [
+        ("alice", 1),
+        ("alice ALL\n% ALL", 2),
+        ("\n\nbob dbcheck,,audit", 3),
+        ("bob NOPASSWD: db check", 1),
+    ] {
+        assert!(
+            matches!(Sudoers::parse(text), Err(SudoersError::Syntax(l, _)) if l == line),
+            "{:?} should fail on line {}",
+            text,
+            line
+        );
+    }
+}
+
+#[test]
+fn last_matching_rule_wins() {
+    let sudoers = Sudoers::parse(POLICY).unwrap();
+
+    // alice's own rule comes after %wheel, so it decides for audit
+    let rule = sudoers.check("alice", in_group("alice"), "audit").unwrap();
+    assert!(rule.nopasswd);
+    let rule = sudoers.check("alice", in_group("alice"), "reset").unwrap();
+    assert_eq!(rule.principal, Principal::Group("wheel".to_string()));
+    assert!(!rule.nopasswd);
+}
+
+#[test]
+fn all_and_groups_match_the_right_users() {
+    let sudoers = Sudoers::parse(POLICY).unwrap();
+
+    // ALL as a principal covers everyone, ALL as a command only members of wheel
+    assert!(
+        sudoers
+            .check("carol", in_group("carol"), "whoami")
+            .is_some()
+    );
+    assert!(
+        sudoers
+            .check("carol", in_group("carol"), "dbcheck")
+            .is_none()
+    );
+    assert!(sudoers.check("bob", in_group("bob"), "dbcheck").is_some());
+    assert!(sudoers.check("bob", in_group("bob"), "reset").is_none());
+    assert_eq!(sudoers.rules_for("carol", in_group("carol")).len(), 1);
+    assert_eq!(sudoers.rules_for("alice", in_group("alice")).len(), 3);
+}
@@ -0,0 +1,130 @@
+// tests/token.rs
+//! Bearer tokens: only untampered HS256 tokens within their validity period verify
+
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use credential_playground::token::*;
+use std::fs::{create_dir_all, remove_dir_all};
+
+/// creates an empty scratch directory unique to a test
+fn scratch_dir(name: &str) -> String {
+    let dir = std::env::temp_dir()
+        .join(format!("credplay-token-{}-{}", name, std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    let _ = remove_dir_all(&dir);
+    create_dir_all(&dir).expect("failed to create scratch dir");
+    dir
+}
+
+/// claims for alice, issued at `iat` and valid for an hour
+fn claims(iat: u64) -> Claims {
+    Claims {
+        sub: "alice".to_string(),
+        iat,
+        exp: iat + 3600,
+        jti: "00112233445566778899aabbccddeeff".to_string(),
+        caps: Vec::new(),
+    }
+}
+
+const KEY: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+#[test]
+fn signed_token_verifies() {
+    let token = sign(KEY, &claims(1000));
+    assert_eq!(verify(KEY, &token, 1000).unwrap(), claims(1000));
+    assert!(matches!(
+        verify(b"another key", &token, 1000),
+        Err(TokenError::Signature)
+    ));
+}
+
+#[test]
+fn alg_none_is_rejected() {
+    let token = sign(KEY, &claims(1000));
+    let (_, rest) = token.split_once('.').unwrap();
+    let (payload, _) = rest.split_once('.').unwrap();
+    let header = URL_SAFE_NO_PAD.encode(br#"{"alg":"none","typ":"JWT"}"#);
+    let unsigned = format!("{}.{}.", header, payload);
+    assert!(matches!(
+        verify(KEY, &unsigned, 1000),
+        Err(TokenError::Algorithm(alg)) if alg == "none"
+    ));
+}
+
+#[test]
+fn tampered_payload_is_rejected() {
+    let token = sign(KEY, &claims(1000));
+    let parts: Vec<&str> = token.split('.').collect();
+    let mut forged = claims(1000);
+    forged.sub = "root".to_string();
+    let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&forged).unwrap());
+    let tampered = format!("{}.{}.{}", parts[0], payload, parts[2]);
+    assert!(matches!(
+        verify(KEY, &tampered, 1000),
+        Err(TokenError::Signature)
+    ));
+}
+
+#[test]
+fn expired_and_future_tokens_are_rejected() {
+    let token = sign(KEY, &claims(1000));
+    assert!(verify(KEY, &token, 4599).is_ok());
+    assert!(matches!(
+        verify(KEY, &token, 4600),
+        Err(TokenError::Expired(4600))
+    ));
+    assert!(matches!(
+        verify(KEY, &token, 1000 - CLOCK_SKEW - 1),
+        Err(TokenError::NotYetValid(1000))
+    ));
+}
+
+#[test]
+fn revoked_tokens_are_rejected() {
+    let dir = scratch_dir("revoked");
+    let authority = TokenAuthority::new(
+        &format!("{}/token.key", dir),
+        &format!("{}/tokens.revoked", dir),
+    );
+    let (first, first_claims) = authority.issue("alice", &[], 3600).unwrap();
+    let (second, _) = authority.issue("alice", &[], 3600).unwrap();
+    let (other, _) = authority.issue("bob", &[], 3600).unwrap();
+
+    // revoking one token leaves the others alone
+    authority.revoke(&first_claims).unwrap();
+    assert!(matches!(authority.verify(&first), Err(TokenError::Revoked)));
+    assert!(authority.verify(&second).is_ok());
+
+    // changing an account revokes everything issued to it so far
+    authority.revoke_subject("alice").unwrap();
+    assert!(matches!(
+        authority.verify(&second),
+        Err(TokenError::Revoked)
+    ));
+    assert!(authority.verify(&other).is_ok());
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn concurrent_first_issue_shares_one_key() {
+    let dir = scratch_dir("key");
+    let key_path = format!("{}/token.key", dir);
+    let revoked_path = format!("{}/tokens.revoked", dir);
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let (key_path, revoked_path) = (key_path.clone(), revoked_path.clone());
+            std::thread::spawn(move || {
+                TokenAuthority::new(&key_path, &revoked_path)
+                    .issue("alice", &[], 3600)
+                    .map(|(token, _)| token)
+            })
+        })
+        .collect();
+    let authority = TokenAuthority::new(&key_path, &revoked_path);
+    for handle in handles {
+        let token = handle.join().unwrap().expect("issue failed");
+        assert!(authority.verify(&token).is_ok());
+    }
+    remove_dir_all(&dir).unwrap();
+}
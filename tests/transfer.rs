@@ -0,0 +1,187 @@
+// tests/transfer.rs
+//! Export and import: validation of uids and records, merge modes, and round trips
+
+use credential_playground::auth_utils::*;
+use credential_playground::transfer::*;
+use std::fs::{create_dir_all, metadata, remove_dir_all, write};
+
+/// creates an empty scratch directory unique to a test
+fn scratch_dir(name: &str) -> String {
+    let dir = std::env::temp_dir()
+        .join(format!("credplay-transfer-{}-{}", name, std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    let _ = remove_dir_all(&dir);
+    create_dir_all(&dir).expect("failed to create scratch dir");
+    dir
+}
+
+/// a record with the given ids, home and shell defaulted
+fn record(name: &str, hash: &str, uid: u32, gid: u32) -> UserRecord {
+    let line = format!(
+        "{}:{}:{}:{}::/home/{}:{}",
+        name, hash, uid, gid, name, DEF_SHELL
+    );
+    UserRecord::parse(&line, 0).unwrap()
+}
+
+/// writes records to a JSON export file, bypassing the checks `export_database` makes
+fn write_export(path: &str, users: Vec<UserRecord>) {
+    let export = DatabaseExport {
+        version: EXPORT_VERSION,
+        users,
+    };
+    write(path, serde_json::to_string(&export).unwrap()).unwrap();
+}
+
+/// a store holding root and alice
+fn store() -> MemoryStore {
+    let mut store = MemoryStore::new();
+    store
+        .transaction()
+        .set_record(record(ROOT, LOCKED_HASH, ROOT_UID, ROOT_UID))
+        .set_record(record("alice", LOCKED_HASH, 1000, 1000))
+        .commit()
+        .unwrap();
+    store
+}
+
+#[test]
+fn shared_uids_are_refused() {
+    let dir = scratch_dir("uids");
+    let (path, groups) = (format!("{}/in.json", dir), format!("{}/group", dir));
+    let groups = GroupDatabase::new(&groups);
+
+    // two imported accounts sharing a uid
+    write_export(
+        &path,
+        vec![
+            record("bob", LOCKED_HASH, 1001, 1001),
+            record("carol", LOCKED_HASH, 1001, 1001),
+        ],
+    );
+    let mut database = store();
+    assert!(matches!(
+        import_database(&mut database, &groups, &path, MergeMode::Overwrite),
+        Err(TransferError::Invalid(username, _)) if username == "carol"
+    ));
+
+    // an imported account taking the uid of one that stays
+    write_export(&path, vec![record("bob", LOCKED_HASH, 1000, 1000)]);
+    assert!(matches!(
+        import_database(&mut database, &groups, &path, MergeMode::Overwrite),
+        Err(TransferError::Invalid(username, _)) if username == "bob"
+    ));
+    assert!(!database.contains("bob"));
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn only_root_has_id_zero() {
+    for (bad, username) in [
+        (record(ROOT, LOCKED_HASH, 1001, 1001), ROOT),
+        (record(ROOT, LOCKED_HASH, ROOT_UID, 1001), ROOT),
+        (record("bob", LOCKED_HASH, ROOT_UID, 1001), "bob"),
+        (record("bob", LOCKED_HASH, 1001, ROOT_UID), "bob"),
+    ] {
+        assert!(validate_record(&bad).is_err(), "{}", bad.record_line());
+
+        let dir = scratch_dir("root");
+        let path = format!("{}/in.json", dir);
+        write_export(&path, vec![bad]);
+        let groups = GroupDatabase::new(&format!("{}/group", dir));
+        assert!(matches!(
+            import_database(&mut store(), &groups, &path, MergeMode::Overwrite),
+            Err(TransferError::Invalid(name, _)) if name == username
+        ));
+        remove_dir_all(&dir).unwrap();
+    }
+    assert!(validate_record(&record(ROOT, LOCKED_HASH, ROOT_UID, ROOT_UID)).is_ok());
+}
+
+#[test]
+fn merge_modes_decide_for_existing_accounts() {
+    let dir = scratch_dir("merge");
+    let path = format!("{}/in.json", dir);
+    let groups = GroupDatabase::new(&format!("{}/group", dir));
+    let hash = hash_password("hunter2", &base64_encode(b"salt"), 1);
+    write_export(
+        &path,
+        vec![
+            record("alice", &hash, 1000, 1000),
+            record("bob", LOCKED_HASH, 1001, 1001),
+        ],
+    );
+
+    let mut database = store();
+    let summary = import_database(&mut database, &groups, &path, MergeMode::Overwrite).unwrap();
+    assert_eq!(
+        summary,
+        ImportSummary {
+            added: 1,
+            replaced: 1,
+            skipped: 0
+        }
+    );
+    assert_eq!(database.get("alice").unwrap().hash(), hash);
+    assert!(database.contains("bob"));
+
+    let mut database = store();
+    let summary = import_database(&mut database, &groups, &path, MergeMode::SkipExisting).unwrap();
+    assert_eq!(
+        summary,
+        ImportSummary {
+            added: 1,
+            replaced: 0,
+            skipped: 1
+        }
+    );
+    assert_eq!(database.get("alice").unwrap().hash(), LOCKED_HASH);
+    assert!(database.contains("bob"));
+
+    let mut database = store();
+    assert!(matches!(
+        import_database(&mut database, &groups, &path, MergeMode::FailOnConflict),
+        Err(TransferError::Conflict(username)) if username == "alice"
+    ));
+    assert_eq!(database.get("alice").unwrap().hash(), LOCKED_HASH);
+    assert!(!database.contains("bob"));
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn exports_import_again() {
+    let dir = scratch_dir("round-trip");
+    let groups = GroupDatabase::new(&format!("{}/group", dir));
+
+    // the passwd file keeps commas in the last subfield, which import would refuse
+    let mut database = store();
+    let mut alice = database.get("alice").unwrap();
+    alice.set_gecos(Gecos::parse("Alice,101,,,on call, weekends"));
+    assert_eq!(alice.gecos().other, "on call, weekends");
+    database.transaction().set_record(alice).commit().unwrap();
+
+    for path in [format!("{}/out.json", dir), format!("{}/out.toml", dir)] {
+        assert!(matches!(
+            export_database(&database, &path),
+            Err(TransferError::Invalid(username, _)) if username == "alice"
+        ));
+        assert!(metadata(&path).is_err());
+    }
+
+    let mut alice = database.get("alice").unwrap();
+    alice.set_gecos(Gecos::parse("Alice,101,,,on call"));
+    database.transaction().set_record(alice).commit().unwrap();
+    for path in [format!("{}/out.json", dir), format!("{}/out.toml", dir)] {
+        assert_eq!(export_database(&database, &path).unwrap(), 2);
+        let mut copy = MemoryStore::new();
+        import_database(&mut copy, &groups, &path, MergeMode::FailOnConflict).unwrap();
+        for username in [ROOT, "alice"] {
+            assert_eq!(
+                copy.get(username).unwrap().record_line(),
+                database.get(username).unwrap().record_line()
+            );
+        }
+    }
+    remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,109 @@
+// tests/username.rs
+//! Username policy: normalization, refused names, and names confusable with existing accounts
+
+use credential_playground::auth_utils::*;
+use credential_playground::username::*;
+
+/// names of existing accounts
+fn existing(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+/// the default policy, with any character allowed
+fn permissive() -> UsernamePolicy {
+    UsernamePolicy {
+        pattern: regex::Regex::new(".").unwrap(),
+        ..UsernamePolicy::default()
+    }
+}
+
+#[test]
+fn names_are_stored_in_nfkc() {
+    let policy = UsernamePolicy::default();
+    assert_eq!(normalize("ａｌｉｃｅ"), "alice");
+    assert_eq!(policy.check("ａｌｉｃｅ", &existing(&[])).unwrap(), "alice");
+    assert_eq!(policy.check("bob_2", &existing(&[])).unwrap(), "bob_2");
+
+    // the store compares normalized names, so the fullwidth spelling is the same account
+    let mut store = MemoryStore::new();
+    store
+        .transaction()
+        .set("alice", LOCKED_HASH)
+        .commit()
+        .unwrap();
+    assert!(matches!(
+        store.transaction().set("ａｌｉｃｅ", LOCKED_HASH).commit(),
+        Err(StoreError::AlreadyExists(username)) if username == "alice"
+    ));
+    assert_eq!(store.list_users(), vec!["alice".to_string()]);
+}
+
+#[test]
+fn unsafe_long_and_reserved_names_are_refused() {
+    let policy = UsernamePolicy::default();
+    let none = existing(&[]);
+    for name in ["al:ice", "al,ice", "al ice", "al\tice", "al\u{7}ice"] {
+        assert_eq!(
+            permissive().check(name, &none),
+            Err(UsernameError::Unsafe),
+            "{:?}",
+            name
+        );
+    }
+    assert_eq!(
+        policy.check("", &none),
+        Err(UsernameError::Length(DEF_USERNAME_MIN, DEF_USERNAME_MAX))
+    );
+    assert!(matches!(
+        policy.check(&"a".repeat(DEF_USERNAME_MAX + 1), &none),
+        Err(UsernameError::Length(_, _))
+    ));
+    assert!(matches!(
+        policy.check("-alice", &none),
+        Err(UsernameError::Pattern(_))
+    ));
+    assert_eq!(policy.check("Wheel", &none), Err(UsernameError::Reserved));
+}
+
+#[test]
+fn confusable_names_are_refused() {
+    let policy = permissive();
+    let accounts = existing(&["alice", "paypal"]);
+
+    // cyrillic a, and a digit one for a lowercase l
+    assert_eq!(
+        policy.check("\u{430}lice", &accounts),
+        Err(UsernameError::Confusable("alice".to_string()))
+    );
+    assert_eq!(
+        policy.check("paypa1", &accounts),
+        Err(UsernameError::Confusable("paypal".to_string()))
+    );
+    assert!(policy.check("alicia", &accounts).is_ok());
+
+    let lenient = UsernamePolicy {
+        reject_confusables: false,
+        ..permissive()
+    };
+    assert!(lenient.check("paypa1", &accounts).is_ok());
+}
+
+#[test]
+fn case_conflicts_depend_on_the_policy() {
+    let accounts = existing(&["alice"]);
+    assert_eq!(
+        UsernamePolicy::default().check("Alice", &accounts).unwrap(),
+        "Alice"
+    );
+    let strict = UsernamePolicy {
+        case_insensitive: true,
+        ..UsernamePolicy::default()
+    };
+    assert_eq!(
+        strict.check("Alice", &accounts),
+        Err(UsernameError::CaseConflict("alice".to_string()))
+    );
+
+    // an account's own name never conflicts with itself
+    assert_eq!(strict.check("alice", &accounts).unwrap(), "alice");
+}